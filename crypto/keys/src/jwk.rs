@@ -0,0 +1,267 @@
+// Copyright (c) 2018-2022 The MobileCoin Foundation
+
+//! JSON Web Key (JWK) import and export for Ed25519 and X25519 keys, as
+//! specified for octet key pairs in RFC 8037.
+
+use crate::{Ed25519Private, Ed25519Public, KeyError, X25519Private, X25519Public, B64URL_CONFIG};
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::{Debug, Formatter, Result as FmtResult};
+use serde::{Deserialize, Serialize};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// The `kty` of Ed25519 and X25519 keys.
+pub const JWK_KEY_TYPE_OKP: &str = "OKP";
+
+/// The `crv` of Ed25519 keys.
+pub const JWK_CURVE_ED25519: &str = "Ed25519";
+
+/// The `crv` of X25519 keys.
+pub const JWK_CURVE_X25519: &str = "X25519";
+
+/// A JSON Web Key for an octet key pair (`kty: OKP`).
+///
+/// Public keys only carry `x`, the base64url-encoded public key. Private keys
+/// also carry `d`, the base64url-encoded private key, which is zeroized when
+/// the JWK is dropped.
+#[derive(Clone, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Jwk {
+    /// The key type, always `OKP`.
+    pub kty: String,
+
+    /// The curve, `Ed25519` or `X25519`.
+    pub crv: String,
+
+    /// The base64url-encoded public key.
+    pub x: String,
+
+    /// The base64url-encoded private key, if this is a private key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub d: Option<String>,
+}
+
+/// The debug implementation does not output the private key
+impl Debug for Jwk {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("Jwk")
+            .field("kty", &self.kty)
+            .field("crv", &self.crv)
+            .field("x", &self.x)
+            .field("d", &self.d.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+impl Drop for Jwk {
+    fn drop(&mut self) {
+        self.d.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for Jwk {}
+
+impl Jwk {
+    fn new(crv: &str, public: &[u8], private: Option<&[u8]>) -> Self {
+        Self {
+            kty: JWK_KEY_TYPE_OKP.to_string(),
+            crv: crv.to_string(),
+            x: base64::encode_config(public, B64URL_CONFIG),
+            d: private.map(|private| base64::encode_config(private, B64URL_CONFIG)),
+        }
+    }
+
+    /// Check the key type and curve, and decode `x`.
+    fn public_bytes(&self, crv: &str) -> Result<Vec<u8>, KeyError> {
+        if self.kty != JWK_KEY_TYPE_OKP || self.crv != crv {
+            return Err(KeyError::AlgorithmMismatch);
+        }
+        base64::decode_config(&self.x, B64URL_CONFIG).map_err(|_e| KeyError::InvalidEncoding)
+    }
+
+    /// Check the key type and curve, and decode `d`.
+    fn private_bytes(&self, crv: &str) -> Result<Vec<u8>, KeyError> {
+        if self.kty != JWK_KEY_TYPE_OKP || self.crv != crv {
+            return Err(KeyError::AlgorithmMismatch);
+        }
+        let d = self.d.as_ref().ok_or(KeyError::InvalidPrivateKey)?;
+        base64::decode_config(d, B64URL_CONFIG).map_err(|_e| KeyError::InvalidEncoding)
+    }
+}
+
+impl From<&Ed25519Public> for Jwk {
+    fn from(src: &Ed25519Public) -> Self {
+        Self::new(JWK_CURVE_ED25519, src.as_ref(), None)
+    }
+}
+
+impl From<&Ed25519Private> for Jwk {
+    fn from(src: &Ed25519Private) -> Self {
+        let public = Ed25519Public::from(src);
+        Self::new(JWK_CURVE_ED25519, public.as_ref(), Some(src.as_ref()))
+    }
+}
+
+impl From<&X25519Public> for Jwk {
+    fn from(src: &X25519Public) -> Self {
+        Self::new(JWK_CURVE_X25519, src.as_ref(), None)
+    }
+}
+
+impl From<&X25519Private> for Jwk {
+    fn from(src: &X25519Private) -> Self {
+        let public = X25519Public::from(src);
+        let mut private: Vec<u8> = src.clone().into();
+        let jwk = Self::new(JWK_CURVE_X25519, public.as_ref(), Some(&private));
+        private.zeroize();
+        jwk
+    }
+}
+
+impl TryFrom<&Jwk> for Ed25519Public {
+    type Error = KeyError;
+
+    fn try_from(src: &Jwk) -> Result<Self, KeyError> {
+        let bytes = src.public_bytes(JWK_CURVE_ED25519)?;
+        Ed25519Public::try_from(bytes.as_slice()).map_err(|_e| KeyError::InvalidPublicKey)
+    }
+}
+
+impl TryFrom<&Jwk> for Ed25519Private {
+    type Error = KeyError;
+
+    /// The `x` field must match the public key of `d`.
+    fn try_from(src: &Jwk) -> Result<Self, KeyError> {
+        let mut bytes = src.private_bytes(JWK_CURVE_ED25519)?;
+        let private = Ed25519Private::try_from(bytes.as_slice());
+        bytes.zeroize();
+        let private = private.map_err(|_e| KeyError::InvalidPrivateKey)?;
+        if Ed25519Public::try_from(src)? != Ed25519Public::from(&private) {
+            return Err(KeyError::InvalidPublicKey);
+        }
+        Ok(private)
+    }
+}
+
+impl TryFrom<&Jwk> for X25519Public {
+    type Error = KeyError;
+
+    fn try_from(src: &Jwk) -> Result<Self, KeyError> {
+        let bytes = src.public_bytes(JWK_CURVE_X25519)?;
+        X25519Public::try_from(bytes.as_slice())
+    }
+}
+
+impl TryFrom<&Jwk> for X25519Private {
+    type Error = KeyError;
+
+    /// The `x` field must match the public key of `d`.
+    fn try_from(src: &Jwk) -> Result<Self, KeyError> {
+        let mut bytes = src.private_bytes(JWK_CURVE_X25519)?;
+        let private = X25519Private::try_from(bytes.as_slice());
+        bytes.zeroize();
+        let private = private?;
+        if X25519Public::try_from(src)? != X25519Public::from(&private) {
+            return Err(KeyError::InvalidPublicKey);
+        }
+        Ok(private)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mc_util_from_random::FromRandom;
+    use rand_core::SeedableRng;
+    use rand_hc::Hc128Rng;
+
+    #[test]
+    fn ed25519_round_trip() {
+        let mut rng = Hc128Rng::seed_from_u64(0);
+        let private = Ed25519Private::from_random(&mut rng);
+        let public = Ed25519Public::from(&private);
+
+        let public_jwk = Jwk::from(&public);
+        assert_eq!(public_jwk.kty, "OKP");
+        assert_eq!(public_jwk.crv, "Ed25519");
+        assert_eq!(public_jwk.d, None);
+        let json = serde_json::to_string(&public_jwk).unwrap();
+        assert!(!json.contains("\"d\""));
+        let decoded: Jwk = serde_json::from_str(&json).unwrap();
+        assert_eq!(Ed25519Public::try_from(&decoded).unwrap(), public);
+
+        let private_jwk = Jwk::from(&private);
+        assert_eq!(private_jwk.x, public_jwk.x);
+        let json = serde_json::to_string(&private_jwk).unwrap();
+        let decoded: Jwk = serde_json::from_str(&json).unwrap();
+        let round_tripped = Ed25519Private::try_from(&decoded).unwrap();
+        assert_eq!(round_tripped.as_ref(), private.as_ref());
+
+        // A public JWK can't be imported as a private key.
+        assert_eq!(
+            Ed25519Private::try_from(&public_jwk).unwrap_err(),
+            KeyError::InvalidPrivateKey
+        );
+    }
+
+    #[test]
+    fn x25519_round_trip() {
+        let mut rng = Hc128Rng::seed_from_u64(0);
+        let private = X25519Private::from_random(&mut rng);
+        let public = X25519Public::from(&private);
+
+        let public_jwk = Jwk::from(&public);
+        assert_eq!(public_jwk.kty, "OKP");
+        assert_eq!(public_jwk.crv, "X25519");
+        let json = serde_json::to_string(&public_jwk).unwrap();
+        let decoded: Jwk = serde_json::from_str(&json).unwrap();
+        assert_eq!(X25519Public::try_from(&decoded).unwrap(), public);
+
+        let private_jwk = Jwk::from(&private);
+        let json = serde_json::to_string(&private_jwk).unwrap();
+        let decoded: Jwk = serde_json::from_str(&json).unwrap();
+        let round_tripped = X25519Private::try_from(&decoded).unwrap();
+        assert_eq!(X25519Public::from(&round_tripped), public);
+    }
+
+    #[test]
+    fn x_decodes_to_raw_public_key() {
+        let mut rng = Hc128Rng::seed_from_u64(1);
+        let ed25519 = Ed25519Public::from(&Ed25519Private::from_random(&mut rng));
+        let x25519 = X25519Public::from(&X25519Private::from_random(&mut rng));
+
+        let ed25519_bytes: &[u8] = ed25519.as_ref();
+        let x = base64::decode_config(&Jwk::from(&ed25519).x, base64::URL_SAFE_NO_PAD).unwrap();
+        assert_eq!(x, ed25519_bytes);
+
+        let x25519_bytes: &[u8] = x25519.as_ref();
+        let x = base64::decode_config(&Jwk::from(&x25519).x, base64::URL_SAFE_NO_PAD).unwrap();
+        assert_eq!(x, x25519_bytes);
+    }
+
+    #[test]
+    fn mismatched_curve_or_public_key_is_rejected() {
+        let mut rng = Hc128Rng::seed_from_u64(2);
+        let ed25519 = Ed25519Private::from_random(&mut rng);
+        let x25519 = X25519Private::from_random(&mut rng);
+
+        assert_eq!(
+            X25519Public::try_from(&Jwk::from(&ed25519)).unwrap_err(),
+            KeyError::AlgorithmMismatch
+        );
+        assert_eq!(
+            Ed25519Private::try_from(&Jwk::from(&x25519)).unwrap_err(),
+            KeyError::AlgorithmMismatch
+        );
+
+        let mut jwk = Jwk::from(&ed25519);
+        jwk.x = Jwk::from(&Ed25519Public::from(&Ed25519Private::from_random(&mut rng)))
+            .x
+            .clone();
+        assert_eq!(
+            Ed25519Private::try_from(&jwk).unwrap_err(),
+            KeyError::InvalidPublicKey
+        );
+    }
+}
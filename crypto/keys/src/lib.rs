@@ -51,28 +51,47 @@
 extern crate alloc;
 
 mod ed25519;
+mod jwk;
+mod membership_proof;
+#[cfg(feature = "bip39")]
+mod mnemonic;
 mod ristretto;
 mod traits;
 mod x25519;
 
 pub use crate::{
-    ed25519::{Ed25519Pair, Ed25519Private, Ed25519Public, Ed25519Signature},
+    ed25519::{
+        sign_ed25519_chain_link, sign_validator_set_delta, verify_batch_deterministic,
+        verify_ed25519_chain, verify_encoded, Ed25519Pair, Ed25519Private, Ed25519Public,
+        Ed25519Signature, Ed25519StreamingVerifier, Ed25519VerificationMode, ValidatorSetDelta,
+        MAX_ED25519_CHAIN_DEPTH,
+    },
+    jwk::{Jwk, JWK_CURVE_ED25519, JWK_CURVE_X25519, JWK_KEY_TYPE_OKP},
+    membership_proof::RistrettoMembershipProof,
     ristretto::{
-        CompressedRistrettoPublic, Ristretto, RistrettoEphemeralPrivate, RistrettoPrivate,
-        RistrettoPublic, RistrettoSecret, RistrettoSignature,
+        CompressedRistrettoPublic, Ristretto, RistrettoBatchError, RistrettoEphemeralPrivate,
+        RistrettoPrivate, RistrettoPublic, RistrettoSecret, RistrettoSignature,
     },
     traits::{
-        DistinguishedEncoding, Fingerprintable, Kex, KexEphemeralPrivate, KexPrivate, KexPublic,
-        KexReusablePrivate, KexSecret, KeyError, PrivateKey, PublicKey,
+        verify_and_take, Base64UrlEncoding, CanonicalEncode, DistinguishedEncoding,
+        Fingerprintable, HexEncoding, Kex, KexEphemeralPrivate, KexPrivate, KexPublic,
+        KexReusablePrivate, KexSecret, KeyError, PemEncoding, PrivateKey, PublicKey,
     },
     x25519::{
-        X25519EphemeralPrivate, X25519Private, X25519Public, X25519Secret, X25519, X25519_LEN,
+        MessageKey, RatchetState, X25519EphemeralPrivate, X25519Private, X25519Public,
+        X25519RecvKey, X25519Secret, X25519SendKey, X25519, X25519_LEN,
     },
 };
 
+#[cfg(feature = "bip39")]
+pub use crate::mnemonic::MnemonicError;
+
 // Expected format for base64 strings
 pub(crate) const B64_CONFIG: base64::Config = base64::STANDARD;
 
+// Expected format for base64 strings embedded in URLs
+pub(crate) const B64URL_CONFIG: base64::Config = base64::URL_SAFE_NO_PAD;
+
 pub use digest::Digest;
 pub use mc_util_repr_bytes::{typenum::Unsigned, GenericArray, LengthMismatch, ReprBytes};
 pub use signature::{
@@ -0,0 +1,357 @@
+// Copyright (c) 2018-2022 The MobileCoin Foundation
+
+//! One-of-many membership proofs over a small committee of Ristretto keys.
+//!
+//! A prover holding the private key of one committee member proves that it
+//! does, without revealing which member it is. This is a Schnorr ring
+//! signature in the style of Abe, Ohkubo and Suzuki: the proof contains one
+//! challenge and one response per committee member, so its size and
+//! verification time are linear in the size of the committee.
+
+use crate::{KeyError, RistrettoPrivate, RistrettoPublic};
+use alloc::{vec, vec::Vec};
+use curve25519_dalek::{
+    constants::RISTRETTO_BASEPOINT_POINT, ristretto::RistrettoPoint, scalar::Scalar,
+};
+use mc_crypto_digestible::MerlinTranscript;
+use rand_core::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+
+/// Domain separator for the transcript of a membership proof.
+const MEMBERSHIP_PROOF_DOMAIN_TAG: &[u8] = b"mc_ristretto_membership_proof";
+
+/// The length of an encoded scalar.
+const SCALAR_LEN: usize = 32;
+
+/// A proof that the prover holds the private key of one member of a committee,
+/// bound to a context and a message.
+///
+/// A proof is encoded as its challenge followed by its responses, each as a
+/// 32-byte canonical scalar, and is serialized as those bytes.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(into = "Vec<u8>", try_from = "Vec<u8>")]
+pub struct RistrettoMembershipProof {
+    /// The challenge for the first committee member.
+    challenge: Scalar,
+
+    /// One response per committee member, in committee order.
+    responses: Vec<Scalar>,
+}
+
+impl RistrettoMembershipProof {
+    /// Prove that `private` is the private key of a member of `committee`.
+    ///
+    /// Arguments:
+    /// * context: A domain separator for the action being proven
+    /// * message: The message the proof is bound to
+    /// * committee: The public keys of the committee members
+    /// * private: The prover's private key
+    /// * rng: The source of the proof's nonces
+    ///
+    /// Returns `KeyError::InvalidPrivateKey` if `private` does not belong to a
+    /// member of `committee`.
+    pub fn prove<R: CryptoRng + RngCore>(
+        context: &[u8],
+        message: &[u8],
+        committee: &[RistrettoPublic],
+        private: &RistrettoPrivate,
+        rng: &mut R,
+    ) -> Result<Self, KeyError> {
+        let public = RistrettoPublic::from(private);
+        let index = committee
+            .iter()
+            .position(|member| *member == public)
+            .ok_or(KeyError::InvalidPrivateKey)?;
+        let size = committee.len();
+        let transcript = transcript(context, message, committee);
+
+        let mut challenges = vec![Scalar::zero(); size];
+        let mut responses = vec![Scalar::zero(); size];
+
+        // Commit to a nonce at our own position, then close the ring by
+        // simulating every other member's response.
+        let nonce = RistrettoPrivate::from_random_nonzero(rng);
+        let mut commitment = nonce.0 * RISTRETTO_BASEPOINT_POINT;
+        for offset in 1..size {
+            let i = (index + offset) % size;
+            challenges[i] = challenge(&transcript, i, &commitment);
+            responses[i] = Scalar::random(rng);
+            commitment = member_commitment(&committee[i], &challenges[i], &responses[i]);
+        }
+        challenges[index] = challenge(&transcript, index, &commitment);
+        responses[index] = nonce.0 - challenges[index] * private.0;
+
+        Ok(Self {
+            challenge: challenges[0],
+            responses,
+        })
+    }
+
+    /// Verify that the prover holds the private key of a member of
+    /// `committee`, for the given context and message.
+    pub fn verify(
+        &self,
+        context: &[u8],
+        message: &[u8],
+        committee: &[RistrettoPublic],
+    ) -> Result<(), KeyError> {
+        if committee.is_empty() {
+            return Err(KeyError::InvalidPublicKey);
+        }
+        if self.responses.len() != committee.len() {
+            return Err(KeyError::LengthMismatch(
+                self.responses.len(),
+                committee.len(),
+            ));
+        }
+        let transcript = transcript(context, message, committee);
+
+        let mut next_challenge = self.challenge;
+        for (i, (member, response)) in committee.iter().zip(&self.responses).enumerate() {
+            let commitment = member_commitment(member, &next_challenge, response);
+            next_challenge = challenge(&transcript, (i + 1) % committee.len(), &commitment);
+        }
+
+        if bool::from(next_challenge.ct_eq(&self.challenge)) {
+            Ok(())
+        } else {
+            Err(KeyError::SignatureMismatch)
+        }
+    }
+
+    /// Encode this proof as its challenge followed by its responses.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity((1 + self.responses.len()) * SCALAR_LEN);
+        bytes.extend_from_slice(self.challenge.as_bytes());
+        for response in &self.responses {
+            bytes.extend_from_slice(response.as_bytes());
+        }
+        bytes
+    }
+
+    /// The number of committee members this proof was made for.
+    pub fn committee_size(&self) -> usize {
+        self.responses.len()
+    }
+}
+
+impl From<RistrettoMembershipProof> for Vec<u8> {
+    fn from(src: RistrettoMembershipProof) -> Vec<u8> {
+        src.to_bytes()
+    }
+}
+
+impl TryFrom<&[u8]> for RistrettoMembershipProof {
+    type Error = KeyError;
+
+    fn try_from(src: &[u8]) -> Result<Self, KeyError> {
+        // A proof has a challenge and at least one response.
+        if src.len() < 2 * SCALAR_LEN || src.len() % SCALAR_LEN != 0 {
+            return Err(KeyError::InvalidEncoding);
+        }
+        let mut scalars = src.chunks_exact(SCALAR_LEN).map(|chunk| {
+            let mut bytes = [0u8; SCALAR_LEN];
+            bytes.copy_from_slice(chunk);
+            Scalar::from_canonical_bytes(bytes).ok_or(KeyError::InvalidEncoding)
+        });
+        let challenge = scalars.next().ok_or(KeyError::InvalidEncoding)??;
+        let responses = scalars.collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            challenge,
+            responses,
+        })
+    }
+}
+
+impl TryFrom<Vec<u8>> for RistrettoMembershipProof {
+    type Error = KeyError;
+
+    fn try_from(src: Vec<u8>) -> Result<Self, KeyError> {
+        Self::try_from(src.as_slice())
+    }
+}
+
+/// The transcript every challenge of a proof is derived from.
+fn transcript(context: &[u8], message: &[u8], committee: &[RistrettoPublic]) -> MerlinTranscript {
+    let mut transcript = MerlinTranscript::new(MEMBERSHIP_PROOF_DOMAIN_TAG);
+    transcript.append_message(b"context", context);
+    transcript.append_message(b"message", message);
+    transcript.append_u64(b"committee_size", committee.len() as u64);
+    for member in committee {
+        transcript.append_message(b"member", member.0.compress().as_bytes());
+    }
+    transcript
+}
+
+/// The challenge for member `index`, given the previous member's commitment.
+fn challenge(transcript: &MerlinTranscript, index: usize, commitment: &RistrettoPoint) -> Scalar {
+    let mut transcript = transcript.clone();
+    transcript.append_u64(b"index", index as u64);
+    transcript.append_message(b"commitment", commitment.compress().as_bytes());
+    let mut bytes = [0u8; 64];
+    transcript.challenge_bytes(b"challenge", &mut bytes);
+    Scalar::from_bytes_mod_order_wide(&bytes)
+}
+
+/// Recompute a member's commitment from its challenge and response.
+fn member_commitment(
+    member: &RistrettoPublic,
+    challenge: &Scalar,
+    response: &Scalar,
+) -> RistrettoPoint {
+    response * RISTRETTO_BASEPOINT_POINT + challenge * member.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mc_util_from_random::FromRandom;
+
+    const CONTEXT: &[u8] = b"committee action";
+
+    fn committee<R: CryptoRng + RngCore>(
+        size: usize,
+        rng: &mut R,
+    ) -> (Vec<RistrettoPrivate>, Vec<RistrettoPublic>) {
+        let privates = (0..size)
+            .map(|_| RistrettoPrivate::from_random(rng))
+            .collect::<Vec<_>>();
+        let publics = privates.iter().map(RistrettoPublic::from).collect();
+        (privates, publics)
+    }
+
+    #[test]
+    fn member_proof_verifies() {
+        mc_util_test_helper::run_with_several_seeds(|mut rng| {
+            let (privates, publics) = committee(5, &mut rng);
+            for private in &privates {
+                let proof = RistrettoMembershipProof::prove(
+                    CONTEXT, b"message", &publics, private, &mut rng,
+                )
+                .unwrap();
+                assert_eq!(proof.committee_size(), publics.len());
+                assert_eq!(proof.verify(CONTEXT, b"message", &publics), Ok(()));
+
+                // The proof is bound to the context, message and committee.
+                assert_eq!(
+                    proof.verify(b"other action", b"message", &publics),
+                    Err(KeyError::SignatureMismatch)
+                );
+                assert_eq!(
+                    proof.verify(CONTEXT, b"other message", &publics),
+                    Err(KeyError::SignatureMismatch)
+                );
+                let mut reordered = publics.clone();
+                reordered.swap(0, 1);
+                assert_eq!(
+                    proof.verify(CONTEXT, b"message", &reordered),
+                    Err(KeyError::SignatureMismatch)
+                );
+                assert_eq!(
+                    proof.verify(CONTEXT, b"message", &publics[1..]),
+                    Err(KeyError::LengthMismatch(5, 4))
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn single_member_committee() {
+        mc_util_test_helper::run_with_several_seeds(|mut rng| {
+            let (privates, publics) = committee(1, &mut rng);
+            let proof = RistrettoMembershipProof::prove(
+                CONTEXT,
+                b"message",
+                &publics,
+                &privates[0],
+                &mut rng,
+            )
+            .unwrap();
+            assert_eq!(proof.verify(CONTEXT, b"message", &publics), Ok(()));
+        });
+    }
+
+    #[test]
+    fn non_member_cannot_prove_membership() {
+        mc_util_test_helper::run_with_several_seeds(|mut rng| {
+            let (_privates, publics) = committee(4, &mut rng);
+            let outsider = RistrettoPrivate::from_random(&mut rng);
+            assert_eq!(
+                RistrettoMembershipProof::prove(CONTEXT, b"message", &publics, &outsider, &mut rng),
+                Err(KeyError::InvalidPrivateKey)
+            );
+
+            // A proof over a committee the outsider swapped itself into does
+            // not verify against the real committee.
+            let mut forged_committee = publics.clone();
+            forged_committee[2] = RistrettoPublic::from(&outsider);
+            let forged = RistrettoMembershipProof::prove(
+                CONTEXT,
+                b"message",
+                &forged_committee,
+                &outsider,
+                &mut rng,
+            )
+            .unwrap();
+            assert_eq!(
+                forged.verify(CONTEXT, b"message", &forged_committee),
+                Ok(())
+            );
+            assert_eq!(
+                forged.verify(CONTEXT, b"message", &publics),
+                Err(KeyError::SignatureMismatch)
+            );
+
+            // Neither does a tampered proof.
+            let mut tampered = forged;
+            tampered.responses[2] += Scalar::one();
+            assert_eq!(
+                tampered.verify(CONTEXT, b"message", &forged_committee),
+                Err(KeyError::SignatureMismatch)
+            );
+        });
+    }
+
+    #[test]
+    fn proof_round_trips() {
+        mc_util_test_helper::run_with_several_seeds(|mut rng| {
+            let (privates, publics) = committee(3, &mut rng);
+            let proof = RistrettoMembershipProof::prove(
+                CONTEXT,
+                b"message",
+                &publics,
+                &privates[1],
+                &mut rng,
+            )
+            .unwrap();
+
+            let bytes = proof.to_bytes();
+            assert_eq!(bytes.len(), 4 * SCALAR_LEN);
+            let decoded = RistrettoMembershipProof::try_from(&bytes[..]).unwrap();
+            assert_eq!(decoded, proof);
+            assert_eq!(decoded.verify(CONTEXT, b"message", &publics), Ok(()));
+
+            let serialized = mc_util_serial::serialize(&proof).unwrap();
+            let deserialized: RistrettoMembershipProof =
+                mc_util_serial::deserialize(&serialized).unwrap();
+            assert_eq!(deserialized, proof);
+
+            // Truncated, empty and non-canonical encodings are rejected.
+            assert_eq!(
+                RistrettoMembershipProof::try_from(&bytes[..bytes.len() - 1]),
+                Err(KeyError::InvalidEncoding)
+            );
+            assert_eq!(
+                RistrettoMembershipProof::try_from(&bytes[..SCALAR_LEN]),
+                Err(KeyError::InvalidEncoding)
+            );
+            let mut non_canonical = bytes.clone();
+            non_canonical[SCALAR_LEN..2 * SCALAR_LEN].copy_from_slice(&[0xff; SCALAR_LEN]);
+            assert_eq!(
+                RistrettoMembershipProof::try_from(&non_canonical[..]),
+                Err(KeyError::InvalidEncoding)
+            );
+        });
+    }
+}
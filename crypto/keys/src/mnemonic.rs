@@ -0,0 +1,130 @@
+// Copyright (c) 2018-2022 The MobileCoin Foundation
+
+//! Construction of Ed25519 keypairs from BIP-39 mnemonic phrases.
+
+use crate::{Ed25519Pair, Ed25519Private};
+use alloc::string::{String, ToString};
+use bip39::{ErrorKind, Language, Mnemonic, Seed};
+use displaydoc::Display;
+use hkdf::SimpleHkdf;
+use sha2::Sha512;
+use zeroize::Zeroize;
+
+/// The BIP44 "usage" component of a BIP32 path.
+const USAGE_BIP44: u32 = 44;
+/// The MobileCoin "coin type" component of a BIP32 path.
+const COINTYPE_MOBILECOIN: u32 = 866;
+/// The HKDF info which separates signing keys from the account keys derived
+/// from the same SLIP-0010 key.
+const SIGNING_KEY_HKDF_INFO: &[u8] = b"mc-ed25519-signing-key";
+
+/// An error which can occur while creating a key from a mnemonic phrase
+#[derive(Clone, Debug, Display, Eq, PartialEq)]
+pub enum MnemonicError {
+    /// The mnemonic phrase has an invalid checksum
+    InvalidChecksum,
+    /// The mnemonic phrase is invalid: {0}
+    InvalidPhrase(String),
+}
+
+impl Ed25519Pair {
+    /// Construct a keypair from an English BIP-39 mnemonic phrase and
+    /// passphrase.
+    ///
+    /// The BIP-39 seed is derived with the given passphrase, and the private
+    /// key is HKDF-SHA512 of the SLIP-0010 Ed25519 key at `m/44'/866'/0'`,
+    /// with the info `mc-ed25519-signing-key`.
+    ///
+    /// That SLIP-0010 key is also what the spend and view keys of MobileCoin
+    /// account 0 are derived from (with their own HKDF labels), so the phrase
+    /// of a wallet yields a signing key which is independent of the wallet's
+    /// keys, rather than reusing them. Anyone holding the phrase can still
+    /// derive both, so a phrase should be dedicated to this signing key.
+    pub fn from_mnemonic(phrase: &str, passphrase: &str) -> Result<Self, MnemonicError> {
+        let mnemonic = Mnemonic::from_phrase(phrase, Language::English).map_err(|err| {
+            match err.downcast_ref::<ErrorKind>() {
+                Some(ErrorKind::InvalidChecksum) => MnemonicError::InvalidChecksum,
+                _ => MnemonicError::InvalidPhrase(err.to_string()),
+            }
+        })?;
+        let seed = Seed::new(&mnemonic, passphrase);
+
+        let path = [USAGE_BIP44, COINTYPE_MOBILECOIN, 0];
+        let mut slip10_key = slip10_ed25519::derive_ed25519_private_key(seed.as_bytes(), &path);
+        let mut key = [0u8; 32];
+        // 32 bytes is a valid output length for HKDF-SHA512, so this cannot fail.
+        SimpleHkdf::<Sha512>::new(None, &slip10_key)
+            .expand(SIGNING_KEY_HKDF_INFO, &mut key)
+            .expect("buffer size arithmetic is wrong");
+        slip10_key.zeroize();
+        let private = Ed25519Private::try_from(&key[..])
+            .expect("HKDF produced an Ed25519 private key of the wrong length");
+        key.zeroize();
+
+        Ok(Self::from(private))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PHRASE: &str = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+    #[test]
+    fn known_mnemonic_produces_known_key() {
+        let pair = Ed25519Pair::from_mnemonic(PHRASE, "").expect("Valid mnemonic was rejected");
+        assert_eq!(
+            hex::encode(pair.private_key()),
+            "395fa1a31e68c4bd969a01c24935c6f1ba95a52e8f88718af06be4ecbff47686"
+        );
+        assert_eq!(
+            hex::encode(pair.public_key()),
+            "d4e1484041eb3ac708c3389da81ee698feb2722c9da1b7e6415491f330697168"
+        );
+    }
+
+    #[test]
+    fn key_is_not_the_account_slip10_key() {
+        let pair = Ed25519Pair::from_mnemonic(PHRASE, "").expect("Valid mnemonic was rejected");
+        let mnemonic = Mnemonic::from_phrase(PHRASE, Language::English).unwrap();
+        let seed = Seed::new(&mnemonic, "");
+        let account_key = slip10_ed25519::derive_ed25519_private_key(
+            seed.as_bytes(),
+            &[USAGE_BIP44, COINTYPE_MOBILECOIN, 0],
+        );
+        assert_eq!(
+            hex::encode(account_key),
+            "33a3d759f0f8a715dec0ea055b437fcc25a57df88ce6bdddbc37b049c2167b68"
+        );
+        assert_ne!(hex::encode(pair.private_key()), hex::encode(account_key));
+    }
+
+    #[test]
+    fn passphrase_changes_key() {
+        let pair =
+            Ed25519Pair::from_mnemonic(PHRASE, "TREZOR").expect("Valid mnemonic was rejected");
+        assert_eq!(
+            hex::encode(pair.public_key()),
+            "9c9ea569c9986204f7556cdc8e479ba9a9f83ab77e5d2aca8441c207683c0962"
+        );
+    }
+
+    #[test]
+    fn invalid_checksum_is_rejected() {
+        let phrase = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon";
+        assert_eq!(
+            Ed25519Pair::from_mnemonic(phrase, "").unwrap_err(),
+            MnemonicError::InvalidChecksum
+        );
+    }
+
+    #[test]
+    fn unknown_word_is_rejected() {
+        let phrase = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon mobilecoin";
+        assert!(matches!(
+            Ed25519Pair::from_mnemonic(phrase, ""),
+            Err(MnemonicError::InvalidPhrase(_))
+        ));
+    }
+}
@@ -3,8 +3,8 @@
 #![allow(non_snake_case)]
 
 use crate::{
-    GenericArray, Kex, KexEphemeralPrivate, KexPrivate, KexPublic, KexReusablePrivate, KexSecret,
-    KeyError, PrivateKey, PublicKey, Signature,
+    Base64UrlEncoding, GenericArray, HexEncoding, Kex, KexEphemeralPrivate, KexPrivate, KexPublic,
+    KexReusablePrivate, KexSecret, KeyError, PrivateKey, PublicKey, Signature,
 };
 use alloc::vec::Vec;
 use core::{
@@ -18,6 +18,7 @@ use curve25519_dalek::{
     scalar::Scalar,
 };
 use digest::generic_array::typenum::{U32, U64};
+use displaydoc::Display;
 use hex_fmt::HexFmt;
 use mc_crypto_digestible::{Digestible, MerlinTranscript};
 use mc_crypto_digestible_signature::{DigestibleSigner, DigestibleVerifier};
@@ -35,11 +36,19 @@ use schnorrkel_og::{
     Signature as SchnorrkelSignature, SignatureError as SchnorrkelError, SIGNATURE_LENGTH,
 };
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
 use signature::Error as SignatureError;
 use subtle::{Choice, ConstantTimeEq};
-use zeroize::Zeroize;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// Domain separator for deriving private keys from a seed.
+const RISTRETTO_SEED_DERIVATION_DOMAIN_TAG: &[u8] = b"mc_ristretto_private_derive_from_seed";
 
 /// A Ristretto-format private scalar
+///
+/// This type is `Copy`, so it cannot zeroize itself on drop: code holding
+/// long-lived copies should call `zeroize()` when done with them, or use
+/// `RistrettoEphemeralPrivate` where possible.
 #[derive(Clone, Copy, Default, Zeroize)]
 pub struct RistrettoPrivate(pub(crate) Scalar);
 
@@ -87,7 +96,54 @@ impl RistrettoPrivate {
         *self.0.as_bytes()
     }
 
+    /// Generate a random private key which is guaranteed not to be zero.
+    ///
+    /// A zero scalar yields the identity as public key, and a zero nonce
+    /// reveals the key it is used with. Drawing zero from a working RNG is
+    /// negligibly likely, but if it happens, this draws again.
+    pub fn from_random_nonzero<R: CryptoRng + RngCore>(csprng: &mut R) -> RistrettoPrivate {
+        loop {
+            let scalar = Scalar::random(csprng);
+            if scalar != Scalar::zero() {
+                return Self(scalar);
+            }
+        }
+    }
+
+    /// Deterministically derive the private key at `index` from `seed`.
+    ///
+    /// The key is the SHA-512 hash of the domain tag
+    /// `mc_ristretto_private_derive_from_seed`, the 32 seed bytes and the
+    /// little-endian bytes of `index`, reduced modulo the group order. The
+    /// same seed and index always yield the same key, and keys at different
+    /// indices are independent of each other.
+    pub fn derive_from_seed(seed: &[u8; 32], index: u64) -> RistrettoPrivate {
+        Self(Scalar::from_hash(
+            Sha512::new()
+                .chain_update(RISTRETTO_SEED_DERIVATION_DOMAIN_TAG)
+                .chain_update(seed)
+                .chain_update(index.to_le_bytes()),
+        ))
+    }
+
     /// Sign the given bytes using a deterministic scheme based on Schnorrkel.
+    ///
+    /// No RNG is needed: the signing nonce is derived from the private key,
+    /// the context and the message, so signing the same message twice yields
+    /// the same signature. Signatures verify with
+    /// [`RistrettoPublic::verify_schnorrkel()`], exactly like those made by
+    /// [`RistrettoPrivate::sign_schnorrkel_with_rng()`].
+    ///
+    /// Other implementations must derive the nonce the same way to produce
+    /// byte-identical signatures:
+    /// 1. Start a merlin transcript labeled `SigningNonce`, append `context`
+    ///    (label `context`), the 32-byte private scalar (label `private`) and
+    ///    `message` (label `message`), then draw 32 challenge bytes labeled
+    ///    `nonce`.
+    /// 2. Use the private scalar and those bytes as the key and nonce halves of
+    ///    a 64-byte schnorrkel secret key.
+    /// 3. Sign the schnorrkel transcript for `context` and `message`, with an
+    ///    HC-128 RNG seeded with the nonce bytes attached to it.
     pub fn sign_schnorrkel(&self, context: &[u8], message: &[u8]) -> RistrettoSignature {
         // Create a deterministic nonce using a merlin transcript. See this crate's
         // README for a security statement.
@@ -101,20 +157,50 @@ impl RistrettoPrivate {
             nonce
         };
 
+        // NOTE: This signature is deterministic due to using the above nonce as the rng
+        // seed
+        let csprng = Hc128Rng::from_seed(nonce);
+        self.sign_schnorrkel_with_nonce(context, message, nonce, csprng)
+    }
+
+    /// Sign the given bytes using Schnorrkel, drawing the signing nonce from
+    /// `csprng`.
+    ///
+    /// Signatures are valid exactly when those of
+    /// [`RistrettoPrivate::sign_schnorrkel()`] are, but signing the same
+    /// message twice yields different signatures.
+    pub fn sign_schnorrkel_with_rng<R: CryptoRng + RngCore>(
+        &self,
+        context: &[u8],
+        message: &[u8],
+        csprng: &mut R,
+    ) -> RistrettoSignature {
+        let mut nonce = [0u8; 32];
+        csprng.fill_bytes(&mut nonce);
+        self.sign_schnorrkel_with_nonce(context, message, nonce, csprng)
+    }
+
+    /// Sign the given bytes using Schnorrkel, with the given nonce half of the
+    /// secret key, and witness randomness drawn from `csprng`.
+    fn sign_schnorrkel_with_nonce<R: CryptoRng + RngCore>(
+        &self,
+        context: &[u8],
+        message: &[u8],
+        nonce: [u8; 32],
+        csprng: R,
+    ) -> RistrettoSignature {
         // Construct a Schnorrkel SecretKey object from ourselves, and our nonce value
         let mut secret_bytes = [0u8; 64];
         secret_bytes[0..32].copy_from_slice(&self.to_bytes());
         secret_bytes[32..64].copy_from_slice(&nonce);
         let secret_key = SchnorrkelPrivate::from_bytes(&secret_bytes).unwrap();
+        secret_bytes.zeroize();
         let keypair = secret_key.to_keypair();
 
         // SigningContext provides domain separation for signature
         let mut t = MerlinTranscript::new(b"SigningContext");
         t.append_message(b"", context);
         t.append_message(b"sign-bytes", message);
-        // NOTE: This signature is deterministic due to using the above nonce as the rng
-        // seed
-        let csprng = Hc128Rng::from_seed(nonce);
         let transcript = attach_rng(t, csprng);
         RistrettoSignature::from(keypair.sign(transcript))
     }
@@ -197,8 +283,7 @@ impl ConstantTimeEq for RistrettoPrivate {
 
 /// A private ristretto key which is ephemeral, should never be copied,
 /// and should be zeroized
-#[derive(Zeroize)]
-#[zeroize(drop)]
+#[derive(Zeroize, ZeroizeOnDrop)]
 pub struct RistrettoEphemeralPrivate(Scalar);
 
 impl PrivateKey for RistrettoEphemeralPrivate {
@@ -295,6 +380,18 @@ impl RistrettoPublic {
         self.0.compress().to_bytes()
     }
 
+    /// Hash arbitrary bytes to a point, e.g. to obtain a generator whose
+    /// discrete log relative to any other point is unknown.
+    ///
+    /// The point is `RistrettoPoint::from_uniform_bytes(SHA-512(data))`: the
+    /// two halves of the 64-byte SHA-512 digest are each mapped to a point
+    /// with the ristretto255 Elligator map of RFC 9496, and the results are
+    /// added. This is the `hash_to_group` construction of RFC 9496 section
+    /// 4.3.4, and callers should include a domain separator in `data`.
+    pub fn hash_from_bytes(data: &[u8]) -> RistrettoPublic {
+        Self(RistrettoPoint::hash_from_bytes::<Sha512>(data))
+    }
+
     /// Verify a deterministic Schnorrkel signature created with the
     /// corresponding [`RistrettoPrivate::sign_schnorrkel()`] method.
     pub fn verify_schnorrkel(
@@ -307,6 +404,63 @@ impl RistrettoPublic {
         let pubkey = SchnorrkelPublic::from_point(*self.as_ref());
         pubkey.verify(ctx.bytes(message), &signature.try_into()?)
     }
+
+    /// Verify a Schnorrkel signature like
+    /// [`RistrettoPublic::verify_schnorrkel()`], and return `message` only if
+    /// it is valid (see [`verify_and_take()`](crate::verify_and_take)).
+    pub fn verify_schnorrkel_and_take<'a>(
+        &self,
+        context: &'static [u8],
+        message: &'a [u8],
+        signature: &RistrettoSignature,
+    ) -> Result<&'a [u8], SchnorrkelError> {
+        self.verify_schnorrkel(context, message, signature)?;
+        Ok(message)
+    }
+
+    /// Verify a batch of Schnorrkel signatures like
+    /// [`RistrettoPublic::verify_schnorrkel()`], all made with the same
+    /// `context`, succeeding only if all of them are valid.
+    ///
+    /// The `i`-th signature is checked against the `i`-th message and public
+    /// key. The signatures are verified one by one, and the index of the first
+    /// invalid one is returned. schnorrkel's `verify_batch()` (behind its
+    /// `alloc` feature, which this crate does not enable) is faster for large
+    /// batches, but only reports whether the batch as a whole is valid.
+    pub fn verify_schnorrkel_batch(
+        context: &'static [u8],
+        messages: &[&[u8]],
+        signatures: &[RistrettoSignature],
+        public_keys: &[RistrettoPublic],
+    ) -> Result<(), RistrettoBatchError> {
+        if messages.len() != signatures.len() || messages.len() != public_keys.len() {
+            return Err(RistrettoBatchError::LengthMismatch(
+                messages.len(),
+                signatures.len(),
+                public_keys.len(),
+            ));
+        }
+
+        messages
+            .iter()
+            .zip(signatures)
+            .zip(public_keys)
+            .enumerate()
+            .try_for_each(|(index, ((message, signature), public_key))| {
+                public_key
+                    .verify_schnorrkel(context, message, signature)
+                    .map_err(|_e| RistrettoBatchError::InvalidSignature(index))
+            })
+    }
+}
+
+/// An error which can occur while verifying a batch of Schnorrkel signatures
+#[derive(Clone, Copy, Debug, Display, Eq, PartialEq)]
+pub enum RistrettoBatchError {
+    /// The batch has {0} messages, {1} signatures and {2} public keys
+    LengthMismatch(usize, usize, usize),
+    /// Signature {0} is invalid
+    InvalidSignature(usize),
 }
 
 impl PartialOrd for RistrettoPublic {
@@ -330,12 +484,22 @@ impl Hash for RistrettoPublic {
 impl Eq for RistrettoPublic {}
 impl PartialEq for RistrettoPublic {
     fn eq(&self, other: &Self) -> bool {
-        self.0 == other.0
+        self.ct_eq(other).into()
+    }
+}
+
+impl ConstantTimeEq for RistrettoPublic {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.0.ct_eq(&other.0)
     }
 }
 
 impl PublicKey for RistrettoPublic {}
 
+impl Base64UrlEncoding for RistrettoPublic {}
+
+impl HexEncoding for RistrettoPublic {}
+
 impl From<&RistrettoPrivate> for RistrettoPublic {
     fn from(private: &RistrettoPrivate) -> Self {
         let x = private.0;
@@ -436,6 +600,10 @@ impl KexSecret for RistrettoSecret {}
 ///
 /// As a result, this does not implement the `PublicKey` interface, nor is it
 /// usable in a key-exchange.
+///
+/// Keys are ordered lexicographically by their 32-byte encoding, so that they
+/// can be used in sorted collections. The ordering is deterministic, but has
+/// no other meaning. Equality and hashing are over the same bytes.
 #[derive(Clone, Copy, Default, Digestible, Zeroize)]
 #[digestible(transparent)]
 pub struct CompressedRistrettoPublic(pub(crate) CompressedRistretto);
@@ -519,6 +687,10 @@ derive_prost_message_from_repr_bytes!(CompressedRistrettoPublic);
 derive_core_cmp_from_as_ref!(CompressedRistrettoPublic, [u8; 32]);
 derive_debug_and_display_hex_from_as_ref!(CompressedRistrettoPublic);
 
+impl Base64UrlEncoding for CompressedRistrettoPublic {}
+
+impl HexEncoding for CompressedRistrettoPublic {}
+
 /// A zero-width type used to identify the Ristretto key exchange system.
 pub struct Ristretto;
 
@@ -602,11 +774,140 @@ derive_repr_bytes_from_as_ref_and_try_from!(RistrettoSignature, U64);
 derive_serde_from_repr_bytes!(RistrettoSignature);
 derive_prost_message_from_repr_bytes!(RistrettoSignature);
 
+impl Base64UrlEncoding for RistrettoSignature {}
+
 #[cfg(test)]
 mod test {
     extern crate mc_util_test_helper;
+    extern crate std;
 
     use super::*;
+    use core::cmp::Ordering;
+
+    fn hash_of<T: Hash>(value: &T) -> u64 {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_compressed_ord_and_hash_follow_bytes() {
+        mc_util_test_helper::run_with_several_seeds(|mut rng| {
+            let keys: Vec<CompressedRistrettoPublic> = (0..16)
+                .map(|_| CompressedRistrettoPublic::from_random(&mut rng))
+                .collect();
+
+            for a in &keys {
+                for b in &keys {
+                    assert_eq!(a.cmp(b), a.as_bytes().cmp(b.as_bytes()));
+                    assert_eq!(a.partial_cmp(b), Some(a.cmp(b)));
+                    assert_eq!(a == b, a.cmp(b) == Ordering::Equal);
+                }
+            }
+
+            let copy = CompressedRistrettoPublic::from(keys[0].as_bytes());
+            assert_eq!(hash_of(&keys[0]), hash_of(&copy));
+
+            let set: std::collections::BTreeSet<_> = keys.iter().chain([&copy]).collect();
+            assert_eq!(set.len(), keys.len());
+        });
+    }
+
+    #[test]
+    fn test_derive_from_seed_is_deterministic() {
+        let seed = [7u8; 32];
+        let key = RistrettoPrivate::derive_from_seed(&seed, 3);
+        assert_eq!(
+            key.to_bytes(),
+            RistrettoPrivate::derive_from_seed(&seed, 3).to_bytes()
+        );
+        // Pin the construction, so that it cannot change between versions.
+        assert_eq!(
+            hex::encode(key.to_bytes()),
+            "1f80d4ff55df77ec4039929ab7f81503ae7e03396c7e0b83a5f9080f9db3800c"
+        );
+    }
+
+    #[test]
+    fn test_derive_from_seed_distinct_indices_and_seeds() {
+        mc_util_test_helper::run_with_several_seeds(|mut rng| {
+            let mut seed = [0u8; 32];
+            rng.fill_bytes(&mut seed);
+            let keys: std::collections::BTreeSet<[u8; 32]> = (0..32)
+                .map(|index| RistrettoPrivate::derive_from_seed(&seed, index).to_bytes())
+                .collect();
+            assert_eq!(keys.len(), 32);
+
+            let mut other_seed = seed;
+            other_seed[0] ^= 1;
+            assert_ne!(
+                RistrettoPrivate::derive_from_seed(&seed, 0).to_bytes(),
+                RistrettoPrivate::derive_from_seed(&other_seed, 0).to_bytes()
+            );
+        });
+    }
+
+    #[test]
+    fn test_derive_from_seed_is_reduced() {
+        mc_util_test_helper::run_with_several_seeds(|mut rng| {
+            let mut seed = [0u8; 32];
+            rng.fill_bytes(&mut seed);
+            for index in [0, 1, u64::MAX] {
+                let key = RistrettoPrivate::derive_from_seed(&seed, index);
+                assert!(Scalar::from_canonical_bytes(key.to_bytes()).is_some());
+                assert!(RistrettoPrivate::try_from(&key.to_bytes()).is_ok());
+            }
+        });
+    }
+
+    #[test]
+    fn test_hash_from_bytes_vectors() {
+        // The first vector is from curve25519-dalek's ristretto255 tests, the
+        // others pin the construction for this crate.
+        let vectors: [(&[u8], &str); 4] = [
+            (
+                b"Ristretto is traditionally a short shot of espresso coffee",
+                "3066f82a1a747d45120d1740f14358531a8f04bbffe6a819f86dfe50f44a0a46",
+            ),
+            (
+                b"",
+                "8472865eba3c2c54e55e71e4ae6b1f88c6e8a8e44c493b59bc46b835e168681d",
+            ),
+            (
+                b"abc",
+                "5a5dbd5c765abf60b2076133482c1ada189c319034ae0b933f4908b3b68d0225",
+            ),
+            (
+                b"nothing up my sleeve",
+                "badf73c75f33b1c06b0efd05a26064c554871a1ad565d8a61e2da9970807ab51",
+            ),
+        ];
+        for (data, expected) in vectors {
+            let point = RistrettoPublic::hash_from_bytes(data);
+            assert_eq!(hex::encode(point.to_bytes()), expected);
+            assert_eq!(point, RistrettoPublic::hash_from_bytes(data));
+        }
+    }
+
+    #[test]
+    fn test_ephemeral_private_zeroizes_on_drop() {
+        fn assert_zeroize_on_drop<T: Zeroize + ZeroizeOnDrop>() {}
+        assert_zeroize_on_drop::<RistrettoEphemeralPrivate>();
+    }
+
+    #[test]
+    fn test_pubkey_ct_eq_matches_eq() {
+        mc_util_test_helper::run_with_several_seeds(|mut rng| {
+            let pubkey = RistrettoPublic::from_random(&mut rng);
+            let same = RistrettoPublic::try_from(&pubkey.to_bytes()).unwrap();
+            let other = RistrettoPublic::from_random(&mut rng);
+
+            assert!(bool::from(pubkey.ct_eq(&same)));
+            assert_eq!(pubkey, same);
+            assert!(!bool::from(pubkey.ct_eq(&other)));
+            assert_ne!(pubkey, other);
+        });
+    }
 
     // Test that mc-util-serial can serialize a pubkey
     #[test]
@@ -621,6 +922,18 @@ mod test {
         });
     }
 
+    #[test]
+    fn test_compressed_pubkey_serialize() {
+        mc_util_test_helper::run_with_several_seeds(|mut rng| {
+            let pubkey = CompressedRistrettoPublic::from_random(&mut rng);
+            let serialized =
+                mc_util_serial::serialize(&pubkey).expect("Could not serialize pubkey");
+            let deserialized: CompressedRistrettoPublic =
+                mc_util_serial::deserialize(&serialized).expect("Could not deserialize pubkey");
+            assert_eq!(deserialized, pubkey);
+        });
+    }
+
     // Test that mc-util-serial can serialize a private key
     #[test]
     fn test_privkey_serialize() {
@@ -637,4 +950,210 @@ mod test {
     }
 
     // Note: serde_json currently fails on RistrettoPublic and RistrettoPrivate
+
+    #[test]
+    fn test_sign_schnorrkel_is_deterministic() {
+        mc_util_test_helper::run_with_several_seeds(|mut rng| {
+            let privkey = RistrettoPrivate::from_random(&mut rng);
+            let pubkey = RistrettoPublic::from(&privkey);
+
+            let sig = privkey.sign_schnorrkel(b"test", b"foobar");
+            assert_eq!(sig, privkey.sign_schnorrkel(b"test", b"foobar"));
+            // A copy of the key, e.g. restored from its bytes, signs the same.
+            let restored = RistrettoPrivate::try_from(&privkey.to_bytes()).unwrap();
+            assert_eq!(sig, restored.sign_schnorrkel(b"test", b"foobar"));
+            pubkey
+                .verify_schnorrkel(b"test", b"foobar", &sig)
+                .expect("deterministic signature did not verify");
+
+            // The nonce depends on the context and message.
+            assert_ne!(sig, privkey.sign_schnorrkel(b"test", b"foobaz"));
+            assert_ne!(sig, privkey.sign_schnorrkel(b"other", b"foobar"));
+        });
+    }
+
+    fn schnorrkel_batch(
+        rng: &mut (impl CryptoRng + RngCore),
+        count: usize,
+    ) -> (Vec<Vec<u8>>, Vec<RistrettoSignature>, Vec<RistrettoPublic>) {
+        let keys: Vec<RistrettoPrivate> = (0..count)
+            .map(|_| RistrettoPrivate::from_random(rng))
+            .collect();
+        let messages: Vec<Vec<u8>> = (0..count).map(|i| i.to_le_bytes().to_vec()).collect();
+        let signatures = keys
+            .iter()
+            .zip(&messages)
+            .map(|(key, message)| key.sign_schnorrkel(b"batch", message))
+            .collect();
+        let public_keys = keys.iter().map(RistrettoPublic::from).collect();
+        (messages, signatures, public_keys)
+    }
+
+    #[test]
+    fn test_verify_schnorrkel_batch() {
+        mc_util_test_helper::run_with_several_seeds(|mut rng| {
+            let (messages, mut signatures, public_keys) = schnorrkel_batch(&mut rng, 16);
+            let messages: Vec<&[u8]> = messages.iter().map(Vec::as_slice).collect();
+
+            RistrettoPublic::verify_schnorrkel_batch(
+                b"batch",
+                &messages,
+                &signatures,
+                &public_keys,
+            )
+            .expect("valid batch should verify");
+            RistrettoPublic::verify_schnorrkel_batch(b"batch", &[], &[], &[])
+                .expect("empty batch is valid");
+
+            // The wrong context fails the first signature.
+            assert_eq!(
+                RistrettoPublic::verify_schnorrkel_batch(
+                    b"other",
+                    &messages,
+                    &signatures,
+                    &public_keys
+                ),
+                Err(RistrettoBatchError::InvalidSignature(0))
+            );
+
+            // Swapping two signatures fails the first of them.
+            signatures.swap(5, 11);
+            assert_eq!(
+                RistrettoPublic::verify_schnorrkel_batch(
+                    b"batch",
+                    &messages,
+                    &signatures,
+                    &public_keys
+                ),
+                Err(RistrettoBatchError::InvalidSignature(5))
+            );
+        });
+    }
+
+    #[test]
+    fn test_verify_schnorrkel_batch_rejects_mismatched_lengths() {
+        mc_util_test_helper::run_with_several_seeds(|mut rng| {
+            let (messages, signatures, public_keys) = schnorrkel_batch(&mut rng, 4);
+            let messages: Vec<&[u8]> = messages.iter().map(Vec::as_slice).collect();
+
+            assert_eq!(
+                RistrettoPublic::verify_schnorrkel_batch(
+                    b"batch",
+                    &messages[1..],
+                    &signatures,
+                    &public_keys
+                ),
+                Err(RistrettoBatchError::LengthMismatch(3, 4, 4))
+            );
+            assert_eq!(
+                RistrettoPublic::verify_schnorrkel_batch(
+                    b"batch",
+                    &messages,
+                    &signatures,
+                    &public_keys[1..]
+                ),
+                Err(RistrettoBatchError::LengthMismatch(4, 4, 3))
+            );
+        });
+    }
+
+    #[test]
+    fn test_sign_schnorrkel_with_rng_differs_only_in_reproducibility() {
+        mc_util_test_helper::run_with_several_seeds(|mut rng| {
+            let privkey = RistrettoPrivate::from_random(&mut rng);
+            let pubkey = RistrettoPublic::from(&privkey);
+
+            let deterministic = privkey.sign_schnorrkel(b"test", b"foobar");
+            let randomized = privkey.sign_schnorrkel_with_rng(b"test", b"foobar", &mut rng);
+            let randomized2 = privkey.sign_schnorrkel_with_rng(b"test", b"foobar", &mut rng);
+            assert_ne!(randomized, randomized2);
+            assert_ne!(randomized, deterministic);
+
+            for sig in [deterministic, randomized, randomized2] {
+                pubkey
+                    .verify_schnorrkel(b"test", b"foobar", &sig)
+                    .expect("signature did not verify");
+                assert!(pubkey.verify_schnorrkel(b"other", b"foobar", &sig).is_err());
+                assert!(pubkey.verify_schnorrkel(b"test", b"foobaz", &sig).is_err());
+            }
+        });
+    }
+
+    #[test]
+    fn test_verify_schnorrkel_and_take() {
+        mc_util_test_helper::run_with_several_seeds(|mut rng| {
+            let privkey = RistrettoPrivate::from_random(&mut rng);
+            let pubkey = RistrettoPublic::from(&privkey);
+            let message = b"foobar";
+            let sig = privkey.sign_schnorrkel(b"test", message);
+
+            let taken = pubkey
+                .verify_schnorrkel_and_take(b"test", message, &sig)
+                .expect("signature did not verify");
+            assert_eq!(taken, message);
+            assert!(pubkey
+                .verify_schnorrkel_and_take(b"test", b"foobaz", &sig)
+                .is_err());
+            assert!(pubkey
+                .verify_schnorrkel_and_take(b"other", message, &sig)
+                .is_err());
+        });
+    }
+
+    /// An RNG which outputs zeros for its first `zero_draws` calls to
+    /// `fill_bytes`, and then defers to a real RNG.
+    struct ZeroFirstRng {
+        zero_draws: usize,
+        fill_bytes_calls: usize,
+        rng: Hc128Rng,
+    }
+
+    impl RngCore for ZeroFirstRng {
+        fn next_u32(&mut self) -> u32 {
+            self.rng.next_u32()
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.rng.next_u64()
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            self.fill_bytes_calls += 1;
+            if self.fill_bytes_calls <= self.zero_draws {
+                dest.fill(0);
+            } else {
+                self.rng.fill_bytes(dest);
+            }
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    impl CryptoRng for ZeroFirstRng {}
+
+    #[test]
+    fn test_from_random_nonzero_retries_on_zero() {
+        let mut rng = ZeroFirstRng {
+            zero_draws: 1,
+            fill_bytes_calls: 0,
+            rng: Hc128Rng::from_seed([1u8; 32]),
+        };
+        // Sanity check that the mocked draw does produce the zero key.
+        assert_eq!(
+            RistrettoPrivate::from_random(&mut rng).to_bytes(),
+            [0u8; 32]
+        );
+
+        let mut rng = ZeroFirstRng {
+            zero_draws: 2,
+            fill_bytes_calls: 0,
+            rng: Hc128Rng::from_seed([1u8; 32]),
+        };
+        let privkey = RistrettoPrivate::from_random_nonzero(&mut rng);
+        assert_eq!(rng.fill_bytes_calls, 3);
+        assert_ne!(privkey.to_bytes(), [0u8; 32]);
+    }
 }
@@ -2,14 +2,16 @@
 
 //! Abstract traits used by Structs which implement key management
 
-use crate::{Digest, LengthMismatch, ReprBytes, Unsigned};
-use alloc::{string::String, vec::Vec};
+use crate::{Digest, GenericArray, LengthMismatch, ReprBytes, Unsigned, B64URL_CONFIG, B64_CONFIG};
+use alloc::{format, string::String, vec::Vec};
 use core::{fmt::Debug, hash::Hash};
 use displaydoc::Display;
 use mc_crypto_digestible::Digestible;
 use mc_util_from_random::FromRandom;
 use rand_core::{CryptoRng, RngCore};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::Sha256;
+use signature::{Error as SignatureError, Signature, Verifier};
 
 /// A collection of common errors for use by implementers
 #[derive(
@@ -31,6 +33,10 @@ pub enum KeyError {
     SignatureMismatch,
     /// There was an opaque error returned by another crate or library
     InternalError,
+    /// The provided string is not a valid encoding of the object
+    InvalidEncoding,
+    /// The PEM label does not match the type of key expected
+    PemLabelMismatch,
 }
 
 impl From<LengthMismatch> for KeyError {
@@ -52,10 +58,127 @@ pub trait DistinguishedEncoding: Sized {
     fn to_der(&self) -> Vec<u8>;
 }
 
+/// The number of base64 characters per line of PEM output.
+const PEM_LINE_LENGTH: usize = 64;
+
+/// A trait indicating that a key can be read/written as PEM (RFC 7468): its
+/// DER encoding in base64, between `-----BEGIN <label>-----` and
+/// `-----END <label>-----` lines.
+pub trait PemEncoding: DistinguishedEncoding {
+    /// The label of the PEM encapsulation boundaries, e.g. "PUBLIC KEY".
+    const PEM_LABEL: &'static str;
+
+    /// Encode this object as PEM, with lines of 64 base64 characters.
+    fn to_pem(&self) -> String {
+        let encoded = base64::encode_config(self.to_der(), B64_CONFIG);
+        let mut pem = format!("-----BEGIN {}-----\n", Self::PEM_LABEL);
+        for line in encoded.as_bytes().chunks(PEM_LINE_LENGTH) {
+            // Base64 output is ASCII, so every chunk is valid UTF-8.
+            pem.push_str(core::str::from_utf8(line).expect("base64 output is always ASCII"));
+            pem.push('\n');
+        }
+        pem.push_str(&format!("-----END {}-----\n", Self::PEM_LABEL));
+        pem
+    }
+
+    /// Decode an object from PEM, e.g. as created by `to_pem()`.
+    ///
+    /// PEM for a different kind of key, e.g. a private key where a public key
+    /// is expected, is rejected with `KeyError::PemLabelMismatch`.
+    fn from_pem(src: &str) -> Result<Self, KeyError> {
+        let mut lines = src.lines().map(str::trim).filter(|line| !line.is_empty());
+
+        let label = lines
+            .next()
+            .and_then(|line| line.strip_prefix("-----BEGIN "))
+            .and_then(|line| line.strip_suffix("-----"))
+            .ok_or(KeyError::InvalidEncoding)?;
+        if label != Self::PEM_LABEL {
+            return Err(KeyError::PemLabelMismatch);
+        }
+
+        let end = format!("-----END {}-----", label);
+        let mut encoded = String::new();
+        loop {
+            match lines.next() {
+                Some(line) if line == end => break,
+                Some(line) => encoded.push_str(line),
+                None => return Err(KeyError::InvalidEncoding),
+            }
+        }
+        if lines.next().is_some() {
+            return Err(KeyError::InvalidEncoding);
+        }
+
+        let der =
+            base64::decode_config(&encoded, B64_CONFIG).map_err(|_e| KeyError::InvalidEncoding)?;
+        Self::try_from_der(&der)
+    }
+}
+
+/// A trait indicating that an object has a single canonical byte encoding,
+/// which is what gets signed and verified.
+///
+/// Implementations must be deterministic, and must not depend on e.g. the
+/// order in which fields were set or the serializer which was used, so that
+/// every party encodes the same logical value to the same bytes.
+pub trait CanonicalEncode {
+    /// Encode this object into its canonical bytes.
+    fn canonical_encode(&self) -> Vec<u8>;
+}
+
+/// A trait indicating that an object can be written as unpadded, URL-safe
+/// base64 (RFC 4648, section 5) of its canonical bytes.
+///
+/// Unlike the standard base64 alphabet, this never produces `+`, `/`, or `=`,
+/// so the output can be embedded in URLs without escaping.
+pub trait Base64UrlEncoding: ReprBytes + Sized {
+    /// Encode the canonical bytes of this object as base64url.
+    fn to_base64url(&self) -> String {
+        base64::encode_config(self.to_bytes(), B64URL_CONFIG)
+    }
+
+    /// Decode an object from a base64url string created by `to_base64url()`.
+    fn from_base64url(src: &str) -> Result<Self, KeyError> {
+        let bytes =
+            base64::decode_config(src, B64URL_CONFIG).map_err(|_e| KeyError::InvalidEncoding)?;
+        let expected_len = Self::Size::USIZE;
+        if bytes.len() != expected_len {
+            return Err(KeyError::LengthMismatch(bytes.len(), expected_len));
+        }
+        Self::from_bytes(GenericArray::from_slice(&bytes)).map_err(|_e| KeyError::InvalidEncoding)
+    }
+}
+
+/// A trait indicating that an object can be written as lowercase hex of its
+/// canonical bytes, without a `0x` prefix.
+pub trait HexEncoding: ReprBytes + Sized {
+    /// Encode the canonical bytes of this object as hex.
+    fn to_hex(&self) -> String {
+        hex::encode(self.to_bytes())
+    }
+
+    /// Decode an object from a hex string created by `to_hex()`.
+    fn from_hex(src: &str) -> Result<Self, KeyError> {
+        let bytes = hex::decode(src).map_err(|_e| KeyError::InvalidEncoding)?;
+        let expected_len = Self::Size::USIZE;
+        if bytes.len() != expected_len {
+            return Err(KeyError::LengthMismatch(bytes.len(), expected_len));
+        }
+        Self::from_bytes(GenericArray::from_slice(&bytes)).map_err(|_e| KeyError::InvalidEncoding)
+    }
+}
+
 /// A trait indicating that a string fingerprint can be generated for an
 /// object.
 pub trait Fingerprintable {
     fn fingerprint<D: Digest>(&self) -> Result<String, KeyError>;
+
+    /// The SHA-256 fingerprint, as colon-separated uppercase hex, e.g.
+    /// "4F:0A:...", like SSH and TLS fingerprints.
+    fn fingerprint_hex(&self) -> Result<String, KeyError> {
+        Ok(self.fingerprint::<Sha256>()?.to_uppercase())
+    }
 }
 
 /// Blanket implementation of fingerprinting for any public key which also
@@ -199,3 +322,269 @@ pub trait Kex {
         + KexPrivate<Secret = Self::Secret>;
     type Secret: KexSecret;
 }
+
+/// Verify `signature` over `message`, and return `message` only if it is
+/// valid.
+///
+/// Code which goes on to use the returned slice is guaranteed to use exactly
+/// the bytes which were verified, rather than e.g. a re-serialization of them.
+pub fn verify_and_take<'a, V, S>(
+    public_key: &V,
+    message: &'a [u8],
+    signature: &S,
+) -> Result<&'a [u8], SignatureError>
+where
+    V: Verifier<S> + ?Sized,
+    S: Signature,
+{
+    public_key.verify(message, signature)?;
+    Ok(message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        CompressedRistrettoPublic, Ed25519Pair, Ed25519Private, Ed25519Public, RistrettoPrivate,
+        RistrettoPublic, Signer, X25519Private, X25519Public,
+    };
+    use rand_core::SeedableRng;
+    use rand_hc::Hc128Rng;
+
+    fn assert_url_safe(encoded: &str) {
+        assert!(!encoded.contains('+'), "{} contains '+'", encoded);
+        assert!(!encoded.contains('/'), "{} contains '/'", encoded);
+        assert!(!encoded.contains('='), "{} contains '='", encoded);
+    }
+
+    fn assert_round_trip<T: Base64UrlEncoding + Debug + PartialEq>(value: &T) {
+        let encoded = value.to_base64url();
+        assert_url_safe(&encoded);
+        assert_eq!(&T::from_base64url(&encoded).expect("from_base64url"), value);
+    }
+
+    #[test]
+    fn base64url_round_trip() {
+        let mut rng = Hc128Rng::seed_from_u64(0);
+        // Try several keys, so that we are likely to hit bytes which would
+        // encode to '+' or '/' in the standard alphabet.
+        for _ in 0..32 {
+            let pair = Ed25519Pair::from_random(&mut rng);
+            assert_round_trip(&pair.public_key());
+            assert_round_trip(&pair.sign(b"base64url"));
+
+            let ristretto_private = RistrettoPrivate::from_random(&mut rng);
+            let ristretto_public = RistrettoPublic::from(&ristretto_private);
+            assert_round_trip(&ristretto_public);
+            assert_round_trip(&CompressedRistrettoPublic::from(&ristretto_public));
+            assert_round_trip(&ristretto_private.sign_schnorrkel(b"context", b"base64url"));
+
+            let x25519_private = X25519Private::from_random(&mut rng);
+            assert_round_trip(&X25519Public::from(&x25519_private));
+        }
+    }
+
+    #[test]
+    fn base64url_differs_from_standard_alphabet() {
+        // 0xfb 0xff encodes to "+/8=" in the standard alphabet.
+        let mut bytes = [0u8; 32];
+        bytes[0] = 0xfb;
+        bytes[1] = 0xff;
+        let key = CompressedRistrettoPublic::from(&bytes);
+        let encoded = key.to_base64url();
+        assert!(encoded.starts_with("-_8"));
+        assert_url_safe(&encoded);
+    }
+
+    fn assert_pem_round_trip<T: PemEncoding>(value: &T) {
+        let pem = value.to_pem();
+        assert!(pem.starts_with(&format!("-----BEGIN {}-----\n", T::PEM_LABEL)));
+        assert!(pem.ends_with(&format!("-----END {}-----\n", T::PEM_LABEL)));
+        assert!(pem.lines().all(|line| line.len() <= PEM_LINE_LENGTH));
+        assert_eq!(
+            T::from_pem(&pem).expect("from_pem").to_der(),
+            value.to_der()
+        );
+    }
+
+    #[test]
+    fn pem_round_trip() {
+        let mut rng = Hc128Rng::seed_from_u64(0);
+
+        let ed25519_private = Ed25519Private::from_random(&mut rng);
+        assert_pem_round_trip(&Ed25519Public::from(&ed25519_private));
+        assert_pem_round_trip(&ed25519_private);
+
+        let x25519_private = X25519Private::from_random(&mut rng);
+        assert_pem_round_trip(&X25519Public::from(&x25519_private));
+        assert_pem_round_trip(&x25519_private);
+    }
+
+    #[test]
+    fn pem_matches_openssl_format() {
+        // RFC 8410 section 10.1, as written by `openssl pkey -pubout`.
+        let pem = "-----BEGIN PUBLIC KEY-----\n\
+                   MCowBQYDK2VwAyEAGb9ECWmEzf6FQbrBZ9w7lshQhqowtrbLDFw4rXAxZuE=\n\
+                   -----END PUBLIC KEY-----\n";
+        let public = Ed25519Public::from_pem(pem).unwrap();
+        assert_eq!(public.to_pem(), pem);
+    }
+
+    #[test]
+    fn pem_rejects_mismatched_label() {
+        let mut rng = Hc128Rng::seed_from_u64(0);
+        let private = Ed25519Private::from_random(&mut rng);
+
+        assert_eq!(
+            Ed25519Public::from_pem(&private.to_pem()).unwrap_err(),
+            KeyError::PemLabelMismatch
+        );
+        assert_eq!(
+            Ed25519Private::from_pem(&Ed25519Public::from(&private).to_pem()).unwrap_err(),
+            KeyError::PemLabelMismatch
+        );
+
+        // Public keys of the other algorithm have the same label, but are
+        // rejected by the DER decoding.
+        let x25519_public = X25519Public::from(&X25519Private::from_random(&mut rng));
+        assert_eq!(
+            Ed25519Public::from_pem(&x25519_public.to_pem()).unwrap_err(),
+            KeyError::AlgorithmMismatch
+        );
+    }
+
+    #[test]
+    fn pem_rejects_garbage() {
+        let mut rng = Hc128Rng::seed_from_u64(0);
+        let pem = Ed25519Public::from(&Ed25519Private::from_random(&mut rng)).to_pem();
+
+        // Truncated anywhere, including in the middle of the base64 or the
+        // boundaries.
+        for len in 0..pem.len() - 1 {
+            assert!(Ed25519Public::from_pem(&pem[..len]).is_err(), "{}", len);
+        }
+
+        for garbage in [
+            "",
+            "not a pem",
+            "-----BEGIN PUBLIC KEY-----\n!!!!\n-----END PUBLIC KEY-----\n",
+            "-----BEGIN PUBLIC KEY-----\nMCow\n-----END PRIVATE KEY-----\n",
+            "-----BEGIN PUBLIC KEY-----\n-----END PUBLIC KEY-----\n",
+        ] {
+            assert!(Ed25519Public::from_pem(garbage).is_err(), "{}", garbage);
+        }
+        assert!(Ed25519Public::from_pem(&format!("{}trailing\n", pem)).is_err());
+    }
+
+    fn assert_hex_round_trip<T: HexEncoding + Debug + PartialEq>(value: &T) {
+        let encoded = value.to_hex();
+        assert_eq!(encoded, hex::encode(value.to_bytes()));
+        assert_eq!(encoded, encoded.to_lowercase());
+        assert_eq!(&T::from_hex(&encoded).expect("from_hex"), value);
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        let mut rng = Hc128Rng::seed_from_u64(0);
+        let ristretto_public = RistrettoPublic::from(&RistrettoPrivate::from_random(&mut rng));
+
+        assert_hex_round_trip(&Ed25519Pair::from_random(&mut rng).public_key());
+        assert_hex_round_trip(&ristretto_public);
+        assert_hex_round_trip(&CompressedRistrettoPublic::from(&ristretto_public));
+        assert_hex_round_trip(&X25519Public::from(&X25519Private::from_random(&mut rng)));
+    }
+
+    #[test]
+    fn hex_rejects_wrong_length_and_invalid_hex() {
+        let mut rng = Hc128Rng::seed_from_u64(0);
+        let encoded = Ed25519Pair::from_random(&mut rng).public_key().to_hex();
+
+        assert_eq!(
+            Ed25519Public::from_hex(&encoded[2..]).unwrap_err(),
+            KeyError::LengthMismatch(31, 32)
+        );
+        assert_eq!(
+            X25519Public::from_hex(&format!("{}00", encoded)).unwrap_err(),
+            KeyError::LengthMismatch(33, 32)
+        );
+        assert_eq!(
+            RistrettoPublic::from_hex(&encoded[1..]).unwrap_err(),
+            KeyError::InvalidEncoding
+        );
+        assert_eq!(
+            CompressedRistrettoPublic::from_hex(&format!("0x{}", &encoded[2..])).unwrap_err(),
+            KeyError::InvalidEncoding
+        );
+    }
+
+    #[test]
+    fn fingerprint_hex_format() {
+        let mut rng = Hc128Rng::seed_from_u64(0);
+        let first = Ed25519Public::from(&Ed25519Private::from_random(&mut rng));
+        let second = Ed25519Public::from(&Ed25519Private::from_random(&mut rng));
+
+        let fingerprint = first.fingerprint_hex().unwrap();
+        let pairs: Vec<&str> = fingerprint.split(':').collect();
+        assert_eq!(pairs.len(), 32);
+        for pair in pairs {
+            assert_eq!(pair.len(), 2);
+            assert!(pair
+                .chars()
+                .all(|c| c.is_ascii_digit() || ('A'..='F').contains(&c)));
+        }
+        assert_eq!(
+            fingerprint.replace(':', ""),
+            hex::encode_upper(Sha256::digest(&first.to_der()))
+        );
+
+        assert_eq!(fingerprint, first.fingerprint_hex().unwrap());
+        assert_ne!(fingerprint, second.fingerprint_hex().unwrap());
+        let x25519 = X25519Public::from(&X25519Private::from_random(&mut rng));
+        assert_ne!(fingerprint, x25519.fingerprint_hex().unwrap());
+    }
+
+    #[test]
+    fn verify_and_take_returns_verified_message() {
+        let mut rng = Hc128Rng::seed_from_u64(0);
+        let pair = Ed25519Pair::from_random(&mut rng);
+        let message = b"the signed message";
+        let signature = pair.sign(message);
+
+        let taken = verify_and_take(&pair.public_key(), message, &signature).unwrap();
+        assert_eq!(taken, message);
+        assert_eq!(taken.as_ptr(), message.as_ptr());
+    }
+
+    #[test]
+    fn verify_and_take_rejects_invalid_signature() {
+        let mut rng = Hc128Rng::seed_from_u64(0);
+        let pair = Ed25519Pair::from_random(&mut rng);
+        let signature = pair.sign(b"the signed message");
+
+        assert!(verify_and_take(&pair.public_key(), b"another message", &signature).is_err());
+        let other = Ed25519Pair::from_random(&mut rng);
+        assert!(verify_and_take(&other.public_key(), b"the signed message", &signature).is_err());
+    }
+
+    #[test]
+    fn base64url_rejects_bad_input() {
+        assert_eq!(
+            Ed25519Public::from_base64url("not base64!"),
+            Err(KeyError::InvalidEncoding)
+        );
+        assert_eq!(
+            Ed25519Public::from_base64url("AAAA"),
+            Err(KeyError::LengthMismatch(3, 32))
+        );
+        // The standard alphabet's '+' and '/' are not accepted.
+        let mut bytes = [0u8; 32];
+        bytes[0] = 0xfb;
+        bytes[1] = 0xff;
+        let standard = base64::encode(bytes);
+        assert!(standard.starts_with("+/8"));
+        assert_eq!(
+            CompressedRistrettoPublic::from_base64url(&standard),
+            Err(KeyError::InvalidEncoding)
+        );
+    }
+}
@@ -3,8 +3,9 @@
 //! dalek-cryptography based keys implementations
 
 use crate::{
-    Digest, DistinguishedEncoding, Fingerprintable, Kex, KexEphemeralPrivate, KexPrivate,
-    KexPublic, KexReusablePrivate, KexSecret, KeyError, PrivateKey, PublicKey, B64_CONFIG,
+    Base64UrlEncoding, Digest, DistinguishedEncoding, Fingerprintable, HexEncoding, Kex,
+    KexEphemeralPrivate, KexPrivate, KexPublic, KexReusablePrivate, KexSecret, KeyError,
+    PemEncoding, PrivateKey, PublicKey, B64_CONFIG,
 };
 use alloc::{string::ToString, vec, vec::Vec};
 use core::{
@@ -12,6 +13,7 @@ use core::{
     str::from_utf8,
 };
 use digest::generic_array::typenum::U32;
+use hkdf::SimpleHkdf;
 use mc_crypto_digestible::Digestible;
 use mc_util_from_random::FromRandom;
 use mc_util_repr_bytes::{
@@ -24,8 +26,24 @@ use serde::{
     ser::{Serialize, Serializer},
 };
 use sha2::{self, Sha256};
+use subtle::{Choice, ConstantTimeEq};
 use x25519_dalek::{EphemeralSecret, PublicKey as DalekPublicKey, SharedSecret, StaticSecret};
-use zeroize::Zeroize;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+mod ratchet;
+
+pub use ratchet::{MessageKey, RatchetState};
+
+/// The domain separator used to derive handshake keys from a shared secret
+const HANDSHAKE_SALT_DOMAIN: &[u8] = b"mc-x25519-handshake";
+
+/// The HKDF info for the key used by the peer with the lesser public key to
+/// send to the peer with the greater public key
+const HANDSHAKE_LOW_TO_HIGH_LABEL: &[u8] = b"low-to-high";
+
+/// The HKDF info for the key used by the peer with the greater public key to
+/// send to the peer with the lesser public key
+const HANDSHAKE_HIGH_TO_LOW_LABEL: &[u8] = b"high-to-low";
 
 /// The length in bytes of canonical representation of x25519 (public and
 /// private keys)
@@ -93,6 +111,10 @@ const X25519_SPKI_DER_LEN: usize = 0x02 + 0x2A;
 
 impl PublicKey for X25519Public {}
 
+impl PemEncoding for X25519Public {
+    const PEM_LABEL: &'static str = "PUBLIC KEY";
+}
+
 impl DistinguishedEncoding for X25519Public {
     fn der_size() -> usize {
         X25519_SPKI_DER_LEN
@@ -199,6 +221,10 @@ impl TryFrom<&[u8]> for X25519Public {
 derive_repr_bytes_from_as_ref_and_try_from!(X25519Public, U32);
 derive_into_vec_from_repr_bytes!(X25519Public);
 
+impl Base64UrlEncoding for X25519Public {}
+
+impl HexEncoding for X25519Public {}
+
 impl Clone for X25519Public {
     /// Public keys can be cloned.
     ///
@@ -275,6 +301,12 @@ impl AsRef<[u8; X25519_LEN]> for X25519Public {
 
 derive_core_cmp_from_as_ref!(X25519Public, [u8; X25519_LEN]);
 
+impl ConstantTimeEq for X25519Public {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        AsRef::<[u8]>::as_ref(self).ct_eq(AsRef::<[u8]>::as_ref(other))
+    }
+}
+
 impl Serialize for X25519Public {
     /// Public keys are serialized as simple DER-encoded byte streams
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
@@ -334,8 +366,18 @@ impl From<&X25519Public> for Vec<u8> {
 }
 
 /// A KeyPair for use with an X25519 key exchange
+///
+/// The wrapped dalek secret zeroizes itself when dropped.
 pub struct X25519EphemeralPrivate(EphemeralSecret);
 
+impl Zeroize for X25519EphemeralPrivate {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for X25519EphemeralPrivate {}
+
 impl PrivateKey for X25519EphemeralPrivate {
     type Public = X25519Public;
 }
@@ -366,9 +408,100 @@ impl Debug for X25519EphemeralPrivate {
     }
 }
 
+impl X25519EphemeralPrivate {
+    /// Perform a key exchange with a peer, and derive independent keys for
+    /// each direction of a tunnel between us.
+    ///
+    /// Both peers call this method with their own ephemeral private key and
+    /// the other's public key. The shared secret is expanded with HKDF-SHA256
+    /// (salted with both public keys) into two labeled keys, and the labels
+    /// are assigned by comparing the public keys, so that the send key of one
+    /// side is the receive key of the other, without either side needing to
+    /// know whether it is the client or the server.
+    pub fn handshake(self, peer: &X25519Public) -> (X25519SendKey, X25519RecvKey) {
+        let our_public = X25519Public::from(&self);
+        let we_are_low = &our_public < peer;
+        let (low, high) = if we_are_low {
+            (&our_public, peer)
+        } else {
+            (peer, &our_public)
+        };
+
+        let mut salt = [0u8; HANDSHAKE_SALT_DOMAIN.len() + 2 * X25519_LEN];
+        let (domain, keys) = salt.split_at_mut(HANDSHAKE_SALT_DOMAIN.len());
+        domain.copy_from_slice(HANDSHAKE_SALT_DOMAIN);
+        keys[..X25519_LEN].copy_from_slice(low.as_ref());
+        keys[X25519_LEN..].copy_from_slice(high.as_ref());
+
+        let secret = self.key_exchange(peer);
+        let kdf = SimpleHkdf::<Sha256>::new(Some(&salt), secret.as_ref());
+
+        let mut low_to_high = [0u8; X25519_LEN];
+        kdf.expand(HANDSHAKE_LOW_TO_HIGH_LABEL, &mut low_to_high)
+            .expect("Digest output size is insufficient");
+        let mut high_to_low = [0u8; X25519_LEN];
+        kdf.expand(HANDSHAKE_HIGH_TO_LOW_LABEL, &mut high_to_low)
+            .expect("Digest output size is insufficient");
+
+        if we_are_low {
+            (X25519SendKey(low_to_high), X25519RecvKey(high_to_low))
+        } else {
+            (X25519SendKey(high_to_low), X25519RecvKey(low_to_high))
+        }
+    }
+}
+
+/// A symmetric key used to encrypt messages we send to a peer, derived by
+/// [`X25519EphemeralPrivate::handshake`]
+#[derive(Zeroize)]
+#[zeroize(drop)]
+pub struct X25519SendKey([u8; X25519_LEN]);
+
+impl AsRef<[u8]> for X25519SendKey {
+    fn as_ref(&self) -> &[u8] {
+        &self.0[..]
+    }
+}
+
+/// The debug implementation does not output the key
+impl Debug for X25519SendKey {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "X25519SendKey")
+    }
+}
+
+/// A symmetric key used to decrypt messages we receive from a peer, derived
+/// by [`X25519EphemeralPrivate::handshake`]
+#[derive(Zeroize)]
+#[zeroize(drop)]
+pub struct X25519RecvKey([u8; X25519_LEN]);
+
+impl AsRef<[u8]> for X25519RecvKey {
+    fn as_ref(&self) -> &[u8] {
+        &self.0[..]
+    }
+}
+
+/// The debug implementation does not output the key
+impl Debug for X25519RecvKey {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "X25519RecvKey")
+    }
+}
+
 /// An X25519 private key which can be saved and restored
+///
+/// The wrapped dalek secret zeroizes itself when dropped.
 pub struct X25519Private(StaticSecret);
 
+impl Zeroize for X25519Private {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for X25519Private {}
+
 impl PrivateKey for X25519Private {
     type Public = X25519Public;
 }
@@ -386,11 +519,11 @@ impl KexPrivate for X25519Private {
 // ASN.1 DER PrivateKeyInfo Bytes -- this is a set of nested TLVs
 // describing a private key -- use https://lapo.it/asn1js/
 //
-//   SEQUENCE(30), Length = 0x2a                  -- T,L
+//   SEQUENCE(30), Length = 0x2e                  -- T,L
 //     INTEGER(0)
 //     SEQUENCE(30), Length = 0x05                -- T,L
 //        OBJECT IDENTIFIER(06), Length = 0x03    -- T,L,V
-//           curveX25519(1.3.101.110 = 2B 65 70)
+//           curveX25519(1.3.101.110 = 2B 65 6E)
 //     OCTET STRING(04), Length = 0x22            -- T,L
 //        OCTET STRING(04), Length = 0x20         -- T,L,V
 //           actualKeyBytesGoHere
@@ -400,6 +533,10 @@ const X25519_PKI_DER_PREFIX: [u8; 16] = [
 
 const X25519_PKI_DER_LEN: usize = 0x02 + 0x2E;
 
+impl PemEncoding for X25519Private {
+    const PEM_LABEL: &'static str = "PRIVATE KEY";
+}
+
 impl DistinguishedEncoding for X25519Private {
     fn der_size() -> usize {
         X25519_PKI_DER_LEN
@@ -429,6 +566,10 @@ impl DistinguishedEncoding for X25519Private {
     }
 }
 
+/// A static key can be used in any number of key exchanges, e.g. as a server's
+/// identity key. This gives up forward secrecy: anyone who later learns the
+/// key can recompute every secret it was used to derive, so prefer
+/// [`X25519EphemeralPrivate`] for keys which are only needed once.
 impl KexReusablePrivate for X25519Private {
     fn key_exchange(&self, their_public: &X25519Public) -> X25519Secret {
         X25519Secret(self.0.diffie_hellman(&their_public.0))
@@ -562,12 +703,105 @@ mod test {
     use super::*;
     use crate::{ReprBytes, Unsigned};
     use mc_util_serial::{deserialize, serialize};
+    use rand_core::SeedableRng;
+    use rand_hc::Hc128Rng;
+
+    #[test]
+    fn test_private_keys_zeroize_on_drop() {
+        fn assert_zeroize_on_drop<T: Zeroize + ZeroizeOnDrop>() {}
+        assert_zeroize_on_drop::<X25519Private>();
+        assert_zeroize_on_drop::<X25519EphemeralPrivate>();
+
+        let mut rng = Hc128Rng::seed_from_u64(0);
+        let mut private = X25519Private::from_random(&mut rng);
+        assert_ne!(private.0.to_bytes(), [0u8; X25519_LEN]);
+        private.zeroize();
+        assert_eq!(private.0.to_bytes(), [0u8; X25519_LEN]);
+    }
+
+    #[test]
+    fn test_public_ct_eq_matches_eq() {
+        let mut rng = Hc128Rng::seed_from_u64(0);
+        let public = X25519Public::from(&X25519Private::from_random(&mut rng));
+        let same = public.clone();
+        let other = X25519Public::from(&X25519Private::from_random(&mut rng));
+
+        assert!(bool::from(public.ct_eq(&same)));
+        assert_eq!(public, same);
+        assert!(!bool::from(public.ct_eq(&other)));
+        assert_ne!(public, other);
+    }
+
+    /// Alice's keys from RFC 7748 section 6.1.
+    const RFC7748_PRIVATE: &str =
+        "77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a";
+    const RFC7748_PUBLIC: &str = "8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a";
+
+    #[test]
+    fn test_public_der_matches_rfc8410() {
+        let der =
+            hex::decode(alloc::format!("302a300506032b656e032100{}", RFC7748_PUBLIC)).unwrap();
+
+        let public = X25519Public::try_from_der(&der).expect("Could not parse SPKI DER");
+        assert_eq!(hex::encode(&public), RFC7748_PUBLIC);
+        assert_eq!(public.to_der(), der);
+    }
+
+    #[test]
+    fn test_private_der_matches_rfc8410() {
+        let der = hex::decode(alloc::format!(
+            "302e020100300506032b656e04220420{}",
+            RFC7748_PRIVATE
+        ))
+        .unwrap();
+
+        let private = X25519Private::try_from_der(&der).expect("Could not parse PKCS#8 DER");
+        assert_eq!(hex::encode(X25519Public::from(&private)), RFC7748_PUBLIC);
+        assert_eq!(private.to_der(), der);
+
+        // An Ed25519 key is rejected.
+        let mut ed25519_der = der;
+        ed25519_der[11] = 0x70;
+        assert!(matches!(
+            X25519Private::try_from_der(&ed25519_der),
+            Err(KeyError::AlgorithmMismatch)
+        ));
+    }
 
     #[test]
     fn test_repr_bytes_size_vs_constant() {
         assert_eq!(<X25519Public as ReprBytes>::Size::USIZE, X25519_LEN);
     }
 
+    #[test]
+    fn test_static_key_exchange_is_reusable() {
+        let mut rng = Hc128Rng::seed_from_u64(0);
+        let server = X25519Private::from_random(&mut rng);
+        let server_public = X25519Public::from(&server);
+
+        for _ in 0..2 {
+            let first_peer = X25519EphemeralPrivate::from_random(&mut rng);
+            let first_public = X25519Public::from(&first_peer);
+            let second_peer = X25519Private::from_random(&mut rng);
+            let second_public = X25519Public::from(&second_peer);
+
+            // The server's key is borrowed, so it stays usable after each
+            // exchange.
+            let first_secret = server.key_exchange(&first_public);
+            let second_secret = server.key_exchange(&second_public);
+
+            assert_eq!(
+                first_secret.as_ref(),
+                first_peer.key_exchange(&server_public).as_ref()
+            );
+            assert_eq!(
+                second_secret.as_ref(),
+                second_peer.key_exchange(&server_public).as_ref()
+            );
+            assert_ne!(first_secret.as_ref(), second_secret.as_ref());
+        }
+    }
+
     #[test]
     fn test_pubkey_serialize() {
         let pubkey = X25519Public::try_from(&[0x55u8; 32] as &[u8]).expect("Could not load pubkey");
@@ -589,4 +823,35 @@ mod test {
         let deserialize_pubkey: X25519Public = X25519Public::from(&deserialized);
         assert_eq!(pubkey, deserialize_pubkey);
     }
+
+    #[test]
+    fn test_handshake_keys_match() {
+        let mut rng = Hc128Rng::seed_from_u64(0);
+        for _ in 0..16 {
+            let client_private = X25519EphemeralPrivate::from_random(&mut rng);
+            let client_public = X25519Public::from(&client_private);
+            let server_private = X25519EphemeralPrivate::from_random(&mut rng);
+            let server_public = X25519Public::from(&server_private);
+
+            let (client_send, client_recv) = client_private.handshake(&server_public);
+            let (server_send, server_recv) = server_private.handshake(&client_public);
+
+            assert_eq!(client_send.as_ref(), server_recv.as_ref());
+            assert_eq!(server_send.as_ref(), client_recv.as_ref());
+            assert_ne!(client_send.as_ref(), client_recv.as_ref());
+        }
+    }
+
+    #[test]
+    fn test_handshake_keys_differ_per_peer() {
+        let mut rng = Hc128Rng::seed_from_u64(1);
+        let server_private = X25519Private::from_random(&mut rng);
+        let server_public = X25519Public::from(&server_private);
+
+        let (send1, _recv1) =
+            X25519EphemeralPrivate::from_random(&mut rng).handshake(&server_public);
+        let (send2, _recv2) =
+            X25519EphemeralPrivate::from_random(&mut rng).handshake(&server_public);
+        assert_ne!(send1.as_ref(), send2.as_ref());
+    }
 }
@@ -0,0 +1,194 @@
+// Copyright (c) 2018-2022 The MobileCoin Foundation
+
+//! A minimal symmetric-key ratchet, providing per-message forward secrecy for
+//! long-lived sessions keyed by [`X25519EphemeralPrivate::handshake`].
+//!
+//! Each step derives a message key and the next chain key from the current
+//! chain key with HKDF-SHA256, and then erases the current chain key, so
+//! compromising the state of a session does not reveal the keys of messages
+//! which were already sent or received.
+//!
+//! [`X25519EphemeralPrivate::handshake`]: super::X25519EphemeralPrivate::handshake
+
+use super::{X25519RecvKey, X25519SendKey, X25519_LEN};
+use core::fmt::{Debug, Formatter, Result as FmtResult};
+use hkdf::SimpleHkdf;
+use sha2::Sha256;
+use zeroize::Zeroize;
+
+/// The HKDF info used to derive the next chain key
+const RATCHET_CHAIN_LABEL: &[u8] = b"mc-x25519-ratchet-chain";
+
+/// The HKDF info used to derive a message key
+const RATCHET_MESSAGE_LABEL: &[u8] = b"mc-x25519-ratchet-message";
+
+/// The state of one direction of a ratcheting session.
+///
+/// Both peers start from the same key (e.g. our send key and the peer's
+/// receive key from a handshake), and advance once per message, so that the
+/// n-th message key is the same on both sides.
+#[derive(Zeroize)]
+#[zeroize(drop)]
+pub struct RatchetState {
+    /// The current chain key
+    chain_key: [u8; X25519_LEN],
+
+    /// The index of the next message key
+    index: u64,
+}
+
+impl RatchetState {
+    /// Start a ratchet from an initial chain key.
+    pub fn new(chain_key: [u8; X25519_LEN]) -> Self {
+        Self {
+            chain_key,
+            index: 0,
+        }
+    }
+
+    /// The index of the message key the next call to `advance` returns.
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    /// Derive the key for the next message, and ratchet the chain key forward,
+    /// zeroizing the previous one.
+    pub fn advance(&mut self) -> MessageKey {
+        let kdf = SimpleHkdf::<Sha256>::from_prk(&self.chain_key)
+            .expect("Chain key is shorter than the digest output");
+
+        let mut message_key = MessageKey {
+            key: [0u8; X25519_LEN],
+            index: self.index,
+        };
+        kdf.expand(RATCHET_MESSAGE_LABEL, &mut message_key.key)
+            .expect("Digest output size is insufficient");
+
+        self.chain_key.zeroize();
+        kdf.expand(RATCHET_CHAIN_LABEL, &mut self.chain_key)
+            .expect("Digest output size is insufficient");
+        self.index += 1;
+
+        message_key
+    }
+}
+
+impl From<X25519SendKey> for RatchetState {
+    fn from(src: X25519SendKey) -> Self {
+        Self::new(src.0)
+    }
+}
+
+impl From<X25519RecvKey> for RatchetState {
+    fn from(src: X25519RecvKey) -> Self {
+        Self::new(src.0)
+    }
+}
+
+/// The debug implementation does not output the chain key
+impl Debug for RatchetState {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "RatchetState {{ index: {} }}", self.index)
+    }
+}
+
+/// A symmetric key used for a single message, derived by
+/// [`RatchetState::advance`]
+#[derive(Zeroize)]
+#[zeroize(drop)]
+pub struct MessageKey {
+    /// The key material
+    key: [u8; X25519_LEN],
+
+    /// The index of the message this key is for
+    index: u64,
+}
+
+impl MessageKey {
+    /// The index of the message this key is for.
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+}
+
+impl AsRef<[u8]> for MessageKey {
+    fn as_ref(&self) -> &[u8] {
+        &self.key[..]
+    }
+}
+
+/// The debug implementation does not output the key
+impl Debug for MessageKey {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "MessageKey {{ index: {} }}", self.index)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{X25519EphemeralPrivate, X25519Public};
+    use alloc::vec::Vec;
+    use mc_util_from_random::FromRandom;
+    use rand_core::SeedableRng;
+    use rand_hc::Hc128Rng;
+
+    #[test]
+    fn test_message_keys_are_distinct() {
+        let mut state = RatchetState::new([7u8; X25519_LEN]);
+        let keys: Vec<Vec<u8>> = (0..64)
+            .map(|index| {
+                let key = state.advance();
+                assert_eq!(key.index(), index);
+                key.as_ref().to_vec()
+            })
+            .collect();
+        assert_eq!(state.index(), 64);
+
+        for (i, key) in keys.iter().enumerate() {
+            assert!(keys[i + 1..].iter().all(|other| other != key));
+            assert_ne!(&key[..], &[7u8; X25519_LEN][..]);
+        }
+    }
+
+    #[test]
+    fn test_ratchet_is_deterministic() {
+        let mut rng = Hc128Rng::seed_from_u64(0);
+        let client_private = X25519EphemeralPrivate::from_random(&mut rng);
+        let client_public = X25519Public::from(&client_private);
+        let server_private = X25519EphemeralPrivate::from_random(&mut rng);
+        let server_public = X25519Public::from(&server_private);
+
+        let (client_send, _client_recv) = client_private.handshake(&server_public);
+        let (_server_send, server_recv) = server_private.handshake(&client_public);
+
+        let mut sender = RatchetState::from(client_send);
+        let mut receiver = RatchetState::from(server_recv);
+        for _ in 0..16 {
+            let sent = sender.advance();
+            let received = receiver.advance();
+            assert_eq!(sent.index(), received.index());
+            assert_eq!(sent.as_ref(), received.as_ref());
+        }
+    }
+
+    #[test]
+    fn test_old_chain_keys_are_zeroized() {
+        let initial = [3u8; X25519_LEN];
+        let mut state = RatchetState::new(initial);
+
+        let first = state.advance();
+        let chain_key = state.chain_key;
+        assert_ne!(chain_key, initial);
+        assert_ne!(&chain_key[..], first.as_ref());
+
+        // The previous chain key is overwritten in place, and not kept.
+        let _second = state.advance();
+        assert_ne!(state.chain_key, chain_key);
+        assert_ne!(state.chain_key, initial);
+
+        state.zeroize();
+        assert_eq!(state.chain_key, [0u8; X25519_LEN]);
+        assert_eq!(state.index(), 0);
+    }
+}
@@ -23,3 +23,6 @@ pub const HASH_TO_SCALAR_DOMAIN_TAG: &str = "mc_onetime_key_hash_to_scalar";
 
 /// Domain separator for RingMLSAG's challenges.
 pub const RING_MLSAG_CHALLENGE_DOMAIN_TAG: &str = "mc_ring_mlsag_challenge";
+
+/// Domain separator for the TxOut deduplication tag.
+pub const TXOUT_TAG_DOMAIN_TAG: &str = "mc_onetime_key_txout_tag";
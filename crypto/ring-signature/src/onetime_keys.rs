@@ -71,13 +71,13 @@
 
 #![allow(non_snake_case)]
 
-use crate::domain_separators::HASH_TO_SCALAR_DOMAIN_TAG;
+use crate::domain_separators::{HASH_TO_SCALAR_DOMAIN_TAG, TXOUT_TAG_DOMAIN_TAG};
 use curve25519_dalek::{
     constants::RISTRETTO_BASEPOINT_POINT, ristretto::RistrettoPoint, scalar::Scalar,
 };
 use mc_account_keys::PublicAddress;
 use mc_crypto_hashes::{Blake2b512, Digest};
-use mc_crypto_keys::{RistrettoPrivate, RistrettoPublic};
+use mc_crypto_keys::{KexReusablePrivate, RistrettoPrivate, RistrettoPublic};
 
 const G: RistrettoPoint = RISTRETTO_BASEPOINT_POINT;
 
@@ -195,6 +195,31 @@ pub fn create_shared_secret(
     RistrettoPublic::from(x * Y)
 }
 
+/// Returns a 16-byte tag identifying a TxOut to the holder of a view key.
+///
+/// The tag is a domain-separated hash of the key exchange `a * R`, so it is
+/// deterministic for a given view key and TxOut, and unlinkable to the TxOut
+/// without the view key. Clients use it to deduplicate TxOuts.
+///
+/// # Arguments
+/// * `view_private_key` - The recipient's private view key `a`.
+/// * `tx_out_public_key` - The TxOut's public key `R`.
+pub fn compute_txout_tag(
+    view_private_key: &RistrettoPrivate,
+    tx_out_public_key: &RistrettoPublic,
+) -> [u8; 16] {
+    let shared_secret = view_private_key.key_exchange(tx_out_public_key);
+
+    let mut hasher = Blake2b512::new();
+    hasher.update(&TXOUT_TAG_DOMAIN_TAG);
+    hasher.update(shared_secret.as_ref());
+    let digest = hasher.finalize();
+
+    let mut tag = [0u8; 16];
+    tag.copy_from_slice(&digest[..16]);
+    tag
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -431,4 +456,34 @@ mod tests {
             assert_eq!(aB, bA);
         })
     }
+
+    #[test]
+    // `compute_txout_tag` should return the same tag each time for a given view
+    // key and TxOut.
+    fn test_compute_txout_tag_is_deterministic() {
+        run_with_several_seeds(|mut rng| {
+            let view_private_key = RistrettoPrivate::from_random(&mut rng);
+            let tx_out_public_key = RistrettoPublic::from_random(&mut rng);
+
+            assert_eq!(
+                compute_txout_tag(&view_private_key, &tx_out_public_key),
+                compute_txout_tag(&view_private_key, &tx_out_public_key)
+            );
+        });
+    }
+
+    #[test]
+    // Different view keys should produce different tags for the same TxOut.
+    fn test_compute_txout_tag_depends_on_view_key() {
+        run_with_several_seeds(|mut rng| {
+            let view_private_key = RistrettoPrivate::from_random(&mut rng);
+            let other_view_private_key = RistrettoPrivate::from_random(&mut rng);
+            let tx_out_public_key = RistrettoPublic::from_random(&mut rng);
+
+            assert_ne!(
+                compute_txout_tag(&view_private_key, &tx_out_public_key),
+                compute_txout_tag(&other_view_private_key, &tx_out_public_key)
+            );
+        });
+    }
 }
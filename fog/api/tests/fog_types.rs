@@ -526,6 +526,7 @@ impl Sample for mc_fog_types::ledger::KeyImageResult {
             timestamp: 11,
             timestamp_result_code: TimestampResultCode::TimestampFound as u32,
             key_image_result_code: mc_fog_types::ledger::KeyImageResultCode::Spent as u32,
+            client_request_id: rng.next_u64(),
         }
     }
 }
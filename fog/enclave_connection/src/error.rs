@@ -5,6 +5,7 @@ use displaydoc::Display;
 use mc_attest_ake::Error as AkeError;
 use mc_connection::AttestationError;
 use mc_crypto_noise::CipherError;
+use mc_util_grpc::GrpcRetryError;
 use mc_util_serial::DecodeError;
 use mc_util_uri::UriConversionError;
 
@@ -38,6 +39,15 @@ impl AttestationError for Error {
     }
 }
 
+impl GrpcRetryError for Error {
+    fn grpc_error(&self) -> Option<&grpcio::Error> {
+        match self {
+            Error::Rpc(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
 impl From<grpcio::Error> for Error {
     fn from(err: grpcio::Error) -> Self {
         Error::Rpc(err)
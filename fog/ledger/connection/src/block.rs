@@ -50,9 +50,12 @@ impl FogBlockGrpcClient {
         request.ranges = RepeatedField::from_vec(missed_block_ranges);
 
         self.grpc_retry_config
-            .retry(|| {
-                self.blocks_client
-                    .get_blocks_opt(&request, self.creds.call_option()?)
+            .retry_grpc(|| {
+                self.blocks_client.get_blocks_opt(
+                    &request,
+                    self.grpc_retry_config
+                        .call_option(self.creds.call_option()?),
+                )
             })
             .map_err(|grpcio_error| Error::Grpc(self.uri.clone(), grpcio_error))
     }
@@ -63,6 +63,7 @@ impl FogKeyImageGrpcClient {
                 .map(|key_image| KeyImageQuery {
                     key_image: *key_image,
                     start_block: 0,
+                    client_request_id: 0,
                 })
                 .collect(),
         };
@@ -70,7 +71,7 @@ impl FogKeyImageGrpcClient {
         let retry_config = self.grpc_retry_config;
 
         let response: CheckKeyImagesResponse = retry_config
-            .retry(|| self.conn.retriable_encrypted_enclave_request(&request, &[]))
+            .retry_grpc(|| self.conn.retriable_encrypted_enclave_request(&request, &[]))
             .map_err(|err| Error::Connection(self.uri.clone(), err))?;
 
         Ok(response)
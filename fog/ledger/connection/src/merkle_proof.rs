@@ -66,7 +66,7 @@ impl FogMerkleProofGrpcClient {
         let retry_config = self.grpc_retry_config;
 
         let response: GetOutputsResponse = retry_config
-            .retry(|| self.conn.retriable_encrypted_enclave_request(&request, &[]))
+            .retry_grpc(|| self.conn.retriable_encrypted_enclave_request(&request, &[]))
             .map_err(|err| Error::Connection(self.uri.clone(), err))?;
 
         Ok(response)
@@ -67,9 +67,12 @@ impl FogUntrustedLedgerGrpcClient {
         }
 
         self.grpc_retry_config
-            .retry(|| {
-                self.blocks_client
-                    .get_blocks_opt(&request, self.creds.call_option()?)
+            .retry_grpc(|| {
+                self.blocks_client.get_blocks_opt(
+                    &request,
+                    self.grpc_retry_config
+                        .call_option(self.creds.call_option()?),
+                )
             })
             .map_err(|grpcio_error| Error::Grpc(self.uri.clone(), grpcio_error))
     }
@@ -91,9 +94,12 @@ impl FogUntrustedLedgerGrpcClient {
         }
 
         self.grpc_retry_config
-            .retry(|| {
-                self.tx_out_client
-                    .get_tx_outs_opt(&request, self.creds.call_option()?)
+            .retry_grpc(|| {
+                self.tx_out_client.get_tx_outs_opt(
+                    &request,
+                    self.grpc_retry_config
+                        .call_option(self.creds.call_option()?),
+                )
             })
             .map_err(|grpcio_error| Error::Grpc(self.uri.clone(), grpcio_error))
     }
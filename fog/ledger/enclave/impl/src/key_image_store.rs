@@ -12,7 +12,7 @@ use alloc::boxed::Box;
 use mc_common::logger::{log, Logger};
 use mc_crypto_rand::McRng;
 use mc_fog_ledger_enclave_api::AddRecordsError;
-use mc_fog_types::ledger::{KeyImageResult, KeyImageResultCode};
+use mc_fog_types::ledger::{KeyImageQuery, KeyImageResult, KeyImageResultCode};
 use mc_oblivious_map::CuckooHashTableCreator;
 use mc_oblivious_ram::PathORAM4096Z4Creator;
 use mc_oblivious_traits::{
@@ -133,6 +133,7 @@ impl<OSC: ORAMStorageCreator<StorageDataSize, StorageMetaSize>> KeyImageStore<OS
             key_image_result_code: KeyImageResultCode::KeyImageError as u32,
             timestamp: u64::MAX,
             timestamp_result_code: TimestampResultCode::TimestampFound as u32,
+            client_request_id: 0,
         };
 
         let mut key = A8Bytes::<KeySize>::default(); // key used to query the oram for key image
@@ -194,4 +195,14 @@ impl<OSC: ORAMStorageCreator<StorageDataSize, StorageMetaSize>> KeyImageStore<OS
 
         result
     }
+
+    /// Answer a query, echoing its client request id in the result.
+    ///
+    /// The id is chosen by the client, not derived from any secret, so copying
+    /// it does not leak anything about the result.
+    pub fn find_query(&mut self, query: &KeyImageQuery) -> KeyImageResult {
+        let mut result = self.find_record(&query.key_image);
+        result.client_request_id = query.client_request_id;
+        result
+    }
 }
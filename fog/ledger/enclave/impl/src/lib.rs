@@ -167,7 +167,7 @@ where
             resp.results = req
                 .queries
                 .iter() //  get the key images used to find the key image data using the oram
-                .map(|key| store.find_record(&key.key_image))
+                .map(|query| store.find_query(query))
                 .collect();
         }
 
@@ -298,4 +298,44 @@ mod tests {
             mc_fog_types::ledger::KeyImageResultCode::NotSpent as u32
         );
     }
+
+    // Test that client request ids are echoed alongside the result codes
+    #[test]
+    fn test_find_query_echoes_client_request_id() {
+        use mc_fog_types::ledger::{KeyImageQuery, KeyImageResultCode};
+
+        let logger = create_root_logger();
+        let mut key_image_store = KeyImageStore::<HeapORAMStorageCreator>::new(1024 * 1024, logger);
+        key_image_store
+            .add_record(&KeyImage::from(2), 15, 1_600_000_000)
+            .unwrap();
+
+        let spent = KeyImageQuery {
+            key_image: KeyImage::from(2),
+            start_block: 0,
+            client_request_id: 0xdead_beef,
+        };
+        let not_spent = KeyImageQuery {
+            key_image: KeyImage::from(3),
+            start_block: 0,
+            client_request_id: 7,
+        };
+        let results = [&not_spent, &spent, &not_spent]
+            .iter()
+            .map(|query| key_image_store.find_query(query))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            results
+                .iter()
+                .map(|result| (result.client_request_id, result.key_image_result_code))
+                .collect::<Vec<_>>(),
+            vec![
+                (7, KeyImageResultCode::NotSpent as u32),
+                (0xdead_beef, KeyImageResultCode::Spent as u32),
+                (7, KeyImageResultCode::NotSpent as u32),
+            ]
+        );
+        assert_eq!(results[1].spent_at, 15);
+    }
 }
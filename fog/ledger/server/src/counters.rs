@@ -12,4 +12,6 @@ lazy_static::lazy_static! {
           pub static ref BLOCKS_ADDED_COUNT: IntCounter = OP_COUNTERS.counter("blocks_added_count");
           // Number of keyimages fetched (from the database) since startup.
           pub static ref KEY_IMAGES_FETCHED_COUNT: IntCounter = OP_COUNTERS.counter("keyimages_fetched_count");
+          // Number of shard results for key images spent outside of the shard's block range.
+          pub static ref SHARD_RESULTS_OUT_OF_RANGE_COUNT: IntCounter = OP_COUNTERS.counter("shard_results_out_of_range_count");
 }
@@ -7,6 +7,7 @@ mod db_fetcher;
 mod key_image_service;
 mod merkle_proof_service;
 mod server;
+mod shard_diagnostics;
 mod untrusted_tx_out_service;
 
 pub use block_service::BlockService;
@@ -14,4 +15,5 @@ pub use config::LedgerServerConfig;
 pub use key_image_service::KeyImageService;
 pub use merkle_proof_service::MerkleProofService;
 pub use server::LedgerServer;
+pub use shard_diagnostics::check_shard_results_in_range;
 pub use untrusted_tx_out_service::UntrustedTxOutService;
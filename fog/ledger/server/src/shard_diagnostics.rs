@@ -0,0 +1,75 @@
+// Copyright (c) 2018-2022 The MobileCoin Foundation
+
+//! Diagnostics for the responses of key image shards.
+
+use crate::counters;
+use mc_common::logger::{log, Logger};
+use mc_fog_types::{
+    common::BlockRange,
+    ledger::{results_outside_shard_range, CheckKeyImagesResponse},
+};
+
+/// Check that a shard only returned results for key images spent within its
+/// assigned block range, counting and logging any that were not.
+///
+/// This inspects the results, so it is NOT oblivious: it must not be used on
+/// the path which produces the client's results, only alongside it, e.g. when
+/// debugging shard configuration. Returns the number of out-of-range results.
+pub fn check_shard_results_in_range(
+    shard_range: &BlockRange,
+    shard_response: &CheckKeyImagesResponse,
+    logger: &Logger,
+) -> usize {
+    let outside = results_outside_shard_range(shard_range, shard_response);
+    if !outside.is_empty() {
+        counters::SHARD_RESULTS_OUT_OF_RANGE_COUNT.inc_by(outside.len() as u64);
+        log::warn!(
+            logger,
+            "Shard for blocks [{}, {}) returned {} results spent outside of its range, e.g. at block {}",
+            shard_range.start_block,
+            shard_range.end_block,
+            outside.len(),
+            outside[0].spent_at,
+        );
+    }
+    outside.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mc_common::logger::{test_with_logger, Logger};
+    use mc_fog_types::ledger::{KeyImageResult, KeyImageResultCode};
+    use mc_transaction_core::ring_signature::KeyImage;
+
+    fn spent_at(n: u64, block: u64) -> KeyImageResult {
+        KeyImageResult {
+            key_image: KeyImage::from(n),
+            spent_at: block,
+            timestamp: 0,
+            timestamp_result_code: 0,
+            key_image_result_code: KeyImageResultCode::Spent as u32,
+            client_request_id: 0,
+        }
+    }
+
+    #[test_with_logger]
+    fn out_of_range_results_are_counted(logger: Logger) {
+        let range = BlockRange::new(100, 200);
+        let mut response = CheckKeyImagesResponse {
+            num_blocks: 200,
+            global_txo_count: 0,
+            results: vec![spent_at(1, 150), spent_at(2, 199)],
+            latest_block_version: 0,
+            max_block_version: 0,
+        };
+
+        let before = counters::SHARD_RESULTS_OUT_OF_RANGE_COUNT.get();
+        assert_eq!(check_shard_results_in_range(&range, &response, &logger), 0);
+        assert_eq!(counters::SHARD_RESULTS_OUT_OF_RANGE_COUNT.get(), before);
+
+        response.results.push(spent_at(3, 42));
+        assert_eq!(check_shard_results_in_range(&range, &response, &logger), 1);
+        assert_eq!(counters::SHARD_RESULTS_OUT_OF_RANGE_COUNT.get(), before + 1);
+    }
+}
@@ -29,7 +29,7 @@ use mc_transaction_core::{
     Token,
 };
 use mc_util_from_random::FromRandom;
-use mc_util_grpc::GrpcRetryConfig;
+use mc_util_grpc::{GrpcRetryConfig, RetriableCodes, RetryJitter};
 use mc_util_test_helper::{CryptoRng, RngCore, RngType, SeedableRng};
 use mc_watcher::watcher_db::WatcherDB;
 use std::{path::PathBuf, str::FromStr, sync::Arc, thread::sleep, time::Duration};
@@ -43,6 +43,11 @@ const OMAP_CAPACITY: u64 = 128 * 128;
 const GRPC_RETRY_CONFIG: GrpcRetryConfig = GrpcRetryConfig {
     grpc_retry_count: 3,
     grpc_retry_millis: 20,
+    grpc_retry_max_millis: 10_000,
+    grpc_retry_multiplier: 1,
+    grpc_retry_jitter: RetryJitter::Full,
+    grpc_wait_for_ready: false,
+    grpc_retry_retriable: RetriableCodes::ALL,
 };
 
 fn setup_watcher_db(logger: Logger) -> (WatcherDB, PathBuf) {
@@ -1,6 +1,7 @@
 // Copyright (c) 2018-2022 The MobileCoin Foundation
 
-use alloc::vec::Vec;
+use crate::common::BlockRange;
+use alloc::{vec, vec::Vec};
 use displaydoc::Display;
 use mc_transaction_core::{
     ring_signature::KeyImage,
@@ -101,7 +102,7 @@ pub struct CheckKeyImagesRequest {
 }
 
 /// Query about a particular key image
-#[derive(Message, Eq, PartialEq)]
+#[derive(Clone, Message, Eq, PartialEq)]
 pub struct KeyImageQuery {
     /// The key image to query about
     #[prost(message, required, tag = "1")]
@@ -110,6 +111,99 @@ pub struct KeyImageQuery {
     /// A lower bound on the range to search. This is an optimization.
     #[prost(fixed64, tag = "2")]
     pub start_block: u64,
+
+    /// An opaque id chosen by the client, which is echoed in the result for
+    /// this query, so that clients can correlate them without relying on
+    /// position. Zero if unused.
+    #[prost(fixed64, tag = "3")]
+    pub client_request_id: u64,
+}
+
+/// A reason a key image query is malformed.
+#[derive(Clone, Copy, Debug, Display, Eq, PartialEq)]
+pub enum QueryError {
+    /// Query {0}: the key image is not a valid Ristretto point
+    InvalidKeyImage(usize),
+    /// Query {0}: the key image is all zeros
+    ZeroKeyImage(usize),
+}
+
+impl QueryError {
+    /// The index of the malformed query in its batch.
+    pub fn index(&self) -> usize {
+        match self {
+            Self::InvalidKeyImage(index) | Self::ZeroKeyImage(index) => *index,
+        }
+    }
+}
+
+/// Check that every query in a batch has a well-formed key image, before it
+/// is fanned out.
+///
+/// Key images are 32 bytes by construction (decoding rejects any other
+/// length), but they must also be the canonical encoding of a Ristretto point,
+/// and not the identity, which no private key maps to. Without this check, a
+/// malformed key image is silently reported as `NotSpent`.
+///
+/// Returns one error per malformed query, in batch order.
+///
+/// This only looks at the key images' encodings, and is intended to run on
+/// the client or before queries reach the enclave, so it is not required to be
+/// oblivious.
+pub fn validate_key_image_queries(queries: &[KeyImageQuery]) -> Result<(), Vec<QueryError>> {
+    let errors = queries
+        .iter()
+        .enumerate()
+        .filter_map(|(index, query)| {
+            if query.key_image.as_bytes() == &[0u8; 32] {
+                Some(QueryError::ZeroKeyImage(index))
+            } else if query.key_image.point.decompress().is_none() {
+                Some(QueryError::InvalidKeyImage(index))
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Route each query to the shards which may hold the block its key image was
+/// spent in.
+///
+/// Shards are described by the half-open block range `(start, end)` they serve,
+/// as in a `BlockRange`. A query
+/// can only be answered by blocks at or after its `start_block`, so it is
+/// assigned to every shard whose range ends after `start_block`. Queries are
+/// duplicated across overlapping shards, and queries that no shard covers are
+/// dropped.
+///
+/// The returned vector has one (possibly empty) batch per entry of
+/// `shard_ranges`, in the same order, and each batch preserves the relative
+/// order of `queries`.
+///
+/// Routing only looks at the public `start_block` field, so this is not
+/// required to be oblivious.
+pub fn partition_queries_by_shard(
+    queries: &[KeyImageQuery],
+    shard_ranges: &[(u64, u64)],
+) -> Vec<Vec<KeyImageQuery>> {
+    let shard_ranges = shard_ranges
+        .iter()
+        .map(|(start, end)| BlockRange::new(*start, *end))
+        .collect::<Vec<_>>();
+    let mut result = vec![Vec::new(); shard_ranges.len()];
+    for query in queries {
+        for (shard_queries, range) in result.iter_mut().zip(&shard_ranges) {
+            if range.is_valid() && range.end_block > query.start_block {
+                shard_queries.push(query.clone());
+            }
+        }
+    }
+    result
 }
 
 /// A list that says whether in request key images have been spent. This is the
@@ -190,6 +284,10 @@ pub struct KeyImageResult {
     /// Spent at result code, indicating whether the spent_at block was found.
     #[prost(fixed32, tag = "5")]
     pub key_image_result_code: u32,
+
+    /// The client_request_id of the query this is the result of.
+    #[prost(fixed64, tag = "6")]
+    pub client_request_id: u64,
 }
 
 /// An enum corresponding to the KeyImageResultCode proto enum
@@ -218,3 +316,280 @@ impl TryFrom<u32> for KeyImageResultCode {
         }
     }
 }
+
+/// Counts of key image results, broken down by `KeyImageResultCode`.
+///
+/// This is intended for metrics: it is computed from an already materialized
+/// list of results, after any oblivious processing has completed, so it does
+/// not affect the timing of that processing.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct KeyImageResultCodeCounts {
+    /// Number of results with `KeyImageResultCode::Spent`
+    pub spent: u64,
+    /// Number of results with `KeyImageResultCode::NotSpent`
+    pub not_spent: u64,
+    /// Number of results with `KeyImageResultCode::KeyImageError`
+    pub key_image_error: u64,
+    /// Number of results with a result code we don't recognize
+    pub unknown: u64,
+}
+
+impl KeyImageResultCodeCounts {
+    /// Count the result codes of a list of key image results.
+    pub fn from_results(results: &[KeyImageResult]) -> Self {
+        let mut counts = Self::default();
+        for result in results {
+            counts.record(result.key_image_result_code);
+        }
+        counts
+    }
+
+    /// Record a single (raw) key image result code.
+    pub fn record(&mut self, key_image_result_code: u32) {
+        match KeyImageResultCode::try_from(key_image_result_code) {
+            Ok(KeyImageResultCode::Spent) => self.spent += 1,
+            Ok(KeyImageResultCode::NotSpent) => self.not_spent += 1,
+            Ok(KeyImageResultCode::KeyImageError) => self.key_image_error += 1,
+            Err(()) => self.unknown += 1,
+        }
+    }
+
+    /// The total number of results counted.
+    pub fn total(&self) -> u64 {
+        self.spent + self.not_spent + self.key_image_error + self.unknown
+    }
+}
+
+impl CheckKeyImagesResponse {
+    /// Count the results of this response by result code.
+    pub fn result_code_counts(&self) -> KeyImageResultCodeCounts {
+        KeyImageResultCodeCounts::from_results(&self.results)
+    }
+}
+
+/// The number of blocks which every shard has processed, given one response
+/// from each shard.
+///
+/// This is the minimum of the shards' `num_blocks`, i.e. the ledger tip which
+/// is fully covered: a `NotSpent` result means the key image was not spent in
+/// any of these blocks. It only looks at per-shard metadata, never at the
+/// results, so it reveals nothing about the queries. If there are no shards,
+/// no blocks are covered.
+pub fn fully_covered_num_blocks(shard_responses: &[CheckKeyImagesResponse]) -> u64 {
+    shard_responses
+        .iter()
+        .map(|response| response.num_blocks)
+        .min()
+        .unwrap_or(0)
+}
+
+/// The results in a shard's response which say a key image was spent in a
+/// block outside of the range the shard is assigned.
+///
+/// A correctly configured shard only knows about key images spent in its own
+/// blocks, so any such result indicates a misconfiguration or a bug, and could
+/// corrupt collation. This looks at the results themselves, so it is NOT
+/// oblivious, and is only intended for diagnostics, away from the path which
+/// produces the client's results.
+pub fn results_outside_shard_range<'a>(
+    shard_range: &BlockRange,
+    shard_response: &'a CheckKeyImagesResponse,
+) -> Vec<&'a KeyImageResult> {
+    shard_response
+        .results
+        .iter()
+        .filter(|result| {
+            result.key_image_result_code == KeyImageResultCode::Spent as u32
+                && !shard_range.contains(result.spent_at)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(n: u64, start_block: u64) -> KeyImageQuery {
+        KeyImageQuery {
+            key_image: KeyImage::from(n),
+            start_block,
+            client_request_id: 0,
+        }
+    }
+
+    fn spent_result(n: u64, code: KeyImageResultCode, spent_at: u64) -> KeyImageResult {
+        KeyImageResult {
+            key_image: KeyImage::from(n),
+            spent_at,
+            timestamp: 0,
+            timestamp_result_code: 0,
+            key_image_result_code: code as u32,
+            client_request_id: 0,
+        }
+    }
+
+    #[test]
+    fn results_outside_shard_range_are_found() {
+        let range = BlockRange::new(10, 20);
+        let response = CheckKeyImagesResponse {
+            num_blocks: 20,
+            global_txo_count: 0,
+            results: vec![
+                spent_result(1, KeyImageResultCode::Spent, 10),
+                spent_result(2, KeyImageResultCode::Spent, 20),
+                spent_result(3, KeyImageResultCode::NotSpent, 0),
+                spent_result(4, KeyImageResultCode::Spent, 5),
+                spent_result(5, KeyImageResultCode::KeyImageError, 0),
+                spent_result(6, KeyImageResultCode::Spent, 19),
+            ],
+            latest_block_version: 0,
+            max_block_version: 0,
+        };
+
+        let outside = results_outside_shard_range(&range, &response);
+        assert_eq!(outside, vec![&response.results[1], &response.results[3]]);
+    }
+
+    #[test]
+    fn validate_well_formed_queries() {
+        let queries = [query(1, 0), query(2, 10), query(3, 20)];
+        assert_eq!(validate_key_image_queries(&queries), Ok(()));
+        assert_eq!(validate_key_image_queries(&[]), Ok(()));
+    }
+
+    #[test]
+    fn validate_rejects_malformed_key_image() {
+        // Not the canonical encoding of any Ristretto point.
+        let malformed = KeyImageQuery {
+            key_image: KeyImage::from([0xff; 32]),
+            start_block: 0,
+            client_request_id: 0,
+        };
+        let queries = [query(1, 0), malformed.clone(), query(2, 0), malformed];
+        let errors = validate_key_image_queries(&queries).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                QueryError::InvalidKeyImage(1),
+                QueryError::InvalidKeyImage(3)
+            ]
+        );
+        assert_eq!(errors[1].index(), 3);
+    }
+
+    #[test]
+    fn validate_rejects_zero_key_image() {
+        let zero = KeyImageQuery {
+            key_image: KeyImage::from([0u8; 32]),
+            start_block: 5,
+            client_request_id: 0,
+        };
+        assert_eq!(
+            validate_key_image_queries(&[zero, query(1, 0)]),
+            Err(vec![QueryError::ZeroKeyImage(0)])
+        );
+    }
+
+    #[test]
+    fn partition_queries_single_shard() {
+        let shards = [(0, 10), (10, 20)];
+        let queries = [query(1, 12), query(2, 15)];
+
+        let partition = partition_queries_by_shard(&queries, &shards);
+        assert_eq!(partition.len(), 2);
+        assert!(partition[0].is_empty());
+        assert_eq!(partition[1], queries.to_vec());
+    }
+
+    #[test]
+    fn partition_queries_overlapping_shards() {
+        let shards = [(0, 10), (5, 20)];
+        let queries = [query(1, 0), query(2, 7), query(3, 12)];
+
+        let partition = partition_queries_by_shard(&queries, &shards);
+        assert_eq!(partition[0], vec![query(1, 0), query(2, 7)]);
+        assert_eq!(partition[1], vec![query(1, 0), query(2, 7), query(3, 12)]);
+    }
+
+    #[test]
+    fn partition_queries_out_of_all_ranges() {
+        let shards = [(0, 10), (10, 20)];
+        let queries = [query(1, 20), query(2, 100)];
+
+        let partition = partition_queries_by_shard(&queries, &shards);
+        assert_eq!(partition, vec![Vec::new(), Vec::new()]);
+
+        // No shards means no routing at all.
+        assert!(partition_queries_by_shard(&queries, &[]).is_empty());
+
+        // Empty ranges serve no queries.
+        assert_eq!(
+            partition_queries_by_shard(&[query(1, 0)], &[(10, 10)]),
+            vec![Vec::new()]
+        );
+    }
+
+    fn result(n: u64, key_image_result_code: u32) -> KeyImageResult {
+        KeyImageResult {
+            key_image: KeyImage::from(n),
+            spent_at: 0,
+            timestamp: 0,
+            timestamp_result_code: 0,
+            key_image_result_code,
+            client_request_id: 0,
+        }
+    }
+
+    #[test]
+    fn result_code_counts_match_distribution() {
+        let spent = KeyImageResultCode::Spent as u32;
+        let not_spent = KeyImageResultCode::NotSpent as u32;
+        let error = KeyImageResultCode::KeyImageError as u32;
+        let codes = [
+            spent, not_spent, not_spent, error, spent, not_spent, 0, spent, not_spent, 17,
+        ];
+        let results: Vec<_> = codes
+            .iter()
+            .enumerate()
+            .map(|(i, code)| result(i as u64, *code))
+            .collect();
+
+        let counts = KeyImageResultCodeCounts::from_results(&results);
+        assert_eq!(
+            counts,
+            KeyImageResultCodeCounts {
+                spent: 3,
+                not_spent: 4,
+                key_image_error: 1,
+                unknown: 2,
+            }
+        );
+        assert_eq!(counts.total(), codes.len() as u64);
+
+        let response = CheckKeyImagesResponse {
+            results,
+            ..Default::default()
+        };
+        assert_eq!(response.result_code_counts(), counts);
+    }
+
+    #[test]
+    fn result_code_counts_empty() {
+        let counts = KeyImageResultCodeCounts::from_results(&[]);
+        assert_eq!(counts, KeyImageResultCodeCounts::default());
+        assert_eq!(counts.total(), 0);
+    }
+
+    #[test]
+    fn fully_covered_num_blocks_is_minimum_of_shards() {
+        let shard_response = |num_blocks| CheckKeyImagesResponse {
+            num_blocks,
+            results: vec![result(num_blocks, KeyImageResultCode::NotSpent as u32)],
+            ..Default::default()
+        };
+        let responses = [shard_response(120), shard_response(95), shard_response(130)];
+        assert_eq!(fully_covered_num_blocks(&responses), 95);
+        assert_eq!(fully_covered_num_blocks(&responses[..1]), 120);
+        assert_eq!(fully_covered_num_blocks(&[]), 0);
+    }
+}
@@ -35,13 +35,18 @@ use mc_fog_view_enclave::SgxViewEnclave;
 use mc_fog_view_protocol::FogViewConnection;
 use mc_fog_view_server::{config::MobileAcctViewConfig as ViewConfig, server::ViewServer};
 use mc_util_from_random::FromRandom;
-use mc_util_grpc::GrpcRetryConfig;
+use mc_util_grpc::{GrpcRetryConfig, RetriableCodes, RetryJitter};
 use rand::{rngs::StdRng, SeedableRng};
 use std::{str::FromStr, sync::Arc, thread::sleep, time::Duration};
 
 const GRPC_RETRY_CONFIG: GrpcRetryConfig = GrpcRetryConfig {
     grpc_retry_count: 3,
     grpc_retry_millis: 20,
+    grpc_retry_max_millis: 10_000,
+    grpc_retry_multiplier: 1,
+    grpc_retry_jitter: RetryJitter::Full,
+    grpc_wait_for_ready: false,
+    grpc_retry_retriable: RetriableCodes::ALL,
 };
 
 fn get_test_environment(
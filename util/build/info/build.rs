@@ -35,15 +35,37 @@ fn get_git_commit(current_dir: impl AsRef<Path>) -> String {
     }
 }
 
-/// Get the git commit of what should be the root repository.
-/// This is useful when `mobilecoin` is a submodule of another repository.
-/// Use the GIT_COMMIT environment variable to override this.
-fn get_root_git_commit() -> String {
-    if let Ok(result) = env::var("GIT_COMMIT") {
-        eprintln!("GIT_COMMIT from env: {}", result);
-        return result;
+/// Check whether the working tree in a given directory has uncommitted
+/// changes to tracked files, by running `git status --porcelain`. Untracked
+/// files are ignored, like `git describe --dirty` does. If git is not
+/// available, the tree is assumed to be clean.
+fn get_git_dirty(current_dir: impl AsRef<Path>) -> bool {
+    match Command::new("git")
+        .args(&["status", "--porcelain", "--untracked-files=no"])
+        .current_dir(current_dir)
+        .output()
+    {
+        Err(err) => {
+            eprintln!("Couldn't run git: {}", err);
+            false
+        }
+        Ok(proc_output) => {
+            if !proc_output.status.success() {
+                eprintln!(
+                    "git status failed: {}",
+                    String::from_utf8_lossy(&proc_output.stderr)
+                );
+                false
+            } else {
+                !proc_output.stdout.iter().all(u8::is_ascii_whitespace)
+            }
+        }
     }
+}
 
+/// Find what should be the root repository, i.e. the outermost directory
+/// containing a .git directory.
+fn get_root_git_dir() -> Option<PathBuf> {
     // Traverse up until we can no longer find a git directory.
     let mut current_dir = env::current_dir().expect("failed getting current directory");
     let mut last_git_dir = None;
@@ -58,8 +80,19 @@ fn get_root_git_commit() -> String {
             break;
         }
     }
+    last_git_dir
+}
+
+/// Get the git commit of what should be the root repository.
+/// This is useful when `mobilecoin` is a submodule of another repository.
+/// Use the GIT_COMMIT environment variable to override this.
+fn get_root_git_commit() -> String {
+    if let Ok(result) = env::var("GIT_COMMIT") {
+        eprintln!("GIT_COMMIT from env: {}", result);
+        return result;
+    }
 
-    match last_git_dir {
+    match get_root_git_dir() {
         Some(dir) => {
             eprintln!("Root git commit from: {}", dir.display());
             get_git_commit(dir)
@@ -71,6 +104,24 @@ fn get_root_git_commit() -> String {
     }
 }
 
+/// Check whether the root repository had uncommitted changes.
+/// Use the GIT_DIRTY environment variable to override this, e.g. alongside
+/// GIT_COMMIT.
+fn get_root_git_dirty() -> bool {
+    rerun_if_env_changed!("GIT_DIRTY");
+    if let Ok(result) = env::var("GIT_DIRTY") {
+        eprintln!("GIT_DIRTY from env: {}", result);
+        return result == "true" || result == "1";
+    }
+    if env::var("GIT_COMMIT").is_ok() {
+        // The commit came from the environment, so the local tree may not be
+        // the one which was built.
+        return false;
+    }
+
+    get_root_git_dir().map(get_git_dirty).unwrap_or(false)
+}
+
 /// Get the `mobilecoin` repository's git commit.
 // This build script is run from the directory containing it, so calling
 // get_get_commit with the current directory will give the git commit of the
@@ -99,6 +150,7 @@ fn env_with_fallback(name: &str, fallback: &str) -> String {
 fn main() {
     // Collect stuff to go in the build info file
     let root_git_commit = get_root_git_commit();
+    let root_git_dirty = get_root_git_dirty();
     let mobilecoin_git_commit = get_mobilecoin_git_commit();
     let profile = env_with_fallback("PROFILE", "?");
     let debug = env_with_fallback("DEBUG", "false");
@@ -116,6 +168,7 @@ fn main() {
         r###"
 // This file is generated
 pub fn git_commit() -> &'static str {{ "{}" }}
+pub fn git_dirty() -> bool {{ {} }}
 pub fn mobilecoin_git_commit() -> &'static str {{ "{}" }}
 pub fn profile() -> &'static str {{ "{}" }}
 pub fn debug() -> &'static str {{ "{}" }}
@@ -130,6 +183,7 @@ pub fn ias_mode() -> &'static str {{ "{}" }}
 // Note: Please update `build-info/src/lib.rs` if you add more stuff
 "###,
         root_git_commit,
+        root_git_dirty,
         mobilecoin_git_commit,
         profile,
         debug,
@@ -8,3 +8,20 @@ fn build_info_report_json() {
 
     json::parse(&buf).unwrap();
 }
+
+/// Test that the dirty flag is reported, and is false when the commit is
+/// unknown, e.g. because git was unavailable at build time
+#[test]
+fn build_info_report_git_dirty() {
+    let mut buf = String::new();
+    mc_util_build_info::write_report(&mut buf).unwrap();
+
+    let report = json::parse(&buf).unwrap();
+    assert_eq!(
+        report["GIT_DIRTY"].as_bool(),
+        Some(mc_util_build_info::git_dirty())
+    );
+    if mc_util_build_info::git_commit() == "??????" {
+        assert!(!mc_util_build_info::git_dirty());
+    }
+}
@@ -6,7 +6,10 @@
 use clap::{Parser, Subcommand};
 use grpcio::ChannelBuilder;
 use mc_util_grpc::{
-    admin::SetRustLogRequest, admin_grpc::AdminApiClient, empty::Empty, ConnectionUriGrpcioChannel,
+    admin::{SetLogLevelRequest, SetRustLogRequest},
+    admin_grpc::AdminApiClient,
+    empty::Empty,
+    ConnectionUriGrpcioChannel,
 };
 use mc_util_uri::AdminUri;
 use std::{str::FromStr, sync::Arc};
@@ -38,6 +41,16 @@ pub enum Command {
         rust_log: String,
     },
 
+    /// Set the log level, either globally or for a single module.
+    SetLogLevel {
+        /// Log level: error, warn, info, debug or trace
+        level: String,
+
+        /// Module to set the level for, instead of the default level
+        #[clap(long)]
+        target: Option<String>,
+    },
+
     /// Logs a test error message.
     TestLogError,
 }
@@ -83,6 +96,18 @@ fn main() {
             println!("Done.");
         }
 
+        Command::SetLogLevel { level, target } => {
+            let mut request = SetLogLevelRequest::new();
+            request.set_level(level);
+            request.set_target(target.unwrap_or_default());
+
+            let response = client
+                .set_log_level(&request)
+                .expect("failed calling set_log_level");
+            println!("Log level: {}", response.level);
+            println!("RUST_LOG:  {}", response.rust_log);
+        }
+
         Command::TestLogError => {
             let _ = client
                 .test_log_error(&Empty::new())
@@ -1,17 +1,30 @@
 //! A standardized admin GRPC server
 
 use crate::{
-    AdminService, BuildInfoService, ConnectionUriGrpcioServer, GetConfigJsonFn, HealthService,
+    services_hash, AdminCommandFn, AdminService, Authenticator, BuildInfoService,
+    ConnectionUriGrpcioServer, GetConfigJsonFn, HealthService,
 };
+use displaydoc::Display;
 use futures::executor::block_on;
 use grpcio::{Environment, ShutdownFuture};
 use mc_common::logger::{log, Logger};
 use mc_util_uri::{AdminUri, ConnectionUri};
-use std::sync::Arc;
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+/// The name of the command reporting the number of active health `Watch`
+/// streams, see `AdminServerBuilder::with_health_service`.
+pub const HEALTH_WATCHERS_COMMAND: &str = "health_watchers";
 
 /// The admin server is a grpc server that serves the admin endpoint
 pub struct AdminServer {
     server: grpcio::Server,
+    config_version: Arc<AtomicU64>,
 }
 
 impl AdminServer {
@@ -23,6 +36,16 @@ impl AdminServer {
         id: String,
         get_config_json: Option<GetConfigJsonFn>,
         logger: Logger,
+    ) -> Result<Self, grpcio::Error> {
+        let admin_service = AdminService::new(name, id, get_config_json, logger.clone());
+        Self::start_with_service(env, admin_listen_uri, admin_service, logger)
+    }
+
+    fn start_with_service(
+        env: Option<Arc<Environment>>,
+        admin_listen_uri: &AdminUri,
+        admin_service: AdminService,
+        logger: Logger,
     ) -> Result<Self, grpcio::Error> {
         log::info!(
             logger,
@@ -40,8 +63,8 @@ impl AdminServer {
         });
 
         // Initialize services.
-        let admin_service =
-            AdminService::new(name, id, get_config_json, logger.clone()).into_service();
+        let config_version = admin_service.config_version_handle();
+        let admin_service = admin_service.into_service();
         let health_service = HealthService::new(None, logger.clone()).into_service();
         let build_info_service = BuildInfoService::new(logger.clone()).into_service();
 
@@ -58,7 +81,17 @@ impl AdminServer {
             log::info!(logger, "Admin GRPC API listening on {}:{}", host, port);
         }
 
-        Ok(Self { server })
+        Ok(Self {
+            server,
+            config_version,
+        })
+    }
+
+    /// Increment the configuration version reported in GetInfo, returning the
+    /// new version. Services should call this whenever they reload their
+    /// configuration, so that admin clients can notice the change.
+    pub fn bump_config_version(&self) -> u64 {
+        self.config_version.fetch_add(1, Ordering::SeqCst) + 1
     }
 
     /// Shuts down the admin server
@@ -72,3 +105,453 @@ impl Drop for AdminServer {
         block_on(self.shutdown()).expect("Could not shutdown admin server")
     }
 }
+
+/// An error building an admin server
+#[derive(Debug, Display)]
+pub enum AdminServerBuilderError {
+    /// Config keys were redacted, but no config callback was provided
+    RedactWithoutConfig,
+    /// A command name must not be empty
+    EmptyCommandName,
+    /// Command '{0}' was added more than once
+    DuplicateCommand(String),
+    /// Grpc: {0}
+    Grpc(grpcio::Error),
+}
+
+impl From<grpcio::Error> for AdminServerBuilderError {
+    fn from(src: grpcio::Error) -> Self {
+        Self::Grpc(src)
+    }
+}
+
+/// A builder for an `AdminServer` with a selected set of admin features.
+///
+/// Unlike `AdminServer::start`, which always serves metrics, metrics must be
+/// enabled explicitly using `with_metrics`.
+pub struct AdminServerBuilder {
+    name: String,
+    id: String,
+    env: Option<Arc<Environment>>,
+    get_config_json: Option<GetConfigJsonFn>,
+    metrics_enabled: bool,
+    commands: Vec<(String, AdminCommandFn)>,
+    redacted_config_keys: Vec<String>,
+    authenticator: Option<Arc<dyn Authenticator + Send + Sync>>,
+    services_hash: String,
+    logger: Logger,
+}
+
+impl AdminServerBuilder {
+    /// Start building an admin server for a service with the given name and
+    /// id.
+    pub fn new(name: impl Into<String>, id: impl Into<String>, logger: Logger) -> Self {
+        Self {
+            name: name.into(),
+            id: id.into(),
+            env: None,
+            get_config_json: None,
+            metrics_enabled: false,
+            commands: Vec::new(),
+            redacted_config_keys: Vec::new(),
+            authenticator: None,
+            services_hash: String::new(),
+            logger,
+        }
+    }
+
+    /// Use the given grpcio environment, instead of creating one.
+    pub fn with_env(mut self, env: Arc<Environment>) -> Self {
+        self.env = Some(env);
+        self
+    }
+
+    /// Serve the configuration JSON returned by `get_config_json` in GetInfo.
+    pub fn with_config_fn(mut self, get_config_json: GetConfigJsonFn) -> Self {
+        self.get_config_json = Some(get_config_json);
+        self
+    }
+
+    /// Serve Prometheus metrics.
+    pub fn with_metrics(mut self) -> Self {
+        self.metrics_enabled = true;
+        self
+    }
+
+    /// Add a service-specific command, which can be run with RunCommand.
+    pub fn with_command(mut self, name: impl Into<String>, command: AdminCommandFn) -> Self {
+        self.commands.push((name.into(), command));
+        self
+    }
+
+    /// Report the number of active `Watch` streams of `health` with the
+    /// `health_watchers` command, so that leaked subscriptions can be spotted.
+    pub fn with_health_service(self, health: &HealthService) -> Self {
+        let health = health.clone();
+        self.with_command(
+            HEALTH_WATCHERS_COMMAND,
+            Arc::new(move |_| Ok(health.active_watchers().to_string())),
+        )
+    }
+
+    /// Redact the values of `key`, at any depth, from the configuration JSON.
+    pub fn redact(mut self, key: impl Into<String>) -> Self {
+        self.redacted_config_keys.push(key.into());
+        self
+    }
+
+    /// Require every admin request to pass `authenticator`.
+    pub fn require_auth(mut self, authenticator: Arc<dyn Authenticator + Send + Sync>) -> Self {
+        self.authenticator = Some(authenticator);
+        self
+    }
+
+    /// Report a hash of the set of services the server exposes in GetInfo,
+    /// so that nodes running a different set of services can be spotted.
+    ///
+    /// Each service is given by its proto service name and, if available, its
+    /// version.
+    pub fn with_services<'a>(
+        mut self,
+        services: impl IntoIterator<Item = (&'a str, Option<&'a str>)>,
+    ) -> Self {
+        self.services_hash = services_hash(services);
+        self
+    }
+
+    /// Validate the selected features, and build the admin service.
+    fn build_service(self) -> Result<AdminService, AdminServerBuilderError> {
+        if !self.redacted_config_keys.is_empty() && self.get_config_json.is_none() {
+            return Err(AdminServerBuilderError::RedactWithoutConfig);
+        }
+
+        let mut admin_service =
+            AdminService::new(self.name, self.id, self.get_config_json, self.logger);
+        admin_service.set_metrics_enabled(self.metrics_enabled);
+        admin_service.set_redacted_config_keys(self.redacted_config_keys);
+        admin_service.set_services_hash(self.services_hash);
+        if let Some(authenticator) = self.authenticator {
+            admin_service.set_authenticator(authenticator);
+        }
+
+        let mut names = HashSet::new();
+        for (name, command) in self.commands {
+            if name.is_empty() {
+                return Err(AdminServerBuilderError::EmptyCommandName);
+            }
+            if !names.insert(name.clone()) {
+                return Err(AdminServerBuilderError::DuplicateCommand(name));
+            }
+            admin_service.add_command(name, command);
+        }
+
+        Ok(admin_service)
+    }
+
+    /// Validate the selected features, then build and start the admin server.
+    pub fn build(
+        self,
+        admin_listen_uri: &AdminUri,
+    ) -> Result<AdminServer, AdminServerBuilderError> {
+        let env = self.env.clone();
+        let logger = self.logger.clone();
+        let admin_service = self.build_service()?;
+        Ok(AdminServer::start_with_service(
+            env,
+            admin_listen_uri,
+            admin_service,
+            logger,
+        )?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        admin::{RunCommandRequest, SetLogLevelRequest},
+        admin_grpc::AdminApiClient,
+        empty::Empty,
+        BasicCredentials, TokenAuthenticator, TokenBasicCredentialsGenerator,
+    };
+    use grpcio::{ChannelBuilder, EnvBuilder, RpcStatusCode};
+    use mc_common::{logger::test_with_logger, time::SystemTimeProvider};
+    use std::{str::FromStr, time::Duration};
+
+    fn listen_uri() -> AdminUri {
+        AdminUri::from_str("insecure-mca://127.0.0.1:0/").unwrap()
+    }
+
+    fn connect(server: &AdminServer) -> AdminApiClient {
+        let port = server.server.bind_addrs().next().unwrap().1;
+        let env = Arc::new(EnvBuilder::new().build());
+        let ch = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+        AdminApiClient::new(ch)
+    }
+
+    fn status_code<T: std::fmt::Debug>(result: grpcio::Result<T>) -> RpcStatusCode {
+        match result {
+            Err(grpcio::Error::RpcFailure(status)) => status.code(),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    fn run_command(name: &str, args: &[&str]) -> RunCommandRequest {
+        let mut request = RunCommandRequest::new();
+        request.set_name(name.to_string());
+        request.set_args(args.iter().map(|arg| arg.to_string()).collect());
+        request
+    }
+
+    #[test_with_logger]
+    fn builder_with_config_commands_and_metrics(logger: Logger) {
+        let get_config_json: GetConfigJsonFn = Arc::new(|| {
+            Ok(r#"{"peer":"node1","db":{"password":"hunter2","path":"/tmp"}}"#.to_string())
+        });
+        let echo: AdminCommandFn = Arc::new(|args| Ok(args.join(" ")));
+
+        let server = AdminServerBuilder::new("Test Service", "test-id", logger)
+            .with_config_fn(get_config_json)
+            .redact("password")
+            .with_metrics()
+            .with_command("echo", echo)
+            .with_services([("admin.AdminAPI", None), ("test.TestAPI", Some("1"))])
+            .build(&listen_uri())
+            .expect("Could not build admin server");
+        let client = connect(&server);
+
+        let info = client.get_info(&Empty::new()).unwrap();
+        assert_eq!(info.name, "Test Service");
+        assert_eq!(info.id, "test-id");
+        assert!(!info.config_json.contains("hunter2"));
+        let config: serde_json::Value = serde_json::from_str(&info.config_json).unwrap();
+        assert_eq!(config["db"]["password"], crate::REDACTED_VALUE);
+        assert_eq!(config["db"]["path"], "/tmp");
+        assert_eq!(config["peer"], "node1");
+        assert_eq!(
+            info.services_hash,
+            services_hash([("test.TestAPI", Some("1")), ("admin.AdminAPI", None)])
+        );
+
+        assert!(client.get_prometheus_metrics(&Empty::new()).is_ok());
+
+        let response = client
+            .run_command(&run_command("echo", &["hello", "world"]))
+            .unwrap();
+        assert_eq!(response.output, "hello world");
+
+        assert_eq!(
+            status_code(client.run_command(&run_command("missing", &[]))),
+            RpcStatusCode::NOT_FOUND
+        );
+    }
+
+    #[test_with_logger]
+    fn health_watchers_command_reports_active_watchers(logger: Logger) {
+        let health = HealthService::new(None, logger.clone());
+        let server = AdminServerBuilder::new("Test Service", "test-id", logger)
+            .with_health_service(&health)
+            .build(&listen_uri())
+            .expect("Could not build admin server");
+        let client = connect(&server);
+
+        let response = client
+            .run_command(&run_command(HEALTH_WATCHERS_COMMAND, &[]))
+            .unwrap();
+        assert_eq!(response.output, "0");
+    }
+
+    #[test_with_logger]
+    fn metrics_include_registered_counters_and_gauges(logger: Logger) {
+        let counter = mc_util_metrics::IntCounter::new(
+            "admin_metrics_test_counter",
+            "Counter registered by the admin metrics test",
+        )
+        .unwrap();
+        mc_util_metrics::register(Box::new(counter.clone())).unwrap();
+        counter.inc_by(3);
+        let gauge = mc_util_metrics::IntGauge::new(
+            "admin_metrics_test_gauge",
+            "Gauge registered by the admin metrics test",
+        )
+        .unwrap();
+        mc_util_metrics::register(Box::new(gauge.clone())).unwrap();
+        gauge.set(7);
+
+        let server = AdminServerBuilder::new("Test Service", "test-id", logger)
+            .with_metrics()
+            .build(&listen_uri())
+            .expect("Could not build admin server");
+        let client = connect(&server);
+
+        let metrics = client
+            .get_prometheus_metrics(&Empty::new())
+            .unwrap()
+            .metrics;
+        assert!(metrics
+            .lines()
+            .any(|line| line == "admin_metrics_test_counter 3"));
+        assert!(metrics
+            .lines()
+            .any(|line| line == "admin_metrics_test_gauge 7"));
+        // The request itself is counted by SVC_COUNTERS.
+        assert!(metrics.contains(r#"num_req{method="admin.AdminAPI.GetPrometheusMetrics"}"#));
+    }
+
+    #[test_with_logger]
+    fn config_version_is_bumped_on_reload(logger: Logger) {
+        let get_config_json: GetConfigJsonFn = Arc::new(|| Ok(r#"{"peer":"node1"}"#.to_string()));
+
+        let server = AdminServerBuilder::new("Test Service", "test-id", logger)
+            .with_config_fn(get_config_json)
+            .build(&listen_uri())
+            .expect("Could not build admin server");
+        let client = connect(&server);
+
+        let before = client.get_info(&Empty::new()).unwrap();
+        assert_eq!(before.config_version, 0);
+
+        assert_eq!(server.bump_config_version(), 1);
+        let after = client.get_info(&Empty::new()).unwrap();
+        assert_eq!(after.config_version, 1);
+        assert_eq!(after.config_json, before.config_json);
+
+        assert_eq!(server.bump_config_version(), 2);
+        assert_eq!(client.get_info(&Empty::new()).unwrap().config_version, 2);
+    }
+
+    fn set_log_level(level: &str, target: &str) -> SetLogLevelRequest {
+        let mut request = SetLogLevelRequest::new();
+        request.set_level(level.to_string());
+        request.set_target(target.to_string());
+        request
+    }
+
+    #[test_with_logger]
+    fn set_log_level_updates_rust_log(logger: Logger) {
+        let original_rust_log = std::env::var("RUST_LOG");
+        std::env::set_var("RUST_LOG", "info");
+
+        let server = AdminServerBuilder::new("Test Service", "test-id", logger)
+            .build(&listen_uri())
+            .expect("Could not build admin server");
+        let client = connect(&server);
+
+        // Raise the default level.
+        let response = client.set_log_level(&set_log_level("debug", "")).unwrap();
+        assert_eq!(response.level, "debug");
+        assert_eq!(response.rust_log, "debug");
+
+        // Raise the level of a single module.
+        let response = client
+            .set_log_level(&set_log_level("TRACE", "mc_util_grpc"))
+            .unwrap();
+        assert_eq!(response.level, "trace");
+        assert_eq!(response.rust_log, "debug,mc_util_grpc=trace");
+
+        // Lower the default level, keeping the module's level.
+        let response = client.set_log_level(&set_log_level("warn", "")).unwrap();
+        assert_eq!(response.rust_log, "warn,mc_util_grpc=trace");
+
+        // Lower the module's level.
+        let response = client
+            .set_log_level(&set_log_level("error", "mc_util_grpc"))
+            .unwrap();
+        assert_eq!(response.rust_log, "warn,mc_util_grpc=error");
+
+        // The filter in effect is reported back.
+        assert_eq!(
+            std::env::var("RUST_LOG").unwrap(),
+            "warn,mc_util_grpc=error"
+        );
+        let info = client.get_info(&Empty::new()).unwrap();
+        assert_eq!(info.rust_log, "warn,mc_util_grpc=error");
+
+        // Invalid levels and targets are rejected, leaving the filter as is.
+        assert_eq!(
+            status_code(client.set_log_level(&set_log_level("verbose", ""))),
+            RpcStatusCode::INVALID_ARGUMENT
+        );
+        assert_eq!(
+            status_code(client.set_log_level(&set_log_level("info", "a=b"))),
+            RpcStatusCode::INVALID_ARGUMENT
+        );
+        assert_eq!(
+            std::env::var("RUST_LOG").unwrap(),
+            "warn,mc_util_grpc=error"
+        );
+
+        match original_rust_log {
+            Ok(rust_log) => std::env::set_var("RUST_LOG", rust_log),
+            Err(_) => std::env::remove_var("RUST_LOG"),
+        }
+    }
+
+    #[test_with_logger]
+    fn builder_without_metrics_and_with_auth(logger: Logger) {
+        let shared_secret = [5; 32];
+        let authenticator = Arc::new(TokenAuthenticator::new(
+            shared_secret,
+            Duration::from_secs(60),
+            SystemTimeProvider::default(),
+        ));
+
+        let server = AdminServerBuilder::new("Test Service", "test-id", logger)
+            .require_auth(authenticator)
+            .build(&listen_uri())
+            .expect("Could not build admin server");
+        let client = connect(&server);
+
+        assert_eq!(
+            status_code(client.get_info(&Empty::new())),
+            RpcStatusCode::UNAUTHENTICATED
+        );
+        let bad_creds = BasicCredentials::new("admin", "admin:0:00");
+        assert_eq!(
+            status_code(client.get_info_opt(&Empty::new(), bad_creds.call_option().unwrap())),
+            RpcStatusCode::UNAUTHENTICATED
+        );
+
+        let generator =
+            TokenBasicCredentialsGenerator::new(shared_secret, SystemTimeProvider::default());
+        let creds = generator.generate_for("admin").unwrap();
+        let info = client
+            .get_info_opt(&Empty::new(), creds.call_option().unwrap())
+            .unwrap();
+        assert_eq!(info.config_json, "");
+
+        assert_eq!(
+            status_code(
+                client.get_prometheus_metrics_opt(&Empty::new(), creds.call_option().unwrap())
+            ),
+            RpcStatusCode::UNIMPLEMENTED
+        );
+    }
+
+    #[test_with_logger]
+    fn builder_rejects_invalid_combinations(logger: Logger) {
+        let noop: AdminCommandFn = Arc::new(|_| Ok(String::new()));
+
+        assert!(matches!(
+            AdminServerBuilder::new("Test Service", "test-id", logger.clone())
+                .redact("password")
+                .build(&listen_uri()),
+            Err(AdminServerBuilderError::RedactWithoutConfig)
+        ));
+
+        assert!(matches!(
+            AdminServerBuilder::new("Test Service", "test-id", logger.clone())
+                .with_command("noop", noop.clone())
+                .with_command("noop", noop.clone())
+                .build(&listen_uri()),
+            Err(AdminServerBuilderError::DuplicateCommand(name)) if name == "noop"
+        ));
+
+        assert!(matches!(
+            AdminServerBuilder::new("Test Service", "test-id", logger)
+                .with_command("", noop)
+                .build(&listen_uri()),
+            Err(AdminServerBuilderError::EmptyCommandName)
+        ));
+    }
+}
@@ -3,21 +3,42 @@
 //! Customizable implementation of the AdminApi service.
 
 use crate::{
-    admin::{GetInfoResponse, GetPrometheusMetricsResponse, SetRustLogRequest},
+    admin::{
+        GetInfoResponse, GetPrometheusMetricsResponse, RunCommandRequest, RunCommandResponse,
+        SetLogLevelRequest, SetLogLevelResponse, SetRustLogRequest,
+    },
     admin_grpc::{create_admin_api, AdminApi},
     build_info_service::get_build_info,
     empty::Empty,
-    rpc_logger, send_result,
+    rpc_logger, send_result, Authenticator, AuthenticatorError,
 };
 use grpcio::{RpcContext, RpcStatus, RpcStatusCode, Service, UnarySink};
 use mc_common::logger::{log, Logger};
 use mc_util_metrics::SVC_COUNTERS;
 use prometheus::{self, Encoder};
-use std::{env, sync::Arc};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    env,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
 /// A callback for getting service-specific configuration data.
 pub type GetConfigJsonFn = Arc<dyn Fn() -> Result<String, RpcStatus> + Sync + Send>;
 
+/// A callback implementing a service-specific admin command. It is given the
+/// arguments of the request, and returns the output of the command.
+pub type AdminCommandFn = Arc<dyn Fn(&[String]) -> Result<String, RpcStatus> + Sync + Send>;
+
+/// The value which redacted configuration values are replaced with.
+pub const REDACTED_VALUE: &str = "<redacted>";
+
+/// The log levels accepted by `SetLogLevel`.
+const LOG_LEVELS: [&str; 5] = ["error", "warn", "info", "debug", "trace"];
+
 /// Admin GRPC service.
 #[derive(Clone)]
 pub struct AdminService {
@@ -30,6 +51,25 @@ pub struct AdminService {
     /// Optional callback for returning service-specific configuration JSON blob
     get_config_json: Option<GetConfigJsonFn>,
 
+    /// Whether Prometheus metrics are served.
+    metrics_enabled: bool,
+
+    /// Service-specific commands, by name.
+    commands: HashMap<String, AdminCommandFn>,
+
+    /// Keys whose values are redacted from the configuration JSON blob.
+    redacted_config_keys: Vec<String>,
+
+    /// Optional authenticator which every request must pass.
+    authenticator: Option<Arc<dyn Authenticator + Send + Sync>>,
+
+    /// Hash of the set of services exposed by the server this admin service
+    /// belongs to, if known.
+    services_hash: String,
+
+    /// Version of the configuration, shared by all clones of this service.
+    config_version: Arc<AtomicU64>,
+
     /// Logger.
     logger: Logger,
 }
@@ -53,15 +93,93 @@ impl AdminService {
             name,
             id,
             get_config_json,
+            metrics_enabled: true,
+            commands: HashMap::new(),
+            redacted_config_keys: Vec::new(),
+            authenticator: None,
+            services_hash: String::new(),
+            config_version: Arc::new(AtomicU64::new(0)),
             logger,
         }
     }
 
+    /// The current configuration version, as reported in GetInfo.
+    pub fn config_version(&self) -> u64 {
+        self.config_version.load(Ordering::SeqCst)
+    }
+
+    /// Increment the configuration version reported in GetInfo, returning the
+    /// new version. Services should call this whenever they reload their
+    /// configuration, so that admin clients can notice the change.
+    pub fn bump_config_version(&self) -> u64 {
+        self.config_version.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// Get a handle to the configuration version, which outlives conversion
+    /// into a grpcio::Service.
+    pub(crate) fn config_version_handle(&self) -> Arc<AtomicU64> {
+        self.config_version.clone()
+    }
+
     /// Convert into a grpcio::Service
     pub fn into_service(self) -> Service {
         create_admin_api(self)
     }
 
+    /// Set whether Prometheus metrics are served.
+    pub(crate) fn set_metrics_enabled(&mut self, metrics_enabled: bool) {
+        self.metrics_enabled = metrics_enabled;
+    }
+
+    /// Add a service-specific command, replacing any command with the same
+    /// name.
+    pub(crate) fn add_command(&mut self, name: String, command: AdminCommandFn) {
+        self.commands.insert(name, command);
+    }
+
+    /// Set the keys whose values are redacted from the configuration JSON.
+    pub(crate) fn set_redacted_config_keys(&mut self, keys: Vec<String>) {
+        self.redacted_config_keys = keys;
+    }
+
+    /// Require every request to pass the given authenticator.
+    pub(crate) fn set_authenticator(
+        &mut self,
+        authenticator: Arc<dyn Authenticator + Send + Sync>,
+    ) {
+        self.authenticator = Some(authenticator);
+    }
+
+    /// Set the hash of the set of services exposed by the server, as computed
+    /// by `services_hash`.
+    pub(crate) fn set_services_hash(&mut self, services_hash: String) {
+        self.services_hash = services_hash;
+    }
+
+    fn authenticate(&self, ctx: &RpcContext) -> Result<(), AuthenticatorError> {
+        if let Some(authenticator) = self.authenticator.as_ref() {
+            authenticator.authenticate_rpc(ctx)?;
+        }
+        Ok(())
+    }
+
+    /// Replace the values of any redacted keys, at any depth, of the
+    /// configuration JSON.
+    fn redact_config_json(&self, config_json: String) -> Result<String, RpcStatus> {
+        if self.redacted_config_keys.is_empty() || config_json.is_empty() {
+            return Ok(config_json);
+        }
+
+        let mut value: Value = serde_json::from_str(&config_json).map_err(|err| {
+            RpcStatus::with_message(
+                RpcStatusCode::INTERNAL,
+                format!("Could not parse config json for redaction: {}", err),
+            )
+        })?;
+        redact_value(&mut value, &self.redacted_config_keys);
+        Ok(value.to_string())
+    }
+
     fn get_prometheus_metrics_impl(
         &mut self,
         _request: Empty,
@@ -69,6 +187,13 @@ impl AdminService {
     ) -> Result<GetPrometheusMetricsResponse, RpcStatus> {
         log::trace!(logger, "get_prometheus_metrics_impl");
 
+        if !self.metrics_enabled {
+            return Err(RpcStatus::with_message(
+                RpcStatusCode::UNIMPLEMENTED,
+                "Metrics are not enabled on this admin service".to_string(),
+            ));
+        }
+
         let metric_families = prometheus::gather();
         let encoder = prometheus::TextEncoder::new();
         let mut buffer = vec![];
@@ -102,7 +227,7 @@ impl AdminService {
         let build_info = get_build_info();
 
         let config_json = if let Some(get_config_json) = self.get_config_json.as_ref() {
-            get_config_json()?
+            self.redact_config_json(get_config_json()?)?
         } else {
             String::from("")
         };
@@ -116,6 +241,8 @@ impl AdminService {
         response.set_build_info(build_info);
         response.set_config_json(config_json);
         response.set_rust_log(rust_log);
+        response.set_services_hash(self.services_hash.clone());
+        response.set_config_version(self.config_version());
         Ok(response)
     }
 
@@ -131,6 +258,47 @@ impl AdminService {
         Ok(Empty::new())
     }
 
+    fn set_log_level_impl(
+        &mut self,
+        request: SetLogLevelRequest,
+        logger: &Logger,
+    ) -> Result<SetLogLevelResponse, RpcStatus> {
+        let level = request.level.to_lowercase();
+        if !LOG_LEVELS.contains(&level.as_str()) {
+            return Err(RpcStatus::with_message(
+                RpcStatusCode::INVALID_ARGUMENT,
+                format!("Invalid log level '{}'", request.level),
+            ));
+        }
+        let target = request.target.trim();
+        if target
+            .chars()
+            .any(|c| c == ',' || c == '=' || c.is_whitespace())
+        {
+            return Err(RpcStatus::with_message(
+                RpcStatusCode::INVALID_ARGUMENT,
+                format!("Invalid log target '{}'", request.target),
+            ));
+        }
+
+        let current = env::var("RUST_LOG").unwrap_or_else(|_| "".to_string());
+        let rust_log =
+            rust_log_with_level(&current, &level, Some(target).filter(|t| !t.is_empty()));
+        log::info!(
+            logger,
+            "Updating RUST_LOG from '{}' to '{}'",
+            current,
+            rust_log
+        );
+        env::set_var("RUST_LOG", &rust_log);
+        mc_common::logger::recreate_app_logger();
+
+        let mut response = SetLogLevelResponse::new();
+        response.set_level(level);
+        response.set_rust_log(rust_log);
+        Ok(response)
+    }
+
     fn test_log_error_impl(
         &mut self,
         _request: Empty,
@@ -140,6 +308,70 @@ impl AdminService {
 
         Ok(Empty::new())
     }
+
+    fn run_command_impl(
+        &mut self,
+        request: RunCommandRequest,
+        logger: &Logger,
+    ) -> Result<RunCommandResponse, RpcStatus> {
+        log::info!(logger, "Running admin command '{}'", request.name);
+
+        let command = self.commands.get(&request.name).ok_or_else(|| {
+            RpcStatus::with_message(
+                RpcStatusCode::NOT_FOUND,
+                format!("Unknown command '{}'", request.name),
+            )
+        })?;
+        let output = command(&request.args)?;
+
+        let mut response = RunCommandResponse::new();
+        response.set_output(output);
+        Ok(response)
+    }
+}
+
+/// Update a RUST_LOG value so that `target` (or the default, if `target` is
+/// `None`) is logged at `level`, keeping all other directives.
+fn rust_log_with_level(current: &str, level: &str, target: Option<&str>) -> String {
+    let others = current
+        .split(',')
+        .filter(|directive| !directive.is_empty())
+        .filter(|directive| match (directive.split_once('='), target) {
+            (None, None) => false,
+            (Some((directive_target, _)), Some(target)) => directive_target != target,
+            _ => true,
+        });
+    match target {
+        None => std::iter::once(level.to_string())
+            .chain(others.map(str::to_string))
+            .collect::<Vec<_>>()
+            .join(","),
+        Some(target) => others
+            .map(str::to_string)
+            .chain(std::iter::once(format!("{}={}", target, level)))
+            .collect::<Vec<_>>()
+            .join(","),
+    }
+}
+
+fn redact_value(value: &mut Value, keys: &[String]) {
+    match value {
+        Value::Object(map) => {
+            for (key, child) in map.iter_mut() {
+                if keys.contains(key) {
+                    *child = Value::String(REDACTED_VALUE.to_string());
+                } else {
+                    redact_value(child, keys);
+                }
+            }
+        }
+        Value::Array(values) => {
+            for child in values {
+                redact_value(child, keys);
+            }
+        }
+        _ => {}
+    }
 }
 
 impl AdminApi for AdminService {
@@ -151,6 +383,10 @@ impl AdminApi for AdminService {
     ) {
         let _timer = SVC_COUNTERS.req(&ctx);
         mc_common::logger::scoped_global_logger(&rpc_logger(&ctx, &self.logger), |logger| {
+            if let Err(err) = self.authenticate(&ctx) {
+                return send_result(ctx, sink, err.into(), logger);
+            }
+
             send_result(
                 ctx,
                 sink,
@@ -163,6 +399,10 @@ impl AdminApi for AdminService {
     fn get_info(&mut self, ctx: RpcContext, request: Empty, sink: UnarySink<GetInfoResponse>) {
         let _timer = SVC_COUNTERS.req(&ctx);
         mc_common::logger::scoped_global_logger(&rpc_logger(&ctx, &self.logger), |logger| {
+            if let Err(err) = self.authenticate(&ctx) {
+                return send_result(ctx, sink, err.into(), logger);
+            }
+
             send_result(ctx, sink, self.get_info_impl(request, logger), logger)
         });
     }
@@ -175,14 +415,54 @@ impl AdminApi for AdminService {
     ) {
         let _timer = SVC_COUNTERS.req(&ctx);
         mc_common::logger::scoped_global_logger(&rpc_logger(&ctx, &self.logger), |logger| {
+            if let Err(err) = self.authenticate(&ctx) {
+                return send_result(ctx, sink, err.into(), logger);
+            }
+
             send_result(ctx, sink, self.set_rust_log_impl(request, logger), logger)
         });
     }
 
+    fn set_log_level(
+        &mut self,
+        ctx: RpcContext,
+        request: SetLogLevelRequest,
+        sink: UnarySink<SetLogLevelResponse>,
+    ) {
+        let _timer = SVC_COUNTERS.req(&ctx);
+        mc_common::logger::scoped_global_logger(&rpc_logger(&ctx, &self.logger), |logger| {
+            if let Err(err) = self.authenticate(&ctx) {
+                return send_result(ctx, sink, err.into(), logger);
+            }
+
+            send_result(ctx, sink, self.set_log_level_impl(request, logger), logger)
+        });
+    }
+
     fn test_log_error(&mut self, ctx: RpcContext, request: Empty, sink: UnarySink<Empty>) {
         let _timer = SVC_COUNTERS.req(&ctx);
         mc_common::logger::scoped_global_logger(&rpc_logger(&ctx, &self.logger), |logger| {
+            if let Err(err) = self.authenticate(&ctx) {
+                return send_result(ctx, sink, err.into(), logger);
+            }
+
             send_result(ctx, sink, self.test_log_error_impl(request, logger), logger)
         });
     }
+
+    fn run_command(
+        &mut self,
+        ctx: RpcContext,
+        request: RunCommandRequest,
+        sink: UnarySink<RunCommandResponse>,
+    ) {
+        let _timer = SVC_COUNTERS.req(&ctx);
+        mc_common::logger::scoped_global_logger(&rpc_logger(&ctx, &self.logger), |logger| {
+            if let Err(err) = self.authenticate(&ctx) {
+                return send_result(ctx, sink, err.into(), logger);
+            }
+
+            send_result(ctx, sink, self.run_command_impl(request, logger), logger)
+        });
+    }
 }
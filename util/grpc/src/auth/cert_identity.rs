@@ -0,0 +1,379 @@
+// Copyright (c) 2018-2022 The MobileCoin Foundation
+
+//! Extraction of the identity presented in a peer's client certificate.
+
+use grpcio::RpcContext;
+use sha2::{Digest, Sha256};
+
+/// Name of the auth context property holding the certificate's common name.
+const X509_COMMON_NAME_PROPERTY: &str = "x509_common_name";
+
+/// Name of the auth context property holding a subject alternative name. There
+/// is one property per SAN.
+const X509_SUBJECT_ALT_NAME_PROPERTY: &str = "x509_subject_alternative_name";
+
+/// Name of the auth context property holding the PEM-encoded certificate.
+const X509_PEM_CERT_PROPERTY: &str = "x509_pem_cert";
+
+/// The identity presented by a peer's client certificate, as verified during
+/// the TLS handshake.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct CertIdentity {
+    /// The common name (CN) of the certificate's subject, if any.
+    pub common_name: Option<String>,
+
+    /// The subject alternative names (SANs) of the certificate, in the order
+    /// they appear in the certificate.
+    pub subject_alt_names: Vec<String>,
+}
+
+impl CertIdentity {
+    /// Build an identity from auth context properties, given as (name, value)
+    /// pairs. Returns `None` if no certificate properties are present.
+    fn from_properties<'a>(
+        properties: impl IntoIterator<Item = (&'a str, &'a [u8])>,
+    ) -> Option<Self> {
+        let mut identity = Self::default();
+        let mut found = false;
+        for (name, value) in properties {
+            let value = String::from_utf8_lossy(value).into_owned();
+            match name {
+                X509_COMMON_NAME_PROPERTY => {
+                    identity.common_name = Some(value);
+                    found = true;
+                }
+                X509_SUBJECT_ALT_NAME_PROPERTY => {
+                    identity.subject_alt_names.push(value);
+                    found = true;
+                }
+                _ => {}
+            }
+        }
+        if found {
+            Some(identity)
+        } else {
+            None
+        }
+    }
+
+    /// Iterate over the names this identity may be authorized as: its SANs,
+    /// followed by its common name.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.subject_alt_names
+            .iter()
+            .chain(self.common_name.iter())
+            .map(String::as_str)
+    }
+
+    /// Check whether any of the names of this identity match `pattern`.
+    ///
+    /// A pattern starting with `*.` matches any name with that suffix and at
+    /// least one more label, e.g. `*.internal` matches `a.internal` and
+    /// `a.b.internal`, but not `internal`. Other patterns must match exactly.
+    /// Comparisons are ASCII case-insensitive.
+    pub fn matches(&self, pattern: &str) -> bool {
+        self.names().any(|name| name_matches(name, pattern))
+    }
+}
+
+/// A peer's client certificate, as verified during the TLS handshake.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ClientCert {
+    /// The PEM encoding of the certificate.
+    pub pem: String,
+
+    /// The identity the certificate presents.
+    pub identity: CertIdentity,
+}
+
+impl ClientCert {
+    /// Build a certificate from auth context properties, given as (name,
+    /// value) pairs. Returns `None` if the certificate itself is not present.
+    fn from_properties<'a>(
+        properties: impl IntoIterator<Item = (&'a str, &'a [u8])>,
+    ) -> Option<Self> {
+        let properties = properties.into_iter().collect::<Vec<_>>();
+        let pem = properties
+            .iter()
+            .find(|(name, _)| *name == X509_PEM_CERT_PROPERTY)
+            .map(|(_, value)| String::from_utf8_lossy(value).into_owned())?;
+        let identity = CertIdentity::from_properties(properties).unwrap_or_default();
+        Some(Self { pem, identity })
+    }
+
+    /// The SHA-256 fingerprint of the certificate, i.e. the hash of its DER
+    /// encoding. Returns `None` if the PEM is malformed.
+    pub fn fingerprint(&self) -> Option<[u8; 32]> {
+        let der = pem_certificate_der(&self.pem)?;
+        Some(Sha256::digest(&der).into())
+    }
+}
+
+/// Decode the first certificate in a PEM string to DER.
+fn pem_certificate_der(pem: &str) -> Option<Vec<u8>> {
+    let (_, rest) = pem.split_once("-----BEGIN CERTIFICATE-----")?;
+    let (body, _) = rest.split_once("-----END CERTIFICATE-----")?;
+    let body = body
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace())
+        .collect::<String>();
+    base64::decode(body).ok()
+}
+
+fn name_matches(name: &str, pattern: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    let pattern = pattern.to_ascii_lowercase();
+    match pattern.strip_prefix('*') {
+        Some(suffix) if suffix.starts_with('.') => {
+            name.len() > suffix.len() && name.ends_with(suffix)
+        }
+        _ => name == pattern,
+    }
+}
+
+/// Get the identity presented by the peer's client certificate, if the call
+/// arrived over mTLS and the client presented a certificate.
+pub fn peer_client_cert_subject(ctx: &RpcContext) -> Option<CertIdentity> {
+    let auth_context = ctx.auth_context()?;
+    CertIdentity::from_properties(
+        auth_context
+            .into_iter()
+            .map(|property| (property.name(), property.value())),
+    )
+}
+
+/// Get the peer's client certificate, if the call arrived over mTLS and the
+/// client presented a certificate. Returns `None` for plaintext connections.
+pub fn peer_certificate(ctx: &RpcContext) -> Option<ClientCert> {
+    let auth_context = ctx.auth_context()?;
+    ClientCert::from_properties(
+        auth_context
+            .into_iter()
+            .map(|property| (property.name(), property.value())),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        create_health, send_result, Health, HealthCheckRequest, HealthCheckResponse, HealthClient,
+        PingRequest, PingResponse,
+    };
+    use grpcio::{
+        CertificateRequestType, ChannelBuilder, ChannelCredentialsBuilder, EnvBuilder, RpcStatus,
+        RpcStatusCode, ServerBuilder, ServerCredentialsBuilder, ServerStreamingSink, UnarySink,
+    };
+    use mc_common::logger::{test_with_logger, Logger};
+    use mc_crypto_x509_test_vectors::{ok_self_signed_1, ok_self_signed_2};
+    use std::sync::{Arc, Mutex};
+
+    /// A health service which records the identity and certificate of the
+    /// peer on each ping.
+    struct IdentityRecordingHealth {
+        identities: Arc<Mutex<Vec<Option<CertIdentity>>>>,
+        certs: Arc<Mutex<Vec<Option<ClientCert>>>>,
+        logger: Logger,
+    }
+
+    impl Health for IdentityRecordingHealth {
+        fn check(
+            &mut self,
+            ctx: RpcContext,
+            _req: HealthCheckRequest,
+            sink: UnarySink<HealthCheckResponse>,
+        ) {
+            let err = RpcStatus::new(RpcStatusCode::UNIMPLEMENTED);
+            send_result(ctx, sink, Err(err), &self.logger);
+        }
+
+        fn ping(&mut self, ctx: RpcContext, mut req: PingRequest, sink: UnarySink<PingResponse>) {
+            self.identities
+                .lock()
+                .unwrap()
+                .push(peer_client_cert_subject(&ctx));
+            self.certs.lock().unwrap().push(peer_certificate(&ctx));
+            let mut resp = PingResponse::new();
+            resp.set_data(req.take_data());
+            send_result(ctx, sink, Ok(resp), &self.logger);
+        }
+
+        fn watch(
+            &mut self,
+            ctx: RpcContext,
+            _req: HealthCheckRequest,
+            sink: ServerStreamingSink<HealthCheckResponse>,
+        ) {
+            ctx.spawn(async move {
+                let _ = sink
+                    .fail(RpcStatus::new(RpcStatusCode::UNIMPLEMENTED))
+                    .await;
+            });
+        }
+    }
+
+    #[test]
+    fn from_properties_collects_common_name_and_sans() {
+        let properties: Vec<(&str, &[u8])> = vec![
+            ("transport_security_type", b"ssl"),
+            (X509_COMMON_NAME_PROPERTY, b"client.internal"),
+            (X509_SUBJECT_ALT_NAME_PROPERTY, b"a.internal"),
+            (X509_SUBJECT_ALT_NAME_PROPERTY, b"b.internal"),
+            ("x509_pem_cert", b"-----BEGIN CERTIFICATE-----"),
+        ];
+        let identity = CertIdentity::from_properties(properties).unwrap();
+        assert_eq!(identity.common_name.as_deref(), Some("client.internal"));
+        assert_eq!(identity.subject_alt_names, vec!["a.internal", "b.internal"]);
+    }
+
+    #[test]
+    fn from_properties_without_certificate_is_none() {
+        let properties: Vec<(&str, &[u8])> = vec![("transport_security_type", b"insecure")];
+        assert_eq!(CertIdentity::from_properties(properties), None);
+    }
+
+    #[test]
+    fn client_cert_from_properties() {
+        let properties: Vec<(&str, &[u8])> = vec![
+            ("transport_security_type", b"ssl"),
+            (X509_COMMON_NAME_PROPERTY, b"client.internal"),
+            (X509_PEM_CERT_PROPERTY, b"-----BEGIN CERTIFICATE-----"),
+        ];
+        let cert = ClientCert::from_properties(properties).unwrap();
+        assert_eq!(cert.pem, "-----BEGIN CERTIFICATE-----");
+        assert_eq!(
+            cert.identity.common_name.as_deref(),
+            Some("client.internal")
+        );
+
+        let properties: Vec<(&str, &[u8])> = vec![
+            ("transport_security_type", b"ssl"),
+            (X509_COMMON_NAME_PROPERTY, b"client.internal"),
+        ];
+        assert_eq!(ClientCert::from_properties(properties), None);
+    }
+
+    #[test]
+    fn client_cert_fingerprint() {
+        let (pem, _key) = ok_self_signed_2();
+        let cert = ClientCert {
+            pem: pem.clone(),
+            identity: CertIdentity::default(),
+        };
+        let der = pem_certificate_der(&pem).unwrap();
+        assert_eq!(der[0], 0x30, "DER certificates are SEQUENCEs");
+        let fingerprint: [u8; 32] = Sha256::digest(&der).into();
+        assert_eq!(cert.fingerprint(), Some(fingerprint));
+
+        let malformed = ClientCert {
+            pem: "-----BEGIN CERTIFICATE-----\n!!!\n-----END CERTIFICATE-----".to_owned(),
+            identity: CertIdentity::default(),
+        };
+        assert_eq!(malformed.fingerprint(), None);
+    }
+
+    #[test]
+    fn matches_wildcard_and_exact_patterns() {
+        let identity = CertIdentity {
+            common_name: Some("Client.Internal".to_owned()),
+            subject_alt_names: vec!["svc.example.com".to_owned()],
+        };
+        assert!(identity.matches("*.internal"));
+        assert!(identity.matches("client.internal"));
+        assert!(identity.matches("svc.example.com"));
+        assert!(identity.matches("*.example.com"));
+        assert!(!identity.matches("*.client.internal"));
+        assert!(!identity.matches("other.internal"));
+
+        let identity = CertIdentity {
+            common_name: Some("internal".to_owned()),
+            subject_alt_names: vec![],
+        };
+        assert!(!identity.matches("*.internal"));
+    }
+
+    #[test_with_logger]
+    fn peer_client_cert_subject_over_mtls(logger: Logger) {
+        let (server_cert, server_key) = ok_self_signed_1();
+        let (client_cert, client_key) = ok_self_signed_2();
+        let identities = Arc::new(Mutex::new(Vec::new()));
+        let certs = Arc::new(Mutex::new(Vec::new()));
+
+        let env = Arc::new(EnvBuilder::new().build());
+        let service = create_health(IdentityRecordingHealth {
+            identities: identities.clone(),
+            certs: certs.clone(),
+            logger,
+        });
+        let server_credentials = ServerCredentialsBuilder::new()
+            .add_cert(server_cert.clone().into(), server_key.into())
+            .root_cert(
+                client_cert.clone(),
+                CertificateRequestType::RequestAndRequireClientCertificateAndVerify,
+            )
+            .build();
+        let mut server = ServerBuilder::new(env.clone())
+            .register_service(service)
+            .bind_with_cred("127.0.0.1", 0, server_credentials)
+            .build()
+            .unwrap();
+        server.start();
+        let port = server.bind_addrs().next().unwrap().1;
+
+        let client_credentials = ChannelCredentialsBuilder::new()
+            .root_cert(server_cert.into())
+            .cert(client_cert.clone().into(), client_key.into())
+            .build();
+        let channel = ChannelBuilder::new(env)
+            .override_ssl_target("www.server1.com")
+            .secure_connect(&format!("127.0.0.1:{}", port), client_credentials);
+        HealthClient::new(channel)
+            .ping(&PingRequest::new())
+            .expect("ping");
+
+        let identities = identities.lock().unwrap();
+        assert_eq!(
+            *identities,
+            vec![Some(CertIdentity {
+                common_name: Some("www.server2.com".to_owned()),
+                subject_alt_names: vec![],
+            })]
+        );
+
+        let certs = certs.lock().unwrap();
+        let cert = certs[0].as_ref().expect("no client certificate");
+        assert_eq!(cert.pem.trim(), client_cert.trim());
+        assert_eq!(
+            cert.identity.common_name.as_deref(),
+            Some("www.server2.com")
+        );
+    }
+
+    #[test_with_logger]
+    fn peer_client_cert_subject_is_none_over_plaintext(logger: Logger) {
+        let identities = Arc::new(Mutex::new(Vec::new()));
+        let certs = Arc::new(Mutex::new(Vec::new()));
+
+        let env = Arc::new(EnvBuilder::new().build());
+        let service = create_health(IdentityRecordingHealth {
+            identities: identities.clone(),
+            certs: certs.clone(),
+            logger,
+        });
+        let mut server = ServerBuilder::new(env.clone())
+            .register_service(service)
+            .bind("127.0.0.1", 0)
+            .build()
+            .unwrap();
+        server.start();
+        let port = server.bind_addrs().next().unwrap().1;
+
+        let channel = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+        HealthClient::new(channel)
+            .ping(&PingRequest::new())
+            .expect("ping");
+
+        assert_eq!(*identities.lock().unwrap(), vec![None]);
+        assert_eq!(*certs.lock().unwrap(), vec![None]);
+    }
+}
@@ -0,0 +1,195 @@
+// Copyright (c) 2018-2022 The MobileCoin Foundation
+
+//! Detection of drift between the local clock, which token authentication
+//! relies on, and a trusted reference clock.
+
+use crate::{counters::OP_COUNTERS, health_service::ServiceHealthCheckCallback, HealthCheckStatus};
+use displaydoc::Display;
+use mc_common::{
+    logger::{log, Logger},
+    time::TimeProvider,
+};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// The difference between the local clock and a reference clock.
+#[derive(Clone, Copy, Debug, Display, Eq, PartialEq)]
+pub enum ClockDrift {
+    /// Local clock is {0:?} ahead of the reference
+    Ahead(Duration),
+
+    /// Local clock is {0:?} behind the reference
+    Behind(Duration),
+}
+
+impl ClockDrift {
+    /// Compute the drift of `local` relative to `reference`, both measured
+    /// since the unix epoch.
+    pub fn between(local: Duration, reference: Duration) -> Self {
+        match local.checked_sub(reference) {
+            Some(ahead) => Self::Ahead(ahead),
+            None => Self::Behind(reference - local),
+        }
+    }
+
+    /// The absolute size of the drift.
+    pub fn magnitude(&self) -> Duration {
+        match self {
+            Self::Ahead(drift) | Self::Behind(drift) => *drift,
+        }
+    }
+}
+
+/// Compares the local clock to reference times supplied by a trusted source
+/// (e.g. the timestamp of a trusted peer's response), and flags the node as
+/// degraded while the drift exceeds a threshold.
+///
+/// Token expiry checks assume the local clock is correct, so a drifting clock
+/// causes valid tokens to be rejected, or expired tokens to be accepted. The
+/// monitor can be turned into a `ServiceHealthCheckCallback`, which reports
+/// `DEGRADED` while drifted, so that skewed nodes are visible in health
+/// checks instead of only as mysterious authentication failures.
+#[derive(Clone)]
+pub struct ClockDriftMonitor<TP: TimeProvider> {
+    /// The largest drift which is not reported.
+    max_drift: Duration,
+
+    /// Source of local time.
+    time_provider: TP,
+
+    /// Whether the last observation exceeded `max_drift`.
+    is_degraded: Arc<AtomicBool>,
+
+    /// Logger.
+    logger: Logger,
+}
+
+impl<TP: TimeProvider> ClockDriftMonitor<TP> {
+    /// Create a new clock drift monitor.
+    ///
+    /// Arguments:
+    /// * max_drift: Drift larger than this is reported, and flips health to
+    ///   degraded. This should be well below the maximum token lifetime.
+    /// * time_provider: A generic object that provides "Duration since the
+    ///   epoch"
+    /// * logger
+    pub fn new(max_drift: Duration, time_provider: TP, logger: Logger) -> Self {
+        Self {
+            max_drift,
+            time_provider,
+            is_degraded: Arc::new(AtomicBool::new(false)),
+            logger,
+        }
+    }
+
+    /// Compare the local clock to a reference time, measured since the unix
+    /// epoch, and update the degraded state accordingly.
+    ///
+    /// Returns the observed drift, or None if local time is not available.
+    pub fn observe(&self, reference: Duration) -> Option<ClockDrift> {
+        let local = match self.time_provider.since_epoch() {
+            Ok(local) => local,
+            Err(err) => {
+                log::warn!(self.logger, "Could not get local time: {:?}", err);
+                return None;
+            }
+        };
+
+        let drift = ClockDrift::between(local, reference);
+        OP_COUNTERS
+            .gauge("clock_drift_millis")
+            .set(drift.magnitude().as_millis() as i64);
+
+        let is_drifted = drift.magnitude() > self.max_drift;
+        let was_drifted = self.is_degraded.swap(is_drifted, Ordering::SeqCst);
+        if is_drifted {
+            log::warn!(
+                self.logger,
+                "{}, which exceeds the maximum of {:?}. Token validation may be unreliable.",
+                drift,
+                self.max_drift
+            );
+        } else if was_drifted {
+            log::info!(self.logger, "Clock drift recovered: {}", drift);
+        }
+
+        Some(drift)
+    }
+
+    /// Whether the last observed drift exceeded the threshold.
+    pub fn is_degraded(&self) -> bool {
+        self.is_degraded.load(Ordering::SeqCst)
+    }
+}
+
+impl<TP: TimeProvider + 'static> From<ClockDriftMonitor<TP>> for ServiceHealthCheckCallback {
+    fn from(src: ClockDriftMonitor<TP>) -> Self {
+        Arc::new(move |_| -> HealthCheckStatus {
+            if src.is_degraded() {
+                HealthCheckStatus::DEGRADED
+            } else {
+                HealthCheckStatus::SERVING
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mc_common::{logger::test_with_logger, time::MockTimeProvider};
+
+    const MAX_DRIFT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn drift_between() {
+        let t = Duration::from_secs(1_000);
+        assert_eq!(
+            ClockDrift::between(t + Duration::from_secs(3), t),
+            ClockDrift::Ahead(Duration::from_secs(3))
+        );
+        assert_eq!(
+            ClockDrift::between(t, t + Duration::from_secs(3)),
+            ClockDrift::Behind(Duration::from_secs(3))
+        );
+        assert_eq!(ClockDrift::between(t, t).magnitude(), Duration::ZERO);
+    }
+
+    #[test_with_logger]
+    fn drift_within_threshold_is_healthy(logger: Logger) {
+        let time_provider = MockTimeProvider::default();
+        let now = time_provider.since_epoch().unwrap();
+        let monitor = ClockDriftMonitor::new(MAX_DRIFT, time_provider, logger);
+        let callback = ServiceHealthCheckCallback::from(monitor.clone());
+
+        let drift = monitor.observe(now + Duration::from_secs(2)).unwrap();
+        assert_eq!(drift, ClockDrift::Behind(Duration::from_secs(2)));
+        assert!(!monitor.is_degraded());
+        assert_eq!(callback(""), HealthCheckStatus::SERVING);
+    }
+
+    #[test_with_logger]
+    fn large_drift_is_reported(logger: Logger) {
+        let time_provider = MockTimeProvider::default();
+        let now = time_provider.since_epoch().unwrap();
+        let monitor = ClockDriftMonitor::new(MAX_DRIFT, time_provider.clone(), logger);
+        let callback = ServiceHealthCheckCallback::from(monitor.clone());
+
+        // A reference an hour behind us means our clock is an hour ahead.
+        let drift = monitor.observe(now - Duration::from_secs(3600)).unwrap();
+        assert_eq!(drift, ClockDrift::Ahead(Duration::from_secs(3600)));
+        assert!(monitor.is_degraded());
+        assert_eq!(callback(""), HealthCheckStatus::DEGRADED);
+
+        // Once the local clock is corrected, health recovers.
+        time_provider.set_cur_since_epoch(now - Duration::from_secs(3600));
+        monitor.observe(now - Duration::from_secs(3600)).unwrap();
+        assert!(!monitor.is_degraded());
+        assert_eq!(callback(""), HealthCheckStatus::SERVING);
+    }
+}
@@ -0,0 +1,326 @@
+// Copyright (c) 2018-2022 The MobileCoin Foundation
+
+//! GRPC authenticator that accepts JWTs issued by an identity provider, signed
+//! with Ed25519 (`alg: EdDSA`).
+
+use super::*;
+
+use mc_common::time::TimeProvider;
+use mc_crypto_keys::{Ed25519Public, Ed25519Signature, Verifier};
+use serde_json::Value;
+use std::time::Duration;
+
+/// JWT-based authentication: An object that implements `Authenticator`,
+/// allowing to authenticate users presenting an `Authorization: Bearer <jwt>`
+/// header. The authenticated user is the `sub` claim of the token.
+pub struct JwtAuthenticator<TP: TimeProvider> {
+    /// The key the identity provider signs tokens with.
+    public_key: Ed25519Public,
+
+    /// The audience tokens must be issued for.
+    audience: String,
+
+    /// Time provider.
+    time_provider: TP,
+}
+
+impl<TP: TimeProvider> Authenticator for JwtAuthenticator<TP> {
+    fn authenticate(
+        &self,
+        maybe_credentials: Option<BasicCredentials>,
+    ) -> Result<String, AuthenticatorError> {
+        // JWTs are never carried as basic credentials.
+        match maybe_credentials {
+            None => Err(AuthenticatorError::Unauthenticated),
+            Some(_) => Err(AuthorizationHeaderError::UnsupportedAuthorizationMethod.into()),
+        }
+    }
+
+    fn authenticate_metadata(&self, metadata: &Metadata) -> Result<String, AuthenticatorError> {
+        let header = authorization_header(metadata)?.ok_or(AuthenticatorError::Unauthenticated)?;
+
+        self.authenticate_token(bearer_token(header)?)
+    }
+}
+
+impl<TP: TimeProvider> JwtAuthenticator<TP> {
+    /// Create a new JWT authenticator
+    ///
+    /// Arguments:
+    /// * public_key: The key the identity provider signs tokens with
+    /// * audience: The `aud` claim tokens must contain
+    /// * time_provider: A generic object that provides "Duration since the
+    ///   epoch"
+    pub fn new(public_key: Ed25519Public, audience: &str, time_provider: TP) -> Self {
+        Self {
+            public_key,
+            audience: audience.to_owned(),
+            time_provider,
+        }
+    }
+
+    /// Authenticate a compact-serialized JWT, returning its subject.
+    pub fn authenticate_token(&self, token: &str) -> Result<String, AuthenticatorError> {
+        let (signing_input, signature) = token
+            .rsplit_once('.')
+            .ok_or(AuthenticatorError::InvalidAuthorizationToken)?;
+        let (header, payload) = signing_input
+            .split_once('.')
+            .ok_or(AuthenticatorError::InvalidAuthorizationToken)?;
+        if payload.contains('.') {
+            return Err(AuthenticatorError::InvalidAuthorizationToken);
+        }
+
+        let header = decode_json(header)?;
+        if header.get("alg").and_then(Value::as_str) != Some("EdDSA") {
+            return Err(AuthenticatorError::InvalidAuthorizationToken);
+        }
+
+        let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD)
+            .ok()
+            .and_then(|bytes| Ed25519Signature::try_from(bytes).ok())
+            .ok_or(AuthenticatorError::InvalidAuthorizationToken)?;
+        self.public_key
+            .verify(signing_input.as_bytes(), &signature)
+            .map_err(|_| AuthenticatorError::InvalidAuthorizationToken)?;
+
+        let claims = decode_json(payload)?;
+        if !self.is_valid_time(&claims)? {
+            return Err(AuthenticatorError::ExpiredAuthorizationToken);
+        }
+        if !self.is_valid_audience(&claims) {
+            return Err(AuthenticatorError::InvalidAuthorizationToken);
+        }
+        claims
+            .get("sub")
+            .and_then(Value::as_str)
+            .map(str::to_owned)
+            .ok_or(AuthenticatorError::InvalidAuthorizationToken)
+    }
+
+    fn is_valid_time(&self, claims: &Value) -> Result<bool, AuthenticatorError> {
+        let expires_at =
+            numeric_date(claims, "exp")?.ok_or(AuthenticatorError::InvalidAuthorizationToken)?;
+        let not_before = numeric_date(claims, "nbf")?.unwrap_or_default();
+        let our_time = self
+            .time_provider
+            .since_epoch()
+            .map_err(|_| AuthenticatorError::ExpiredAuthorizationToken)?;
+        Ok(not_before <= our_time && our_time < expires_at)
+    }
+
+    fn is_valid_audience(&self, claims: &Value) -> bool {
+        match claims.get("aud") {
+            Some(Value::String(audience)) => *audience == self.audience,
+            Some(Value::Array(audiences)) => audiences
+                .iter()
+                .any(|audience| audience.as_str() == Some(self.audience.as_str())),
+            _ => false,
+        }
+    }
+}
+
+/// Extract the token from an HTTP Bearer Authorization header.
+fn bearer_token(header_value: &[u8]) -> Result<&str, AuthorizationHeaderError> {
+    let header = str::from_utf8(header_value)
+        .map_err(|_| AuthorizationHeaderError::InvalidAuthorizationHeader)?;
+    let mut header_parts = header.split(' ');
+
+    if "Bearer"
+        != header_parts
+            .next()
+            .ok_or(AuthorizationHeaderError::InvalidAuthorizationHeader)?
+    {
+        return Err(AuthorizationHeaderError::UnsupportedAuthorizationMethod);
+    }
+
+    match (header_parts.next(), header_parts.next()) {
+        (Some(token), None) if !token.is_empty() => Ok(token),
+        _ => Err(AuthorizationHeaderError::InvalidAuthorizationHeader),
+    }
+}
+
+/// Decode a base64url-encoded JSON object.
+fn decode_json(encoded: &str) -> Result<Value, AuthenticatorError> {
+    let bytes = base64::decode_config(encoded, base64::URL_SAFE_NO_PAD)
+        .map_err(|_| AuthenticatorError::InvalidAuthorizationToken)?;
+    match serde_json::from_slice(&bytes) {
+        Ok(value @ Value::Object(_)) => Ok(value),
+        _ => Err(AuthenticatorError::InvalidAuthorizationToken),
+    }
+}
+
+/// Read an optional NumericDate (seconds since the epoch) claim.
+fn numeric_date(claims: &Value, name: &str) -> Result<Option<Duration>, AuthenticatorError> {
+    claims
+        .get(name)
+        .map(|value| {
+            value
+                .as_u64()
+                .map(Duration::from_secs)
+                .ok_or(AuthenticatorError::InvalidAuthorizationToken)
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use grpcio::MetadataBuilder;
+    use mc_common::time::MockTimeProvider;
+    use mc_crypto_keys::{Ed25519Pair, Ed25519Private, Signer};
+    use serde_json::json;
+
+    const AUDIENCE: &str = "mobilecoin-services";
+
+    fn pair(seed: u8) -> Ed25519Pair {
+        Ed25519Pair::from(Ed25519Private::try_from(&[seed; 32][..]).unwrap())
+    }
+
+    fn encode(value: &Value) -> String {
+        base64::encode_config(value.to_string(), base64::URL_SAFE_NO_PAD)
+    }
+
+    fn sign_jwt(signer: &Ed25519Pair, claims: Value) -> String {
+        let signing_input = format!(
+            "{}.{}",
+            encode(&json!({"alg": "EdDSA", "typ": "JWT"})),
+            encode(&claims)
+        );
+        let signature = signer.sign(signing_input.as_bytes());
+        format!(
+            "{}.{}",
+            signing_input,
+            base64::encode_config(signature.as_ref(), base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    fn bearer_metadata(token: &str) -> Metadata {
+        let mut metadata_builder = MetadataBuilder::new();
+        metadata_builder
+            .add_str("Authorization", &format!("Bearer {}", token))
+            .unwrap();
+        metadata_builder.build()
+    }
+
+    fn authenticator(time_provider: &MockTimeProvider) -> JwtAuthenticator<MockTimeProvider> {
+        JwtAuthenticator::new(pair(1).public_key(), AUDIENCE, time_provider.clone())
+    }
+
+    #[test]
+    fn valid_token_authenticates_successfully() {
+        let time_provider = MockTimeProvider::default();
+        let now = time_provider.since_epoch().unwrap().as_secs();
+        let token = sign_jwt(
+            &pair(1),
+            json!({"sub": "user123", "aud": AUDIENCE, "nbf": now - 10, "exp": now + 60}),
+        );
+
+        let user = authenticator(&time_provider)
+            .authenticate_metadata(&bearer_metadata(&token))
+            .expect("authenticate failed");
+        assert_eq!(user, "user123");
+
+        // An audience list containing ours is also accepted.
+        let token = sign_jwt(
+            &pair(1),
+            json!({"sub": "user123", "aud": ["other", AUDIENCE], "exp": now + 60}),
+        );
+        assert_eq!(
+            authenticator(&time_provider).authenticate_token(&token),
+            Ok("user123".to_owned())
+        );
+    }
+
+    #[test]
+    fn expired_token_fails_authentication() {
+        let time_provider = MockTimeProvider::default();
+        let now = time_provider.since_epoch().unwrap().as_secs();
+        let authenticator = authenticator(&time_provider);
+
+        let expired = sign_jwt(
+            &pair(1),
+            json!({"sub": "user123", "aud": AUDIENCE, "exp": now - 1}),
+        );
+        assert_eq!(
+            authenticator.authenticate_metadata(&bearer_metadata(&expired)),
+            Err(AuthenticatorError::ExpiredAuthorizationToken)
+        );
+
+        let not_yet_valid = sign_jwt(
+            &pair(1),
+            json!({"sub": "user123", "aud": AUDIENCE, "nbf": now + 60, "exp": now + 120}),
+        );
+        assert_eq!(
+            authenticator.authenticate_metadata(&bearer_metadata(&not_yet_valid)),
+            Err(AuthenticatorError::ExpiredAuthorizationToken)
+        );
+    }
+
+    #[test]
+    fn wrong_audience_fails_authentication() {
+        let time_provider = MockTimeProvider::default();
+        let now = time_provider.since_epoch().unwrap().as_secs();
+        let token = sign_jwt(
+            &pair(1),
+            json!({"sub": "user123", "aud": "someone-else", "exp": now + 60}),
+        );
+
+        assert_eq!(
+            authenticator(&time_provider).authenticate_metadata(&bearer_metadata(&token)),
+            Err(AuthenticatorError::InvalidAuthorizationToken)
+        );
+    }
+
+    #[test]
+    fn wrong_key_fails_authentication() {
+        let time_provider = MockTimeProvider::default();
+        let now = time_provider.since_epoch().unwrap().as_secs();
+        let token = sign_jwt(
+            &pair(2),
+            json!({"sub": "user123", "aud": AUDIENCE, "exp": now + 60}),
+        );
+
+        assert_eq!(
+            authenticator(&time_provider).authenticate_metadata(&bearer_metadata(&token)),
+            Err(AuthenticatorError::InvalidAuthorizationToken)
+        );
+    }
+
+    #[test]
+    fn invalid_headers_fail_authentication() {
+        let authenticator = authenticator(&MockTimeProvider::default());
+
+        assert_eq!(
+            authenticator.authenticate_metadata(&MetadataBuilder::new().build()),
+            Err(AuthenticatorError::Unauthenticated)
+        );
+
+        for (header, expected) in [
+            (
+                "Basic YTpi",
+                AuthorizationHeaderError::UnsupportedAuthorizationMethod,
+            ),
+            (
+                "Bearer",
+                AuthorizationHeaderError::InvalidAuthorizationHeader,
+            ),
+            (
+                "Bearer a b",
+                AuthorizationHeaderError::InvalidAuthorizationHeader,
+            ),
+        ] {
+            let mut metadata_builder = MetadataBuilder::new();
+            metadata_builder.add_str("Authorization", header).unwrap();
+            assert_eq!(
+                authenticator.authenticate_metadata(&metadata_builder.build()),
+                Err(AuthenticatorError::AuthorizationHeader(expected))
+            );
+        }
+
+        assert_eq!(
+            authenticator.authenticate_metadata(&bearer_metadata("not.a.jwt")),
+            Err(AuthenticatorError::InvalidAuthorizationToken)
+        );
+    }
+}
@@ -0,0 +1,165 @@
+// Copyright (c) 2018-2022 The MobileCoin Foundation
+
+//! GRPC authenticator wrapper that counts authentication outcomes.
+
+use super::*;
+
+use crate::counters::OP_COUNTERS;
+use mc_util_metrics::IntCounter;
+
+/// An `Authenticator` which wraps another `Authenticator`, and counts the
+/// outcome of each authentication attempt it makes.
+///
+/// Outcomes are counted as one of:
+/// * `auth_success`: the inner authenticator accepted the credentials,
+/// * `auth_failure`: the credentials were missing, malformed, invalid or
+///   expired,
+/// * `auth_error`: the inner authenticator failed for some other reason.
+///
+/// Each counter is labeled with the name given when constructing the wrapper,
+/// which can be used to tell authenticators (or the methods they protect)
+/// apart.
+pub struct MeteredAuthenticator<A: Authenticator> {
+    /// The authenticator doing the actual work.
+    inner: A,
+
+    /// Counter of successful authentications.
+    success: IntCounter,
+
+    /// Counter of rejected credentials.
+    failure: IntCounter,
+
+    /// Counter of other authentication errors.
+    error: IntCounter,
+}
+
+impl<A: Authenticator> MeteredAuthenticator<A> {
+    /// Wrap `inner`, counting its outcomes under the label `name`.
+    pub fn new(inner: A, name: &str) -> Self {
+        Self {
+            inner,
+            success: OP_COUNTERS.peer_counter("auth_success", name),
+            failure: OP_COUNTERS.peer_counter("auth_failure", name),
+            error: OP_COUNTERS.peer_counter("auth_error", name),
+        }
+    }
+
+    /// Get a reference to the wrapped authenticator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    fn record(&self, result: &Result<String, AuthenticatorError>) {
+        match result {
+            Ok(_) => self.success.inc(),
+            Err(AuthenticatorError::Other(_)) => self.error.inc(),
+            Err(_) => self.failure.inc(),
+        }
+    }
+}
+
+impl<A: Authenticator> Authenticator for MeteredAuthenticator<A> {
+    fn authenticate(
+        &self,
+        maybe_credentials: Option<BasicCredentials>,
+    ) -> Result<String, AuthenticatorError> {
+        let result = self.inner.authenticate(maybe_credentials);
+        self.record(&result);
+        result
+    }
+
+    fn authenticate_metadata(&self, metadata: &Metadata) -> Result<String, AuthenticatorError> {
+        // Delegate to the inner authenticator, so that malformed headers are
+        // counted too, and so that any override of this method is respected.
+        let result = self.inner.authenticate_metadata(metadata);
+        self.record(&result);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AnonymousAuthenticator, TokenAuthenticator, TokenBasicCredentialsGenerator};
+    use grpcio::MetadataBuilder;
+    use mc_common::time::SystemTimeProvider;
+    use std::time::Duration;
+
+    struct ErroringAuthenticator;
+
+    impl Authenticator for ErroringAuthenticator {
+        fn authenticate(
+            &self,
+            _maybe_credentials: Option<BasicCredentials>,
+        ) -> Result<String, AuthenticatorError> {
+            Err(AuthenticatorError::Other("backend unavailable".to_owned()))
+        }
+    }
+
+    fn counts(name: &str) -> (u64, u64, u64) {
+        (
+            OP_COUNTERS.peer_counter("auth_success", name).get(),
+            OP_COUNTERS.peer_counter("auth_failure", name).get(),
+            OP_COUNTERS.peer_counter("auth_error", name).get(),
+        )
+    }
+
+    #[test]
+    fn counts_success_and_failure() {
+        const NAME: &str = "metered_token_test";
+        let shared_secret = [7; 32];
+        let authenticator = MeteredAuthenticator::new(
+            TokenAuthenticator::new(
+                shared_secret,
+                Duration::from_secs(60),
+                SystemTimeProvider::default(),
+            ),
+            NAME,
+        );
+        assert_eq!(counts(NAME), (0, 0, 0));
+
+        let generator =
+            TokenBasicCredentialsGenerator::new(shared_secret, SystemTimeProvider::default());
+        let creds = generator.generate_for("user").unwrap();
+        assert!(authenticator.authenticate(Some(creds)).is_ok());
+        assert_eq!(counts(NAME), (1, 0, 0));
+
+        assert!(authenticator.authenticate(None).is_err());
+        let bad_creds = BasicCredentials::new("user", "user:0:00");
+        assert!(authenticator.authenticate(Some(bad_creds)).is_err());
+        assert_eq!(counts(NAME), (1, 2, 0));
+
+        // A malformed header is counted as a failure.
+        let mut metadata_builder = MetadataBuilder::new();
+        metadata_builder
+            .add_str("Authorization", "NotBasic XXX")
+            .unwrap();
+        assert!(authenticator
+            .authenticate_metadata(&metadata_builder.build())
+            .is_err());
+        assert_eq!(counts(NAME), (1, 3, 0));
+    }
+
+    #[test]
+    fn counts_errors() {
+        const NAME: &str = "metered_error_test";
+        let authenticator = MeteredAuthenticator::new(ErroringAuthenticator, NAME);
+
+        assert!(authenticator.authenticate(None).is_err());
+        assert_eq!(counts(NAME), (0, 0, 1));
+    }
+
+    #[test]
+    fn counts_are_labeled_by_name() {
+        let first = MeteredAuthenticator::new(AnonymousAuthenticator, "metered_anon_first");
+        let second = MeteredAuthenticator::new(AnonymousAuthenticator, "metered_anon_second");
+
+        let metadata = MetadataBuilder::new().build();
+        assert!(first.authenticate_metadata(&metadata).is_ok());
+        assert!(first.authenticate(None).is_ok());
+        assert!(second.authenticate(None).is_ok());
+
+        assert_eq!(counts("metered_anon_first"), (2, 0, 0));
+        assert_eq!(counts("metered_anon_second"), (1, 0, 0));
+    }
+}
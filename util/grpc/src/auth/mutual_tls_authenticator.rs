@@ -0,0 +1,117 @@
+// Copyright (c) 2018-2022 The MobileCoin Foundation
+
+//! GRPC authenticator that authenticates peers by pinning the SHA-256
+//! fingerprints of their mTLS client certificates.
+
+use super::*;
+
+use std::collections::HashMap;
+
+/// Mutual-TLS authentication: An object that implements `Authenticator`,
+/// mapping an allowlist of client certificate fingerprints (see
+/// `ClientCert::fingerprint`) to the users they authenticate as.
+///
+/// This pins specific client certificates, without running a PKI to issue
+/// and revoke them. The certificates must still be accepted by the server's
+/// TLS configuration, e.g. by requesting, but not verifying, client
+/// certificates.
+#[derive(Clone, Debug, Default)]
+pub struct MutualTlsAuthenticator {
+    /// Users, by the fingerprint of the certificate they present.
+    users: HashMap<[u8; 32], String>,
+}
+
+impl MutualTlsAuthenticator {
+    /// Create an authenticator which accepts no certificates.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Authenticate peers presenting the certificate with `fingerprint` as
+    /// `user`.
+    pub fn with_fingerprint(mut self, fingerprint: [u8; 32], user: &str) -> Self {
+        self.users.insert(fingerprint, user.to_owned());
+        self
+    }
+
+    /// Authenticate a peer by the client certificate it presented, if any.
+    pub fn authenticate_cert(
+        &self,
+        cert: Option<&ClientCert>,
+    ) -> Result<String, AuthenticatorError> {
+        cert.and_then(ClientCert::fingerprint)
+            .and_then(|fingerprint| self.users.get(&fingerprint))
+            .cloned()
+            .ok_or(AuthenticatorError::Unauthenticated)
+    }
+}
+
+impl Authenticator for MutualTlsAuthenticator {
+    fn authenticate(
+        &self,
+        _maybe_credentials: Option<BasicCredentials>,
+    ) -> Result<String, AuthenticatorError> {
+        // Credentials alone never identify a certificate.
+        Err(AuthenticatorError::Unauthenticated)
+    }
+
+    fn authenticate_rpc(&self, context: &RpcContext) -> Result<String, AuthenticatorError> {
+        self.authenticate_cert(peer_certificate(context).as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mc_crypto_x509_test_vectors::{ok_self_signed_1, ok_self_signed_2};
+
+    fn client_cert(pem: String) -> ClientCert {
+        ClientCert {
+            pem,
+            identity: CertIdentity::default(),
+        }
+    }
+
+    #[test]
+    fn allowlisted_fingerprint_is_accepted() {
+        let cert = client_cert(ok_self_signed_2().0);
+        let authenticator =
+            MutualTlsAuthenticator::new().with_fingerprint(cert.fingerprint().unwrap(), "node2");
+
+        assert_eq!(
+            authenticator.authenticate_cert(Some(&cert)),
+            Ok("node2".to_owned())
+        );
+    }
+
+    #[test]
+    fn unlisted_certificate_is_rejected() {
+        let listed = client_cert(ok_self_signed_2().0);
+        let authenticator =
+            MutualTlsAuthenticator::new().with_fingerprint(listed.fingerprint().unwrap(), "node2");
+
+        let unlisted = client_cert(ok_self_signed_1().0);
+        assert_eq!(
+            authenticator.authenticate_cert(Some(&unlisted)),
+            Err(AuthenticatorError::Unauthenticated)
+        );
+    }
+
+    #[test]
+    fn plaintext_connection_is_rejected() {
+        let cert = client_cert(ok_self_signed_2().0);
+        let authenticator =
+            MutualTlsAuthenticator::new().with_fingerprint(cert.fingerprint().unwrap(), "node2");
+
+        // Plaintext connections present no certificate.
+        assert_eq!(
+            authenticator.authenticate_cert(None),
+            Err(AuthenticatorError::Unauthenticated)
+        );
+        // Nor do credentials stand in for one.
+        assert_eq!(
+            authenticator.authenticate(Some(BasicCredentials::new("node2", "password"))),
+            Err(AuthenticatorError::Unauthenticated)
+        );
+    }
+}
@@ -0,0 +1,160 @@
+// Copyright (c) 2018-2022 The MobileCoin Foundation
+
+//! GRPC authenticator wrapper that evaluates another authenticator without
+//! enforcing its decisions.
+
+use super::*;
+
+use crate::counters::OP_COUNTERS;
+use mc_common::logger::{log, Logger};
+use mc_util_metrics::IntCounter;
+
+/// An `Authenticator` which wraps another `Authenticator` in "shadow" (dry-run)
+/// mode: every request is allowed, but the wrapper logs and counts whether the
+/// inner authenticator would have rejected it. This allows measuring the
+/// would-be rejection rate of a new authenticator before enforcing it.
+///
+/// Outcomes are counted as one of:
+/// * `shadow_auth_accept`: the inner authenticator accepted the credentials,
+/// * `shadow_auth_reject`: the inner authenticator would have rejected them.
+///
+/// Each counter is labeled with the name given when constructing the wrapper.
+///
+/// Requests the inner authenticator accepts are attributed to the user it
+/// returned. All other requests are attributed to `ANONYMOUS_USER`.
+pub struct ShadowAuthenticator<A: Authenticator> {
+    /// The authenticator being evaluated.
+    inner: A,
+
+    /// The name of this wrapper, used in logs.
+    name: String,
+
+    /// Counter of credentials the inner authenticator accepted.
+    accept: IntCounter,
+
+    /// Counter of credentials the inner authenticator would have rejected.
+    reject: IntCounter,
+
+    /// Logger.
+    logger: Logger,
+}
+
+impl<A: Authenticator> ShadowAuthenticator<A> {
+    /// Wrap `inner`, counting its outcomes under the label `name`.
+    pub fn new(inner: A, name: &str, logger: Logger) -> Self {
+        Self {
+            inner,
+            name: name.to_owned(),
+            accept: OP_COUNTERS.peer_counter("shadow_auth_accept", name),
+            reject: OP_COUNTERS.peer_counter("shadow_auth_reject", name),
+            logger,
+        }
+    }
+
+    /// Get a reference to the wrapped authenticator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    fn allow(
+        &self,
+        result: Result<String, AuthenticatorError>,
+    ) -> Result<String, AuthenticatorError> {
+        match result {
+            Ok(username) => {
+                self.accept.inc();
+                Ok(username)
+            }
+            Err(err) => {
+                self.reject.inc();
+                log::info!(
+                    self.logger,
+                    "Shadow authenticator {} would have rejected request: {}",
+                    self.name,
+                    err
+                );
+                Ok(ANONYMOUS_USER.to_owned())
+            }
+        }
+    }
+}
+
+impl<A: Authenticator> Authenticator for ShadowAuthenticator<A> {
+    fn authenticate(
+        &self,
+        maybe_credentials: Option<BasicCredentials>,
+    ) -> Result<String, AuthenticatorError> {
+        self.allow(self.inner.authenticate(maybe_credentials))
+    }
+
+    fn authenticate_metadata(&self, metadata: &Metadata) -> Result<String, AuthenticatorError> {
+        // Delegate to the inner authenticator, so that malformed headers are
+        // evaluated too, and so that any override of this method is respected.
+        self.allow(self.inner.authenticate_metadata(metadata))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TokenAuthenticator, TokenBasicCredentialsGenerator};
+    use grpcio::MetadataBuilder;
+    use mc_common::{logger::test_with_logger, time::SystemTimeProvider};
+    use std::time::Duration;
+
+    fn counts(name: &str) -> (u64, u64) {
+        (
+            OP_COUNTERS.peer_counter("shadow_auth_accept", name).get(),
+            OP_COUNTERS.peer_counter("shadow_auth_reject", name).get(),
+        )
+    }
+
+    #[test_with_logger]
+    fn passes_requests_through_and_counts_rejections(logger: Logger) {
+        const NAME: &str = "shadow_token_test";
+        let shared_secret = [8; 32];
+        let authenticator = ShadowAuthenticator::new(
+            TokenAuthenticator::new(
+                shared_secret,
+                Duration::from_secs(60),
+                SystemTimeProvider::default(),
+            ),
+            NAME,
+            logger,
+        );
+        assert_eq!(counts(NAME), (0, 0));
+
+        // Valid credentials are attributed to their user.
+        let generator =
+            TokenBasicCredentialsGenerator::new(shared_secret, SystemTimeProvider::default());
+        let creds = generator.generate_for("user").unwrap();
+        assert_eq!(
+            authenticator.authenticate(Some(creds)),
+            Ok("user".to_owned())
+        );
+        assert_eq!(counts(NAME), (1, 0));
+
+        // Missing and invalid credentials are allowed, but counted.
+        assert_eq!(
+            authenticator.authenticate(None),
+            Ok(ANONYMOUS_USER.to_owned())
+        );
+        let bad_creds = BasicCredentials::new("user", "user:0:00");
+        assert_eq!(
+            authenticator.authenticate(Some(bad_creds)),
+            Ok(ANONYMOUS_USER.to_owned())
+        );
+        assert_eq!(counts(NAME), (1, 2));
+
+        // So are malformed headers.
+        let mut metadata_builder = MetadataBuilder::new();
+        metadata_builder
+            .add_str("Authorization", "NotBasic XXX")
+            .unwrap();
+        assert_eq!(
+            authenticator.authenticate_metadata(&metadata_builder.build()),
+            Ok(ANONYMOUS_USER.to_owned())
+        );
+        assert_eq!(counts(NAME), (1, 3));
+    }
+}
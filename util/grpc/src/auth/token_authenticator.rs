@@ -8,22 +8,45 @@ use super::*;
 use displaydoc::Display;
 use hmac::{Hmac, Mac};
 use mc_common::time::TimeProvider;
+use mc_crypto_keys::{Ed25519Pair, Ed25519Public, Fingerprintable};
 use sha2::Sha256;
-use std::{str, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    str,
+    sync::Mutex,
+    time::Duration,
+};
 use subtle::ConstantTimeEq;
 use zeroize::Zeroize;
 
 /// Token-based authentication: An object that implements `Authenticator`,
 /// allowing to authenticate users using HMAC-generated tokens.
+///
+/// Tokens have the form `user:timestamp:signature`, or
+/// `user:timestamp:key_id:signature` when the generator was given a key id
+/// (see `TokenBasicCredentialsGenerator::with_key_id`). Keyed tokens are
+/// verified with the secret registered for their key id, which lets secrets be
+/// rotated without downtime: register the new key id, move generators over to
+/// it, then retire the old one.
 pub struct TokenAuthenticator<TP: TimeProvider> {
     /// Secret shared between the authenticator and then token generator,
     /// allowing for generated tokens to be cryptographically-verified by
-    /// the authenticator.
+    /// the authenticator. Used for tokens without a key id.
     shared_secret: [u8; 32],
 
+    /// Secrets for tokens with a key id, by key id.
+    keyed_secrets: HashMap<String, [u8; 32]>,
+
+    /// Key ids whose tokens are rejected.
+    retired_key_ids: HashSet<String>,
+
     /// The maximum duration a token is valid for.
     max_token_lifetime: Duration,
 
+    /// The maximum difference tolerated between our clock and the clock of the
+    /// token generator, on top of `max_token_lifetime`.
+    max_clock_skew: Duration,
+
     /// Time provider.
     time_provider: TP,
 }
@@ -31,6 +54,9 @@ pub struct TokenAuthenticator<TP: TimeProvider> {
 impl<TP: TimeProvider> Drop for TokenAuthenticator<TP> {
     fn drop(&mut self) {
         self.shared_secret.zeroize();
+        for secret in self.keyed_secrets.values_mut() {
+            secret.zeroize();
+        }
     }
 }
 
@@ -40,32 +66,37 @@ impl<TP: TimeProvider> Authenticator for TokenAuthenticator<TP> {
         maybe_credentials: Option<BasicCredentials>,
     ) -> Result<String, AuthenticatorError> {
         let credentials = maybe_credentials.ok_or(AuthenticatorError::Unauthenticated)?;
-        let mut parts = credentials.password.split(':');
-        let username = parts
-            .next()
-            .ok_or(AuthenticatorError::InvalidAuthorizationToken)?;
-        let timestamp = parts
-            .next()
-            .ok_or(AuthenticatorError::InvalidAuthorizationToken)?;
-        let signature = parts
-            .next()
-            .ok_or(AuthenticatorError::InvalidAuthorizationToken)?;
-        if parts.next().is_some() {
-            return Err(AuthenticatorError::InvalidAuthorizationToken);
-        }
+        let parts = credentials.password.split(':').collect::<Vec<_>>();
+        let (username, timestamp, key_id, signature) = match parts[..] {
+            [username, timestamp, signature] => (username, timestamp, None, signature),
+            [username, timestamp, key_id, signature] => {
+                (username, timestamp, Some(key_id), signature)
+            }
+            _ => return Err(AuthenticatorError::InvalidAuthorizationToken),
+        };
         if username != credentials.username {
             return Err(AuthenticatorError::InvalidAuthorizationToken);
         }
         if !self.is_valid_time(timestamp)? {
             return Err(AuthenticatorError::ExpiredAuthorizationToken);
         }
-        if !self.is_valid_signature(&format!("{}:{}", username, timestamp), signature)? {
+        let (secret, data) = match key_id {
+            None => (&self.shared_secret, format!("{}:{}", username, timestamp)),
+            Some(key_id) => (
+                self.keyed_secret(key_id)?,
+                format!("{}:{}:{}", username, timestamp, key_id),
+            ),
+        };
+        if !is_valid_signature(secret, &data, signature)? {
             return Err(AuthenticatorError::InvalidAuthorizationToken);
         }
         Ok(credentials.username)
     }
 }
 
+/// The default for `TokenAuthenticator::max_clock_skew`.
+const DEFAULT_MAX_CLOCK_SKEW: Duration = Duration::from_secs(30);
+
 impl<TP: TimeProvider> TokenAuthenticator<TP> {
     /// Create a new Token authenticator
     ///
@@ -79,11 +110,56 @@ impl<TP: TimeProvider> TokenAuthenticator<TP> {
     pub fn new(shared_secret: [u8; 32], max_token_lifetime: Duration, time_provider: TP) -> Self {
         Self {
             shared_secret,
+            keyed_secrets: HashMap::new(),
+            retired_key_ids: HashSet::new(),
             max_token_lifetime,
+            max_clock_skew: DEFAULT_MAX_CLOCK_SKEW,
             time_provider,
         }
     }
 
+    /// Accept tokens carrying `key_id`, verifying them with `secret`.
+    ///
+    /// Key ids may not contain ':', since it separates the parts of a token.
+    pub fn with_key(mut self, key_id: &str, secret: [u8; 32]) -> Self {
+        self.retired_key_ids.remove(key_id);
+        if let Some(mut old_secret) = self.keyed_secrets.insert(key_id.to_owned(), secret) {
+            old_secret.zeroize();
+        }
+        self
+    }
+
+    /// Reject tokens carrying `key_id`, forgetting its secret if one was
+    /// registered. Such tokens fail with `RetiredTokenKeyId` rather than
+    /// `UnknownTokenKeyId`, so that stragglers still using the old key can be
+    /// told apart from garbage.
+    pub fn with_retired_key(mut self, key_id: &str) -> Self {
+        if let Some(mut secret) = self.keyed_secrets.remove(key_id) {
+            secret.zeroize();
+        }
+        self.retired_key_ids.insert(key_id.to_owned());
+        self
+    }
+
+    /// The secret for tokens carrying `key_id`.
+    fn keyed_secret(&self, key_id: &str) -> Result<&[u8; 32], AuthenticatorError> {
+        if self.retired_key_ids.contains(key_id) {
+            return Err(AuthenticatorError::RetiredTokenKeyId(key_id.to_owned()));
+        }
+        self.keyed_secrets
+            .get(key_id)
+            .ok_or_else(|| AuthenticatorError::UnknownTokenKeyId(key_id.to_owned()))
+    }
+
+    /// Set the maximum clock skew tolerated between the token generator and
+    /// us. Tokens are accepted if their timestamp is within
+    /// `max_token_lifetime + max_clock_skew` of our time, in either direction.
+    /// Defaults to 30 seconds.
+    pub fn with_max_clock_skew(mut self, max_clock_skew: Duration) -> Self {
+        self.max_clock_skew = max_clock_skew;
+        self
+    }
+
     fn is_valid_time(&self, timestamp: &str) -> Result<bool, AuthenticatorError> {
         let token_time: Duration = Duration::from_secs(
             timestamp
@@ -97,21 +173,26 @@ impl<TP: TimeProvider> TokenAuthenticator<TP> {
         let distance: Duration = our_time
             .checked_sub(token_time)
             .unwrap_or_else(|| token_time - our_time);
-        Ok(distance < self.max_token_lifetime)
+        Ok(distance < self.max_token_lifetime + self.max_clock_skew)
     }
+}
 
-    fn is_valid_signature(&self, data: &str, signature: &str) -> Result<bool, AuthenticatorError> {
-        let their_suffix: Vec<u8> =
-            hex::decode(signature).map_err(|_| AuthenticatorError::InvalidAuthorizationToken)?;
-
-        let mut mac = Hmac::<Sha256>::new_from_slice(&self.shared_secret)
-            .map_err(|_| AuthenticatorError::Other("Invalid HMAC key".to_owned()))?;
-        mac.update(data.as_bytes());
-        let our_signature = mac.finalize().into_bytes();
-
-        let our_suffix: &[u8] = &our_signature[..10];
-        Ok(bool::from(our_suffix.ct_eq(&their_suffix)))
-    }
+/// Check that `signature` is the (truncated) HMAC of `data` under `secret`.
+fn is_valid_signature(
+    secret: &[u8; 32],
+    data: &str,
+    signature: &str,
+) -> Result<bool, AuthenticatorError> {
+    let their_suffix: Vec<u8> =
+        hex::decode(signature).map_err(|_| AuthenticatorError::InvalidAuthorizationToken)?;
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret)
+        .map_err(|_| AuthenticatorError::Other("Invalid HMAC key".to_owned()))?;
+    mac.update(data.as_bytes());
+    let our_signature = mac.finalize().into_bytes();
+
+    let our_suffix: &[u8] = &our_signature[..10];
+    Ok(bool::from(our_suffix.ct_eq(&their_suffix)))
 }
 
 /// Error values for token generator.
@@ -122,12 +203,47 @@ pub enum TokenBasicCredentialsGeneratorError {
 
     /// Invalid HMAC key
     InvalidHmacKey,
+
+    /// The generator has no identity
+    NoIdentity,
+
+    /// Could not fingerprint the identity key
+    InvalidIdentityKey,
+
+    /// Key ids may not be empty or contain ':'
+    InvalidKeyId,
+}
+
+/// The token identity of a node, derived from its Ed25519 identity key.
+///
+/// This is the hex-encoded SHA-256 fingerprint of the key, without the byte
+/// separators, since ':' is not allowed in token identities.
+pub fn ed25519_token_identity(
+    public_key: &Ed25519Public,
+) -> Result<String, TokenBasicCredentialsGeneratorError> {
+    Ok(public_key
+        .fingerprint::<Sha256>()
+        .map_err(|_| TokenBasicCredentialsGeneratorError::InvalidIdentityKey)?
+        .replace(':', ""))
 }
 
 /// Token generator - an object that can generate HMAC authentication tokens.
 pub struct TokenBasicCredentialsGenerator<TP: TimeProvider> {
     shared_secret: [u8; 32],
     time_provider: TP,
+    identity: Option<String>,
+    key_id: Option<String>,
+    cache: Option<TokenCache>,
+}
+
+/// Previously generated credentials, reused until they are close to expiring.
+struct TokenCache {
+    /// How long after generation credentials are reused for.
+    reuse_for: Duration,
+
+    /// The most recent credentials for each user id, with the time (in
+    /// seconds since the epoch) they were generated at.
+    credentials: Mutex<HashMap<String, (u64, BasicCredentials)>>,
 }
 
 impl<TP: TimeProvider> TokenBasicCredentialsGenerator<TP> {
@@ -141,7 +257,77 @@ impl<TP: TimeProvider> TokenBasicCredentialsGenerator<TP> {
         Self {
             shared_secret,
             time_provider,
+            identity: None,
+            key_id: None,
+            cache: None,
+        }
+    }
+
+    /// Create a new token credential generator, whose identity is derived
+    /// from a node's Ed25519 identity key (see `ed25519_token_identity`), so
+    /// that authenticators can tie its tokens to a known node.
+    ///
+    /// Arguments:
+    /// * shared_secret: The shared secret used as hmac key
+    /// * identity_key: The node's identity key
+    /// * time_provider: A generic object that provides "Duration since the
+    ///   epoch"
+    pub fn with_ed25519_identity(
+        shared_secret: [u8; 32],
+        identity_key: &Ed25519Pair,
+        time_provider: TP,
+    ) -> Result<Self, TokenBasicCredentialsGeneratorError> {
+        Ok(Self {
+            shared_secret,
+            time_provider,
+            identity: Some(ed25519_token_identity(&identity_key.public_key())?),
+            key_id: None,
+            cache: None,
+        })
+    }
+
+    /// Embed `key_id` in generated tokens, so that authenticators verify them
+    /// with the secret they registered for that id (see
+    /// `TokenAuthenticator::with_key`).
+    pub fn with_key_id(
+        mut self,
+        key_id: &str,
+    ) -> Result<Self, TokenBasicCredentialsGeneratorError> {
+        if key_id.is_empty() || key_id.contains(':') {
+            return Err(TokenBasicCredentialsGeneratorError::InvalidKeyId);
         }
+        self.key_id = Some(key_id.to_owned());
+        Ok(self)
+    }
+
+    /// Reuse generated credentials instead of generating new ones for every
+    /// call, until `refresh_before` before they expire. `max_token_lifetime`
+    /// should match the authenticator's (see `TokenAuthenticator::new`).
+    pub fn with_cache(mut self, max_token_lifetime: Duration, refresh_before: Duration) -> Self {
+        self.cache = Some(TokenCache {
+            reuse_for: max_token_lifetime.saturating_sub(refresh_before),
+            credentials: Mutex::new(HashMap::new()),
+        });
+        self
+    }
+
+    /// The key id embedded in generated tokens, if any.
+    pub fn key_id(&self) -> Option<&str> {
+        self.key_id.as_deref()
+    }
+
+    /// The identity this generator generates tokens for, if any.
+    pub fn identity(&self) -> Option<&str> {
+        self.identity.as_deref()
+    }
+
+    /// Generate a token for this generator's identity.
+    pub fn generate(&self) -> Result<BasicCredentials, TokenBasicCredentialsGeneratorError> {
+        let identity = self
+            .identity
+            .as_deref()
+            .ok_or(TokenBasicCredentialsGeneratorError::NoIdentity)?;
+        self.generate_for(identity)
     }
 
     /// Generate a token for a user-id
@@ -154,7 +340,32 @@ impl<TP: TimeProvider> TokenBasicCredentialsGenerator<TP> {
             .since_epoch()
             .map_err(|_| TokenBasicCredentialsGeneratorError::TimeProvider)?
             .as_secs();
-        let prefix = format!("{}:{}", user_id, current_time_seconds);
+
+        let cache = match &self.cache {
+            None => return self.generate_at(user_id, current_time_seconds),
+            Some(cache) => cache,
+        };
+        let mut credentials = cache.credentials.lock().expect("mutex poisoned");
+        if let Some((generated_at, creds)) = credentials.get(user_id) {
+            let age = current_time_seconds.checked_sub(*generated_at);
+            if age.map_or(false, |age| age < cache.reuse_for.as_secs()) {
+                return Ok(creds.clone());
+            }
+        }
+        let creds = self.generate_at(user_id, current_time_seconds)?;
+        credentials.insert(user_id.to_owned(), (current_time_seconds, creds.clone()));
+        Ok(creds)
+    }
+
+    fn generate_at(
+        &self,
+        user_id: &str,
+        current_time_seconds: u64,
+    ) -> Result<BasicCredentials, TokenBasicCredentialsGeneratorError> {
+        let prefix = match &self.key_id {
+            None => format!("{}:{}", user_id, current_time_seconds),
+            Some(key_id) => format!("{}:{}:{}", user_id, current_time_seconds, key_id),
+        };
 
         let mut mac = Hmac::<Sha256>::new_from_slice(&self.shared_secret)
             .map_err(|_| TokenBasicCredentialsGeneratorError::InvalidHmacKey)?;
@@ -163,12 +374,7 @@ impl<TP: TimeProvider> TokenBasicCredentialsGenerator<TP> {
 
         Ok(BasicCredentials::new(
             user_id,
-            &format!(
-                "{}:{}:{}",
-                user_id,
-                current_time_seconds,
-                hex::encode(&signature[..10])
-            ),
+            &format!("{}:{}", prefix, hex::encode(&signature[..10])),
         ))
     }
 }
@@ -184,6 +390,7 @@ mod tests {
     use super::*;
     const TOKEN_MAX_LIFETIME: Duration = Duration::from_secs(60);
     use mc_common::time::{MockTimeProvider, SystemTimeProvider};
+    use mc_crypto_keys::Ed25519Private;
 
     #[test]
     fn valid_token_authenticates_successfully() {
@@ -240,12 +447,52 @@ mod tests {
         );
     }
 
+    #[test]
+    fn cached_credentials_are_reused_until_refresh() {
+        const TEST_USERNAME: &str = "test user";
+        const REFRESH_BEFORE: Duration = Duration::from_secs(10);
+
+        let time_provider = MockTimeProvider::default();
+        let start = Duration::from_secs(time_provider.since_epoch().unwrap().as_secs());
+        time_provider.set_cur_since_epoch(start);
+        let generator = TokenBasicCredentialsGenerator::new([5; 32], time_provider.clone())
+            .with_cache(TOKEN_MAX_LIFETIME, REFRESH_BEFORE);
+
+        // Every token embeds the time it was generated at, so each distinct
+        // password is one token computation.
+        let mut generated = HashSet::new();
+        for secs in 0..(TOKEN_MAX_LIFETIME - REFRESH_BEFORE).as_secs() {
+            time_provider.set_cur_since_epoch(start + Duration::from_secs(secs));
+            for _ in 0..10 {
+                let creds = generator.generate_for(TEST_USERNAME).unwrap();
+                generated.insert(creds.password().to_owned());
+            }
+        }
+        assert_eq!(generated.len(), 1);
+
+        time_provider.set_cur_since_epoch(start + TOKEN_MAX_LIFETIME - REFRESH_BEFORE);
+        for _ in 0..10 {
+            let creds = generator.generate_for(TEST_USERNAME).unwrap();
+            generated.insert(creds.password().to_owned());
+        }
+        assert_eq!(generated.len(), 2);
+
+        // Cached credentials still authenticate.
+        let authenticator =
+            TokenAuthenticator::new([5; 32], TOKEN_MAX_LIFETIME, time_provider.clone());
+        assert_eq!(
+            authenticator.authenticate(Some(generator.generate_for(TEST_USERNAME).unwrap())),
+            Ok(TEST_USERNAME.to_owned())
+        );
+    }
+
     #[test]
     fn is_valid_time_rejects_expired() {
         let time_provider = MockTimeProvider::default();
 
         let authenticator =
-            TokenAuthenticator::new([4; 32], TOKEN_MAX_LIFETIME, time_provider.clone());
+            TokenAuthenticator::new([4; 32], TOKEN_MAX_LIFETIME, time_provider.clone())
+                .with_max_clock_skew(Duration::ZERO);
 
         // Initially, we should be valid.
         let now_in_seconds = time_provider.since_epoch().unwrap().as_secs();
@@ -261,4 +508,179 @@ mod tests {
             .is_valid_time(&now_in_seconds.to_string())
             .unwrap());
     }
+
+    #[test]
+    fn tokens_within_clock_skew_authenticate() {
+        let shared_secret = [3; 32];
+        const TEST_USERNAME: &str = "test user";
+        const MAX_CLOCK_SKEW: Duration = Duration::from_secs(30);
+
+        let generator_time = MockTimeProvider::default();
+        let generator = TokenBasicCredentialsGenerator::new(shared_secret, generator_time.clone());
+        // Tokens carry whole seconds, so keep our time whole too.
+        let authenticator_time = MockTimeProvider::default();
+        let now = Duration::from_secs(authenticator_time.since_epoch().unwrap().as_secs());
+        authenticator_time.set_cur_since_epoch(now);
+        let authenticator =
+            TokenAuthenticator::new(shared_secret, TOKEN_MAX_LIFETIME, authenticator_time)
+                .with_max_clock_skew(MAX_CLOCK_SKEW);
+
+        let authenticate_at = |generated_at: Duration| {
+            generator_time.set_cur_since_epoch(generated_at);
+            authenticator.authenticate(Some(generator.generate_for(TEST_USERNAME).unwrap()))
+        };
+
+        // Generated slightly in the future, beyond the lifetime but within skew.
+        let future = now + TOKEN_MAX_LIFETIME + Duration::from_secs(20);
+        assert_eq!(authenticate_at(future), Ok(TEST_USERNAME.to_owned()));
+
+        // Slightly expired, but within skew.
+        let expired = now - TOKEN_MAX_LIFETIME - Duration::from_secs(20);
+        assert_eq!(authenticate_at(expired), Ok(TEST_USERNAME.to_owned()));
+
+        // Outside of the skew in either direction.
+        let far_future = now + TOKEN_MAX_LIFETIME + MAX_CLOCK_SKEW;
+        assert_eq!(
+            authenticate_at(far_future),
+            Err(AuthenticatorError::ExpiredAuthorizationToken)
+        );
+        let long_expired = now - TOKEN_MAX_LIFETIME - MAX_CLOCK_SKEW;
+        assert_eq!(
+            authenticate_at(long_expired),
+            Err(AuthenticatorError::ExpiredAuthorizationToken)
+        );
+    }
+
+    #[test]
+    fn default_clock_skew_tolerates_slightly_expired_tokens() {
+        let time_provider = MockTimeProvider::default();
+        let authenticator =
+            TokenAuthenticator::new([4; 32], TOKEN_MAX_LIFETIME, time_provider.clone());
+
+        let now_in_seconds = time_provider.since_epoch().unwrap().as_secs();
+        let expired = time_provider.since_epoch().unwrap() + TOKEN_MAX_LIFETIME;
+        time_provider.set_cur_since_epoch(expired);
+        assert!(authenticator
+            .is_valid_time(&now_in_seconds.to_string())
+            .unwrap());
+
+        time_provider.set_cur_since_epoch(expired + DEFAULT_MAX_CLOCK_SKEW);
+        assert!(!authenticator
+            .is_valid_time(&now_in_seconds.to_string())
+            .unwrap());
+    }
+
+    #[test]
+    fn ed25519_identity_token_authenticates_successfully() {
+        let shared_secret = [3; 32];
+        let identity_key = Ed25519Pair::from(Ed25519Private::try_from(&[9u8; 32][..]).unwrap());
+
+        let generator = TokenBasicCredentialsGenerator::with_ed25519_identity(
+            shared_secret,
+            &identity_key,
+            SystemTimeProvider::default(),
+        )
+        .unwrap();
+        let authenticator = TokenAuthenticator::new(
+            shared_secret,
+            TOKEN_MAX_LIFETIME,
+            SystemTimeProvider::default(),
+        );
+
+        let fingerprint = identity_key
+            .public_key()
+            .fingerprint::<Sha256>()
+            .unwrap()
+            .replace(':', "");
+        assert_eq!(generator.identity(), Some(fingerprint.as_str()));
+
+        let creds = generator.generate().unwrap();
+        assert_eq!(creds.username, fingerprint);
+        let user = authenticator
+            .authenticate(Some(creds))
+            .expect("authenticate failed");
+        assert_eq!(user, fingerprint);
+
+        // A generator without an identity can only generate for explicit users.
+        let anonymous =
+            TokenBasicCredentialsGenerator::new(shared_secret, SystemTimeProvider::default());
+        assert_eq!(anonymous.identity(), None);
+        assert!(matches!(
+            anonymous.generate(),
+            Err(TokenBasicCredentialsGeneratorError::NoIdentity)
+        ));
+    }
+
+    #[test]
+    fn keyed_tokens_are_verified_by_key_id() {
+        const TEST_USERNAME: &str = "test user";
+        let old_secret = [5; 32];
+        let new_secret = [6; 32];
+
+        let old_generator =
+            TokenBasicCredentialsGenerator::new(old_secret, SystemTimeProvider::default())
+                .with_key_id("2022-01")
+                .unwrap();
+        let new_generator =
+            TokenBasicCredentialsGenerator::new(new_secret, SystemTimeProvider::default())
+                .with_key_id("2022-02")
+                .unwrap();
+        assert_eq!(new_generator.key_id(), Some("2022-02"));
+
+        // During rotation, both key ids are active, and unkeyed tokens still
+        // use the shared secret.
+        let authenticator =
+            TokenAuthenticator::new([3; 32], TOKEN_MAX_LIFETIME, SystemTimeProvider::default())
+                .with_key("2022-01", old_secret)
+                .with_key("2022-02", new_secret);
+        for generator in [&old_generator, &new_generator] {
+            let creds = generator.generate_for(TEST_USERNAME).unwrap();
+            assert_eq!(
+                authenticator.authenticate(Some(creds)),
+                Ok(TEST_USERNAME.to_owned())
+            );
+        }
+        let unkeyed = TokenBasicCredentialsGenerator::new([3; 32], SystemTimeProvider::default());
+        assert_eq!(
+            authenticator.authenticate(Some(unkeyed.generate_for(TEST_USERNAME).unwrap())),
+            Ok(TEST_USERNAME.to_owned())
+        );
+
+        // A token can't be moved to another key id.
+        let creds = old_generator.generate_for(TEST_USERNAME).unwrap();
+        let forged = BasicCredentials::new(
+            TEST_USERNAME,
+            &creds.password().replace(":2022-01:", ":2022-02:"),
+        );
+        assert_eq!(
+            authenticator.authenticate(Some(forged)),
+            Err(AuthenticatorError::InvalidAuthorizationToken)
+        );
+
+        // Once the old key id is retired, its tokens are rejected.
+        let authenticator = authenticator.with_retired_key("2022-01");
+        assert_eq!(
+            authenticator.authenticate(Some(old_generator.generate_for(TEST_USERNAME).unwrap())),
+            Err(AuthenticatorError::RetiredTokenKeyId("2022-01".to_owned()))
+        );
+        assert_eq!(
+            authenticator.authenticate(Some(new_generator.generate_for(TEST_USERNAME).unwrap())),
+            Ok(TEST_USERNAME.to_owned())
+        );
+
+        // Key ids the authenticator never heard of are rejected.
+        let unknown =
+            TokenBasicCredentialsGenerator::new(new_secret, SystemTimeProvider::default())
+                .with_key_id("2022-03")
+                .unwrap();
+        assert_eq!(
+            authenticator.authenticate(Some(unknown.generate_for(TEST_USERNAME).unwrap())),
+            Err(AuthenticatorError::UnknownTokenKeyId("2022-03".to_owned()))
+        );
+
+        assert!(matches!(
+            unkeyed.with_key_id("bad:id"),
+            Err(TokenBasicCredentialsGeneratorError::InvalidKeyId)
+        ));
+    }
 }
@@ -4,11 +4,12 @@
 
 use crate::{
     build_info::BuildInfo,
-    build_info_grpc::{create_build_info_api, BuildInfoApi},
+    build_info_grpc::{create_build_info_api, BuildInfoApi, BuildInfoApiClient},
     empty::Empty,
     rpc_logger, send_result,
 };
-use grpcio::{RpcContext, Service, UnarySink};
+use displaydoc::Display;
+use grpcio::{Channel, RpcContext, Service, UnarySink};
 use mc_common::logger::Logger;
 use mc_util_metrics::SVC_COUNTERS;
 
@@ -44,9 +45,50 @@ pub fn get_build_info() -> BuildInfo {
     build_info.set_rustflags(::mc_util_build_info::rustflags().to_owned());
     build_info.set_sgx_mode(::mc_util_build_info::sgx_mode().to_owned());
     build_info.set_ias_mode(::mc_util_build_info::ias_mode().to_owned());
+    build_info.set_git_dirty(::mc_util_build_info::git_dirty());
     build_info
 }
 
+/// An error returned by `require_build`.
+#[derive(Debug, Display)]
+pub enum BuildMismatch {
+    /// Expected the server to run git commit {expected}, but it runs {actual}
+    GitCommit {
+        /// The git commit that was expected
+        expected: String,
+        /// The git commit the server reported
+        actual: String,
+    },
+
+    /// Could not get the server's build info: {0}
+    Grpc(grpcio::Error),
+}
+
+impl From<grpcio::Error> for BuildMismatch {
+    fn from(src: grpcio::Error) -> Self {
+        Self::Grpc(src)
+    }
+}
+
+impl std::error::Error for BuildMismatch {}
+
+/// Check that the server at the other end of `channel` runs the build with the
+/// given git commit, as reported by its `BuildInfoService`.
+///
+/// The commit is compared verbatim to the `git_commit` of the server's build
+/// info, which is the output of `git describe --always --dirty=-modified` at
+/// build time, so builds of a dirty working tree never match a clean commit.
+pub fn require_build(channel: Channel, expected_git_commit: &str) -> Result<(), BuildMismatch> {
+    let build_info = BuildInfoApiClient::new(channel).get_build_info(&Empty::new())?;
+    if build_info.get_git_commit() != expected_git_commit {
+        return Err(BuildMismatch::GitCommit {
+            expected: expected_git_commit.to_owned(),
+            actual: build_info.get_git_commit().to_owned(),
+        });
+    }
+    Ok(())
+}
+
 impl BuildInfoApi for BuildInfoService {
     fn get_build_info(&mut self, ctx: RpcContext, _req: Empty, sink: UnarySink<BuildInfo>) {
         let _timer = SVC_COUNTERS.req(&ctx);
@@ -54,3 +96,93 @@ impl BuildInfoApi for BuildInfoService {
         send_result(ctx, sink, Ok(get_build_info()), &logger);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use grpcio::{ChannelBuilder, EnvBuilder, ServerBuilder};
+    use mc_common::logger::test_with_logger;
+    use std::sync::Arc;
+
+    /// A build info service which reports a fixed git commit.
+    #[derive(Clone)]
+    struct MockBuildInfoService {
+        git_commit: String,
+        logger: Logger,
+    }
+
+    impl BuildInfoApi for MockBuildInfoService {
+        fn get_build_info(&mut self, ctx: RpcContext, _req: Empty, sink: UnarySink<BuildInfo>) {
+            let mut build_info = BuildInfo::new();
+            build_info.set_git_commit(self.git_commit.clone());
+            send_result(ctx, sink, Ok(build_info), &self.logger);
+        }
+    }
+
+    fn serve(service: Service) -> (grpcio::Server, Channel) {
+        let env = Arc::new(EnvBuilder::new().build());
+        let mut server = ServerBuilder::new(env.clone())
+            .register_service(service)
+            .bind("127.0.0.1", 0)
+            .build()
+            .unwrap();
+        server.start();
+        let port = server.bind_addrs().next().unwrap().1;
+        let channel = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+        (server, channel)
+    }
+
+    #[test_with_logger]
+    fn require_build_compares_git_commit(logger: Logger) {
+        let (_server, channel) = serve(create_build_info_api(MockBuildInfoService {
+            git_commit: "v1.2.0-3-gabc1234".to_owned(),
+            logger,
+        }));
+
+        require_build(channel.clone(), "v1.2.0-3-gabc1234").expect("build should match");
+
+        match require_build(channel.clone(), "v1.2.0-3-gabc1234-modified") {
+            Err(BuildMismatch::GitCommit { expected, actual }) => {
+                assert_eq!(expected, "v1.2.0-3-gabc1234-modified");
+                assert_eq!(actual, "v1.2.0-3-gabc1234");
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        assert!(matches!(
+            require_build(channel, "deadbeef"),
+            Err(BuildMismatch::GitCommit { .. })
+        ));
+    }
+
+    #[test]
+    fn require_build_reports_grpc_errors() {
+        // Nothing listens on a port which was just released.
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let env = Arc::new(EnvBuilder::new().build());
+        let channel = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+
+        assert!(matches!(
+            require_build(channel, mc_util_build_info::git_commit()),
+            Err(BuildMismatch::Grpc(_))
+        ));
+    }
+
+    #[test_with_logger]
+    fn response_reports_git_dirty(logger: Logger) {
+        let (_server, channel) = serve(BuildInfoService::new(logger).into_service());
+        let client = BuildInfoApiClient::new(channel);
+        let build_info = client.get_build_info(&Empty::new()).unwrap();
+        assert_eq!(
+            build_info.get_git_commit(),
+            mc_util_build_info::git_commit()
+        );
+        assert_eq!(build_info.get_git_dirty(), mc_util_build_info::git_dirty());
+        if mc_util_build_info::git_commit() == "??????" {
+            assert!(!build_info.get_git_dirty());
+        }
+    }
+}
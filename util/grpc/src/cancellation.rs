@@ -0,0 +1,376 @@
+// Copyright (c) 2018-2022 The MobileCoin Foundation
+
+//! Cooperative cancellation of server work, tied to client timeouts.
+//!
+//! A client sets a timeout on a call using `CallOptionTimeout::with_timeout`.
+//! grpc propagates it to the server as the call's deadline, which handlers
+//! observe through a `CancellationToken`, so that they can stop working once
+//! the client has given up on the response. A `DeadlineLimit` additionally
+//! bounds how long handlers work for clients which set no deadline, or an
+//! unreasonably long one.
+
+use grpcio::{CallOption, Deadline, RpcContext, RpcStatus, RpcStatusCode};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// Extension trait for setting a timeout on client calls.
+pub trait CallOptionTimeout {
+    /// Give up on the call after `timeout`. The server sees this as the call's
+    /// deadline, and `CancellationToken::from_rpc_context` fires once it has
+    /// passed.
+    fn with_timeout(self, timeout: Duration) -> Self;
+}
+
+impl CallOptionTimeout for CallOption {
+    fn with_timeout(self, timeout: Duration) -> Self {
+        self.timeout(timeout)
+    }
+}
+
+/// What to do with calls whose deadline is missing or beyond a
+/// `DeadlineLimit`'s maximum, once the maximum has passed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DeadlinePolicy {
+    /// Treat the call as if its deadline were the maximum, failing it with
+    /// `DEADLINE_EXCEEDED`.
+    Clamp,
+    /// Fail the call with `INVALID_ARGUMENT`, telling the client that its
+    /// deadline is not acceptable.
+    Reject,
+}
+
+/// A maximum on how long handlers work on a call, whatever its deadline.
+///
+/// grpcio doesn't expose how far away a call's deadline is, only whether it
+/// has passed, so a call can't be turned away on arrival because of its
+/// deadline. Instead, handlers stop working on the call once `max` has
+/// passed, even if the client set no deadline, or a later one, and fail it
+/// according to the `DeadlinePolicy`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DeadlineLimit {
+    /// The longest a call may run for.
+    max: Duration,
+
+    /// How to fail calls which would run for longer.
+    policy: DeadlinePolicy,
+}
+
+impl DeadlineLimit {
+    /// Create a limit of `max`, enforced according to `policy`.
+    pub fn new(max: Duration, policy: DeadlinePolicy) -> Self {
+        Self { max, policy }
+    }
+
+    /// The longest a call may run for.
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// How calls which would run for longer than `max` are failed.
+    pub fn policy(&self) -> DeadlinePolicy {
+        self.policy
+    }
+
+    /// Create a token for the call `ctx` belongs to, which fires once the
+    /// call's own deadline has passed, or once `max` has passed from now,
+    /// whichever comes first.
+    ///
+    /// Handlers enforce the limit by calling this on entry, and checking the
+    /// token as they work.
+    pub fn apply(&self, ctx: &RpcContext) -> CancellationToken {
+        CancellationToken::from_rpc_context(ctx).with_deadline_limit(self)
+    }
+
+    /// The status reported by tokens once the limit has passed.
+    fn exceeded_status(&self) -> RpcStatus {
+        match self.policy {
+            DeadlinePolicy::Clamp => RpcStatus::with_message(
+                RpcStatusCode::DEADLINE_EXCEEDED,
+                format!("Deadline limit of {:?} exceeded", self.max),
+            ),
+            DeadlinePolicy::Reject => RpcStatus::with_message(
+                RpcStatusCode::INVALID_ARGUMENT,
+                format!("A deadline of at most {:?} is required", self.max),
+            ),
+        }
+    }
+}
+
+/// A token which long-running handlers can poll to find out whether they
+/// should stop working.
+///
+/// The token fires when `cancel` is called on it (or on any of its clones), or
+/// when the deadline of the call it was created for passes.
+#[derive(Clone)]
+pub struct CancellationToken {
+    /// Whether `cancel` was called.
+    cancelled: Arc<AtomicBool>,
+
+    /// The deadline of the call this token was created for, if any.
+    deadline: Option<Deadline>,
+
+    /// When the token's `DeadlineLimit` passes, if it has one.
+    limit: Option<(Instant, DeadlineLimit)>,
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CancellationToken {
+    /// Create a token which only fires when cancelled explicitly.
+    pub fn new() -> Self {
+        Self {
+            cancelled: Arc::new(AtomicBool::new(false)),
+            deadline: None,
+            limit: None,
+        }
+    }
+
+    /// Create a token which fires once the deadline of the call `ctx` belongs
+    /// to has passed, i.e. once the client has stopped waiting for a response.
+    pub fn from_rpc_context(ctx: &RpcContext) -> Self {
+        Self {
+            cancelled: Arc::new(AtomicBool::new(false)),
+            deadline: Some(ctx.deadline()),
+            limit: None,
+        }
+    }
+
+    /// Also fire the token once `limit.max()` has passed from now, see
+    /// `DeadlineLimit::apply`.
+    pub fn with_deadline_limit(mut self, limit: &DeadlineLimit) -> Self {
+        self.limit = Some((Instant::now() + limit.max(), *limit));
+        self
+    }
+
+    /// Whether the token's `DeadlineLimit` has passed.
+    fn limit_exceeded(&self) -> Option<&DeadlineLimit> {
+        self.limit
+            .as_ref()
+            .filter(|(expires_at, _)| Instant::now() >= *expires_at)
+            .map(|(_, limit)| limit)
+    }
+
+    /// Fire the token.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the token has fired.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+            || self.deadline.map_or(false, |deadline| deadline.exceeded())
+            || self.limit_exceeded().is_some()
+    }
+
+    /// Return an error if the token has fired, for use with `?` in handlers.
+    /// The error is `DEADLINE_EXCEEDED` if the deadline passed, depends on
+    /// the policy if the `DeadlineLimit` passed, and is `CANCELLED` otherwise.
+    pub fn check(&self) -> Result<(), RpcStatus> {
+        if self.deadline.map_or(false, |deadline| deadline.exceeded()) {
+            return Err(RpcStatus::with_message(
+                RpcStatusCode::DEADLINE_EXCEEDED,
+                "Deadline exceeded".to_owned(),
+            ));
+        }
+        if let Some(limit) = self.limit_exceeded() {
+            return Err(limit.exceeded_status());
+        }
+        if self.cancelled.load(Ordering::SeqCst) {
+            return Err(RpcStatus::with_message(
+                RpcStatusCode::CANCELLED,
+                "Cancelled".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{send_result, test_fixtures::TestHealth, HealthClient, PingRequest, PingResponse};
+    use grpcio::Server;
+    use mc_common::logger::{test_with_logger, Logger};
+    use std::{
+        sync::{
+            mpsc::{channel, Receiver},
+            Mutex,
+        },
+        thread,
+        time::Instant,
+    };
+
+    /// How long the slow handler works for, if not cancelled.
+    const WORK_DURATION: Duration = Duration::from_secs(10);
+
+    /// Start a server whose pings work until done or cancelled, honoring
+    /// `limit`, returning it along with a client, and a receiver of how long
+    /// pings worked for.
+    fn start_slow_server(
+        limit: Option<DeadlineLimit>,
+        logger: Logger,
+    ) -> (Server, HealthClient, Receiver<Duration>) {
+        let (worked_for, worked_for_receiver) = channel();
+        let worked_for = Mutex::new(worked_for);
+        let ping_logger = logger.clone();
+
+        let (server, client) = TestHealth::new(logger)
+            .with_ping(move |ctx, mut req, sink| {
+                let token = match limit {
+                    Some(limit) => limit.apply(&ctx),
+                    None => CancellationToken::from_rpc_context(&ctx),
+                };
+                let start = Instant::now();
+                let result = loop {
+                    if let Err(err) = token.check() {
+                        break Err(err);
+                    }
+                    if start.elapsed() >= WORK_DURATION {
+                        let mut resp = PingResponse::new();
+                        resp.set_data(req.take_data());
+                        break Ok(resp);
+                    }
+                    thread::sleep(Duration::from_millis(10));
+                };
+                worked_for.lock().unwrap().send(start.elapsed()).unwrap();
+                send_result(ctx, sink, result, &ping_logger);
+            })
+            .start();
+        (server, client, worked_for_receiver)
+    }
+
+    /// Ping with `call_option`, expecting the call to fail, and return the
+    /// status it failed with.
+    fn ping_failure(client: &HealthClient, call_option: CallOption) -> RpcStatus {
+        match client.ping_opt(&PingRequest::new(), call_option) {
+            Err(grpcio::Error::RpcFailure(status)) => status,
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    /// Wait for the handler to report how long it worked for, and check that
+    /// it stopped promptly, rather than doing all of its work.
+    fn assert_stopped_promptly(worked_for_receiver: &Receiver<Duration>) {
+        let worked_for = worked_for_receiver
+            .recv_timeout(Duration::from_secs(5))
+            .expect("handler did not stop");
+        assert!(worked_for < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn explicit_cancellation_is_shared_by_clones() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        assert!(!clone.is_cancelled());
+        assert!(clone.check().is_ok());
+
+        token.cancel();
+        assert!(clone.is_cancelled());
+        assert_eq!(clone.check().unwrap_err().code(), RpcStatusCode::CANCELLED);
+    }
+
+    #[test]
+    fn deadline_limit_fires_token() {
+        for policy in [DeadlinePolicy::Clamp, DeadlinePolicy::Reject] {
+            let generous = DeadlineLimit::new(Duration::from_secs(60), policy);
+            let token = CancellationToken::new().with_deadline_limit(&generous);
+            assert!(!token.is_cancelled());
+            assert!(token.check().is_ok());
+        }
+
+        let clamp = DeadlineLimit::new(Duration::ZERO, DeadlinePolicy::Clamp);
+        let token = CancellationToken::new().with_deadline_limit(&clamp);
+        assert!(token.is_cancelled());
+        assert_eq!(
+            token.check().unwrap_err().code(),
+            RpcStatusCode::DEADLINE_EXCEEDED
+        );
+
+        let reject = DeadlineLimit::new(Duration::ZERO, DeadlinePolicy::Reject);
+        let token = CancellationToken::new().with_deadline_limit(&reject);
+        assert!(token.is_cancelled());
+        assert_eq!(
+            token.check().unwrap_err().code(),
+            RpcStatusCode::INVALID_ARGUMENT
+        );
+    }
+
+    #[test_with_logger]
+    fn missing_deadline_is_clamped(logger: Logger) {
+        let limit = DeadlineLimit::new(Duration::from_millis(200), DeadlinePolicy::Clamp);
+        let (_server, client, worked_for_receiver) = start_slow_server(Some(limit), logger);
+
+        let status = ping_failure(&client, CallOption::default());
+        assert_eq!(status.code(), RpcStatusCode::DEADLINE_EXCEEDED);
+        assert!(status.message().contains("Deadline limit"), "{:?}", status);
+        assert_stopped_promptly(&worked_for_receiver);
+    }
+
+    #[test_with_logger]
+    fn missing_deadline_is_rejected(logger: Logger) {
+        let limit = DeadlineLimit::new(Duration::from_millis(200), DeadlinePolicy::Reject);
+        let (_server, client, worked_for_receiver) = start_slow_server(Some(limit), logger);
+
+        let status = ping_failure(&client, CallOption::default());
+        assert_eq!(status.code(), RpcStatusCode::INVALID_ARGUMENT);
+        assert_stopped_promptly(&worked_for_receiver);
+    }
+
+    #[test_with_logger]
+    fn over_long_deadline_is_clamped(logger: Logger) {
+        let limit = DeadlineLimit::new(Duration::from_millis(200), DeadlinePolicy::Clamp);
+        let (_server, client, worked_for_receiver) = start_slow_server(Some(limit), logger);
+
+        let hours = Duration::from_secs(3 * 60 * 60);
+        let status = ping_failure(&client, CallOption::default().with_timeout(hours));
+        assert_eq!(status.code(), RpcStatusCode::DEADLINE_EXCEEDED);
+        assert!(status.message().contains("Deadline limit"), "{:?}", status);
+        assert_stopped_promptly(&worked_for_receiver);
+    }
+
+    #[test_with_logger]
+    fn over_long_deadline_is_rejected(logger: Logger) {
+        let limit = DeadlineLimit::new(Duration::from_millis(200), DeadlinePolicy::Reject);
+        let (_server, client, worked_for_receiver) = start_slow_server(Some(limit), logger);
+
+        let hours = Duration::from_secs(3 * 60 * 60);
+        let status = ping_failure(&client, CallOption::default().with_timeout(hours));
+        assert_eq!(status.code(), RpcStatusCode::INVALID_ARGUMENT);
+        assert_stopped_promptly(&worked_for_receiver);
+    }
+
+    #[test_with_logger]
+    fn reasonable_deadline_is_unchanged(logger: Logger) {
+        for policy in [DeadlinePolicy::Clamp, DeadlinePolicy::Reject] {
+            let limit = DeadlineLimit::new(Duration::from_secs(60), policy);
+            let (_server, client, worked_for_receiver) =
+                start_slow_server(Some(limit), logger.clone());
+
+            // The client's own deadline passes well before the limit.
+            let timeout = Duration::from_millis(200);
+            let status = ping_failure(&client, CallOption::default().with_timeout(timeout));
+            assert_eq!(status.code(), RpcStatusCode::DEADLINE_EXCEEDED);
+            assert!(!status.message().contains("Deadline limit"), "{:?}", status);
+            assert_stopped_promptly(&worked_for_receiver);
+        }
+    }
+
+    #[test_with_logger]
+    fn client_timeout_cancels_server_work(logger: Logger) {
+        let (_server, client, worked_for_receiver) = start_slow_server(None, logger);
+
+        let timeout = Duration::from_millis(200);
+        let status = ping_failure(&client, CallOption::default().with_timeout(timeout));
+        assert_eq!(status.code(), RpcStatusCode::DEADLINE_EXCEEDED);
+        assert_stopped_promptly(&worked_for_receiver);
+    }
+}
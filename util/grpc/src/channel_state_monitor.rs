@@ -0,0 +1,240 @@
+// Copyright (c) 2018-2022 The MobileCoin Foundation
+
+//! A background monitor that exports a grpcio channel's connectivity state as
+//! a gauge, making connection churn visible in metrics.
+
+use crate::counters::OP_COUNTERS;
+use futures::executor::block_on;
+use grpcio::{Channel, ConnectivityState};
+use mc_common::logger::{log, Logger};
+use mc_util_metrics::IntGauge;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{Builder as ThreadBuilder, JoinHandle},
+    time::Duration,
+};
+
+/// How long to wait for a state change before checking whether we were asked
+/// to stop.
+const STATE_CHANGE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Records the connectivity state of a channel into a gauge labeled by the
+/// channel's target.
+///
+/// The gauge holds the numeric value of the grpc-core connectivity state:
+/// IDLE = 0, CONNECTING = 1, READY = 2, TRANSIENT_FAILURE = 3, SHUTDOWN = 4.
+#[derive(Clone)]
+pub struct ChannelStateGauge {
+    gauge: IntGauge,
+}
+
+impl ChannelStateGauge {
+    /// Create a gauge for the given channel target (e.g. "host:port").
+    pub fn new(target: &str) -> Self {
+        Self {
+            gauge: OP_COUNTERS.peer_gauge("channel_connectivity_state", target),
+        }
+    }
+
+    /// Record an observed connectivity state.
+    pub fn record(&self, state: ConnectivityState) {
+        self.gauge.set(state as i64);
+    }
+
+    /// Get the most recently recorded state value.
+    pub fn get(&self) -> i64 {
+        self.gauge.get()
+    }
+}
+
+/// A background thread that subscribes to connectivity state changes of a
+/// channel and keeps a `ChannelStateGauge` up to date.
+pub struct ChannelStateMonitor {
+    /// Join handle used to wait for the thread to terminate.
+    join_handle: Option<JoinHandle<()>>,
+
+    /// Stop request trigger, used to signal the thread to stop.
+    stop_requested: Arc<AtomicBool>,
+}
+
+impl ChannelStateMonitor {
+    /// Start monitoring `channel`, exporting its state under `target`.
+    pub fn start(channel: Channel, target: &str, logger: Logger) -> Self {
+        let gauge = ChannelStateGauge::new(target);
+        Self::start_with_observer(channel, target, move |state| gauge.record(state), logger)
+    }
+
+    /// Start monitoring `channel`, passing every observed state to `observer`.
+    /// The current state is observed before this returns.
+    fn start_with_observer(
+        channel: Channel,
+        target: &str,
+        observer: impl Fn(ConnectivityState) + Send + 'static,
+        logger: Logger,
+    ) -> Self {
+        let stop_requested = Arc::new(AtomicBool::new(false));
+        let thread_stop_requested = stop_requested.clone();
+        let target = target.to_owned();
+
+        let mut state = channel.check_connectivity_state(false);
+        observer(state);
+
+        let join_handle = Some(
+            ThreadBuilder::new()
+                .name(format!("ChannelStateMonitor:{}", target))
+                .spawn(move || {
+                    while !thread_stop_requested.load(Ordering::SeqCst) {
+                        if !block_on(
+                            channel.wait_for_state_change(state, STATE_CHANGE_POLL_INTERVAL),
+                        ) {
+                            continue;
+                        }
+
+                        state = channel.check_connectivity_state(false);
+                        observer(state);
+                        log::trace!(logger, "Channel to {} is now {:?}", target, state);
+
+                        if state == ConnectivityState::GRPC_CHANNEL_SHUTDOWN {
+                            break;
+                        }
+                    }
+                })
+                .expect("Could not spawn thread"),
+        );
+
+        Self {
+            join_handle,
+            stop_requested,
+        }
+    }
+
+    /// Stop and join the monitor thread.
+    pub fn stop(&mut self) -> Result<(), ()> {
+        if let Some(join_handle) = self.join_handle.take() {
+            self.stop_requested.store(true, Ordering::SeqCst);
+            join_handle.join().map_err(|_| ())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for ChannelStateMonitor {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use grpcio::{ChannelBuilder, EnvBuilder};
+    use mc_common::logger::test_with_logger;
+    use std::{
+        net::TcpListener,
+        sync::Mutex,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    /// Wait up to `timeout` for `condition` to hold.
+    fn wait_until(timeout: Duration, condition: impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + timeout;
+        while !condition() {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+
+    #[test_with_logger]
+    fn monitor_records_transitions_until_dropped(logger: Logger) {
+        // A port which accepts one connection, stalls long enough for the
+        // channel to be seen CONNECTING, and then closes, so that connecting
+        // fails.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = format!("127.0.0.1:{}", listener.local_addr().unwrap().port());
+        let closer = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_millis(500));
+            drop(stream);
+            drop(listener);
+        });
+
+        let env = Arc::new(EnvBuilder::new().build());
+        let channel = ChannelBuilder::new(env).connect(&target);
+
+        let states = Arc::new(Mutex::new(Vec::new()));
+        let observed = states.clone();
+        let gauge = ChannelStateGauge::new(&target);
+        let monitor = ChannelStateMonitor::start_with_observer(
+            channel.clone(),
+            &target,
+            move |state| {
+                gauge.record(state);
+                observed.lock().unwrap().push(state);
+            },
+            logger,
+        );
+
+        // Nothing connects until asked to.
+        channel.check_connectivity_state(true);
+        assert!(wait_until(Duration::from_secs(10), || states
+            .lock()
+            .unwrap()
+            .contains(&ConnectivityState::GRPC_CHANNEL_TRANSIENT_FAILURE)));
+        closer.join().unwrap();
+
+        let recorded = states.lock().unwrap().clone();
+        assert_eq!(
+            recorded[..3],
+            [
+                ConnectivityState::GRPC_CHANNEL_IDLE,
+                ConnectivityState::GRPC_CHANNEL_CONNECTING,
+                ConnectivityState::GRPC_CHANNEL_TRANSIENT_FAILURE,
+            ]
+        );
+
+        // Dropping the monitor joins its thread, which releases the observer.
+        drop(monitor);
+        assert_eq!(Arc::strong_count(&states), 1);
+        let recorded = states.lock().unwrap().len();
+        channel.check_connectivity_state(true);
+        thread::sleep(STATE_CHANGE_POLL_INTERVAL * 2);
+        assert_eq!(states.lock().unwrap().len(), recorded);
+    }
+
+    #[test]
+    fn gauge_tracks_state_transitions() {
+        let gauge = ChannelStateGauge::new("test-target:1234");
+
+        for (state, expected) in [
+            (ConnectivityState::GRPC_CHANNEL_IDLE, 0),
+            (ConnectivityState::GRPC_CHANNEL_CONNECTING, 1),
+            (ConnectivityState::GRPC_CHANNEL_TRANSIENT_FAILURE, 3),
+            (ConnectivityState::GRPC_CHANNEL_CONNECTING, 1),
+            (ConnectivityState::GRPC_CHANNEL_READY, 2),
+            (ConnectivityState::GRPC_CHANNEL_SHUTDOWN, 4),
+        ] {
+            gauge.record(state);
+            assert_eq!(gauge.get(), expected);
+        }
+    }
+
+    #[test]
+    fn gauges_are_labeled_by_target() {
+        let gauge1 = ChannelStateGauge::new("target-a:1");
+        let gauge2 = ChannelStateGauge::new("target-b:2");
+
+        gauge1.record(ConnectivityState::GRPC_CHANNEL_READY);
+        gauge2.record(ConnectivityState::GRPC_CHANNEL_TRANSIENT_FAILURE);
+
+        assert_eq!(gauge1.get(), 2);
+        assert_eq!(gauge2.get(), 3);
+    }
+}
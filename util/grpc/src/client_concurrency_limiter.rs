@@ -0,0 +1,228 @@
+// Copyright (c) 2018-2022 The MobileCoin Foundation
+
+//! Per-client limiting of the number of in-flight calls a server handles.
+
+use crate::{
+    concurrency_limiter::{
+        send_admitted_result, InFlightCounter, InFlightPermit, DEFAULT_RETRY_AFTER,
+    },
+    rpc_resource_exhausted_error_with_retry, RPC_LOGGER_CLIENT_ID_SEED,
+};
+use grpcio::{RpcContext, RpcStatus, UnarySink};
+use mc_common::logger::Logger;
+use std::time::Duration;
+
+/// Caps the number of calls a single client may have in flight at once,
+/// across all of its connections and all methods.
+///
+/// Clients are identified by a hash of their address without its port, seeded
+/// like the `rpc_client_id` of `rpc_logger`, so that one client can't get
+/// around the cap by opening more connections. This complements the
+/// per-method `ConcurrencyLimiter`, which bounds the total load, by keeping a
+/// single client from using all of it.
+///
+/// Cloning a limiter yields a handle to the same in-flight counts.
+#[derive(Clone)]
+pub struct ClientConcurrencyLimiter {
+    /// The maximum number of in-flight calls per client.
+    limit: usize,
+
+    /// Number of in-flight calls, per client id.
+    in_flight: InFlightCounter,
+
+    /// How long rejected clients are asked to wait before retrying.
+    retry_after: Duration,
+}
+
+impl ClientConcurrencyLimiter {
+    /// Create a limiter allowing each client `limit` calls in flight.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            in_flight: Default::default(),
+            retry_after: DEFAULT_RETRY_AFTER,
+        }
+    }
+
+    /// Ask rejected clients to wait `retry_after` before retrying.
+    pub fn with_retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = retry_after;
+        self
+    }
+
+    /// How long rejected clients are asked to wait before retrying.
+    pub fn retry_after(&self) -> Duration {
+        self.retry_after
+    }
+
+    /// The maximum number of in-flight calls per client.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// The hashed id of the client a call comes from.
+    pub fn client_id(ctx: &RpcContext) -> String {
+        client_id_for_peer(&ctx.peer())
+    }
+
+    /// The number of calls from `client_id` currently in flight.
+    pub fn in_flight(&self, client_id: &str) -> usize {
+        self.in_flight.in_flight(client_id)
+    }
+
+    /// Try to admit a call from `client_id`. The call counts as in flight
+    /// until the returned permit is dropped.
+    pub fn try_acquire(&self, client_id: &str) -> Option<ClientConcurrencyPermit> {
+        self.in_flight
+            .try_acquire(client_id, self.limit)
+            .map(|permit| ClientConcurrencyPermit { _permit: permit })
+    }
+
+    /// Try to admit the call `ctx` belongs to, or produce the
+    /// `RESOURCE_EXHAUSTED` status to reject it with.
+    pub fn acquire(
+        &self,
+        ctx: &RpcContext,
+        logger: &Logger,
+    ) -> Result<ClientConcurrencyPermit, RpcStatus> {
+        let client_id = Self::client_id(ctx);
+        self.try_acquire(&client_id).ok_or_else(|| {
+            rpc_resource_exhausted_error_with_retry(
+                format!("client {}", client_id),
+                format!("more than {} calls in flight", self.limit),
+                self.retry_after,
+                logger,
+            )
+        })
+    }
+
+    /// Like `send_result`, but `handler` is only run if the call is admitted,
+    /// and the call counts as in flight while it runs. Otherwise, the call is
+    /// rejected with `RESOURCE_EXHAUSTED`.
+    pub fn send_result<T: protobuf::Message>(
+        &self,
+        ctx: RpcContext,
+        sink: UnarySink<T>,
+        handler: impl FnOnce() -> Result<T, RpcStatus>,
+        logger: &Logger,
+    ) {
+        let admitted = self.acquire(&ctx, logger);
+        send_admitted_result(ctx, sink, admitted, handler, logger)
+    }
+}
+
+/// Proof that a call was admitted by a `ClientConcurrencyLimiter`. The call
+/// stops counting as in flight when this is dropped.
+pub struct ClientConcurrencyPermit {
+    _permit: InFlightPermit,
+}
+
+/// Hash a peer address, e.g. `ipv4:10.0.0.1:53210`, without its port.
+fn client_id_for_peer(peer: &str) -> String {
+    let host = peer.rsplit_once(':').map_or(peer, |(host, _port)| host);
+    let hash = mc_common::fast_hash(format!("{}{}", *RPC_LOGGER_CLIENT_ID_SEED, host).as_bytes());
+    hex_fmt::HexFmt(hash).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_fixtures::slow_admitting_health, HealthCheckRequest, HealthClient, PingRequest,
+        RpcStatusDetails,
+    };
+    use futures::executor::block_on;
+    use grpcio::{ChannelBuilder, EnvBuilder, RpcStatusCode};
+    use mc_common::logger::test_with_logger;
+    use std::sync::Arc;
+
+    #[test]
+    fn client_ids_ignore_ports() {
+        assert_eq!(
+            client_id_for_peer("ipv4:10.0.0.1:53210"),
+            client_id_for_peer("ipv4:10.0.0.1:53211")
+        );
+        assert_eq!(
+            client_id_for_peer("ipv6:[::1]:53210"),
+            client_id_for_peer("ipv6:[::1]:443")
+        );
+        assert_ne!(
+            client_id_for_peer("ipv4:10.0.0.1:53210"),
+            client_id_for_peer("ipv4:10.0.0.2:53210")
+        );
+    }
+
+    #[test]
+    fn saturated_client_does_not_affect_others() {
+        let limiter = ClientConcurrencyLimiter::new(2);
+        let busy = client_id_for_peer("ipv4:10.0.0.1:1000");
+        let other = client_id_for_peer("ipv4:10.0.0.2:1000");
+
+        let first = limiter.try_acquire(&busy).unwrap();
+        let _second = limiter.try_acquire(&busy).unwrap();
+        assert!(limiter.try_acquire(&busy).is_none());
+        assert_eq!(limiter.in_flight(&busy), 2);
+
+        // Another client still gets its full allowance.
+        let _other_first = limiter.try_acquire(&other).unwrap();
+        let _other_second = limiter.clone().try_acquire(&other).unwrap();
+        assert!(limiter.try_acquire(&other).is_none());
+
+        // Releasing a permit frees up a slot for its own client.
+        drop(first);
+        assert_eq!(limiter.in_flight(&busy), 1);
+        assert!(limiter.try_acquire(&busy).is_some());
+    }
+
+    #[test_with_logger]
+    fn rejects_client_calls_over_the_cap(logger: Logger) {
+        const LIMIT: usize = 2;
+        let limiter = ClientConcurrencyLimiter::new(LIMIT);
+        assert_eq!(limiter.retry_after(), DEFAULT_RETRY_AFTER);
+
+        let (server, first_client) = {
+            let limiter = limiter.clone();
+            let admit_logger = logger.clone();
+            slow_admitting_health(
+                Duration::from_secs(1),
+                move |ctx: &RpcContext| limiter.acquire(ctx, &admit_logger),
+                logger,
+            )
+            .start()
+        };
+        let port = server.bind_addrs().next().unwrap().1;
+
+        // Calls over two connections from the same host share one cap.
+        let env = Arc::new(EnvBuilder::new().build());
+        let channel = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+        let clients = [first_client, HealthClient::new(channel)];
+        let calls = (0..=LIMIT)
+            .map(|i| clients[i % 2].ping_async(&PingRequest::new()).unwrap())
+            .collect::<Vec<_>>();
+        let codes = calls
+            .into_iter()
+            .map(|call| match block_on(call) {
+                Ok(_) => RpcStatusCode::OK,
+                Err(grpcio::Error::RpcFailure(status)) => {
+                    // Rejections tell the client when to retry.
+                    if status.code() == RpcStatusCode::RESOURCE_EXHAUSTED {
+                        let details = RpcStatusDetails::from_rpc_status(&status).unwrap();
+                        assert_eq!(details.retry_delay(), Some(DEFAULT_RETRY_AFTER));
+                    }
+                    status.code()
+                }
+                Err(err) => panic!("unexpected error: {}", err),
+            })
+            .collect::<Vec<_>>();
+
+        let rejected = codes
+            .iter()
+            .filter(|code| **code == RpcStatusCode::RESOURCE_EXHAUSTED)
+            .count();
+        assert_eq!(rejected, 1);
+
+        // Once the slow calls are done, new calls are admitted again.
+        assert!(clients[0].ping(&PingRequest::new()).is_ok());
+        assert!(clients[1].check(&HealthCheckRequest::new()).is_ok());
+    }
+}
@@ -0,0 +1,292 @@
+// Copyright (c) 2018-2022 The MobileCoin Foundation
+
+//! Per-method limiting of the number of in-flight calls a server handles.
+
+use crate::{rpc_resource_exhausted_error_with_retry, send_result};
+use grpcio::{RpcContext, RpcStatus, UnarySink};
+use mc_common::logger::Logger;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// How long clients are asked to wait before retrying a rejected call, unless
+/// configured otherwise.
+pub(crate) const DEFAULT_RETRY_AFTER: Duration = Duration::from_millis(100);
+
+/// Counts in-flight calls per key, e.g. per method or per client, and admits
+/// new calls while their key is under a limit. This is the core shared by
+/// `ConcurrencyLimiter` and `ClientConcurrencyLimiter`.
+///
+/// Cloning a counter yields a handle to the same counts.
+#[derive(Clone, Default)]
+pub(crate) struct InFlightCounter {
+    /// Number of in-flight calls, per key. Keys without calls in flight are
+    /// removed, so that the map doesn't grow with every key ever seen.
+    counts: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl InFlightCounter {
+    /// The number of calls for `key` currently in flight.
+    pub fn in_flight(&self, key: &str) -> usize {
+        let counts = self.counts.lock().expect("mutex poisoned");
+        counts.get(key).copied().unwrap_or(0)
+    }
+
+    /// Try to admit a call for `key`, if fewer than `limit` are in flight.
+    /// The call counts as in flight until the returned permit is dropped.
+    pub fn try_acquire(&self, key: &str, limit: usize) -> Option<InFlightPermit> {
+        let mut counts = self.counts.lock().expect("mutex poisoned");
+        let count = counts.entry(key.to_owned()).or_insert(0);
+        if *count >= limit {
+            return None;
+        }
+        *count += 1;
+        Some(InFlightPermit {
+            key: key.to_owned(),
+            counts: self.counts.clone(),
+        })
+    }
+}
+
+/// Proof that a call was admitted by an `InFlightCounter`. The call stops
+/// counting as in flight when this is dropped.
+pub(crate) struct InFlightPermit {
+    key: String,
+    counts: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl Drop for InFlightPermit {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock().expect("mutex poisoned");
+        if let Some(count) = counts.get_mut(&self.key) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                counts.remove(&self.key);
+            }
+        }
+    }
+}
+
+/// Like `send_result`, but `handler` is only run if the call was `admitted`,
+/// while its permit is held. Otherwise, the call is rejected with the given
+/// status.
+pub(crate) fn send_admitted_result<T: protobuf::Message, P>(
+    ctx: RpcContext,
+    sink: UnarySink<T>,
+    admitted: Result<P, RpcStatus>,
+    handler: impl FnOnce() -> Result<T, RpcStatus>,
+    logger: &Logger,
+) {
+    let resp = admitted.and_then(|_permit| handler());
+    send_result(ctx, sink, resp, logger)
+}
+
+/// Caps the number of calls to each method that may be in flight at once.
+///
+/// Methods are identified by their full path, as reported by
+/// `RpcContext::method`, e.g. `/grpc.health.v1.Health/Ping`. Calls over the
+/// limit are rejected with `RESOURCE_EXHAUSTED`, carrying a `RetryInfo` which
+/// asks the client to wait before retrying. Since rejections are sent
+/// using `send_result`, they are counted by `SVC_COUNTERS` like any other
+/// response.
+///
+/// Cloning a limiter yields a handle to the same limits and in-flight counts,
+/// so it can be shared between the services (and clones of services) of a
+/// server.
+#[derive(Clone)]
+pub struct ConcurrencyLimiter {
+    /// Per-method limits.
+    limits: Arc<HashMap<&'static str, usize>>,
+
+    /// Limit for methods not in `limits`.
+    default_limit: usize,
+
+    /// Number of in-flight calls, per method.
+    in_flight: InFlightCounter,
+
+    /// How long rejected clients are asked to wait before retrying.
+    retry_after: Duration,
+}
+
+impl ConcurrencyLimiter {
+    /// Create a new limiter.
+    ///
+    /// Arguments:
+    /// * limits: The maximum number of in-flight calls, per method path
+    /// * default_limit: The maximum number of in-flight calls for methods not
+    ///   in `limits`
+    pub fn new(limits: HashMap<&'static str, usize>, default_limit: usize) -> Self {
+        Self {
+            limits: Arc::new(limits),
+            default_limit,
+            in_flight: Default::default(),
+            retry_after: DEFAULT_RETRY_AFTER,
+        }
+    }
+
+    /// Ask rejected clients to wait `retry_after` before retrying.
+    pub fn with_retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = retry_after;
+        self
+    }
+
+    /// How long rejected clients are asked to wait before retrying.
+    pub fn retry_after(&self) -> Duration {
+        self.retry_after
+    }
+
+    /// The maximum number of in-flight calls to `method`.
+    pub fn limit(&self, method: &str) -> usize {
+        self.limits
+            .get(method)
+            .copied()
+            .unwrap_or(self.default_limit)
+    }
+
+    /// The number of calls to `method` currently in flight.
+    pub fn in_flight(&self, method: &str) -> usize {
+        self.in_flight.in_flight(method)
+    }
+
+    /// Try to admit a call to `method`. The call counts as in flight until the
+    /// returned permit is dropped.
+    pub fn try_acquire(&self, method: &str) -> Option<ConcurrencyPermit> {
+        self.in_flight
+            .try_acquire(method, self.limit(method))
+            .map(|permit| ConcurrencyPermit { _permit: permit })
+    }
+
+    /// Try to admit the call `ctx` belongs to, or produce the
+    /// `RESOURCE_EXHAUSTED` status to reject it with.
+    ///
+    /// This is useful for handlers which finish their work asynchronously, and
+    /// so need to hold on to the permit.
+    pub fn acquire(
+        &self,
+        ctx: &RpcContext,
+        logger: &Logger,
+    ) -> Result<ConcurrencyPermit, RpcStatus> {
+        let method = String::from_utf8_lossy(ctx.method());
+        self.try_acquire(&method).ok_or_else(|| {
+            rpc_resource_exhausted_error_with_retry(
+                &method,
+                format!("more than {} calls in flight", self.limit(&method)),
+                self.retry_after,
+                logger,
+            )
+        })
+    }
+
+    /// Like `send_result`, but `handler` is only run if the call is admitted,
+    /// and the call counts as in flight while it runs. Otherwise, the call is
+    /// rejected with `RESOURCE_EXHAUSTED`.
+    pub fn send_result<T: protobuf::Message>(
+        &self,
+        ctx: RpcContext,
+        sink: UnarySink<T>,
+        handler: impl FnOnce() -> Result<T, RpcStatus>,
+        logger: &Logger,
+    ) {
+        let admitted = self.acquire(&ctx, logger);
+        send_admitted_result(ctx, sink, admitted, handler, logger)
+    }
+}
+
+/// Proof that a call was admitted by a `ConcurrencyLimiter`. The call stops
+/// counting as in flight when this is dropped.
+pub struct ConcurrencyPermit {
+    _permit: InFlightPermit,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_fixtures::slow_admitting_health, HealthCheckRequest, PingRequest, RpcStatusDetails,
+    };
+    use futures::executor::block_on;
+    use grpcio::RpcStatusCode;
+    use mc_common::logger::test_with_logger;
+
+    const PING_PATH: &str = "/grpc.health.v1.Health/Ping";
+    const CHECK_PATH: &str = "/grpc.health.v1.Health/Check";
+
+    #[test]
+    fn permits_are_limited_per_method() {
+        let mut limits = HashMap::new();
+        limits.insert(PING_PATH, 2);
+        let limiter = ConcurrencyLimiter::new(limits, 1);
+        assert_eq!(limiter.limit(PING_PATH), 2);
+        assert_eq!(limiter.limit(CHECK_PATH), 1);
+
+        let first = limiter.try_acquire(PING_PATH).unwrap();
+        let _second = limiter.try_acquire(PING_PATH).unwrap();
+        assert!(limiter.try_acquire(PING_PATH).is_none());
+        assert_eq!(limiter.in_flight(PING_PATH), 2);
+
+        // Other methods are limited separately.
+        let _check = limiter.try_acquire(CHECK_PATH).unwrap();
+        assert!(limiter.try_acquire(CHECK_PATH).is_none());
+
+        // Dropping a permit frees up a slot, including for clones.
+        drop(first);
+        assert_eq!(limiter.in_flight(PING_PATH), 1);
+        assert!(limiter.clone().try_acquire(PING_PATH).is_some());
+    }
+
+    #[test_with_logger]
+    fn rejects_calls_over_the_limit(logger: Logger) {
+        const LIMIT: usize = 3;
+        let mut limits = HashMap::new();
+        limits.insert(PING_PATH, LIMIT);
+        let limiter =
+            ConcurrencyLimiter::new(limits, 1).with_retry_after(Duration::from_millis(250));
+
+        let (_server, client) = {
+            let limiter = limiter.clone();
+            let admit_logger = logger.clone();
+            slow_admitting_health(
+                Duration::from_secs(1),
+                move |ctx: &RpcContext| limiter.acquire(ctx, &admit_logger),
+                logger,
+            )
+            .start()
+        };
+
+        let calls = (0..=LIMIT)
+            .map(|_| client.ping_async(&PingRequest::new()).unwrap())
+            .collect::<Vec<_>>();
+        let codes = calls
+            .into_iter()
+            .map(|call| match block_on(call) {
+                Ok(_) => RpcStatusCode::OK,
+                Err(grpcio::Error::RpcFailure(status)) => {
+                    // Rejections tell the client when to retry.
+                    if status.code() == RpcStatusCode::RESOURCE_EXHAUSTED {
+                        let details = RpcStatusDetails::from_rpc_status(&status).unwrap();
+                        assert_eq!(details.retry_delay(), Some(Duration::from_millis(250)));
+                    }
+                    status.code()
+                }
+                Err(err) => panic!("unexpected error: {}", err),
+            })
+            .collect::<Vec<_>>();
+
+        let rejected = codes
+            .iter()
+            .filter(|code| **code == RpcStatusCode::RESOURCE_EXHAUSTED)
+            .count();
+        let succeeded = codes
+            .iter()
+            .filter(|code| **code == RpcStatusCode::OK)
+            .count();
+        assert_eq!((succeeded, rejected), (LIMIT, 1));
+        assert_eq!(limiter.in_flight(PING_PATH), 0);
+
+        // Once the slow calls are done, new calls are admitted again.
+        assert!(client.ping(&PingRequest::new()).is_ok());
+        assert!(client.check(&HealthCheckRequest::new()).is_ok());
+    }
+}
@@ -1,9 +1,14 @@
 // Copyright (c) 2018-2022 The MobileCoin Foundation
 
-use cookie::{Cookie, CookieJar, ParseError};
+use cookie::{time::OffsetDateTime, Cookie, CookieJar, ParseError, SameSite};
 use displaydoc::Display;
 use grpcio::{Error as GrpcioError, Metadata, MetadataBuilder};
-use std::string::FromUtf8Error;
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    string::FromUtf8Error,
+};
 
 /// Errors which can occur while parsing or printing cookies.
 #[derive(Debug, Display, Eq, PartialEq)]
@@ -14,6 +19,10 @@ pub enum Error {
     Parse(ParseError),
     /// There was an error building metadata from cookie storage: {0}
     Builder(String),
+    /// Invalid SameSite value '{0}', expected Strict, Lax or None
+    InvalidSameSite(String),
+    /// There was an error reading or writing saved cookies: {0}
+    Io(String),
 }
 
 impl From<ParseError> for Error {
@@ -28,12 +37,64 @@ impl From<FromUtf8Error> for Error {
     }
 }
 
+impl From<io::Error> for Error {
+    fn from(src: io::Error) -> Error {
+        Error::Io(src.to_string())
+    }
+}
+
 impl From<GrpcioError> for Error {
     fn from(src: GrpcioError) -> Error {
         Error::Builder(src.to_string())
     }
 }
 
+/// Parse a `SameSite` attribute value, ignoring case.
+pub fn parse_same_site(value: &str) -> Result<SameSite, Error> {
+    match value.to_ascii_lowercase().as_str() {
+        "strict" => Ok(SameSite::Strict),
+        "lax" => Ok(SameSite::Lax),
+        "none" => Ok(SameSite::None),
+        _ => Err(Error::InvalidSameSite(value.to_owned())),
+    }
+}
+
+/// Attributes to set on cookies sent to clients, for the sake of browsers and
+/// proxies between us and them.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CookieAttributes {
+    /// The `SameSite` attribute, if any.
+    pub same_site: Option<SameSite>,
+
+    /// Whether to set the `Secure` flag.
+    pub secure: bool,
+}
+
+impl CookieAttributes {
+    /// Set the `SameSite` attribute.
+    pub fn with_same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    /// Set whether to set the `Secure` flag.
+    pub fn with_secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// Apply these attributes to a cookie. Attributes which are not
+    /// configured are left as they are.
+    pub fn apply(&self, cookie: &mut Cookie) {
+        if let Some(same_site) = self.same_site {
+            cookie.set_same_site(same_site);
+        }
+        if self.secure {
+            cookie.set_secure(true);
+        }
+    }
+}
+
 /// A trait used to monkey-patch helper methods onto the `cookie::CookieJar`
 /// type.
 pub trait GrpcCookieStore {
@@ -48,6 +109,32 @@ pub trait GrpcCookieStore {
     /// Copy the contents of this CookieJar into a Metadata structure containing
     /// any `Cookie` headers to send to a server.
     fn to_client_metadata(&self) -> Result<MetadataBuilder, Error>;
+
+    /// Copy the contents of this CookieJar into a Metadata structure containing
+    /// `Set-Cookie` headers to send to a client, with the given attributes
+    /// applied to each cookie.
+    fn to_server_metadata(&self, attributes: &CookieAttributes) -> Result<MetadataBuilder, Error>;
+
+    /// Save the cookies in this CookieJar to a file, one `Set-Cookie` value
+    /// per line, so that a later process can resume the session. On unix, the
+    /// file is only readable by its owner.
+    ///
+    /// Expired cookies are not saved, and `Max-Age` is converted to an
+    /// absolute expiry, since it is relative to when the cookie was received.
+    fn save_to_path(&self, path: &Path) -> Result<(), Error>;
+
+    /// Load cookies saved with `save_to_path`, dropping any that have expired
+    /// since.
+    fn load_from_path(path: &Path) -> Result<Self, Error>
+    where
+        Self: Sized;
+}
+
+/// Whether `cookie` has an expiry, and it has passed.
+fn is_expired(cookie: &Cookie, now: OffsetDateTime) -> bool {
+    cookie
+        .expires_datetime()
+        .map_or(false, |expires| expires <= now)
 }
 
 fn append_to_cookies(dest: &mut Vec<Cookie>, src: Option<&Metadata>) -> Result<(), Error> {
@@ -87,4 +174,167 @@ impl GrpcCookieStore for CookieJar {
 
         Ok(builder)
     }
+
+    fn to_server_metadata(&self, attributes: &CookieAttributes) -> Result<MetadataBuilder, Error> {
+        let mut builder = MetadataBuilder::new();
+
+        for cookie in self.iter() {
+            let mut cookie = cookie.clone();
+            attributes.apply(&mut cookie);
+            builder.add_str("Set-Cookie", cookie.to_string().as_str())?;
+        }
+
+        Ok(builder)
+    }
+
+    fn save_to_path(&self, path: &Path) -> Result<(), Error> {
+        let now = OffsetDateTime::now_utc();
+        let mut contents = String::new();
+        for cookie in self.iter() {
+            let mut cookie = cookie.clone();
+            if let Some(max_age) = cookie.max_age() {
+                cookie.set_expires(now + max_age);
+                cookie.unset_max_age();
+            }
+            if is_expired(&cookie, now) {
+                continue;
+            }
+            contents.push_str(&cookie.to_string());
+            contents.push('\n');
+        }
+
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+            options.mode(0o600);
+            // The mode only applies to newly created files.
+            if path.exists() {
+                fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+            }
+        }
+        let mut file = options.open(path)?;
+        file.write_all(contents.as_bytes())?;
+        Ok(())
+    }
+
+    fn load_from_path(path: &Path) -> Result<Self, Error> {
+        let now = OffsetDateTime::now_utc();
+        let mut jar = CookieJar::new();
+        for line in fs::read_to_string(path)?.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let cookie = Cookie::parse(line.to_owned())?;
+            if !is_expired(&cookie, now) {
+                jar.add_original(cookie);
+            }
+        }
+        Ok(jar)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Send the cookies in `jar` to a client with the given attributes, and
+    /// return the client's jar.
+    fn round_trip(jar: &CookieJar, attributes: &CookieAttributes) -> CookieJar {
+        let metadata = jar.to_server_metadata(attributes).unwrap().build();
+        let mut client_jar = CookieJar::new();
+        client_jar
+            .update_from_server_metadata(Some(&metadata), None)
+            .unwrap();
+        client_jar
+    }
+
+    #[test]
+    fn same_site_and_secure_round_trip() {
+        let mut jar = CookieJar::new();
+        jar.add(Cookie::new("session", "abc123"));
+
+        for same_site in [SameSite::Strict, SameSite::Lax, SameSite::None] {
+            for secure in [false, true] {
+                let attributes = CookieAttributes::default()
+                    .with_same_site(same_site)
+                    .with_secure(secure);
+                let client_jar = round_trip(&jar, &attributes);
+                let cookie = client_jar.get("session").unwrap();
+                assert_eq!(cookie.value(), "abc123");
+                assert_eq!(cookie.same_site(), Some(same_site));
+                assert_eq!(cookie.secure().unwrap_or(false), secure);
+            }
+        }
+
+        // Without attributes, none are added.
+        let client_jar = round_trip(&jar, &CookieAttributes::default());
+        let cookie = client_jar.get("session").unwrap();
+        assert_eq!(cookie.same_site(), None);
+        assert_eq!(cookie.secure(), None);
+    }
+
+    #[test]
+    fn saved_sessions_survive_until_they_expire() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("cookies");
+        let now = OffsetDateTime::now_utc();
+
+        let mut jar = CookieJar::new();
+        let mut valid = Cookie::new("session", "abc123");
+        valid.set_expires(now + cookie::time::Duration::hours(1));
+        valid.set_secure(true);
+        jar.add(valid);
+        let mut short_lived = Cookie::new("short", "lived");
+        short_lived.set_max_age(cookie::time::Duration::seconds(1));
+        jar.add(short_lived);
+        jar.add(Cookie::new("no_expiry", "xyz"));
+        let mut expired = Cookie::new("expired", "old");
+        expired.set_expires(now - cookie::time::Duration::hours(1));
+        jar.add(expired);
+
+        jar.save_to_path(&path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        assert!(!fs::read_to_string(&path).unwrap().contains("expired"));
+
+        // Let the Max-Age cookie expire before reloading.
+        std::thread::sleep(std::time::Duration::from_millis(1500));
+        let loaded = CookieJar::load_from_path(&path).unwrap();
+        let session = loaded.get("session").unwrap();
+        assert_eq!(session.value(), "abc123");
+        assert_eq!(session.secure(), Some(true));
+        assert_eq!(loaded.get("no_expiry").unwrap().value(), "xyz");
+        assert!(loaded.get("short").is_none());
+        assert!(loaded.get("expired").is_none());
+
+        // Loaded cookies are sent like any others.
+        let metadata = loaded.to_client_metadata().unwrap().build();
+        assert_eq!(metadata.len(), 2);
+    }
+
+    #[test]
+    fn loading_a_missing_file_is_an_io_error() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        assert!(matches!(
+            CookieJar::load_from_path(&temp_dir.path().join("missing")),
+            Err(Error::Io(_))
+        ));
+    }
+
+    #[test]
+    fn parse_same_site_values() {
+        assert_eq!(parse_same_site("Strict"), Ok(SameSite::Strict));
+        assert_eq!(parse_same_site("lax"), Ok(SameSite::Lax));
+        assert_eq!(parse_same_site("NONE"), Ok(SameSite::None));
+        assert_eq!(
+            parse_same_site("sometimes"),
+            Err(Error::InvalidSameSite("sometimes".to_owned()))
+        );
+    }
 }
@@ -0,0 +1,21 @@
+// Copyright (c) 2018-2022 The MobileCoin Foundation
+
+use mc_util_metrics::{IntCounterVec, OpMetrics, Opts};
+
+lazy_static::lazy_static! {
+    pub static ref OP_COUNTERS: OpMetrics = OpMetrics::new_and_registered("grpc_util");
+
+    /// Number of retries of grpc calls, by the status code which caused them.
+    pub static ref GRPC_RETRIES: IntCounterVec = {
+        let counter = IntCounterVec::new(
+            Opts::new(
+                "grpc_util_retries",
+                "Number of grpc call retries, by the status code which caused them",
+            ),
+            &["status_code"],
+        )
+        .unwrap();
+        let _res = mc_util_metrics::register(Box::new(counter.clone()));
+        counter
+    };
+}
@@ -0,0 +1,224 @@
+// Copyright (c) 2018-2022 The MobileCoin Foundation
+
+//! Graceful shutdown of a grpc server on SIGTERM or SIGINT.
+
+use crate::HealthService;
+use grpcio::Server;
+use mc_common::logger::{log, Logger};
+use signal_hook::{
+    consts::{SIGINT, SIGTERM},
+    flag, low_level, SigId,
+};
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+/// How often to check whether a shutdown signal was received.
+const SIGNAL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The signals which trigger a graceful shutdown.
+const SHUTDOWN_SIGNALS: [i32; 2] = [SIGTERM, SIGINT];
+
+lazy_static::lazy_static! {
+    /// Whether the shutdown signals run their default action, terminating the
+    /// process. This is only unset while `run_until_signal` handles them.
+    static ref DEFAULT_ACTION_ARMED: Arc<AtomicBool> = Arc::new(AtomicBool::new(true));
+
+    /// Whether the default action was registered with signal-hook, which is
+    /// done the first time the signals are handled.
+    static ref DEFAULT_ACTION_REGISTERED: Mutex<bool> = Mutex::new(false);
+}
+
+/// Block until a SIGTERM or SIGINT is received, and then shut `server` down
+/// gracefully, before returning control to the caller.
+///
+/// Shutting down starts by calling `health.begin_drain()`, so that load
+/// balancers stop routing new calls here. The server then stops accepting
+/// calls, and calls which are already in flight get up to `grace` to complete,
+/// after which they are cancelled.
+///
+/// The signal handlers are only installed for the duration of this call.
+/// Once it returns, the signals terminate the process again.
+pub fn run_until_signal(
+    server: Server,
+    health: &HealthService,
+    grace: Duration,
+    logger: &Logger,
+) -> io::Result<()> {
+    let signals = ShutdownSignals::register()?;
+    run_until(server, health, grace, signals.received(), logger);
+    Ok(())
+}
+
+/// Handlers for the shutdown signals, which record that one was received, in
+/// place of their default action. The default action is restored when this is
+/// dropped.
+struct ShutdownSignals {
+    received: Arc<AtomicBool>,
+    signal_ids: Vec<SigId>,
+}
+
+impl ShutdownSignals {
+    fn register() -> io::Result<Self> {
+        // Once signal-hook handles a signal, the signal's default action is
+        // gone for good, so it is emulated by an action of our own, which is
+        // only disarmed while the signals are registered here.
+        {
+            let mut registered = DEFAULT_ACTION_REGISTERED.lock().expect("lock poisoned");
+            if !*registered {
+                for signal in SHUTDOWN_SIGNALS {
+                    flag::register_conditional_default(signal, DEFAULT_ACTION_ARMED.clone())?;
+                }
+                *registered = true;
+            }
+        }
+
+        let mut signals = Self {
+            received: Arc::new(AtomicBool::new(false)),
+            signal_ids: Vec::new(),
+        };
+        for signal in SHUTDOWN_SIGNALS {
+            signals
+                .signal_ids
+                .push(flag::register(signal, signals.received.clone())?);
+        }
+        DEFAULT_ACTION_ARMED.store(false, Ordering::SeqCst);
+        Ok(signals)
+    }
+
+    /// Whether a shutdown signal was received.
+    fn received(&self) -> &AtomicBool {
+        &self.received
+    }
+}
+
+impl Drop for ShutdownSignals {
+    fn drop(&mut self) {
+        DEFAULT_ACTION_ARMED.store(true, Ordering::SeqCst);
+        for id in self.signal_ids.drain(..) {
+            low_level::unregister(id);
+        }
+    }
+}
+
+/// Block until `stop` is set, and then drain and shut down `server`.
+fn run_until(
+    mut server: Server,
+    health: &HealthService,
+    grace: Duration,
+    stop: &AtomicBool,
+    logger: &Logger,
+) {
+    while !stop.load(Ordering::SeqCst) {
+        thread::sleep(SIGNAL_POLL_INTERVAL);
+    }
+    log::info!(logger, "Shutdown signal received, draining");
+    health.begin_drain();
+
+    // The shutdown future completes once all in-flight calls are done, so wait
+    // for it on another thread, to be able to give up after `grace`.
+    let shutdown = server.shutdown();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(futures::executor::block_on(shutdown));
+    });
+
+    let result = match receiver.recv_timeout(grace) {
+        Ok(result) => result,
+        Err(_) => {
+            log::warn!(
+                logger,
+                "Calls still in flight after {:?}, cancelling them",
+                grace
+            );
+            server.cancel_all_calls();
+            match receiver.recv() {
+                Ok(result) => result,
+                Err(_) => return,
+            }
+        }
+    };
+    match result {
+        Ok(()) => log::info!(logger, "Server shut down"),
+        Err(err) => log::warn!(logger, "Failed to shut down server: {}", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        health_api::PingRequest,
+        health_api_grpc::HealthClient,
+        test_fixtures::{respond_after, TestHealth},
+        HealthCheckStatus,
+    };
+    use mc_common::logger::test_with_logger;
+    use std::time::Instant;
+
+    /// Start a server whose pings take `delay` to be answered.
+    fn start_server(delay: Duration, logger: &Logger) -> (Server, HealthClient) {
+        TestHealth::new(logger.clone())
+            .with_ping(move |_ctx, req, sink| respond_after(delay, req, sink, ()))
+            .start()
+    }
+
+    #[test_with_logger]
+    fn signal_drains_and_waits_for_in_flight_calls(logger: Logger) {
+        let (server, client) = start_server(Duration::from_millis(500), &logger);
+        let health = HealthService::new(None, logger.clone());
+
+        let in_flight = client.ping_async(&PingRequest::new()).unwrap();
+        // Give the call time to reach the server before it shuts down.
+        thread::sleep(Duration::from_millis(100));
+
+        // Raise a real SIGTERM once the handlers are registered, which must
+        // not terminate the process.
+        let signals = ShutdownSignals::register().unwrap();
+        assert!(!DEFAULT_ACTION_ARMED.load(Ordering::SeqCst));
+        thread::spawn(|| {
+            thread::sleep(Duration::from_millis(100));
+            low_level::raise(SIGTERM).unwrap();
+        });
+        run_until(
+            server,
+            &health,
+            Duration::from_secs(5),
+            signals.received(),
+            &logger,
+        );
+        assert!(signals.received().load(Ordering::SeqCst));
+
+        // Once the handlers are gone, the signals terminate the process again.
+        drop(signals);
+        assert!(DEFAULT_ACTION_ARMED.load(Ordering::SeqCst));
+
+        assert!(health.is_draining());
+        assert_eq!(health.serving_status(""), HealthCheckStatus::NOT_SERVING);
+        // The call that was in flight completed, and the server is gone.
+        assert!(futures::executor::block_on(in_flight).is_ok());
+        assert!(client.ping(&PingRequest::new()).is_err());
+    }
+
+    #[test_with_logger]
+    fn calls_are_cancelled_after_grace(logger: Logger) {
+        let (server, client) = start_server(Duration::from_secs(10), &logger);
+        let health = HealthService::new(None, logger.clone());
+        let stop = AtomicBool::new(true);
+
+        let in_flight = client.ping_async(&PingRequest::new()).unwrap();
+        thread::sleep(Duration::from_millis(100));
+
+        let start = Instant::now();
+        run_until(server, &health, Duration::from_millis(200), &stop, &logger);
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        assert!(futures::executor::block_on(in_flight).is_err());
+    }
+}
@@ -4,55 +4,418 @@
 //! them using URIs.
 
 use crate::ServerCertReloader;
+use displaydoc::Display;
 use grpcio::{
-    CertificateRequestType, Channel, ChannelBuilder, ChannelCredentialsBuilder, Environment,
-    ServerBuilder,
+    Channel, ChannelBuilder, ChannelCredentialsBuilder, CompressionAlgorithms, ConnectivityState,
+    Environment, LbPolicy, ServerBuilder,
 };
 use mc_common::logger::{log, Logger};
 use mc_util_uri::ConnectionUri;
-use std::{sync::Arc, time::Duration};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// A suggested limit on concurrent streams (i.e. calls) per client connection,
+/// for servers that opt into one with
+/// `ServerOptions::with_max_concurrent_streams`. Clients queue calls beyond the
+/// limit until earlier ones finish.
+pub const DEFAULT_MAX_CONCURRENT_STREAMS: u32 = 100;
+
+/// A service discovery hook, which maps a logical service name (the host of a
+/// URI) to the addresses of the endpoints currently serving it.
+pub type ServiceResolverFn = Arc<dyn Fn(&str) -> Result<Vec<SocketAddr>, String> + Sync + Send>;
+
+/// An error resolving a service to connect to.
+#[derive(Clone, Debug, Display, Eq, PartialEq)]
+pub enum ServiceResolverError {
+    /// Resolving {0} failed: {1}
+    Resolver(String, String),
+
+    /// Service {0} resolved to no addresses
+    NoAddresses(String),
+
+    /// Service {0} resolved to both IPv4 and IPv6 addresses
+    MixedAddressFamilies(String),
+}
+
+/// Keepalive and message size settings for client channels.
+///
+/// Keepalive pings keep idle connections from being silently dropped, e.g. by
+/// a NAT, which would otherwise stall the next call until it times out.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ChannelOptions {
+    /// How often to ping the server. Defaults to 10 seconds.
+    pub keepalive_time: Duration,
+
+    /// How long to wait for a ping to be acknowledged before closing the
+    /// connection. Defaults to 20 seconds.
+    pub keepalive_timeout: Duration,
+
+    /// Whether to ping even when there are no calls in flight. Defaults to
+    /// true.
+    pub keepalive_permit_without_calls: bool,
+
+    /// The largest message the channel accepts, in bytes. Defaults to grpc's
+    /// default of 4 MiB.
+    pub max_receive_message_len: Option<usize>,
+
+    /// The largest message the channel sends, in bytes. Defaults to no limit.
+    pub max_send_message_len: Option<usize>,
+}
+
+impl Default for ChannelOptions {
+    fn default() -> Self {
+        Self {
+            keepalive_time: Duration::from_secs(10),
+            keepalive_timeout: Duration::from_secs(20),
+            keepalive_permit_without_calls: true,
+            max_receive_message_len: None,
+            max_send_message_len: None,
+        }
+    }
+}
+
+impl ChannelOptions {
+    /// Set how often to ping the server.
+    pub fn with_keepalive_time(mut self, keepalive_time: Duration) -> Self {
+        self.keepalive_time = keepalive_time;
+        self
+    }
+
+    /// Set how long to wait for a ping to be acknowledged.
+    pub fn with_keepalive_timeout(mut self, keepalive_timeout: Duration) -> Self {
+        self.keepalive_timeout = keepalive_timeout;
+        self
+    }
+
+    /// Set whether to ping when there are no calls in flight.
+    pub fn with_keepalive_permit_without_calls(mut self, permit: bool) -> Self {
+        self.keepalive_permit_without_calls = permit;
+        self
+    }
+
+    /// Set the largest message the channel accepts, in bytes.
+    pub fn with_max_receive_message_len(mut self, len: usize) -> Self {
+        self.max_receive_message_len = Some(len);
+        self
+    }
+
+    /// Set the largest message the channel sends, in bytes.
+    pub fn with_max_send_message_len(mut self, len: usize) -> Self {
+        self.max_send_message_len = Some(len);
+        self
+    }
+}
+
+/// Settings for servers, on top of our default channel args.
+///
+/// Everything here is off by default, so servers keep grpc's behavior unless
+/// they opt in.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ServerOptions {
+    /// The algorithm to compress responses with, e.g.
+    /// `CompressionAlgorithms::GRPC_COMPRESS_GZIP`. Compressed requests are
+    /// accepted either way.
+    pub compression: Option<CompressionAlgorithms>,
+
+    /// The most concurrent streams (i.e. calls) to allow per client
+    /// connection. Defaults to grpc's default, which is unlimited.
+    pub max_concurrent_streams: Option<u32>,
+}
+
+impl ServerOptions {
+    /// Set the algorithm to compress responses with.
+    pub fn with_compression(mut self, algorithm: CompressionAlgorithms) -> Self {
+        self.compression = Some(algorithm);
+        self
+    }
+
+    /// Set the most concurrent streams to allow per client connection.
+    pub fn with_max_concurrent_streams(mut self, max_concurrent_streams: u32) -> Self {
+        self.max_concurrent_streams = Some(max_concurrent_streams);
+        self
+    }
+}
+
+/// grpc takes message lengths as i32, so clamp larger ones.
+fn message_len_arg(len: usize) -> i32 {
+    i32::try_from(len).unwrap_or(i32::MAX)
+}
+
+/// grpc takes stream counts as i32, so clamp larger ones.
+fn max_concurrent_streams_arg(max_concurrent_streams: u32) -> i32 {
+    i32::try_from(max_concurrent_streams).unwrap_or(i32::MAX)
+}
 
 /// A trait to ease grpcio channel construction from URIs.
 pub trait ConnectionUriGrpcioChannel {
     /// Construct a ChannelBuilder with some sane defaults.
     fn default_channel_builder(env: Arc<Environment>) -> ChannelBuilder {
-        ChannelBuilder::new(env)
-            .keepalive_permit_without_calls(true)
-            .keepalive_time(Duration::from_secs(10))
-            .keepalive_timeout(Duration::from_secs(20))
+        Self::channel_builder_with_options(env, &ChannelOptions::default())
+    }
+
+    /// Construct a ChannelBuilder with our default reconnect backoff, and the
+    /// given keepalive and message size settings.
+    fn channel_builder_with_options(
+        env: Arc<Environment>,
+        options: &ChannelOptions,
+    ) -> ChannelBuilder {
+        let mut builder = ChannelBuilder::new(env)
+            .keepalive_permit_without_calls(options.keepalive_permit_without_calls)
+            .keepalive_time(options.keepalive_time)
+            .keepalive_timeout(options.keepalive_timeout)
             .max_reconnect_backoff(Duration::from_millis(2000))
-            .initial_reconnect_backoff(Duration::from_millis(1000))
+            .initial_reconnect_backoff(Duration::from_millis(1000));
+        if let Some(len) = options.max_receive_message_len {
+            builder = builder.max_receive_message_len(message_len_arg(len));
+        }
+        if let Some(len) = options.max_send_message_len {
+            builder = builder.max_send_message_len(message_len_arg(len));
+        }
+        builder
     }
 
+    /// Compress requests sent on the channel using `algorithm`, e.g.
+    /// `CompressionAlgorithms::GRPC_COMPRESS_GZIP`. Compression is off by
+    /// default, for compatibility with peers that don't support it.
+    #[must_use]
+    fn default_compression(self, algorithm: CompressionAlgorithms) -> Self;
+
     /// Connects a ChannelBuilder using a URI.
     fn connect_to_uri(self, uri: &impl ConnectionUri, logger: &Logger) -> Channel;
 }
 
+/// Build a grpc target naming a list of addresses, e.g.
+/// "ipv4:10.0.0.1:3223,10.0.0.2:3223".
+fn resolved_target(service: &str, addrs: &[SocketAddr]) -> Result<String, ServiceResolverError> {
+    if addrs.is_empty() {
+        return Err(ServiceResolverError::NoAddresses(service.to_owned()));
+    }
+    let scheme = if addrs.iter().all(SocketAddr::is_ipv4) {
+        "ipv4"
+    } else if addrs.iter().all(SocketAddr::is_ipv6) {
+        "ipv6"
+    } else {
+        return Err(ServiceResolverError::MixedAddressFamilies(
+            service.to_owned(),
+        ));
+    };
+
+    let addrs: Vec<String> = addrs.iter().map(ToString::to_string).collect();
+    Ok(format!("{}:{}", scheme, addrs.join(",")))
+}
+
 impl ConnectionUriGrpcioChannel for ChannelBuilder {
-    fn connect_to_uri(mut self, uri: &impl ConnectionUri, logger: &Logger) -> Channel {
+    fn default_compression(self, algorithm: CompressionAlgorithms) -> Self {
+        self.default_compression_algorithm(algorithm)
+    }
+
+    fn connect_to_uri(self, uri: &impl ConnectionUri, logger: &Logger) -> Channel {
+        let security = ChannelSecurity::from_uri(uri, uri.tls_hostname_override());
+        security.connect(self, &uri.addr(), &uri.addr(), logger)
+    }
+}
+
+/// How a channel is secured, as given by its URI.
+#[derive(Clone)]
+enum ChannelSecurity {
+    /// No TLS.
+    Insecure,
+
+    /// TLS, checking the server's certificate against `root_cert` (or the
+    /// system roots), for the name `ssl_target` (or the target's host).
+    Tls {
+        ssl_target: Option<String>,
+        root_cert: Option<Vec<u8>>,
+    },
+}
+
+impl ChannelSecurity {
+    /// The security of connections to `uri`, expecting certificates for
+    /// `ssl_target`, if given.
+    fn from_uri(uri: &impl ConnectionUri, ssl_target: Option<String>) -> Self {
         if uri.use_tls() {
-            if let Some(host_override) = uri.tls_hostname_override() {
-                self = self.override_ssl_target(host_override);
+            Self::Tls {
+                ssl_target,
+                root_cert: uri.ca_bundle().expect("failed getting ca bundle"),
             }
+        } else {
+            Self::Insecure
+        }
+    }
 
-            let creds = match uri.ca_bundle().expect("failed getting ca bundle") {
-                Some(cert) => ChannelCredentialsBuilder::new().root_cert(cert).build(),
-                None => ChannelCredentialsBuilder::new().build(),
-            };
+    /// Connect `builder` to `target`, which is described by `name` in logs.
+    fn connect(
+        &self,
+        mut builder: ChannelBuilder,
+        name: &str,
+        target: &str,
+        logger: &Logger,
+    ) -> Channel {
+        match self {
+            Self::Tls {
+                ssl_target,
+                root_cert,
+            } => {
+                if let Some(ssl_target) = ssl_target {
+                    builder = builder.override_ssl_target(ssl_target.clone());
+                }
 
-            log::debug!(logger, "Creating secure gRPC connection to {}", uri.addr());
+                let creds = match root_cert {
+                    Some(cert) => ChannelCredentialsBuilder::new()
+                        .root_cert(cert.clone())
+                        .build(),
+                    None => ChannelCredentialsBuilder::new().build(),
+                };
 
-            self.secure_connect(&uri.addr(), creds)
-        } else {
-            log::warn!(
-                logger,
-                "Creating insecure gRPC connection to {}",
-                uri.addr(),
-            );
+                log::debug!(logger, "Creating secure gRPC connection to {}", name);
+
+                builder.secure_connect(target, creds)
+            }
+            Self::Insecure => {
+                log::warn!(logger, "Creating insecure gRPC connection to {}", name);
+
+                builder.connect(target)
+            }
+        }
+    }
+}
+
+/// The channel a `ResolvingChannel` currently uses.
+struct ResolvedChannel {
+    /// The addresses the channel is connected to.
+    addrs: Vec<SocketAddr>,
+
+    /// The channel.
+    channel: Channel,
+}
+
+/// A client channel to the endpoints a `ServiceResolverFn` returns for the
+/// host of a URI, balancing calls across them round-robin.
+///
+/// grpcio can't plug a custom resolver into grpc itself, so instead the
+/// resolver runs again whenever the channel has to reconnect: `channel` checks
+/// the current channel, and once it fails to connect or was shut down, the
+/// service is re-resolved, and the channel is replaced by one connected to the
+/// new endpoints, if they changed. Clients should therefore get the channel
+/// from here for each call, or at least after a call failed; generated clients
+/// are cheap to create.
+///
+/// When TLS is used, the certificates are checked against the host of the URI
+/// (unless overridden), not the resolved addresses.
+#[derive(Clone)]
+pub struct ResolvingChannel {
+    /// The logical service name, i.e. the host of the URI.
+    service: String,
+
+    /// Resolves the service to the addresses of its endpoints.
+    resolver: ServiceResolverFn,
+
+    /// Creates builders for new channels.
+    builder_fn: Arc<dyn Fn() -> ChannelBuilder + Sync + Send>,
 
-            self.connect(&uri.addr())
+    /// How channels are secured.
+    security: ChannelSecurity,
+
+    /// The channel currently in use.
+    current: Arc<Mutex<ResolvedChannel>>,
+
+    /// Logger.
+    logger: Logger,
+}
+
+impl ResolvingChannel {
+    /// Resolve the host of `uri` with `resolver`, and connect a channel built
+    /// by `builder_fn` to the resulting endpoints. `builder_fn` is called
+    /// again for each new channel.
+    pub fn new(
+        builder_fn: impl Fn() -> ChannelBuilder + Sync + Send + 'static,
+        uri: &impl ConnectionUri,
+        resolver: ServiceResolverFn,
+        logger: &Logger,
+    ) -> Result<Self, ServiceResolverError> {
+        let service = uri.host();
+        let security = ChannelSecurity::from_uri(
+            uri,
+            Some(
+                uri.tls_hostname_override()
+                    .unwrap_or_else(|| service.clone()),
+            ),
+        );
+        let builder_fn: Arc<dyn Fn() -> ChannelBuilder + Sync + Send> = Arc::new(builder_fn);
+
+        let addrs = resolve(&service, &resolver)?;
+        let channel = connect_resolved(&builder_fn, &security, &service, &addrs, logger)?;
+        Ok(Self {
+            service,
+            resolver,
+            builder_fn,
+            security,
+            current: Arc::new(Mutex::new(ResolvedChannel { addrs, channel })),
+            logger: logger.clone(),
+        })
+    }
+
+    /// The channel to use for the next call, re-resolving the service first
+    /// if the current channel is failing to connect.
+    pub fn channel(&self) -> Channel {
+        let mut current = self.current.lock().expect("lock poisoned");
+        let state = current.channel.check_connectivity_state(false);
+        if state == ConnectivityState::GRPC_CHANNEL_TRANSIENT_FAILURE
+            || state == ConnectivityState::GRPC_CHANNEL_SHUTDOWN
+        {
+            if let Err(err) = self.reconnect(&mut current) {
+                log::warn!(self.logger, "Keeping channel to {}: {}", self.service, err);
+            }
         }
+        current.channel.clone()
     }
+
+    /// The addresses the current channel is connected to.
+    pub fn addrs(&self) -> Vec<SocketAddr> {
+        self.current.lock().expect("lock poisoned").addrs.clone()
+    }
+
+    /// Re-resolve the service, and replace the current channel if its
+    /// endpoints changed.
+    fn reconnect(&self, current: &mut ResolvedChannel) -> Result<(), ServiceResolverError> {
+        let addrs = resolve(&self.service, &self.resolver)?;
+        if addrs != current.addrs {
+            let channel = connect_resolved(
+                &self.builder_fn,
+                &self.security,
+                &self.service,
+                &addrs,
+                &self.logger,
+            )?;
+            *current = ResolvedChannel { addrs, channel };
+        }
+        Ok(())
+    }
+}
+
+/// Resolve `service` with `resolver`.
+fn resolve(
+    service: &str,
+    resolver: &ServiceResolverFn,
+) -> Result<Vec<SocketAddr>, ServiceResolverError> {
+    resolver(service).map_err(|err| ServiceResolverError::Resolver(service.to_owned(), err))
+}
+
+/// Connect a new channel to the endpoints `addrs` of `service`.
+fn connect_resolved(
+    builder_fn: &Arc<dyn Fn() -> ChannelBuilder + Sync + Send>,
+    security: &ChannelSecurity,
+    service: &str,
+    addrs: &[SocketAddr],
+    logger: &Logger,
+) -> Result<Channel, ServiceResolverError> {
+    let target = resolved_target(service, addrs)?;
+    let builder = builder_fn().load_balancing_policy(LbPolicy::RoundRobin);
+    let name = format!("{} ({})", service, target);
+    Ok(security.connect(builder, &name, &target, logger))
 }
 
 /// A trait to ease grpio server construction from URIs.
@@ -71,9 +434,33 @@ pub trait ConnectionUriGrpcioServer {
             .http2_min_recv_ping_interval_without_data(Duration::from_secs(5))
     }
 
+    /// Create the default channel settings for server, with `options` applied
+    /// on top.
+    fn channel_builder_with_options(
+        env: Arc<Environment>,
+        options: &ServerOptions,
+    ) -> ChannelBuilder {
+        let mut builder = Self::default_channel_builder(env);
+        if let Some(algorithm) = options.compression {
+            builder = builder.default_compression_algorithm(algorithm);
+        }
+        if let Some(max_concurrent_streams) = options.max_concurrent_streams {
+            builder =
+                builder.max_concurrent_stream(max_concurrent_streams_arg(max_concurrent_streams));
+        }
+        builder
+    }
+
     /// Set the channel args to our defaults.
     #[must_use]
     fn set_default_channel_args(self, env: Arc<Environment>) -> Self;
+
+    /// Set the channel args to our defaults, with `options` applied on top.
+    ///
+    /// This replaces any channel args set before, e.g. by
+    /// `set_default_channel_args`.
+    #[must_use]
+    fn set_channel_args_with_options(self, env: Arc<Environment>, options: &ServerOptions) -> Self;
 }
 
 impl ConnectionUriGrpcioServer for ServerBuilder {
@@ -96,12 +483,8 @@ impl ConnectionUriGrpcioServer for ServerBuilder {
                 uri.port(),
             );
 
-            self.bind_with_fetcher(
-                uri.host(),
-                uri.port(),
-                Box::new(reloader),
-                CertificateRequestType::DontRequestClientCertificate,
-            )
+            let request_type = reloader.certificate_request_type();
+            self.bind_with_fetcher(uri.host(), uri.port(), Box::new(reloader), request_type)
         } else {
             log::warn!(
                 logger,
@@ -118,4 +501,350 @@ impl ConnectionUriGrpcioServer for ServerBuilder {
     fn set_default_channel_args(self, env: Arc<Environment>) -> Self {
         self.channel_args(Self::default_channel_builder(env).build_args())
     }
+
+    fn set_channel_args_with_options(self, env: Arc<Environment>, options: &ServerOptions) -> Self {
+        self.channel_args(Self::channel_builder_with_options(env, options).build_args())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        health_api::{HealthCheckRequest, PingRequest},
+        health_api_grpc::HealthClient,
+        HealthCheckStatus, HealthService,
+    };
+    use futures::{executor::block_on, StreamExt};
+    use grpcio::{CallOption, EnvBuilder, RpcStatusCode, Server, ServerBuilder};
+    use mc_common::logger::test_with_logger;
+    use mc_util_uri::ConsensusClientUri;
+    use std::{
+        str::FromStr,
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+    };
+
+    /// Start a health server on localhost, counting the health checks it
+    /// answers.
+    fn create_counting_server(logger: Logger) -> (Server, SocketAddr, Arc<AtomicUsize>) {
+        let checks = Arc::new(AtomicUsize::new(0));
+        let counter = checks.clone();
+        let service = HealthService::new(
+            Some(Arc::new(move |_: &str| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                HealthCheckStatus::SERVING
+            })),
+            logger,
+        )
+        .into_service();
+
+        let env = Arc::new(EnvBuilder::new().build());
+        let mut server = ServerBuilder::new(env)
+            .register_service(service)
+            .bind("127.0.0.1", 0)
+            .build()
+            .unwrap();
+        server.start();
+        let port = server.bind_addrs().next().unwrap().1;
+        (server, SocketAddr::from(([127, 0, 0, 1], port)), checks)
+    }
+
+    #[test]
+    fn resolved_target_formats_addresses() {
+        let v4: Vec<SocketAddr> = vec![
+            "10.0.0.1:3223".parse().unwrap(),
+            "10.0.0.2:80".parse().unwrap(),
+        ];
+        assert_eq!(
+            resolved_target("svc", &v4).unwrap(),
+            "ipv4:10.0.0.1:3223,10.0.0.2:80"
+        );
+
+        let v6: Vec<SocketAddr> = vec!["[::1]:3223".parse().unwrap()];
+        assert_eq!(resolved_target("svc", &v6).unwrap(), "ipv6:[::1]:3223");
+
+        let mixed = vec![v4[0], v6[0]];
+        assert_eq!(
+            resolved_target("svc", &mixed),
+            Err(ServiceResolverError::MixedAddressFamilies("svc".to_owned()))
+        );
+        assert_eq!(
+            resolved_target("svc", &[]),
+            Err(ServiceResolverError::NoAddresses("svc".to_owned()))
+        );
+    }
+
+    #[test_with_logger]
+    fn resolver_addresses_are_all_used(logger: Logger) {
+        let (_server1, addr1, checks1) = create_counting_server(logger.clone());
+        let (_server2, addr2, checks2) = create_counting_server(logger.clone());
+
+        let resolver: ServiceResolverFn = Arc::new(move |service: &str| {
+            assert_eq!(service, "backend.service");
+            Ok(vec![addr1, addr2])
+        });
+        let uri = ConsensusClientUri::from_str("insecure-mc://backend.service/").unwrap();
+        let env = Arc::new(EnvBuilder::new().build());
+        let channel = ResolvingChannel::new(
+            move || ChannelBuilder::default_channel_builder(env.clone()),
+            &uri,
+            resolver,
+            &logger,
+        )
+        .unwrap();
+        let client = HealthClient::new(channel.channel());
+
+        // Round-robin only uses an endpoint once it is connected, so allow
+        // some time for both connections to come up.
+        for _ in 0..100 {
+            client
+                .check(&Default::default())
+                .expect("health check failed");
+            if checks1.load(Ordering::SeqCst) > 0 && checks2.load(Ordering::SeqCst) > 0 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert!(checks1.load(Ordering::SeqCst) > 0);
+        assert!(checks2.load(Ordering::SeqCst) > 0);
+
+        let mut req = PingRequest::default();
+        req.set_data(vec![1, 2, 3]);
+        assert_eq!(client.ping(&req).unwrap().get_data(), vec![1, 2, 3]);
+    }
+
+    #[test_with_logger]
+    fn large_payloads_round_trip_with_compression(logger: Logger) {
+        let env = Arc::new(EnvBuilder::new().build());
+        let service = HealthService::new(None, logger.clone()).into_service();
+        let mut server = ServerBuilder::new(env.clone())
+            .register_service(service)
+            .set_channel_args_with_options(
+                env.clone(),
+                &ServerOptions::default()
+                    .with_compression(CompressionAlgorithms::GRPC_COMPRESS_GZIP),
+            )
+            .bind("127.0.0.1", 0)
+            .build()
+            .unwrap();
+        server.start();
+        let port = server.bind_addrs().next().unwrap().1;
+
+        let uri =
+            ConsensusClientUri::from_str(&format!("insecure-mc://127.0.0.1:{}/", port)).unwrap();
+        let channel = ChannelBuilder::default_channel_builder(env)
+            .default_compression(CompressionAlgorithms::GRPC_COMPRESS_GZIP)
+            .connect_to_uri(&uri, &logger);
+        let client = HealthClient::new(channel);
+
+        // A couple of megabytes of compressible, but not constant, data.
+        let data = (0..2 * 1024 * 1024)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        let mut req = PingRequest::default();
+        req.set_data(data.clone());
+        assert_eq!(client.ping(&req).unwrap().get_data(), data.as_slice());
+    }
+
+    #[test_with_logger]
+    fn channel_options_limit_message_sizes(logger: Logger) {
+        let (_server, addr, _checks) = create_counting_server(logger.clone());
+        let uri = ConsensusClientUri::from_str(&format!("insecure-mc://{}/", addr)).unwrap();
+        let env = Arc::new(EnvBuilder::new().build());
+        let ping = |options: ChannelOptions, len: usize| {
+            let channel = ChannelBuilder::channel_builder_with_options(env.clone(), &options)
+                .connect_to_uri(&uri, &logger);
+            let mut req = PingRequest::default();
+            req.set_data(vec![7; len]);
+            HealthClient::new(channel).ping(&req)
+        };
+
+        // Responses just over the receive limit are rejected.
+        let options = ChannelOptions::default().with_max_receive_message_len(1024);
+        assert!(ping(options, 512).is_ok());
+        match ping(options, 1024) {
+            Err(grpcio::Error::RpcFailure(status)) => {
+                assert_eq!(status.code(), grpcio::RpcStatusCode::RESOURCE_EXHAUSTED)
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        // So are requests just over the send limit.
+        let options = ChannelOptions::default().with_max_send_message_len(1024);
+        assert!(ping(options, 512).is_ok());
+        assert!(ping(options, 1024).is_err());
+
+        // Without limits, grpc's defaults apply.
+        assert!(ping(ChannelOptions::default(), 1024 * 1024).is_ok());
+    }
+
+    #[test]
+    fn channel_options_builder() {
+        let options = ChannelOptions::default()
+            .with_keepalive_time(Duration::from_secs(30))
+            .with_keepalive_timeout(Duration::from_secs(5))
+            .with_keepalive_permit_without_calls(false)
+            .with_max_receive_message_len(1 << 20)
+            .with_max_send_message_len(usize::MAX);
+        assert_eq!(options.keepalive_time, Duration::from_secs(30));
+        assert_eq!(options.keepalive_timeout, Duration::from_secs(5));
+        assert!(!options.keepalive_permit_without_calls);
+        assert_eq!(options.max_receive_message_len, Some(1 << 20));
+        assert_eq!(message_len_arg(usize::MAX), i32::MAX);
+    }
+
+    #[test]
+    fn max_concurrent_streams_arg_is_clamped() {
+        assert_eq!(
+            max_concurrent_streams_arg(DEFAULT_MAX_CONCURRENT_STREAMS),
+            100
+        );
+        assert_eq!(max_concurrent_streams_arg(1), 1);
+        assert_eq!(max_concurrent_streams_arg(u32::MAX), i32::MAX);
+    }
+
+    #[test]
+    fn server_options_are_opt_in() {
+        assert_eq!(ServerOptions::default().compression, None);
+        assert_eq!(ServerOptions::default().max_concurrent_streams, None);
+
+        let options = ServerOptions::default()
+            .with_max_concurrent_streams(DEFAULT_MAX_CONCURRENT_STREAMS)
+            .with_compression(CompressionAlgorithms::GRPC_COMPRESS_GZIP);
+        assert_eq!(
+            options.compression,
+            Some(CompressionAlgorithms::GRPC_COMPRESS_GZIP)
+        );
+        assert_eq!(
+            options.max_concurrent_streams,
+            Some(DEFAULT_MAX_CONCURRENT_STREAMS)
+        );
+    }
+
+    #[test_with_logger]
+    fn max_concurrent_streams_limits_calls_per_connection(logger: Logger) {
+        let env = Arc::new(EnvBuilder::new().build());
+        let service = HealthService::new(None, logger.clone()).into_service();
+        let mut server = ServerBuilder::new(env.clone())
+            .register_service(service)
+            .set_channel_args_with_options(
+                env.clone(),
+                &ServerOptions::default()
+                    .with_compression(CompressionAlgorithms::GRPC_COMPRESS_GZIP)
+                    .with_max_concurrent_streams(1),
+            )
+            .bind("127.0.0.1", 0)
+            .build()
+            .unwrap();
+        server.start();
+        let port = server.bind_addrs().next().unwrap().1;
+
+        let uri =
+            ConsensusClientUri::from_str(&format!("insecure-mc://127.0.0.1:{}/", port)).unwrap();
+        let channel = ChannelBuilder::default_channel_builder(env).connect_to_uri(&uri, &logger);
+        let client = HealthClient::new(channel);
+        let ping = || {
+            client.ping_opt(
+                &PingRequest::default(),
+                CallOption::default().timeout(Duration::from_millis(500)),
+            )
+        };
+
+        // Learn the server's settings, and check single calls work, including
+        // ones with compressed responses.
+        assert!(ping().is_ok());
+        let data = vec![0; 1024 * 1024];
+        let mut req = PingRequest::default();
+        req.set_data(data.clone());
+        assert_eq!(client.ping(&req).unwrap().get_data(), data.as_slice());
+
+        // While a watch holds the connection's only stream, further calls are
+        // queued, and time out.
+        let mut watch = client.watch(&HealthCheckRequest::default()).unwrap();
+        assert!(block_on(watch.next()).unwrap().is_ok());
+        match ping() {
+            Err(grpcio::Error::RpcFailure(status)) => {
+                assert_eq!(status.code(), RpcStatusCode::DEADLINE_EXCEEDED)
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        // Once the watch is gone, calls go through again.
+        drop(watch);
+        assert!(ping().is_ok());
+    }
+
+    #[test_with_logger]
+    fn resolver_errors_are_reported(logger: Logger) {
+        let resolver: ServiceResolverFn = Arc::new(|_: &str| Err("no such service".to_owned()));
+        let uri = ConsensusClientUri::from_str("insecure-mc://backend.service/").unwrap();
+        let env = Arc::new(EnvBuilder::new().build());
+        let result = ResolvingChannel::new(
+            move || ChannelBuilder::default_channel_builder(env.clone()),
+            &uri,
+            resolver,
+            &logger,
+        );
+        assert_eq!(
+            result.err(),
+            Some(ServiceResolverError::Resolver(
+                "backend.service".to_owned(),
+                "no such service".to_owned()
+            ))
+        );
+    }
+
+    #[test_with_logger]
+    fn resolver_runs_again_on_reconnect(logger: Logger) {
+        // Nothing listens on the first address the resolver hands out.
+        let dead_addr = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap()
+        };
+        let (_server, live_addr, checks) = create_counting_server(logger.clone());
+
+        let resolutions = Arc::new(AtomicUsize::new(0));
+        let counter = resolutions.clone();
+        let resolver: ServiceResolverFn = Arc::new(move |_: &str| {
+            if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                Ok(vec![dead_addr])
+            } else {
+                Ok(vec![live_addr])
+            }
+        });
+        let uri = ConsensusClientUri::from_str("insecure-mc://backend.service/").unwrap();
+        let env = Arc::new(EnvBuilder::new().build());
+        let channel = ResolvingChannel::new(
+            move || ChannelBuilder::default_channel_builder(env.clone()),
+            &uri,
+            resolver,
+            &logger,
+        )
+        .unwrap();
+        assert_eq!(channel.addrs(), vec![dead_addr]);
+
+        // The first call fails to connect...
+        assert!(HealthClient::new(channel.channel())
+            .check(&Default::default())
+            .is_err());
+
+        // ... after which the service is resolved again, and calls reach the
+        // new endpoint.
+        let client = HealthClient::new(channel.channel());
+        assert!(resolutions.load(Ordering::SeqCst) > 1);
+        assert_eq!(channel.addrs(), vec![live_addr]);
+        client
+            .check(&Default::default())
+            .expect("health check failed");
+        assert_eq!(checks.load(Ordering::SeqCst), 1);
+
+        // While the channel is healthy, the resolver isn't consulted.
+        let resolved = resolutions.load(Ordering::SeqCst);
+        client
+            .check(&Default::default())
+            .expect("health check failed");
+        let _ = channel.channel();
+        assert_eq!(resolutions.load(Ordering::SeqCst), resolved);
+    }
 }
@@ -5,20 +5,29 @@
 //! MobileCoin-specific (Ping) extensions.
 
 use crate::{
+    counters::OP_COUNTERS,
     health_api::{
         HealthCheckRequest, HealthCheckResponse, HealthCheckResponse_ServingStatus, PingRequest,
         PingResponse,
     },
     health_api_grpc::{create_health, Health},
-    rpc_logger, send_result,
+    rpc_logger, rpc_resource_exhausted_error_with_retry, send_result, send_stream_result,
+};
+use futures::{channel::mpsc, prelude::*};
+use grpcio::{RpcContext, ServerStreamingSink, Service, UnarySink, WriteFlags};
+use mc_common::{
+    logger::{log, Logger},
+    time::TimeProvider,
 };
-use futures::prelude::*;
-use grpcio::{RpcContext, RpcStatus, RpcStatusCode, ServerStreamingSink, Service, UnarySink};
-use mc_common::logger::{log, Logger};
 use mc_util_metrics::SVC_COUNTERS;
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
 // Re-export the health check status enum for convenience.
@@ -30,10 +39,54 @@ pub use crate::health_api::HealthCheckResponse_ServingStatus as HealthCheckStatu
 // this behavior.
 pub type ServiceHealthCheckCallback = Arc<dyn Fn(&str) -> HealthCheckStatus + Sync + Send>;
 
+/// How often a `Watch` stream polls for changes of the serving status.
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How often a `Watch` stream resends an unchanged serving status. Sending is
+/// the only way to notice that a client has gone away without hanging up
+/// cleanly, so this bounds how long a dead subscription can linger.
+const WATCH_RESEND_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The default maximum number of concurrently active `Watch` streams. Each
+/// stream is served by its own polling thread, so this bounds how many threads
+/// clients can make the server spawn.
+pub const DEFAULT_MAX_WATCHERS: usize = 64;
+
+/// Serving statuses set for individual services, by service name.
+type ServingStatuses = Arc<RwLock<HashMap<String, HealthCheckStatus>>>;
+
+/// Get the status of a service.
+///
+/// Every service is NOT_SERVING while draining. Otherwise, a status set for the
+/// service takes precedence, and then the callback decides, if there is one.
+/// Without a callback, the overall status (the empty service name) is SERVING,
+/// and unknown services are NOT_SERVING.
+fn service_status(
+    callback: &Option<ServiceHealthCheckCallback>,
+    statuses: &ServingStatuses,
+    draining: &AtomicBool,
+    service: &str,
+) -> HealthCheckStatus {
+    if draining.load(Ordering::SeqCst) {
+        return HealthCheckResponse_ServingStatus::NOT_SERVING;
+    }
+    if let Some(status) = statuses.read().expect("lock poisoned").get(service) {
+        return *status;
+    }
+    match callback {
+        Some(callback) => callback(service),
+        None if service.is_empty() => HealthCheckResponse_ServingStatus::SERVING,
+        None => HealthCheckResponse_ServingStatus::NOT_SERVING,
+    }
+}
+
 /// A service that serves the grpcio health API: https://github.com/grpc/grpc/blob/v1.15.0/src/proto/grpc/health/v1/health.proto
 #[derive(Clone)]
 pub struct HealthService {
     service_health_check_callback: Option<ServiceHealthCheckCallback>,
+    serving_statuses: ServingStatuses,
+    draining: Arc<AtomicBool>,
+    watchers: WatchSubscriptions,
     logger: Logger,
 }
 
@@ -45,10 +98,80 @@ impl HealthService {
     ) -> Self {
         Self {
             service_health_check_callback,
+            serving_statuses: Default::default(),
+            draining: Default::default(),
+            watchers: WatchSubscriptions::default(),
             logger,
         }
     }
 
+    /// Set the serving status of a single service, as queried by the `service`
+    /// field of health check requests. Setting the status of the empty service
+    /// name overrides the overall status.
+    ///
+    /// The status is shared by all clones of this service, so it can be
+    /// updated after the service was registered with a server.
+    pub fn set_serving_status(&self, service: &str, status: HealthCheckStatus) {
+        self.serving_statuses
+            .write()
+            .expect("lock poisoned")
+            .insert(service.to_owned(), status);
+    }
+
+    /// The serving status reported for `service`.
+    pub fn serving_status(&self, service: &str) -> HealthCheckStatus {
+        service_status(
+            &self.service_health_check_callback,
+            &self.serving_statuses,
+            &self.draining,
+            service,
+        )
+    }
+
+    /// Start draining: from now on, every service is reported as NOT_SERVING,
+    /// so that load balancers stop routing new calls here before the server
+    /// shuts down.
+    ///
+    /// Like serving statuses, this is shared by all clones of this service.
+    pub fn begin_drain(&self) {
+        log::info!(
+            self.logger,
+            "Draining: reporting all services as NOT_SERVING"
+        );
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether `begin_drain` was called.
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// The number of active `Watch` streams.
+    ///
+    /// This is also reported by the `health_active_watchers` gauge, so that
+    /// leaked subscriptions can be alerted on.
+    pub fn active_watchers(&self) -> usize {
+        self.watchers.active()
+    }
+
+    /// Limit the number of concurrently active `Watch` streams to `max`.
+    /// Streams beyond the limit fail with RESOURCE_EXHAUSTED.
+    pub fn with_max_watchers(mut self, max: usize) -> Self {
+        self.watchers = WatchSubscriptions::new(max);
+        self
+    }
+
+    /// Report NOT_SERVING until the given warm-up is done, and only then
+    /// defer to the health check callback (if any).
+    pub fn with_warm_up<TP: TimeProvider + 'static>(
+        mut self,
+        warm_up: WarmUpIndicator<TP>,
+    ) -> Self {
+        self.service_health_check_callback =
+            Some(warm_up.wrap(self.service_health_check_callback.take()));
+        self
+    }
+
     /// Convert it into a grpc service
     pub fn into_service(self) -> Service {
         create_health(self)
@@ -65,10 +188,7 @@ impl Health for HealthService {
         let _timer = SVC_COUNTERS.req(&ctx);
         let logger = rpc_logger(&ctx, &self.logger);
 
-        let status = match &self.service_health_check_callback {
-            None => HealthCheckResponse_ServingStatus::SERVING,
-            Some(callback) => callback(req.get_service()),
-        };
+        let status = self.serving_status(req.get_service());
 
         let mut resp = HealthCheckResponse::new();
         resp.set_status(status);
@@ -87,18 +207,71 @@ impl Health for HealthService {
     fn watch(
         &mut self,
         ctx: RpcContext,
-        _req: HealthCheckRequest,
-        sink: ServerStreamingSink<HealthCheckResponse>,
+        req: HealthCheckRequest,
+        mut sink: ServerStreamingSink<HealthCheckResponse>,
     ) {
-        let logger = self.logger.clone();
+        let logger = rpc_logger(&ctx, &self.logger);
 
-        let resp = sink
-            .fail(RpcStatus::with_message(
-                RpcStatusCode::UNIMPLEMENTED,
-                "Unimplemented".into(),
-            ))
-            .map_err(move |err| log::error!(logger, "failed to reply: {:?}", err))
-            .map(|_| ());
+        // The current status is sent right away, and then again whenever it
+        // changes, or periodically if it does not. The polling thread holds the
+        // subscription, and exits once the stream is gone, which happens when
+        // a send fails because the client went away, or the server shuts down.
+        let subscription = match self.watchers.subscribe() {
+            Some(subscription) => subscription,
+            None => {
+                let status = rpc_resource_exhausted_error_with_retry(
+                    "watch",
+                    format!("too many active watchers ({})", self.watchers.max()),
+                    WATCH_POLL_INTERVAL,
+                    &logger,
+                );
+                send_stream_result(ctx, sink, Some(Err(status)), &logger);
+                return;
+            }
+        };
+        let callback = self.service_health_check_callback.clone();
+        let statuses = self.serving_statuses.clone();
+        let draining = self.draining.clone();
+        let service = req.get_service().to_owned();
+        let (sender, mut receiver) =
+            mpsc::unbounded::<Result<(HealthCheckResponse, WriteFlags), grpcio::Error>>();
+        let spawn_result = thread::Builder::new()
+            .name("HealthWatch".to_owned())
+            .spawn(move || {
+                let _subscription = subscription;
+                let mut last_sent: Option<(HealthCheckStatus, Instant)> = None;
+                while !sender.is_closed() {
+                    let status = service_status(&callback, &statuses, &draining, &service);
+                    let should_send = match last_sent {
+                        None => true,
+                        Some((last_status, sent_at)) => {
+                            last_status != status || sent_at.elapsed() >= WATCH_RESEND_INTERVAL
+                        }
+                    };
+                    if should_send {
+                        let mut resp = HealthCheckResponse::new();
+                        resp.set_status(status);
+                        if sender
+                            .unbounded_send(Ok((resp, WriteFlags::default())))
+                            .is_err()
+                        {
+                            break;
+                        }
+                        last_sent = Some((status, Instant::now()));
+                    }
+                    thread::sleep(WATCH_POLL_INTERVAL);
+                }
+            });
+        if let Err(err) = spawn_result {
+            log::error!(logger, "Failed to spawn health watch thread: {}", err);
+        }
+
+        let resp = async move {
+            sink.send_all(&mut receiver).await?;
+            sink.close().await
+        }
+        .map_err(move |err| log::error!(logger, "failed to reply: {:?}", err))
+        .map(|_| ());
 
         ctx.spawn(resp);
     }
@@ -112,38 +285,470 @@ impl Health for HealthService {
 /// service is unready, it lets it run, but does not route incoming traffic to
 /// it.
 ///
+/// In between, a server may be "degraded": up, but only partially functional,
+/// e.g. while catching up with the ledger.
+///
 /// We indicate "unready" by making the health check callback return
-/// "NOT_SERVING"
+/// "NOT_SERVING", and "degraded" by making it return "DEGRADED".
 #[derive(Default, Clone)]
 pub struct ReadinessIndicator {
-    is_ready: Arc<AtomicBool>,
+    readiness: Arc<RwLock<Readiness>>,
+}
+
+/// The states a `ReadinessIndicator` can be in.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Readiness {
+    /// The server is not ready to serve requests.
+    NotReady,
+
+    /// The server serves requests, but is only partially functional, for the
+    /// given reason, if any.
+    Degraded(Option<String>),
+
+    /// The server is ready.
+    Ready,
+}
+
+impl Default for Readiness {
+    fn default() -> Self {
+        Self::NotReady
+    }
+}
+
+impl From<&Readiness> for HealthCheckStatus {
+    fn from(src: &Readiness) -> Self {
+        match src {
+            Readiness::NotReady => HealthCheckStatus::NOT_SERVING,
+            Readiness::Degraded(_) => HealthCheckStatus::DEGRADED,
+            Readiness::Ready => HealthCheckStatus::SERVING,
+        }
+    }
 }
 
 impl ReadinessIndicator {
     /// Set the status to ready
     pub fn set_ready(&self) {
-        self.is_ready.store(true, Ordering::SeqCst);
+        self.set(Readiness::Ready);
+    }
+
+    /// Set the status to degraded, optionally with the reason why
+    pub fn set_degraded(&self, reason: Option<String>) {
+        self.set(Readiness::Degraded(reason));
     }
 
     /// Set the status to unready
     pub fn set_unready(&self) {
-        self.is_ready.store(false, Ordering::SeqCst);
+        self.set(Readiness::NotReady);
     }
 
-    /// Check the status
+    /// Check whether the status is ready
     pub fn ready(&self) -> bool {
-        self.is_ready.load(Ordering::SeqCst)
+        self.readiness() == Readiness::Ready
+    }
+
+    /// Get the status
+    pub fn readiness(&self) -> Readiness {
+        self.readiness.read().expect("lock poisoned").clone()
+    }
+
+    fn set(&self, readiness: Readiness) {
+        *self.readiness.write().expect("lock poisoned") = readiness;
     }
 }
 
 impl From<ReadinessIndicator> for ServiceHealthCheckCallback {
     fn from(src: ReadinessIndicator) -> Self {
-        Arc::new(move |_| -> HealthCheckStatus {
-            if src.ready() {
-                HealthCheckStatus::SERVING
-            } else {
-                HealthCheckStatus::NOT_SERVING
+        Arc::new(move |_| -> HealthCheckStatus { (&src.readiness()).into() })
+    }
+}
+
+/// Tracks the active subscriptions of `Watch` streams, up to a maximum.
+#[derive(Clone)]
+pub struct WatchSubscriptions {
+    active: Arc<AtomicUsize>,
+    max: usize,
+}
+
+impl Default for WatchSubscriptions {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_WATCHERS)
+    }
+}
+
+impl WatchSubscriptions {
+    /// Track subscriptions, allowing at most `max` to be active at once.
+    pub fn new(max: usize) -> Self {
+        Self {
+            active: Default::default(),
+            max,
+        }
+    }
+
+    /// Register a new subscription, which stays active until it is dropped.
+    ///
+    /// Returns None if `max` subscriptions are already active.
+    pub fn subscribe(&self) -> Option<WatchSubscription> {
+        self.active
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |active| {
+                if active < self.max {
+                    Some(active + 1)
+                } else {
+                    None
+                }
+            })
+            .ok()?;
+        OP_COUNTERS.gauge("health_active_watchers").inc();
+        Some(WatchSubscription {
+            active: self.active.clone(),
+        })
+    }
+
+    /// The number of active subscriptions.
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    /// The maximum number of active subscriptions.
+    pub fn max(&self) -> usize {
+        self.max
+    }
+}
+
+/// An active subscription of a `Watch` stream, which is unregistered when
+/// dropped.
+pub struct WatchSubscription {
+    active: Arc<AtomicUsize>,
+}
+
+impl Drop for WatchSubscription {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::SeqCst);
+        OP_COUNTERS.gauge("health_active_watchers").dec();
+    }
+}
+
+/// A warm-up indicator can be used when your server is technically up right
+/// after it binds, but performs poorly until e.g. its caches are warm.
+///
+/// The server is considered cold for a fixed duration after the indicator is
+/// created, or until `set_done` is called, whichever comes first. While cold,
+/// the health check callback returns "NOT_SERVING", so that no traffic is
+/// routed to the server.
+#[derive(Clone)]
+pub struct WarmUpIndicator<TP: TimeProvider> {
+    /// The time since the epoch at which the warm-up period ends.
+    warm_at: Option<Duration>,
+
+    /// Whether warm-up was signaled to be done early.
+    is_done: Arc<AtomicBool>,
+
+    /// Time provider.
+    time_provider: TP,
+}
+
+impl<TP: TimeProvider> WarmUpIndicator<TP> {
+    /// Create a new warm-up indicator, starting a warm-up period now.
+    ///
+    /// Arguments:
+    /// * warm_up: How long the server is considered cold for.
+    /// * time_provider: A generic object that provides "Duration since the
+    ///   epoch"
+    pub fn new(warm_up: Duration, time_provider: TP) -> Self {
+        let warm_at = time_provider
+            .since_epoch()
+            .ok()
+            .and_then(|now| now.checked_add(warm_up));
+        Self {
+            warm_at,
+            is_done: Default::default(),
+            time_provider,
+        }
+    }
+
+    /// Signal that warm-up is done, before the warm-up period elapsed.
+    pub fn set_done(&self) {
+        self.is_done.store(true, Ordering::SeqCst);
+    }
+
+    /// Check whether warm-up is done.
+    ///
+    /// If the current time is not available, the server is considered cold
+    /// until `set_done` is called.
+    pub fn is_warm(&self) -> bool {
+        if self.is_done.load(Ordering::SeqCst) {
+            return true;
+        }
+        match (self.warm_at, self.time_provider.since_epoch()) {
+            (Some(warm_at), Ok(now)) => now >= warm_at,
+            _ => false,
+        }
+    }
+
+    /// Wrap a health check callback so that it is only consulted once warm.
+    /// Without a callback, the server is SERVING once warm.
+    pub fn wrap(self, callback: Option<ServiceHealthCheckCallback>) -> ServiceHealthCheckCallback
+    where
+        TP: 'static,
+    {
+        Arc::new(move |service_name| -> HealthCheckStatus {
+            if !self.is_warm() {
+                return HealthCheckStatus::NOT_SERVING;
+            }
+            match &callback {
+                None => HealthCheckStatus::SERVING,
+                Some(callback) => callback(service_name),
             }
         })
     }
 }
+
+impl<TP: TimeProvider + 'static> From<WarmUpIndicator<TP>> for ServiceHealthCheckCallback {
+    fn from(src: WarmUpIndicator<TP>) -> Self {
+        src.wrap(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health_api_grpc::HealthClient;
+    use futures::executor::block_on;
+    use grpcio::{ChannelBuilder, EnvBuilder, ServerBuilder};
+    use mc_common::{logger::test_with_logger, time::MockTimeProvider};
+
+    const WARM_UP: Duration = Duration::from_secs(30);
+
+    #[test]
+    fn watch_subscriptions_are_released_on_drop() {
+        let watchers = WatchSubscriptions::default();
+        assert_eq!(watchers.active(), 0);
+
+        let first = watchers.subscribe().unwrap();
+        let second = watchers.clone().subscribe().unwrap();
+        assert_eq!(watchers.active(), 2);
+
+        drop(first);
+        assert_eq!(watchers.active(), 1);
+        drop(second);
+        assert_eq!(watchers.active(), 0);
+    }
+
+    #[test]
+    fn watch_subscriptions_are_capped() {
+        let watchers = WatchSubscriptions::new(2);
+        let first = watchers.subscribe().unwrap();
+        let _second = watchers.subscribe().unwrap();
+        assert!(watchers.subscribe().is_none());
+        assert_eq!(watchers.active(), 2);
+
+        // Releasing a subscription makes room for another one.
+        drop(first);
+        assert!(watchers.subscribe().is_some());
+        assert_eq!(watchers.active(), 1);
+    }
+
+    #[test_with_logger]
+    fn per_service_statuses(logger: Logger) {
+        let health = HealthService::new(None, logger);
+        assert_eq!(health.serving_status(""), HealthCheckStatus::SERVING);
+        assert_eq!(
+            health.serving_status("unknown"),
+            HealthCheckStatus::NOT_SERVING
+        );
+
+        // Statuses are shared with clones, e.g. the one registered with a server.
+        health.clone().set_serving_status(
+            "consensus_client.ConsensusClientAPI",
+            HealthCheckStatus::SERVING,
+        );
+        health.set_serving_status("attest.AttestedApi", HealthCheckStatus::NOT_SERVING);
+        assert_eq!(
+            health.serving_status("consensus_client.ConsensusClientAPI"),
+            HealthCheckStatus::SERVING
+        );
+        assert_eq!(
+            health.serving_status("attest.AttestedApi"),
+            HealthCheckStatus::NOT_SERVING
+        );
+        assert_eq!(
+            health.serving_status("unknown"),
+            HealthCheckStatus::NOT_SERVING
+        );
+        assert_eq!(health.serving_status(""), HealthCheckStatus::SERVING);
+
+        health.set_serving_status("attest.AttestedApi", HealthCheckStatus::SERVING);
+        assert_eq!(
+            health.serving_status("attest.AttestedApi"),
+            HealthCheckStatus::SERVING
+        );
+
+        // The overall status can be overridden too.
+        health.set_serving_status("", HealthCheckStatus::NOT_SERVING);
+        assert_eq!(health.serving_status(""), HealthCheckStatus::NOT_SERVING);
+    }
+
+    #[test_with_logger]
+    fn draining_overrides_all_statuses(logger: Logger) {
+        let health = HealthService::new(None, logger);
+        health.set_serving_status("attest.AttestedApi", HealthCheckStatus::SERVING);
+        assert!(!health.is_draining());
+
+        // Draining is shared with clones, e.g. the one registered with a server.
+        health.clone().begin_drain();
+        assert!(health.is_draining());
+        assert_eq!(health.serving_status(""), HealthCheckStatus::NOT_SERVING);
+        assert_eq!(
+            health.serving_status("attest.AttestedApi"),
+            HealthCheckStatus::NOT_SERVING
+        );
+    }
+
+    #[test_with_logger]
+    fn per_service_statuses_take_precedence_over_callback(logger: Logger) {
+        let readiness = ReadinessIndicator::default();
+        let health = HealthService::new(Some(readiness.clone().into()), logger);
+        health.set_serving_status("ready.Api", HealthCheckStatus::SERVING);
+
+        // The callback decides the overall status, and that of services without
+        // a status of their own.
+        assert_eq!(health.serving_status(""), HealthCheckStatus::NOT_SERVING);
+        assert_eq!(
+            health.serving_status("other.Api"),
+            HealthCheckStatus::NOT_SERVING
+        );
+        assert_eq!(
+            health.serving_status("ready.Api"),
+            HealthCheckStatus::SERVING
+        );
+
+        readiness.set_ready();
+        assert_eq!(health.serving_status(""), HealthCheckStatus::SERVING);
+        assert_eq!(
+            health.serving_status("other.Api"),
+            HealthCheckStatus::SERVING
+        );
+    }
+
+    #[test]
+    fn warm_up_elapses() {
+        let time_provider = MockTimeProvider::default();
+        let start = time_provider.since_epoch().unwrap();
+        let callback =
+            ServiceHealthCheckCallback::from(WarmUpIndicator::new(WARM_UP, time_provider.clone()));
+        assert_eq!(callback(""), HealthCheckStatus::NOT_SERVING);
+
+        time_provider.set_cur_since_epoch(start + WARM_UP - Duration::from_secs(1));
+        assert_eq!(callback(""), HealthCheckStatus::NOT_SERVING);
+
+        time_provider.set_cur_since_epoch(start + WARM_UP);
+        assert_eq!(callback(""), HealthCheckStatus::SERVING);
+    }
+
+    #[test]
+    fn warm_up_done_early() {
+        let warm_up = WarmUpIndicator::new(WARM_UP, MockTimeProvider::default());
+        let callback = ServiceHealthCheckCallback::from(warm_up.clone());
+        assert!(!warm_up.is_warm());
+        assert_eq!(callback(""), HealthCheckStatus::NOT_SERVING);
+
+        warm_up.set_done();
+        assert!(warm_up.is_warm());
+        assert_eq!(callback(""), HealthCheckStatus::SERVING);
+    }
+
+    #[test]
+    fn warm_up_defers_to_readiness_once_warm() {
+        let time_provider = MockTimeProvider::default();
+        let start = time_provider.since_epoch().unwrap();
+        let readiness = ReadinessIndicator::default();
+        readiness.set_ready();
+
+        let callback = WarmUpIndicator::new(WARM_UP, time_provider.clone())
+            .wrap(Some(readiness.clone().into()));
+        assert_eq!(callback(""), HealthCheckStatus::NOT_SERVING);
+
+        time_provider.set_cur_since_epoch(start + WARM_UP);
+        assert_eq!(callback(""), HealthCheckStatus::SERVING);
+
+        readiness.set_unready();
+        assert_eq!(callback(""), HealthCheckStatus::NOT_SERVING);
+    }
+
+    #[test_with_logger]
+    fn readiness_transitions_are_reported(logger: Logger) {
+        let readiness = ReadinessIndicator::default();
+        let health = HealthService::new(Some(readiness.clone().into()), logger);
+        assert_eq!(readiness.readiness(), Readiness::NotReady);
+        assert!(!readiness.ready());
+        assert_eq!(health.serving_status(""), HealthCheckStatus::NOT_SERVING);
+
+        readiness.set_degraded(Some("catching up".to_owned()));
+        assert_eq!(
+            readiness.readiness(),
+            Readiness::Degraded(Some("catching up".to_owned()))
+        );
+        assert!(!readiness.ready());
+        assert_eq!(health.serving_status(""), HealthCheckStatus::DEGRADED);
+
+        readiness.set_ready();
+        assert!(readiness.ready());
+        assert_eq!(health.serving_status(""), HealthCheckStatus::SERVING);
+
+        readiness.set_degraded(None);
+        assert_eq!(readiness.readiness(), Readiness::Degraded(None));
+        assert_eq!(health.serving_status(""), HealthCheckStatus::DEGRADED);
+
+        readiness.set_unready();
+        assert_eq!(health.serving_status(""), HealthCheckStatus::NOT_SERVING);
+    }
+
+    /// Wait up to `timeout` for `condition` to hold.
+    fn wait_until(timeout: Duration, condition: impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + timeout;
+        while !condition() {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+
+    #[test_with_logger]
+    fn watch_streams_status_changes_and_cleans_up(logger: Logger) {
+        let health = HealthService::new(None, logger);
+        let env = Arc::new(EnvBuilder::new().build());
+        let mut server = ServerBuilder::new(env.clone())
+            .register_service(health.clone().into_service())
+            .bind("127.0.0.1", 0)
+            .build()
+            .unwrap();
+        server.start();
+        let port = server.bind_addrs().next().unwrap().1;
+        let channel = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+        let client = HealthClient::new(channel);
+
+        let mut request = HealthCheckRequest::new();
+        request.set_service("test.Api".to_owned());
+        health.set_serving_status("test.Api", HealthCheckStatus::SERVING);
+        let mut stream = client.watch(&request).unwrap();
+
+        // The current status is sent right away, and then again on changes.
+        let first = block_on(stream.next()).unwrap().unwrap();
+        assert_eq!(first.get_status(), HealthCheckStatus::SERVING);
+        assert_eq!(health.active_watchers(), 1);
+
+        health.set_serving_status("test.Api", HealthCheckStatus::NOT_SERVING);
+        let second = block_on(stream.next()).unwrap().unwrap();
+        assert_eq!(second.get_status(), HealthCheckStatus::NOT_SERVING);
+
+        // The client goes away. The server notices on its next send, after
+        // which the polling thread exits and releases the subscription.
+        stream.cancel();
+        drop(stream);
+        health.set_serving_status("test.Api", HealthCheckStatus::SERVING);
+        assert!(wait_until(Duration::from_secs(5), || health
+            .active_watchers()
+            == 0));
+    }
+}
@@ -0,0 +1,116 @@
+// Copyright (c) 2018-2022 The MobileCoin Foundation
+
+//! Conversion of a service's domain errors into `RpcStatus`es, in one place.
+
+use grpcio::{RpcStatus, RpcStatusCode};
+use mc_common::logger::{log, Level, Logger};
+use std::fmt::Display;
+
+/// An error which knows how it should be reported to grpc clients.
+///
+/// Implementing this for a service's error enum centralizes the choice of
+/// status code and log level per variant, which the `rpc_*_error` helpers
+/// otherwise leave to each call site, so handlers can just do
+/// `result.map_err(|err| err.into_rpc_status(&logger))`. The
+/// `impl_into_rpc_status!` macro implements it from a list of variant
+/// patterns.
+pub trait IntoRpcStatus: Display {
+    /// The status code to report this error with, and the level to log it at.
+    fn rpc_status_code(&self) -> (RpcStatusCode, Level);
+
+    /// Log this error, and convert it into an `RpcStatus` whose message is
+    /// the error's `Display` output.
+    fn into_rpc_status(self, logger: &Logger) -> RpcStatus
+    where
+        Self: Sized,
+    {
+        let (code, level) = self.rpc_status_code();
+        let message = self.to_string();
+        log::log!(logger, level, "", "{}", message);
+        RpcStatus::with_message(code, message)
+    }
+}
+
+/// Implement `IntoRpcStatus` for an error type, given the status code and log
+/// level for each pattern of its values.
+///
+/// ```
+/// use displaydoc::Display;
+/// use grpcio::RpcStatusCode;
+/// use mc_common::logger::Level;
+/// use mc_util_grpc::impl_into_rpc_status;
+///
+/// #[derive(Debug, Display)]
+/// enum Error {
+///     /// Bad request: {0}
+///     BadRequest(String),
+///     /// Database error
+///     Database,
+/// }
+///
+/// impl_into_rpc_status!(Error {
+///     Error::BadRequest(_) => (RpcStatusCode::INVALID_ARGUMENT, Level::Debug),
+///     Error::Database => (RpcStatusCode::INTERNAL, Level::Error),
+/// });
+/// ```
+#[macro_export]
+macro_rules! impl_into_rpc_status {
+    ($ty:ty { $($pattern:pat => $code_and_level:expr),+ $(,)? }) => {
+        impl $crate::IntoRpcStatus for $ty {
+            fn rpc_status_code(
+                &self,
+            ) -> (::grpcio::RpcStatusCode, ::mc_common::logger::Level) {
+                match self {
+                    $($pattern => $code_and_level,)+
+                }
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use displaydoc::Display;
+    use mc_common::logger::test_with_logger;
+
+    #[derive(Debug, Display)]
+    enum SampleError {
+        /// Key image {0} is malformed
+        Malformed(u64),
+        /// Not allowed
+        NotAllowed,
+        /// Database failure: {0}
+        Database(String),
+    }
+
+    impl_into_rpc_status!(SampleError {
+        SampleError::Malformed(_) => (RpcStatusCode::INVALID_ARGUMENT, Level::Debug),
+        SampleError::NotAllowed => (RpcStatusCode::PERMISSION_DENIED, Level::Debug),
+        SampleError::Database(_) => (RpcStatusCode::INTERNAL, Level::Error),
+    });
+
+    #[test_with_logger]
+    fn variants_map_to_their_codes(logger: Logger) {
+        let status = SampleError::Malformed(3).into_rpc_status(&logger);
+        assert_eq!(status.code(), RpcStatusCode::INVALID_ARGUMENT);
+        assert_eq!(status.message(), "Key image 3 is malformed");
+
+        let status = SampleError::NotAllowed.into_rpc_status(&logger);
+        assert_eq!(status.code(), RpcStatusCode::PERMISSION_DENIED);
+        assert_eq!(status.message(), "Not allowed");
+
+        let status = SampleError::Database("connection reset".to_owned()).into_rpc_status(&logger);
+        assert_eq!(status.code(), RpcStatusCode::INTERNAL);
+        assert_eq!(status.message(), "Database failure: connection reset");
+    }
+
+    #[test_with_logger]
+    fn handlers_can_map_results(logger: Logger) {
+        let result: Result<(), SampleError> = Err(SampleError::NotAllowed);
+        let status = result
+            .map_err(|err| err.into_rpc_status(&logger))
+            .unwrap_err();
+        assert_eq!(status.code(), RpcStatusCode::PERMISSION_DENIED);
+    }
+}
@@ -24,47 +24,161 @@ mod admin_server;
 mod admin_service;
 mod auth;
 mod build_info_service;
+mod cancellation;
+mod channel_state_monitor;
+mod client_concurrency_limiter;
+mod concurrency_limiter;
 mod cookie_helper;
+mod counters;
+#[cfg(feature = "signal")]
+mod graceful_shutdown;
 mod grpcio_extensions;
 mod health_service;
+mod into_rpc_status;
 mod retry_config;
 mod server_cert_reloader;
+mod services_hash;
+mod status_details;
+#[cfg(test)]
+mod test_fixtures;
 
 pub use crate::{
-    admin_server::AdminServer,
-    admin_service::{AdminService, GetConfigJsonFn},
+    admin_server::{
+        AdminServer, AdminServerBuilder, AdminServerBuilderError, HEALTH_WATCHERS_COMMAND,
+    },
+    admin_service::{AdminCommandFn, AdminService, GetConfigJsonFn, REDACTED_VALUE},
     auth::{
-        AnonymousAuthenticator, Authenticator, AuthenticatorError, AuthorizationHeaderError,
-        BasicCredentials, TokenAuthenticator, TokenBasicCredentialsGenerator,
-        TokenBasicCredentialsGeneratorError, ANONYMOUS_USER,
+        ed25519_token_identity, peer_certificate, peer_client_cert_subject, AnonymousAuthenticator,
+        Authenticator, AuthenticatorError, AuthorizationHeaderError, BasicCredentials,
+        CertIdentity, ClientCert, ClockDrift, ClockDriftMonitor, JwtAuthenticator,
+        MeteredAuthenticator, MutualTlsAuthenticator, ShadowAuthenticator, TokenAuthenticator,
+        TokenBasicCredentialsGenerator, TokenBasicCredentialsGeneratorError, ANONYMOUS_USER,
     },
     autogenerated_code::*,
-    build_info_service::BuildInfoService,
-    cookie_helper::{Error as CookieError, GrpcCookieStore},
-    grpcio_extensions::{ConnectionUriGrpcioChannel, ConnectionUriGrpcioServer},
-    health_service::{HealthCheckStatus, HealthService, ReadinessIndicator},
-    retry_config::GrpcRetryConfig,
-    server_cert_reloader::{ServerCertReloader, ServerCertReloaderError},
+    build_info_service::{require_build, BuildInfoService, BuildMismatch},
+    cancellation::{CallOptionTimeout, CancellationToken, DeadlineLimit, DeadlinePolicy},
+    channel_state_monitor::{ChannelStateGauge, ChannelStateMonitor},
+    client_concurrency_limiter::{ClientConcurrencyLimiter, ClientConcurrencyPermit},
+    concurrency_limiter::{ConcurrencyLimiter, ConcurrencyPermit},
+    cookie_helper::{parse_same_site, CookieAttributes, Error as CookieError, GrpcCookieStore},
+    grpcio_extensions::{
+        ChannelOptions, ConnectionUriGrpcioChannel, ConnectionUriGrpcioServer, ResolvingChannel,
+        ServerOptions, ServiceResolverError, ServiceResolverFn, DEFAULT_MAX_CONCURRENT_STREAMS,
+    },
+    health_service::{
+        HealthCheckStatus, HealthService, Readiness, ReadinessIndicator, WarmUpIndicator,
+        WatchSubscription, WatchSubscriptions,
+    },
+    into_rpc_status::IntoRpcStatus,
+    retry_config::{
+        GrpcRetryConfig, GrpcRetryConfigParseError, GrpcRetryError, RetriableCodes, RetryJitter,
+    },
+    server_cert_reloader::{ReloadHandle, ServerCertReloader, ServerCertReloaderError},
+    services_hash::services_hash,
+    status_details::{RpcStatusDetails, ERROR_INFO_DOMAIN},
 };
 
+#[cfg(feature = "signal")]
+pub use crate::graceful_shutdown::run_until_signal;
+
 use futures::prelude::*;
-use grpcio::{RpcContext, RpcStatus, RpcStatusCode, UnarySink};
+use grpcio::{
+    Metadata, RpcContext, RpcStatus, RpcStatusCode, ServerStreamingSink, UnarySink, WriteFlags,
+};
 use mc_common::logger::{log, o, Level, Logger};
 use mc_util_metrics::SVC_COUNTERS;
 use rand::Rng;
 use std::{
     fmt::Display,
-    sync::atomic::{AtomicU64, Ordering},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
+/// Whether `send_result` logs the full status of error responses.
+static LOG_STATUS_DETAILS: AtomicBool = AtomicBool::new(false);
+
+/// Enable or disable logging of the full status (code, message and any
+/// attached `RpcStatusDetails`) of the error responses sent by `send_result`.
+///
+/// This only affects the server's logs: what is sent to the client is
+/// unchanged.
+pub fn set_log_status_details(enabled: bool) {
+    LOG_STATUS_DETAILS.store(enabled, Ordering::SeqCst);
+}
+
+/// Log the full status of an error response. Server faults are logged at error
+/// level, and everything else at debug level, since it is usually caused by
+/// clients.
+fn log_status_details<T>(resp: &Result<T, RpcStatus>, logger: &Logger) {
+    let status = match resp {
+        Ok(_) => return,
+        Err(status) => status,
+    };
+
+    let level = match status.code() {
+        RpcStatusCode::INTERNAL | RpcStatusCode::UNKNOWN | RpcStatusCode::DATA_LOSS => Level::Error,
+        _ => Level::Debug,
+    };
+    let details = match RpcStatusDetails::from_rpc_status(status) {
+        Ok(details) => format!("{:?}", details),
+        Err(err) => format!("undecodable ({} bytes): {}", status.details().len(), err),
+    };
+    log::log!(
+        logger,
+        level,
+        "",
+        "Sending error response: code={:?} message={:?} details={}",
+        status.code(),
+        status.message(),
+        details
+    );
+}
+
 /// Helper which reduces boilerplate when implementing grpc API traits.
+///
+/// The serialized size of successful responses is recorded in
+/// `SVC_COUNTERS`. Request sizes are not visible here, so handlers which want
+/// them should call `SVC_COUNTERS.request_size` themselves.
 #[inline]
-pub fn send_result<T>(
+pub fn send_result<T: protobuf::Message>(
     ctx: RpcContext,
     sink: UnarySink<T>,
     resp: Result<T, RpcStatus>,
     logger: &Logger,
 ) {
+    send_result_with_trailers(ctx, sink, resp, None, logger)
+}
+
+/// Like `send_result`, but also sends `metadata` to the client, e.g. a
+/// machine-readable error code or a retry-after hint.
+///
+/// Successful responses carry the metadata as response headers. grpcio
+/// clients only surface the status of failed calls, so there the metadata is
+/// added to the `ErrorInfo` of the status details instead, which travel in the
+/// `grpc-status-details-bin` trailer, and which clients read with
+/// `RpcStatusDetails::from_rpc_status`. Binary (`-bin`) values are base64
+/// encoded.
+pub fn send_result_with_trailers<T: protobuf::Message>(
+    ctx: RpcContext,
+    mut sink: UnarySink<T>,
+    resp: Result<T, RpcStatus>,
+    metadata: Option<Metadata>,
+    logger: &Logger,
+) {
+    let resp = match (resp, metadata) {
+        (Ok(ok), Some(metadata)) => {
+            sink.set_headers(metadata);
+            Ok(ok)
+        }
+        (Err(status), Some(metadata)) => Err(with_trailing_metadata(status, &metadata, logger)),
+        (resp, None) => resp,
+    };
+
+    if LOG_STATUS_DETAILS.load(Ordering::SeqCst) {
+        log_status_details(&resp, logger);
+    }
+
     let logger = logger.clone();
     let success = resp.is_ok();
     let code = match &resp {
@@ -73,11 +187,14 @@ pub fn send_result<T>(
     };
 
     match resp {
-        Ok(ok) => ctx.spawn(
-            sink.success(ok)
-                .map_err(move |err| log::error!(logger, "failed to reply: {}", err))
-                .map(|_| ()),
-        ),
+        Ok(ok) => {
+            SVC_COUNTERS.response_size(&ctx, ok.compute_size());
+            ctx.spawn(
+                sink.success(ok)
+                    .map_err(move |err| log::error!(logger, "failed to reply: {}", err))
+                    .map(|_| ()),
+            )
+        }
         Err(e) => ctx.spawn(
             sink.fail(e)
                 .map_err(move |err| log::error!(logger, "failed to reply: {}", err))
@@ -89,6 +206,88 @@ pub fn send_result<T>(
     SVC_COUNTERS.status_code(&ctx, code);
 }
 
+/// Add `metadata` to the `ErrorInfo` of the details carried by `status`.
+fn with_trailing_metadata(status: RpcStatus, metadata: &Metadata, logger: &Logger) -> RpcStatus {
+    let mut details = RpcStatusDetails::from_rpc_status(&status).unwrap_or_else(|err| {
+        log::warn!(logger, "Replacing undecodable status details: {}", err);
+        RpcStatusDetails::default()
+    });
+    for (key, value) in metadata.iter() {
+        let value = if key.ends_with("-bin") {
+            base64::encode(value)
+        } else {
+            String::from_utf8_lossy(value).into_owned()
+        };
+        details = details.metadata(key, &value);
+    }
+    details.into_rpc_status(status.code(), status.message().to_owned())
+}
+
+/// Helper which reduces boilerplate when implementing server-streaming grpc
+/// API methods.
+///
+/// Each `Ok` item is written to the sink, in order, until the first `Err`, at
+/// which point sending stops and the call fails with that status. If all the
+/// items are `Ok` (including when there are none), the stream is closed
+/// cleanly.
+pub fn send_stream_result<T, I>(
+    ctx: RpcContext,
+    mut sink: ServerStreamingSink<T>,
+    items: I,
+    logger: &Logger,
+) where
+    T: protobuf::Message + Send + 'static,
+    I: IntoIterator<Item = Result<T, RpcStatus>>,
+{
+    let mut messages = Vec::new();
+    let mut failure = None;
+    for item in items {
+        match item {
+            Ok(message) => {
+                SVC_COUNTERS.message(&message);
+                messages.push(message);
+            }
+            Err(status) => {
+                log::debug!(
+                    logger,
+                    "Failing stream after {} messages: {:?} {}",
+                    messages.len(),
+                    status.code(),
+                    status.message()
+                );
+                failure = Some(status);
+                break;
+            }
+        }
+    }
+
+    let success = failure.is_none();
+    let code = failure
+        .as_ref()
+        .map_or(RpcStatusCode::OK, |status| status.code());
+
+    let logger = logger.clone();
+    let send = async move {
+        let mut messages = stream::iter(
+            messages
+                .into_iter()
+                .map(|message| Ok::<_, grpcio::Error>((message, WriteFlags::default()))),
+        );
+        sink.send_all(&mut messages).await?;
+        match failure {
+            Some(status) => sink.fail(status).await,
+            None => sink.close().await,
+        }
+    };
+    ctx.spawn(
+        send.map_err(move |err| log::error!(logger, "failed to reply: {}", err))
+            .map(|_| ()),
+    );
+
+    SVC_COUNTERS.resp(&ctx, success);
+    SVC_COUNTERS.status_code(&ctx, code);
+}
+
 macro_rules! report_err_with_code(
     ($context:expr, $err:expr, $code:expr, $logger:expr, $log_level:expr) => {{
         let err_str = format!("{}: {}", $context, $err);
@@ -229,6 +428,53 @@ pub fn rpc_unavailable_error<S: Display, E: Display>(
     )
 }
 
+/// Aborted error may be returned if e.g. a client-specified test-and-set
+/// fails, and the client should retry at a higher level (see the guidance in
+/// `rpc_unavailable_error`).
+///
+/// This is logged at info level because it can happen in normal operation
+/// under contention, and doesn't indicate a problem with the servers.
+#[inline]
+pub fn rpc_aborted_error<S: Display, E: Display>(context: S, err: E, logger: &Logger) -> RpcStatus {
+    report_err_with_code!(context, err, RpcStatusCode::ABORTED, logger, Level::Info)
+}
+
+/// Resource exhausted error may be returned if e.g. a rate limit or quota is
+/// hit, or the server is out of capacity.
+///
+/// This is logged at warn level because repeated occurrences indicate a
+/// capacity problem that the operator may need to address.
+#[inline]
+pub fn rpc_resource_exhausted_error<S: Display, E: Display>(
+    context: S,
+    err: E,
+    logger: &Logger,
+) -> RpcStatus {
+    report_err_with_code!(
+        context,
+        err,
+        RpcStatusCode::RESOURCE_EXHAUSTED,
+        logger,
+        Level::Warning
+    )
+}
+
+/// Like `rpc_resource_exhausted_error`, but the status also carries a
+/// `RetryInfo`, asking the client to wait at least `retry_after` before
+/// retrying.
+pub fn rpc_resource_exhausted_error_with_retry<S: Display, E: Display>(
+    context: S,
+    err: E,
+    retry_after: Duration,
+    logger: &Logger,
+) -> RpcStatus {
+    let err_str = format!("{}: {}", context, err);
+    log::log!(logger, Level::Warning, "", "{}", err_str);
+    RpcStatusDetails::default()
+        .retry_after(retry_after)
+        .into_rpc_status(RpcStatusCode::RESOURCE_EXHAUSTED, err_str)
+}
+
 /// Converts a serialization Error to an RpcStatus error.
 pub fn ser_to_rpc_err(error: mc_util_serial::encode::Error, logger: &Logger) -> RpcStatus {
     rpc_internal_error("Serialization", error, logger)
@@ -254,33 +500,182 @@ pub fn decode_to_rpc_err(error: mc_util_serial::DecodeError, logger: &Logger) ->
 /// Handles a bunch of grpc boilerplate that was being copy pasted
 use grpcio::{Server, Service};
 
-/// Build and start a server composed of several services
+/// Build and start a server composed of several services, listening on
+/// `host:port`.
+///
+/// Use a loopback `host` (e.g. 127.0.0.1) for services which should only be
+/// reachable from the local machine, such as admin services.
+///
+/// Fails if the server cannot be built, e.g. because the port is in use.
 #[inline]
 pub fn run_server(
     env: std::sync::Arc<grpcio::Environment>,
     services: Vec<Service>,
+    host: IpAddr,
     port: u16,
     logger: &Logger,
-) -> Server {
+) -> Result<Server, grpcio::Error> {
     use grpcio::ServerBuilder;
 
-    // FIXME: This should default to localhost and you should have to provide the IP
     let mut server = ServerBuilder::new(env);
 
     for service in services {
         server = server.register_service(service);
     }
 
-    let mut server = server.bind("0.0.0.0", port).build().unwrap();
+    // grpcio joins the host and port with a colon, so IPv6 hosts need brackets.
+    let host = match host {
+        IpAddr::V4(host) => host.to_string(),
+        IpAddr::V6(host) => format!("[{}]", host),
+    };
+    let mut server = server.bind(host, port).build()?;
     server.start();
     for (host, port) in server.bind_addrs() {
         log::info!(logger, "API listening on {}:{}", host, port);
     }
-    server
+    Ok(server)
+}
+
+/// Build and start a server composed of several services, listening on
+/// `host:port`, panicking if that fails.
+#[inline]
+pub fn run_server_or_panic(
+    env: std::sync::Arc<grpcio::Environment>,
+    services: Vec<Service>,
+    host: IpAddr,
+    port: u16,
+    logger: &Logger,
+) -> Server {
+    run_server(env, services, host, port, logger)
+        .unwrap_or_else(|err| panic!("Failed to start server on {}:{}: {}", host, port, err))
+}
+
+/// Build and start a server composed of several services, listening on
+/// `host:port` using TLS.
+///
+/// The certificate and key are loaded by `cert_reloader`, and so are reloaded
+/// on SIGHUP or through its `reload_handle`, and picked up by the next
+/// handshake. If `cert_reloader` has a
+/// client CA bundle, clients must present a certificate signed by it.
+#[inline]
+pub fn run_tls_server(
+    env: std::sync::Arc<grpcio::Environment>,
+    services: Vec<Service>,
+    host: IpAddr,
+    port: u16,
+    cert_reloader: ServerCertReloader,
+    logger: &Logger,
+) -> Result<Server, grpcio::Error> {
+    use grpcio::ServerBuilder;
+
+    let mut server = ServerBuilder::new(env);
+
+    for service in services {
+        server = server.register_service(service);
+    }
+
+    // grpcio joins the host and port with a colon, so IPv6 hosts need brackets.
+    let host = match host {
+        IpAddr::V4(host) => host.to_string(),
+        IpAddr::V6(host) => format!("[{}]", host),
+    };
+    let request_type = cert_reloader.certificate_request_type();
+    let mut server = server
+        .bind_with_fetcher(host, port, Box::new(cert_reloader), request_type)
+        .build()?;
+    server.start();
+    for (host, port) in server.bind_addrs() {
+        log::info!(logger, "TLS API listening on {}:{}", host, port);
+    }
+    Ok(server)
+}
+
+/// Build and start a server composed of several services, listening on all
+/// interfaces.
+#[inline]
+pub fn run_server_all_interfaces(
+    env: std::sync::Arc<grpcio::Environment>,
+    services: Vec<Service>,
+    port: u16,
+    logger: &Logger,
+) -> Result<Server, grpcio::Error> {
+    run_server(
+        env,
+        services,
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        port,
+        logger,
+    )
+}
+
+/// A data-plane server and an admin server, run side by side on separate
+/// addresses by `run_server_with_private_admin`.
+pub struct SplitServer {
+    /// The server for the data-plane services.
+    pub data: Server,
+
+    /// The server for the admin and health services.
+    pub admin: Server,
+}
+
+impl SplitServer {
+    /// Shut down both servers, completing once both have shut down.
+    pub fn shutdown(&mut self) -> impl Future<Output = Result<(), grpcio::Error>> {
+        future::try_join(self.data.shutdown(), self.admin.shutdown()).map_ok(|_| ())
+    }
+}
+
+/// Build and start a server for the data-plane services on `data_addr`, and a
+/// separate server for the admin and health services on `admin_addr`.
+///
+/// This keeps the admin surface off of the public interface: `admin_addr` is
+/// usually a loopback address, while `data_addr` is public. Both servers share
+/// `env`. If either server fails to start, neither is left running.
+pub fn run_server_with_private_admin(
+    env: std::sync::Arc<grpcio::Environment>,
+    data_services: Vec<Service>,
+    data_addr: SocketAddr,
+    admin_services: Vec<Service>,
+    admin_addr: SocketAddr,
+    logger: &Logger,
+) -> Result<SplitServer, grpcio::Error> {
+    let admin = run_server(
+        env.clone(),
+        admin_services,
+        admin_addr.ip(),
+        admin_addr.port(),
+        logger,
+    )?;
+    let data = match run_server(env, data_services, data_addr.ip(), data_addr.port(), logger) {
+        Ok(data) => data,
+        Err(err) => {
+            let mut admin = admin;
+            if let Err(shutdown_err) = futures::executor::block_on(admin.shutdown()) {
+                log::warn!(logger, "Failed to shut down admin server: {}", shutdown_err);
+            }
+            return Err(err);
+        }
+    };
+    Ok(SplitServer { data, admin })
+}
+
+/// Check whether `addr:port` can currently be bound, by binding a TCP listener
+/// to it and immediately releasing it.
+///
+/// This is intended as a pre-flight check before `run_server`, so that a port
+/// conflict can be reported with a clear message rather than a grpcio error.
+/// Note that the result is only a snapshot: another process may still bind the
+/// address between this check and the server starting.
+pub fn is_addr_available(addr: &str, port: u16) -> bool {
+    std::net::TcpListener::bind((addr, port)).is_ok()
 }
 
 /// A utility method for injecting peer information into a logger, ideally
 /// making it easier to debug RPC-related interactions.
+///
+/// The logger carries a hashed `rpc_client_id`, a `rpc_request_id` unique to
+/// this call, and the `rpc_method` being called, e.g.
+/// `/grpc.health.v1.Health/Ping`.
 pub fn rpc_logger(ctx: &RpcContext, logger: &Logger) -> Logger {
     let hash =
         mc_common::fast_hash(format!("{}{}", *RPC_LOGGER_CLIENT_ID_SEED, ctx.peer()).as_bytes());
@@ -288,7 +683,39 @@ pub fn rpc_logger(ctx: &RpcContext, logger: &Logger) -> Logger {
 
     let request_id = RPC_LOGGER_REQUEST_ID_COUNTER.fetch_add(1, Ordering::SeqCst);
 
-    logger.new(o!("rpc_client_id" => hash_str, "rpc_request_id" => request_id))
+    let method = String::from_utf8_lossy(ctx.method()).into_owned();
+
+    logger.new(o!(
+        "rpc_client_id" => hash_str,
+        "rpc_request_id" => request_id,
+        "rpc_method" => method,
+    ))
+}
+
+/// The default length of the time buckets of `rpc_bucketed_client_id`.
+pub const DEFAULT_CLIENT_ID_BUCKET_LENGTH: Duration = Duration::from_secs(60 * 60);
+
+/// A hashed identifier for the client `peer`, which is stable within a time
+/// bucket of `bucket_length` (counting from the epoch), but changes across
+/// buckets. This allows counting unique clients per bucket without being able
+/// to correlate clients across buckets.
+///
+/// Like the `rpc_client_id` of `rpc_logger`, the hash is seeded with a value
+/// generated at startup, so ids cannot be computed from a known peer address.
+pub fn bucketed_client_id(peer: &str, since_epoch: Duration, bucket_length: Duration) -> String {
+    let bucket = since_epoch.as_secs() / bucket_length.as_secs().max(1);
+    let hash = mc_common::fast_hash(
+        format!("{}/{}/{}", *RPC_LOGGER_CLIENT_ID_SEED, bucket, peer).as_bytes(),
+    );
+    hex_fmt::HexFmt(hash).to_string()
+}
+
+/// The `bucketed_client_id` of the peer of `ctx`, for the current time.
+pub fn rpc_bucketed_client_id(ctx: &RpcContext, bucket_length: Duration) -> String {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    bucketed_client_id(&ctx.peer(), since_epoch, bucket_length)
 }
 
 lazy_static::lazy_static! {
@@ -305,3 +732,484 @@ lazy_static::lazy_static! {
 
     static ref RPC_LOGGER_REQUEST_ID_COUNTER: AtomicU64 = AtomicU64::new(1);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        build_info_grpc::BuildInfoApiClient,
+        health_api::{HealthCheckRequest, HealthCheckResponse, PingRequest, PingResponse},
+        health_api_grpc::HealthClient,
+        test_fixtures::TestHealth,
+    };
+    use futures::executor::block_on;
+    use grpcio::{ChannelBuilder, ChannelCredentialsBuilder, EnvBuilder};
+    use mc_common::logger::{
+        slog::{self, Drain, Key, Never, OwnedKVList, Record, Serializer, KV},
+        test_with_logger,
+    };
+    use mc_crypto_x509_test_vectors::{ok_self_signed_1, ok_self_signed_2};
+    use std::{
+        fmt,
+        net::{TcpListener, TcpStream},
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    /// A drain which keeps the level and message of every record.
+    #[derive(Clone, Default)]
+    struct CapturingDrain(Arc<Mutex<Vec<(Level, String)>>>);
+
+    impl Drain for CapturingDrain {
+        type Ok = ();
+        type Err = Never;
+
+        fn log(&self, record: &Record, _values: &OwnedKVList) -> Result<(), Never> {
+            self.0
+                .lock()
+                .unwrap()
+                .push((record.level(), record.msg().to_string()));
+            Ok(())
+        }
+    }
+
+    /// A drain which keeps the key-value pairs of every record's logger.
+    #[derive(Clone, Default)]
+    struct ContextCapturingDrain(Arc<Mutex<Vec<Vec<(String, String)>>>>);
+
+    /// Collects serialized key-value pairs as strings.
+    struct KvCollector(Vec<(String, String)>);
+
+    impl Serializer for KvCollector {
+        fn emit_arguments(&mut self, key: Key, val: &fmt::Arguments) -> slog::Result {
+            self.0.push((key.to_string(), val.to_string()));
+            Ok(())
+        }
+    }
+
+    impl Drain for ContextCapturingDrain {
+        type Ok = ();
+        type Err = Never;
+
+        fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), Never> {
+            let mut collector = KvCollector(Vec::new());
+            values
+                .serialize(record, &mut collector)
+                .expect("serializing to strings cannot fail");
+            self.0.lock().unwrap().push(collector.0);
+            Ok(())
+        }
+    }
+
+    /// The metadata key and value `metadata_health` sends.
+    const METADATA_KEY: &str = "x-error-code";
+    const METADATA_VALUE: &str = "try-later";
+
+    fn test_metadata() -> Metadata {
+        let mut builder = grpcio::MetadataBuilder::new();
+        builder.add_str(METADATA_KEY, METADATA_VALUE).unwrap();
+        builder.build()
+    }
+
+    /// A health service which attaches metadata to its results: checks fail,
+    /// and pings succeed.
+    fn metadata_health(logger: Logger) -> TestHealth {
+        let check_logger = logger.clone();
+        let ping_logger = logger.clone();
+        TestHealth::new(logger)
+            .with_check(move |ctx, _req, sink| {
+                let err = RpcStatus::with_message(RpcStatusCode::UNAVAILABLE, "busy".to_owned());
+                let metadata = Some(test_metadata());
+                send_result_with_trailers(ctx, sink, Err(err), metadata, &check_logger);
+            })
+            .with_ping(move |ctx, _req, sink| {
+                let resp = Ok(PingResponse::new());
+                let metadata = Some(test_metadata());
+                send_result_with_trailers(ctx, sink, resp, metadata, &ping_logger);
+            })
+    }
+
+    #[test_with_logger]
+    fn send_result_with_trailers_sends_metadata(logger: Logger) {
+        let (_server, client) = metadata_health(logger).start();
+
+        let has_metadata = |metadata: &Metadata| {
+            metadata
+                .iter()
+                .any(|(key, value)| key == METADATA_KEY && value == METADATA_VALUE.as_bytes())
+        };
+
+        let (headers, _resp, _trailers) = client
+            .ping_async_opt(&PingRequest::new(), grpcio::CallOption::default())
+            .unwrap()
+            .receive_sync()
+            .unwrap();
+        assert!(has_metadata(&headers));
+
+        // Failed calls carry the metadata in their trailing status details.
+        match client.check(&HealthCheckRequest::new()) {
+            Err(grpcio::Error::RpcFailure(status)) => {
+                assert_eq!(status.code(), RpcStatusCode::UNAVAILABLE);
+                assert_eq!(status.message(), "busy");
+                let details = RpcStatusDetails::from_rpc_status(&status).unwrap();
+                assert_eq!(
+                    details
+                        .get_metadata()
+                        .and_then(|metadata| metadata.get(METADATA_KEY))
+                        .map(String::as_str),
+                    Some(METADATA_VALUE)
+                );
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rpc_logger_includes_method() {
+        let drain = ContextCapturingDrain::default();
+        let logger = Logger::root(drain.clone(), o!());
+
+        // A health service whose pings log using `rpc_logger`.
+        let ping_logger = logger.clone();
+        let (_server, client) = TestHealth::new(logger)
+            .with_ping(move |ctx, _req, sink| {
+                let logger = rpc_logger(&ctx, &ping_logger);
+                log::info!(logger, "ping");
+                send_result(ctx, sink, Ok(PingResponse::new()), &logger);
+            })
+            .start();
+        client.ping(&PingRequest::new()).unwrap();
+
+        let records = drain.0.lock().unwrap();
+        let context = records.first().expect("nothing was logged");
+        let keys = context
+            .iter()
+            .map(|(key, _value)| key.as_str())
+            .collect::<Vec<_>>();
+        assert!(keys.contains(&"rpc_client_id"));
+        assert!(keys.contains(&"rpc_request_id"));
+        assert!(context.contains(&(
+            "rpc_method".to_owned(),
+            "/grpc.health.v1.Health/Ping".to_owned()
+        )));
+    }
+
+    #[test]
+    fn status_details_are_logged_for_errors_only() {
+        let drain = CapturingDrain::default();
+        let logger = Logger::root(drain.clone(), o!());
+
+        log_status_details(&Ok::<_, RpcStatus>(()), &logger);
+        assert!(drain.0.lock().unwrap().is_empty());
+
+        let status = RpcStatusDetails::with_reason("DB_DOWN")
+            .into_rpc_status(RpcStatusCode::INTERNAL, "Internal error".to_owned());
+        log_status_details(&Err::<(), _>(status), &logger);
+        let status = RpcStatus::with_message(RpcStatusCode::NOT_FOUND, "No such block".to_owned());
+        log_status_details(&Err::<(), _>(status), &logger);
+
+        let records = drain.0.lock().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].0, Level::Error);
+        assert!(records[0].1.contains("INTERNAL"));
+        assert!(records[0].1.contains("Internal error"));
+        assert!(records[0].1.contains("DB_DOWN"));
+        assert_eq!(records[1].0, Level::Debug);
+        assert!(records[1].1.contains("NOT_FOUND"));
+    }
+
+    /// Call `Watch` on a health service which streams `results`, returning
+    /// the statuses received and the error the stream ended with, if any.
+    fn watch_results(
+        results: Vec<Result<HealthCheckStatus, RpcStatusCode>>,
+        logger: Logger,
+    ) -> (Vec<HealthCheckStatus>, Option<RpcStatusCode>) {
+        let watch_logger = logger.clone();
+        let (_server, client) = TestHealth::new(logger)
+            .with_watch(move |ctx, _req, sink| {
+                let items = results.clone().into_iter().map(|result| {
+                    result
+                        .map(|status| {
+                            let mut resp = HealthCheckResponse::new();
+                            resp.set_status(status);
+                            resp
+                        })
+                        .map_err(RpcStatus::new)
+                });
+                send_stream_result(ctx, sink, items, &watch_logger);
+            })
+            .start();
+        let mut receiver = client.watch(&HealthCheckRequest::new()).unwrap();
+
+        let mut statuses = Vec::new();
+        while let Some(item) = block_on(receiver.next()) {
+            match item {
+                Ok(resp) => statuses.push(resp.get_status()),
+                Err(grpcio::Error::RpcFailure(status)) => return (statuses, Some(status.code())),
+                Err(err) => panic!("Unexpected error: {}", err),
+            }
+        }
+        (statuses, None)
+    }
+
+    #[test_with_logger]
+    fn send_stream_result_sends_items_in_order(logger: Logger) {
+        let expected = vec![
+            HealthCheckStatus::SERVING,
+            HealthCheckStatus::NOT_SERVING,
+            HealthCheckStatus::SERVICE_UNKNOWN,
+            HealthCheckStatus::SERVING,
+        ];
+        let results = expected.iter().cloned().map(Ok).collect();
+        assert_eq!(watch_results(results, logger), (expected, None));
+    }
+
+    #[test_with_logger]
+    fn send_stream_result_closes_empty_stream(logger: Logger) {
+        assert_eq!(watch_results(vec![], logger), (vec![], None));
+    }
+
+    #[test_with_logger]
+    fn send_stream_result_stops_at_first_error(logger: Logger) {
+        let results = vec![
+            Ok(HealthCheckStatus::SERVING),
+            Ok(HealthCheckStatus::NOT_SERVING),
+            Err(RpcStatusCode::RESOURCE_EXHAUSTED),
+            Ok(HealthCheckStatus::SERVING),
+            Err(RpcStatusCode::INTERNAL),
+        ];
+        assert_eq!(
+            watch_results(results, logger),
+            (
+                vec![HealthCheckStatus::SERVING, HealthCheckStatus::NOT_SERVING],
+                Some(RpcStatusCode::RESOURCE_EXHAUSTED)
+            )
+        );
+    }
+
+    #[test_with_logger]
+    fn run_server_binds_to_given_host(logger: Logger) {
+        let env = Arc::new(EnvBuilder::new().build());
+        let service = HealthService::new(None, logger.clone()).into_service();
+        let loopback = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let server = run_server(env, vec![service], loopback, 0, &logger).unwrap();
+        let (host, port) = server.bind_addrs().next().expect("No bind address");
+        assert_eq!(host.as_str(), "127.0.0.1");
+
+        let timeout = Duration::from_secs(1);
+        TcpStream::connect_timeout(&SocketAddr::new(loopback, port), timeout)
+            .expect("Could not connect via loopback");
+
+        // Nothing listens on the same port at any other address (on Linux,
+        // all of 127.0.0.0/8 is routed to the loopback interface).
+        let other = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2));
+        assert!(TcpStream::connect_timeout(&SocketAddr::new(other, port), timeout).is_err());
+    }
+
+    #[test_with_logger]
+    fn admin_is_only_served_on_its_own_port(logger: Logger) {
+        let env = Arc::new(EnvBuilder::new().build());
+        let data_service = BuildInfoService::new(logger.clone()).into_service();
+        let admin_service = HealthService::new(None, logger.clone()).into_service();
+        let loopback_any_port = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+        let mut servers = run_server_with_private_admin(
+            env.clone(),
+            vec![data_service],
+            loopback_any_port,
+            vec![admin_service],
+            loopback_any_port,
+            &logger,
+        )
+        .unwrap();
+        let data_port = servers.data.bind_addrs().next().unwrap().1;
+        let admin_port = servers.admin.bind_addrs().next().unwrap().1;
+        assert_ne!(data_port, admin_port);
+
+        let connect =
+            |port: u16| ChannelBuilder::new(env.clone()).connect(&format!("127.0.0.1:{}", port));
+        let code = |result: Result<_, grpcio::Error>| match result {
+            Ok(_) => RpcStatusCode::OK,
+            Err(grpcio::Error::RpcFailure(status)) => status.code(),
+            Err(err) => panic!("unexpected error: {}", err),
+        };
+
+        // Health is only served on the admin port.
+        let admin_health = HealthClient::new(connect(admin_port));
+        assert_eq!(
+            code(admin_health.ping(&PingRequest::new())),
+            RpcStatusCode::OK
+        );
+        let data_health = HealthClient::new(connect(data_port));
+        assert_eq!(
+            code(data_health.ping(&PingRequest::new())),
+            RpcStatusCode::UNIMPLEMENTED
+        );
+
+        // The data-plane service is only served on the data port.
+        let data_build_info = BuildInfoApiClient::new(connect(data_port));
+        assert_eq!(
+            code(data_build_info.get_build_info(&Empty::new())),
+            RpcStatusCode::OK
+        );
+        let admin_build_info = BuildInfoApiClient::new(connect(admin_port));
+        assert_eq!(
+            code(admin_build_info.get_build_info(&Empty::new())),
+            RpcStatusCode::UNIMPLEMENTED
+        );
+
+        block_on(servers.shutdown()).unwrap();
+    }
+
+    #[test_with_logger]
+    fn private_admin_is_not_left_running_on_failure(logger: Logger) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Could not bind");
+        let taken = listener.local_addr().expect("No local addr");
+        let admin_port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .expect("Could not find a free port")
+            .port();
+        let admin_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), admin_port);
+
+        let env = Arc::new(EnvBuilder::new().build());
+        let data_service = BuildInfoService::new(logger.clone()).into_service();
+        let admin_service = HealthService::new(None, logger.clone()).into_service();
+        assert!(run_server_with_private_admin(
+            env,
+            vec![data_service],
+            taken,
+            vec![admin_service],
+            admin_addr,
+            &logger,
+        )
+        .is_err());
+        assert!(is_addr_available("127.0.0.1", admin_port));
+    }
+
+    fn create_tls_client(cert: &str, ssl_target: &str, port: u16) -> HealthClient {
+        let env = Arc::new(EnvBuilder::new().build());
+        let cred = ChannelCredentialsBuilder::new()
+            .root_cert(cert.into())
+            .build();
+        let channel = ChannelBuilder::new(env)
+            .override_ssl_target(ssl_target)
+            .secure_connect(&format!("127.0.0.1:{}", port), cred);
+        HealthClient::new(channel)
+    }
+
+    fn ping(client: &HealthClient) -> Result<Vec<u8>, grpcio::Error> {
+        let mut req = PingRequest::new();
+        req.set_data(vec![1, 2, 3]);
+        client.ping(&req).map(|mut resp| resp.take_data())
+    }
+
+    #[test_with_logger]
+    fn run_tls_server_serves_tls(logger: Logger) {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let cert_file = temp_dir.path().join("server.crt");
+        let key_file = temp_dir.path().join("server.key");
+        let (server1_cert, server1_key) = ok_self_signed_1();
+        std::fs::write(&cert_file, &server1_cert).unwrap();
+        std::fs::write(&key_file, &server1_key).unwrap();
+
+        let env = Arc::new(EnvBuilder::new().build());
+        let service = HealthService::new(None, logger.clone()).into_service();
+        let reloader = ServerCertReloader::new(&cert_file, &key_file, logger.clone()).unwrap();
+        let loopback = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let server = run_tls_server(env, vec![service], loopback, 0, reloader, &logger).unwrap();
+        let port = server.bind_addrs().next().unwrap().1;
+
+        let client = create_tls_client(&server1_cert, "www.server1.com", port);
+        assert_eq!(ping(&client).expect("rpc"), vec![1, 2, 3]);
+
+        // The server does not accept plaintext connections.
+        let env = Arc::new(EnvBuilder::new().build());
+        let channel = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+        assert!(ping(&HealthClient::new(channel)).is_err());
+    }
+
+    #[test_with_logger]
+    fn run_tls_server_picks_up_new_certificate(logger: Logger) {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let cert_file = temp_dir.path().join("server.crt");
+        let key_file = temp_dir.path().join("server.key");
+        let (server1_cert, server1_key) = ok_self_signed_1();
+        let (server2_cert, server2_key) = ok_self_signed_2();
+        std::fs::write(&cert_file, &server1_cert).unwrap();
+        std::fs::write(&key_file, &server1_key).unwrap();
+
+        let env = Arc::new(EnvBuilder::new().build());
+        let service = HealthService::new(None, logger.clone()).into_service();
+        let reloader = ServerCertReloader::new(&cert_file, &key_file, logger.clone()).unwrap();
+        let reload_handle = reloader.reload_handle();
+        let loopback = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let server = run_tls_server(env, vec![service], loopback, 0, reloader, &logger).unwrap();
+        let port = server.bind_addrs().next().unwrap().1;
+
+        assert!(ping(&create_tls_client(&server1_cert, "www.server1.com", port)).is_ok());
+        assert!(ping(&create_tls_client(&server2_cert, "www.server2.com", port)).is_err());
+
+        // Swap the certificate on disk, and trigger reloading.
+        std::fs::write(&cert_file, &server2_cert).unwrap();
+        std::fs::write(&key_file, &server2_key).unwrap();
+        reload_handle.request_reload();
+
+        // Give the reloader time to pick up the changes.
+        std::thread::sleep(Duration::from_secs(2));
+
+        assert!(ping(&create_tls_client(&server2_cert, "www.server2.com", port)).is_ok());
+    }
+
+    #[test_with_logger]
+    fn run_server_fails_on_port_in_use(logger: Logger) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Could not bind");
+        let port = listener.local_addr().expect("No local addr").port();
+
+        let env = Arc::new(EnvBuilder::new().build());
+        let service = HealthService::new(None, logger.clone()).into_service();
+        let loopback = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        assert!(run_server(env, vec![service], loopback, port, &logger).is_err());
+    }
+
+    #[test]
+    fn is_addr_available_detects_bound_port() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Could not bind");
+        let port = listener.local_addr().expect("No local addr").port();
+        assert!(!is_addr_available("127.0.0.1", port));
+
+        drop(listener);
+        assert!(is_addr_available("127.0.0.1", port));
+    }
+
+    #[test]
+    fn bucketed_client_id_is_stable_within_bucket_only() {
+        const PEER: &str = "ipv4:10.0.0.1:53211";
+        let bucket_length = DEFAULT_CLIENT_ID_BUCKET_LENGTH;
+        let bucket_start = bucket_length * 1000;
+
+        let id = bucketed_client_id(PEER, bucket_start, bucket_length);
+        assert_eq!(
+            id,
+            bucketed_client_id(PEER, bucket_start + Duration::from_secs(1), bucket_length)
+        );
+        assert_eq!(
+            id,
+            bucketed_client_id(
+                PEER,
+                bucket_start + bucket_length - Duration::from_secs(1),
+                bucket_length
+            )
+        );
+
+        // The next bucket gets a different id.
+        assert_ne!(
+            id,
+            bucketed_client_id(PEER, bucket_start + bucket_length, bucket_length)
+        );
+
+        // Other peers get different ids.
+        assert_ne!(
+            id,
+            bucketed_client_id("ipv4:10.0.0.2:53211", bucket_start, bucket_length)
+        );
+    }
+}
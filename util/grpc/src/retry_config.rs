@@ -1,10 +1,17 @@
 // Copyright (c) 2018-2022 The MobileCoin Foundation
 #![deny(missing_docs)]
 
+use crate::counters::GRPC_RETRIES;
 use clap::Parser;
-use retry::delay;
-use serde::Serialize;
-use std::time::Duration;
+use displaydoc::Display;
+use grpcio::{CallOption, RpcStatusCode};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Serialize, Serializer};
+use std::{
+    fmt::{self, Display as FmtDisplay, Formatter},
+    str::FromStr,
+    time::Duration,
+};
 
 /// An object which represents a retry policy for retriable errors for a grpc
 /// connection
@@ -15,9 +22,38 @@ pub struct GrpcRetryConfig {
     pub grpc_retry_count: usize,
 
     /// How long to back off (milliseconds) when we get retriable errors (grpc
-    /// connection)
+    /// connection). This is the delay before the first retry.
     #[clap(long, default_value = "20", env = "MC_GRPC_RETRY_MILLIS")]
     pub grpc_retry_millis: u64,
+
+    /// The longest to back off (milliseconds) between retries, however many
+    /// times the delay was multiplied. Delays are never capped below
+    /// `grpc_retry_millis`.
+    #[clap(long, default_value = "10000", env = "MC_GRPC_RETRY_MAX_MILLIS")]
+    pub grpc_retry_max_millis: u64,
+
+    /// The factor the delay grows by after each retry. 1 keeps the delay
+    /// fixed.
+    #[clap(long, default_value = "1", env = "MC_GRPC_RETRY_MULTIPLIER")]
+    pub grpc_retry_multiplier: u32,
+
+    /// How to randomize delays, so that clients which failed together don't
+    /// retry together: `none`, `full` or `equal`
+    #[clap(long, default_value = "full", env = "MC_GRPC_RETRY_JITTER")]
+    pub grpc_retry_jitter: RetryJitter,
+
+    /// Whether calls should wait for the channel to be ready, instead of
+    /// failing fast while it is (re)connecting. Only use this for idempotent
+    /// calls, and together with a timeout, since a call waits for the channel
+    /// until its deadline.
+    #[clap(long, env = "MC_GRPC_WAIT_FOR_READY")]
+    pub grpc_wait_for_ready: bool,
+
+    /// The status codes of the errors `retry_grpc` retries, separated by `|`,
+    /// e.g. `UNAVAILABLE|DEADLINE_EXCEEDED`, or `ALL`. Errors without a status
+    /// code, e.g. connection errors, are always retried.
+    #[clap(long, default_value = "ALL", env = "MC_GRPC_RETRY_RETRIABLE")]
+    pub grpc_retry_retriable: RetriableCodes,
 }
 
 impl Default for GrpcRetryConfig {
@@ -25,16 +61,236 @@ impl Default for GrpcRetryConfig {
         Self {
             grpc_retry_count: 3,
             grpc_retry_millis: 20,
+            grpc_retry_max_millis: 10_000,
+            grpc_retry_multiplier: 1,
+            grpc_retry_jitter: RetryJitter::Full,
+            grpc_wait_for_ready: false,
+            grpc_retry_retriable: RetriableCodes::ALL,
+        }
+    }
+}
+
+/// How retry delays are randomized.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+pub enum RetryJitter {
+    /// Use the computed delay as is.
+    None,
+
+    /// Pick a delay between zero and the computed delay.
+    Full,
+
+    /// Pick a delay between half of the computed delay and all of it.
+    Equal,
+}
+
+impl RetryJitter {
+    /// Randomize a delay of `millis` milliseconds.
+    fn apply<R: Rng>(&self, millis: u64, rng: &mut R) -> u64 {
+        match self {
+            Self::None => millis,
+            Self::Full => rng.gen_range(0..=millis),
+            Self::Equal => millis - millis / 2 + rng.gen_range(0..=millis / 2),
+        }
+    }
+}
+
+impl FromStr for RetryJitter {
+    type Err = GrpcRetryConfigParseError;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        match src {
+            "none" => Ok(Self::None),
+            "full" => Ok(Self::Full),
+            "equal" => Ok(Self::Equal),
+            _ => Err(GrpcRetryConfigParseError::InvalidValue(
+                "jitter".to_string(),
+                src.to_string(),
+            )),
+        }
+    }
+}
+
+impl FmtDisplay for RetryJitter {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let name = match self {
+            Self::None => "none",
+            Self::Full => "full",
+            Self::Equal => "equal",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The names of the grpc status codes, indexed by code.
+const STATUS_CODE_NAMES: [&str; 17] = [
+    "OK",
+    "CANCELLED",
+    "UNKNOWN",
+    "INVALID_ARGUMENT",
+    "DEADLINE_EXCEEDED",
+    "NOT_FOUND",
+    "ALREADY_EXISTS",
+    "PERMISSION_DENIED",
+    "RESOURCE_EXHAUSTED",
+    "FAILED_PRECONDITION",
+    "ABORTED",
+    "OUT_OF_RANGE",
+    "UNIMPLEMENTED",
+    "INTERNAL",
+    "UNAVAILABLE",
+    "DATA_LOSS",
+    "UNAUTHENTICATED",
+];
+
+/// A set of grpc status codes, whose errors are retried.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct RetriableCodes(u32);
+
+impl RetriableCodes {
+    /// Every status code.
+    pub const ALL: Self = Self((1 << STATUS_CODE_NAMES.len()) - 1);
+
+    /// The set of the given status codes.
+    pub fn new(codes: impl IntoIterator<Item = RpcStatusCode>) -> Self {
+        Self(
+            codes
+                .into_iter()
+                .map(i32::from)
+                .filter(|code| (0..STATUS_CODE_NAMES.len() as i32).contains(code))
+                .fold(0, |bits, code| bits | 1 << code),
+        )
+    }
+
+    /// Whether `code` is in the set.
+    pub fn contains(&self, code: RpcStatusCode) -> bool {
+        let code = i32::from(code);
+        (0..STATUS_CODE_NAMES.len() as i32).contains(&code) && self.0 & (1 << code) != 0
+    }
+}
+
+impl FromStr for RetriableCodes {
+    type Err = GrpcRetryConfigParseError;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        if src == "ALL" {
+            return Ok(Self::ALL);
+        }
+        let mut bits = 0;
+        for name in src.split('|').map(str::trim) {
+            let code = STATUS_CODE_NAMES
+                .iter()
+                .position(|known| *known == name)
+                .ok_or_else(|| {
+                    GrpcRetryConfigParseError::InvalidValue(
+                        "retriable".to_string(),
+                        src.to_string(),
+                    )
+                })?;
+            bits |= 1 << code;
+        }
+        Ok(Self(bits))
+    }
+}
+
+impl FmtDisplay for RetriableCodes {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if *self == Self::ALL {
+            return write!(f, "ALL");
         }
+        let names = STATUS_CODE_NAMES
+            .iter()
+            .enumerate()
+            .filter(|(code, _)| self.0 & (1 << code) != 0)
+            .map(|(_, name)| *name)
+            .collect::<Vec<_>>();
+        write!(f, "{}", names.join("|"))
+    }
+}
+
+impl Serialize for RetriableCodes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
     }
 }
 
 impl GrpcRetryConfig {
+    /// Set how many times to retry.
+    pub fn with_retry_count(mut self, retry_count: usize) -> Self {
+        self.grpc_retry_count = retry_count;
+        self
+    }
+
+    /// Set the delay before the first retry.
+    pub fn with_base_delay(mut self, base_delay: Duration) -> Self {
+        self.grpc_retry_millis = duration_millis(base_delay);
+        self
+    }
+
+    /// Set the longest delay between retries.
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.grpc_retry_max_millis = duration_millis(max_delay);
+        self
+    }
+
+    /// Set the factor the delay grows by after each retry.
+    pub fn with_multiplier(mut self, multiplier: u32) -> Self {
+        self.grpc_retry_multiplier = multiplier;
+        self
+    }
+
+    /// Set how delays are randomized.
+    pub fn with_jitter(mut self, jitter: RetryJitter) -> Self {
+        self.grpc_retry_jitter = jitter;
+        self
+    }
+
+    /// Set whether calls should wait for the channel to be ready.
+    pub fn with_wait_for_ready(mut self, wait_for_ready: bool) -> Self {
+        self.grpc_wait_for_ready = wait_for_ready;
+        self
+    }
+
+    /// Set the status codes of the errors `retry_grpc` retries.
+    pub fn with_retriable(mut self, retriable: RetriableCodes) -> Self {
+        self.grpc_retry_retriable = retriable;
+        self
+    }
+
+    /// The delay before retry number `retry` (counting from zero), before
+    /// jitter is applied: the base delay, multiplied once per previous retry,
+    /// and capped at the max delay.
+    pub fn backoff_delay(&self, retry: usize) -> Duration {
+        let cap = self.grpc_retry_max_millis.max(self.grpc_retry_millis);
+        let factor = u32::try_from(retry)
+            .ok()
+            .and_then(|retry| u64::from(self.grpc_retry_multiplier).checked_pow(retry))
+            .unwrap_or(u64::MAX);
+        Duration::from_millis(self.grpc_retry_millis.saturating_mul(factor).min(cap))
+    }
+
+    /// The delays between retries, with jitter drawn from `rng`.
+    pub fn retry_delays<R: Rng>(&self, mut rng: R) -> impl Iterator<Item = Duration> {
+        let config = *self;
+        (0..self.grpc_retry_count).map(move |retry| {
+            let millis = duration_millis(config.backoff_delay(retry));
+            Duration::from_millis(config.grpc_retry_jitter.apply(millis, &mut rng))
+        })
+    }
+
     /// Get a duration iterator for use with retry crate based on this config
     pub fn get_retry_iterator(&self) -> impl Iterator<Item = Duration> {
-        delay::Fixed::from_millis(self.grpc_retry_millis)
-            .take(self.grpc_retry_count)
-            .map(delay::jitter)
+        self.retry_delays(StdRng::from_entropy())
+    }
+
+    /// Apply this config to the options of a call.
+    ///
+    /// With `grpc_wait_for_ready` set, the call is queued while the channel is
+    /// connecting or reconnecting, instead of failing with `UNAVAILABLE`, so
+    /// transient disconnects are handled at the transport layer rather than by
+    /// retries. The call still fails with `DEADLINE_EXCEEDED` once the timeout
+    /// of `call_option` passes; without a timeout, it waits indefinitely.
+    pub fn call_option(&self, call_option: CallOption) -> CallOption {
+        call_option.wait_for_ready(self.grpc_wait_for_ready)
     }
 
     /// Retry an operation using this retry config
@@ -45,4 +301,562 @@ impl GrpcRetryConfig {
     {
         retry::retry(self.get_retry_iterator(), operation)
     }
+
+    /// Retry a grpc call using this retry config, counting each retry by the
+    /// status code of the error which caused it.
+    ///
+    /// The operation may fail with any error which can carry a
+    /// `grpcio::Error`, e.g. that of a call over an attested connection, and
+    /// may decide which errors are retriable by returning an
+    /// `OperationResult`. Failed calls whose status code is not in
+    /// `grpc_retry_retriable` are not retried.
+    pub fn retry_grpc<O, R, E, OR>(&self, mut operation: O) -> Result<R, retry::Error<E>>
+    where
+        O: FnMut() -> OR,
+        OR: Into<retry::OperationResult<R, E>>,
+        E: GrpcRetryError,
+    {
+        let mut last_error_label = None;
+        self.retry(|| {
+            if let Some(label) = last_error_label.take() {
+                GRPC_RETRIES.with_label_values(&[&label]).inc();
+            }
+            match operation().into() {
+                retry::OperationResult::Retry(err) => {
+                    if let Some(grpcio::Error::RpcFailure(status)) = err.grpc_error() {
+                        if !self.grpc_retry_retriable.contains(status.code()) {
+                            return retry::OperationResult::Err(err);
+                        }
+                    }
+                    last_error_label = Some(retry_label(err.grpc_error()));
+                    retry::OperationResult::Retry(err)
+                }
+                result => result,
+            }
+        })
+    }
+}
+
+/// An error which may have been caused by a failed grpc call, and can be
+/// retried with [`GrpcRetryConfig::retry_grpc()`].
+pub trait GrpcRetryError {
+    /// The grpc error which caused this error, if any.
+    fn grpc_error(&self) -> Option<&grpcio::Error>;
+}
+
+impl GrpcRetryError for grpcio::Error {
+    fn grpc_error(&self) -> Option<&grpcio::Error> {
+        Some(self)
+    }
+}
+
+/// The label under which retries caused by errors without a status code are
+/// counted.
+const NO_STATUS_CODE_LABEL: &str = "NO_STATUS_CODE";
+
+/// The label under which a retry caused by `err` is counted.
+fn retry_label(err: Option<&grpcio::Error>) -> String {
+    match err {
+        Some(grpcio::Error::RpcFailure(status)) => status.code().to_string(),
+        _ => NO_STATUS_CODE_LABEL.to_owned(),
+    }
+}
+
+/// An error parsing a `GrpcRetryConfig` from a string
+#[derive(Clone, Debug, Display, Eq, PartialEq)]
+pub enum GrpcRetryConfigParseError {
+    /// Expected key=value, found '{0}'
+    MissingValue(String),
+    /// Unknown key '{0}'
+    UnknownKey(String),
+    /// Key '{0}' was given more than once
+    DuplicateKey(String),
+    /// Invalid value '{1}' for key '{0}'
+    InvalidValue(String, String),
+}
+
+impl std::error::Error for GrpcRetryConfigParseError {}
+
+/// The whole number of milliseconds in a duration, saturating.
+fn duration_millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+/// Parse a duration such as "50ms" or "2s".
+fn parse_duration(src: &str) -> Option<Duration> {
+    if let Some(millis) = src.strip_suffix("ms") {
+        millis.parse().ok().map(Duration::from_millis)
+    } else if let Some(secs) = src.strip_suffix('s') {
+        secs.parse().ok().map(Duration::from_secs)
+    } else {
+        None
+    }
+}
+
+impl FromStr for GrpcRetryConfig {
+    type Err = GrpcRetryConfigParseError;
+
+    /// Parse a compact retry policy, e.g. "attempts=3,base=50ms".
+    ///
+    /// Supported keys are `attempts` (the retry count), `base` and `max` (the
+    /// delay before the first retry, and the longest delay, in `ms` or `s`),
+    /// `multiplier` (the factor the delay grows by), `jitter` (`none`, `full`
+    /// or `equal`), `wait_for_ready` (`true` or `false`) and `retriable` (the
+    /// retried status codes, e.g. `UNAVAILABLE|DEADLINE_EXCEEDED`, or `ALL`).
+    /// Omitted keys take
+    /// their default values, so the empty string parses to the default config.
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        let mut result = Self::default();
+        let mut seen_keys = Vec::new();
+
+        for item in src
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+        {
+            let (key, value) = item
+                .split_once('=')
+                .map(|(key, value)| (key.trim(), value.trim()))
+                .ok_or_else(|| GrpcRetryConfigParseError::MissingValue(item.to_string()))?;
+            if seen_keys.contains(&key) {
+                return Err(GrpcRetryConfigParseError::DuplicateKey(key.to_string()));
+            }
+            seen_keys.push(key);
+
+            let invalid_value =
+                || GrpcRetryConfigParseError::InvalidValue(key.to_string(), value.to_string());
+            match key {
+                "attempts" => {
+                    result.grpc_retry_count = value.parse().map_err(|_| invalid_value())?;
+                }
+                "base" => {
+                    let base = parse_duration(value).ok_or_else(invalid_value)?;
+                    result.grpc_retry_millis =
+                        u64::try_from(base.as_millis()).map_err(|_| invalid_value())?;
+                }
+                "max" => {
+                    let max = parse_duration(value).ok_or_else(invalid_value)?;
+                    result.grpc_retry_max_millis =
+                        u64::try_from(max.as_millis()).map_err(|_| invalid_value())?;
+                }
+                "multiplier" => {
+                    result.grpc_retry_multiplier = value.parse().map_err(|_| invalid_value())?;
+                }
+                "jitter" => {
+                    result.grpc_retry_jitter = value.parse()?;
+                }
+                "wait_for_ready" => {
+                    result.grpc_wait_for_ready = value.parse().map_err(|_| invalid_value())?;
+                }
+                "retriable" => {
+                    result.grpc_retry_retriable = value.parse()?;
+                }
+                _ => return Err(GrpcRetryConfigParseError::UnknownKey(key.to_string())),
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+impl FmtDisplay for GrpcRetryConfig {
+    /// Format as a compact retry policy, which can be parsed with `from_str`.
+    /// Keys other than `attempts` and `base` are only included when they
+    /// differ from their default.
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "attempts={},base={}ms",
+            self.grpc_retry_count, self.grpc_retry_millis
+        )?;
+        let default = Self::default();
+        if self.grpc_retry_max_millis != default.grpc_retry_max_millis {
+            write!(f, ",max={}ms", self.grpc_retry_max_millis)?;
+        }
+        if self.grpc_retry_multiplier != default.grpc_retry_multiplier {
+            write!(f, ",multiplier={}", self.grpc_retry_multiplier)?;
+        }
+        if self.grpc_retry_jitter != default.grpc_retry_jitter {
+            write!(f, ",jitter={}", self.grpc_retry_jitter)?;
+        }
+        if self.grpc_wait_for_ready {
+            write!(f, ",wait_for_ready=true")?;
+        }
+        if self.grpc_retry_retriable != default.grpc_retry_retriable {
+            write!(f, ",retriable={}", self.grpc_retry_retriable)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CallOptionTimeout, HealthClient, HealthService, PingRequest};
+    use grpcio::{ChannelBuilder, EnvBuilder, RpcStatus, RpcStatusCode, ServerBuilder};
+    use mc_common::logger::{test_with_logger, Logger};
+    use std::{net::TcpListener, sync::Arc, thread};
+
+    fn retries(code: RpcStatusCode) -> u64 {
+        GRPC_RETRIES.with_label_values(&[&code.to_string()]).get()
+    }
+
+    #[test]
+    fn retries_are_counted_by_status_code() {
+        let config = GrpcRetryConfig {
+            grpc_retry_count: 5,
+            grpc_retry_millis: 0,
+            ..GrpcRetryConfig::default()
+        };
+        let unavailable = retries(RpcStatusCode::UNAVAILABLE);
+        let aborted = retries(RpcStatusCode::ABORTED);
+
+        let mut errors = vec![
+            RpcStatusCode::UNAVAILABLE,
+            RpcStatusCode::ABORTED,
+            RpcStatusCode::UNAVAILABLE,
+        ]
+        .into_iter();
+        let result = config.retry_grpc(|| match errors.next() {
+            Some(code) => Err(grpcio::Error::RpcFailure(RpcStatus::new(code))),
+            None => Ok("done"),
+        });
+        assert_eq!(result.unwrap(), "done");
+        assert_eq!(retries(RpcStatusCode::UNAVAILABLE), unavailable + 2);
+        assert_eq!(retries(RpcStatusCode::ABORTED), aborted + 1);
+
+        // The final failure is not a retry.
+        let config = GrpcRetryConfig {
+            grpc_retry_count: 0,
+            grpc_retry_millis: 0,
+            ..GrpcRetryConfig::default()
+        };
+        let result = config.retry_grpc(|| -> Result<(), _> {
+            Err(grpcio::Error::RpcFailure(RpcStatus::new(
+                RpcStatusCode::UNAVAILABLE,
+            )))
+        });
+        assert!(result.is_err());
+        assert_eq!(retries(RpcStatusCode::UNAVAILABLE), unavailable + 2);
+    }
+
+    /// An error from an operation which is not a bare grpc call.
+    enum WrappedError {
+        Grpc(grpcio::Error),
+        Other,
+    }
+
+    impl GrpcRetryError for WrappedError {
+        fn grpc_error(&self) -> Option<&grpcio::Error> {
+            match self {
+                WrappedError::Grpc(err) => Some(err),
+                WrappedError::Other => None,
+            }
+        }
+    }
+
+    #[test]
+    fn retries_of_wrapped_errors_are_counted() {
+        let config = GrpcRetryConfig {
+            grpc_retry_count: 5,
+            grpc_retry_millis: 0,
+            ..GrpcRetryConfig::default()
+        };
+        let cancelled = retries(RpcStatusCode::CANCELLED);
+        let no_status_code = GRPC_RETRIES
+            .with_label_values(&[NO_STATUS_CODE_LABEL])
+            .get();
+
+        let mut results = vec![
+            retry::OperationResult::Retry(WrappedError::Grpc(grpcio::Error::RpcFailure(
+                RpcStatus::new(RpcStatusCode::CANCELLED),
+            ))),
+            retry::OperationResult::Retry(WrappedError::Other),
+            retry::OperationResult::Err(WrappedError::Other),
+        ]
+        .into_iter();
+        let result: Result<(), _> = config.retry_grpc(|| results.next().unwrap());
+        assert!(result.is_err());
+        assert_eq!(retries(RpcStatusCode::CANCELLED), cancelled + 1);
+        assert_eq!(
+            GRPC_RETRIES
+                .with_label_values(&[NO_STATUS_CODE_LABEL])
+                .get(),
+            no_status_code + 1
+        );
+    }
+
+    #[test_with_logger]
+    fn wait_for_ready_waits_for_reconnect(logger: Logger) {
+        // Reserve a port, and connect to it before anything listens on it.
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let env = Arc::new(EnvBuilder::new().build());
+        let channel = ChannelBuilder::new(env.clone()).connect(&format!("127.0.0.1:{}", port));
+        let client = HealthClient::new(channel);
+
+        // Without wait-for-ready, calls fail fast.
+        let fail_fast = GrpcRetryConfig::default();
+        let call_option =
+            fail_fast.call_option(CallOption::default().with_timeout(Duration::from_secs(10)));
+        match client.ping_opt(&PingRequest::new(), call_option) {
+            Err(grpcio::Error::RpcFailure(status)) => {
+                assert_eq!(status.code(), RpcStatusCode::UNAVAILABLE)
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        // With it, a call waits until the server comes up.
+        let server_thread = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(500));
+            let mut server = ServerBuilder::new(env)
+                .register_service(HealthService::new(None, logger).into_service())
+                .bind("127.0.0.1", port)
+                .build()
+                .unwrap();
+            server.start();
+            server
+        });
+        let wait_for_ready = GrpcRetryConfig {
+            grpc_wait_for_ready: true,
+            ..GrpcRetryConfig::default()
+        };
+        let call_option =
+            wait_for_ready.call_option(CallOption::default().with_timeout(Duration::from_secs(10)));
+        client
+            .ping_opt(&PingRequest::new(), call_option)
+            .expect("call should wait for the server");
+        let _server = server_thread.join().unwrap();
+    }
+
+    #[test]
+    fn backoff_grows_until_capped() {
+        let config = GrpcRetryConfig::default()
+            .with_retry_count(6)
+            .with_base_delay(Duration::from_millis(100))
+            .with_max_delay(Duration::from_millis(1000))
+            .with_multiplier(2)
+            .with_jitter(RetryJitter::None);
+        let delays = config
+            .retry_delays(StdRng::seed_from_u64(0))
+            .map(|delay| delay.as_millis())
+            .collect::<Vec<_>>();
+        assert_eq!(delays, vec![100, 200, 400, 800, 1000, 1000]);
+
+        // Huge retry counts and multipliers saturate at the cap.
+        let config = config.with_multiplier(u32::MAX);
+        assert_eq!(config.backoff_delay(1000), Duration::from_millis(1000));
+
+        // The default config keeps the delay fixed, like it always has.
+        let default = GrpcRetryConfig::default().with_jitter(RetryJitter::None);
+        assert!(default
+            .retry_delays(StdRng::seed_from_u64(0))
+            .all(|delay| delay == Duration::from_millis(20)));
+        assert_eq!(default.retry_delays(StdRng::seed_from_u64(0)).count(), 3);
+    }
+
+    #[test]
+    fn jitter_stays_in_bounds() {
+        let config = GrpcRetryConfig::default()
+            .with_retry_count(8)
+            .with_base_delay(Duration::from_millis(50))
+            .with_max_delay(Duration::from_millis(3000))
+            .with_multiplier(3);
+        for seed in 0..20 {
+            let full = config.with_jitter(RetryJitter::Full);
+            let equal = config.with_jitter(RetryJitter::Equal);
+            for (retry, (full_delay, equal_delay)) in full
+                .retry_delays(StdRng::seed_from_u64(seed))
+                .zip(equal.retry_delays(StdRng::seed_from_u64(seed)))
+                .enumerate()
+            {
+                let backoff = config.backoff_delay(retry);
+                assert!(full_delay <= backoff);
+                assert!(equal_delay <= backoff);
+                assert!(equal_delay >= backoff / 2);
+            }
+
+            // The same seed gives the same delays.
+            assert!(full
+                .retry_delays(StdRng::seed_from_u64(seed))
+                .eq(full.retry_delays(StdRng::seed_from_u64(seed))));
+        }
+    }
+
+    #[test]
+    fn parse_full_string() {
+        let config = GrpcRetryConfig::from_str("attempts=5,base=2s").unwrap();
+        assert_eq!(
+            config,
+            GrpcRetryConfig {
+                grpc_retry_count: 5,
+                grpc_retry_millis: 2000,
+                ..GrpcRetryConfig::default()
+            }
+        );
+
+        let config = GrpcRetryConfig::from_str(
+            "attempts=5,base=50ms,max=2s,multiplier=2,jitter=equal,retriable=UNAVAILABLE|DEADLINE_EXCEEDED",
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            GrpcRetryConfig::default()
+                .with_retry_count(5)
+                .with_base_delay(Duration::from_millis(50))
+                .with_max_delay(Duration::from_secs(2))
+                .with_multiplier(2)
+                .with_jitter(RetryJitter::Equal)
+                .with_retriable(RetriableCodes::new([
+                    RpcStatusCode::UNAVAILABLE,
+                    RpcStatusCode::DEADLINE_EXCEEDED
+                ]))
+        );
+
+        let config = GrpcRetryConfig::from_str(" base = 50ms , attempts = 7 ").unwrap();
+        assert_eq!(config.grpc_retry_count, 7);
+        assert_eq!(config.grpc_retry_millis, 50);
+    }
+
+    #[test]
+    fn omitted_fields_use_defaults() {
+        let default = GrpcRetryConfig::default();
+        assert_eq!(GrpcRetryConfig::from_str("").unwrap(), default);
+
+        let config = GrpcRetryConfig::from_str("attempts=10").unwrap();
+        assert_eq!(config.grpc_retry_count, 10);
+        assert_eq!(config.grpc_retry_millis, default.grpc_retry_millis);
+
+        let config = GrpcRetryConfig::from_str("base=100ms").unwrap();
+        assert_eq!(config.grpc_retry_count, default.grpc_retry_count);
+        assert_eq!(config.grpc_retry_millis, 100);
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for config in [
+            GrpcRetryConfig::default(),
+            GrpcRetryConfig {
+                grpc_retry_count: 0,
+                grpc_retry_millis: 0,
+                ..GrpcRetryConfig::default()
+            },
+            GrpcRetryConfig {
+                grpc_retry_count: 12,
+                grpc_retry_millis: 1500,
+                ..GrpcRetryConfig::default()
+            },
+            GrpcRetryConfig {
+                grpc_wait_for_ready: true,
+                ..GrpcRetryConfig::default()
+            },
+            GrpcRetryConfig::default()
+                .with_max_delay(Duration::from_secs(2))
+                .with_multiplier(3)
+                .with_jitter(RetryJitter::Equal),
+            GrpcRetryConfig::default().with_jitter(RetryJitter::None),
+            GrpcRetryConfig::default().with_retriable(RetriableCodes::new([
+                RpcStatusCode::DEADLINE_EXCEEDED,
+                RpcStatusCode::UNAVAILABLE,
+            ])),
+            GrpcRetryConfig::default().with_retriable(RetriableCodes::new([])),
+        ] {
+            let string = config.to_string();
+            assert_eq!(GrpcRetryConfig::from_str(&string).unwrap(), config);
+        }
+        assert_eq!(
+            GrpcRetryConfig::default().to_string(),
+            "attempts=3,base=20ms"
+        );
+        assert_eq!(
+            GrpcRetryConfig::from_str("retriable=UNAVAILABLE|DEADLINE_EXCEEDED")
+                .unwrap()
+                .to_string(),
+            "attempts=3,base=20ms,retriable=DEADLINE_EXCEEDED|UNAVAILABLE"
+        );
+    }
+
+    #[test]
+    fn only_retriable_status_codes_are_retried() {
+        let config =
+            GrpcRetryConfig::from_str("attempts=5,base=0ms,retriable=UNAVAILABLE").unwrap();
+
+        let mut attempts = 0;
+        let result: Result<(), _> = config.retry_grpc(|| {
+            attempts += 1;
+            Err(grpcio::Error::RpcFailure(RpcStatus::new(
+                RpcStatusCode::INVALID_ARGUMENT,
+            )))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+
+        let mut attempts = 0;
+        let result: Result<(), _> = config.retry_grpc(|| {
+            attempts += 1;
+            Err(grpcio::Error::RpcFailure(RpcStatus::new(
+                RpcStatusCode::UNAVAILABLE,
+            )))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 6);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            GrpcRetryConfig::from_str("attempts=3,bogus=1"),
+            Err(GrpcRetryConfigParseError::UnknownKey("bogus".to_string()))
+        );
+        assert_eq!(
+            GrpcRetryConfig::from_str("attempts"),
+            Err(GrpcRetryConfigParseError::MissingValue(
+                "attempts".to_string()
+            ))
+        );
+        assert_eq!(
+            GrpcRetryConfig::from_str("attempts=1,attempts=2"),
+            Err(GrpcRetryConfigParseError::DuplicateKey(
+                "attempts".to_string()
+            ))
+        );
+        assert_eq!(
+            GrpcRetryConfig::from_str("attempts=many"),
+            Err(GrpcRetryConfigParseError::InvalidValue(
+                "attempts".to_string(),
+                "many".to_string()
+            ))
+        );
+        assert_eq!(
+            GrpcRetryConfig::from_str("jitter=sometimes"),
+            Err(GrpcRetryConfigParseError::InvalidValue(
+                "jitter".to_string(),
+                "sometimes".to_string()
+            ))
+        );
+        assert_eq!(
+            GrpcRetryConfig::from_str("wait_for_ready=yes"),
+            Err(GrpcRetryConfigParseError::InvalidValue(
+                "wait_for_ready".to_string(),
+                "yes".to_string()
+            ))
+        );
+        assert_eq!(
+            GrpcRetryConfig::from_str("retriable=UNAVAILABLE|SOMETIMES"),
+            Err(GrpcRetryConfigParseError::InvalidValue(
+                "retriable".to_string(),
+                "UNAVAILABLE|SOMETIMES".to_string()
+            ))
+        );
+        assert_eq!(
+            GrpcRetryConfig::from_str("base=50"),
+            Err(GrpcRetryConfigParseError::InvalidValue(
+                "base".to_string(),
+                "50".to_string()
+            ))
+        );
+    }
 }
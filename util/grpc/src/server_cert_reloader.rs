@@ -1,21 +1,31 @@
 // Copyright (c) 2018-2022 The MobileCoin Foundation
 
 //! A `grpcio::ServerCredentialsFetcher` implementation that reloads a GRPC's
-//! server TLS certificate/key when a SIGHUP is received.
+//! server TLS certificate/key, and optionally the CA bundle client
+//! certificates are verified against, when a SIGHUP is received.
 
 use displaydoc::Display;
 use grpcio::{CertificateRequestType, ServerCredentialsBuilder, ServerCredentialsFetcher};
 use mc_common::logger::{log, Logger};
+use sha2::{Digest, Sha256};
+#[cfg(feature = "signal")]
 use signal_hook::{consts::SIGHUP, flag};
 use std::{
     fs, io,
     path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        Arc, Mutex,
     },
+    time::{Duration, Instant, SystemTime},
 };
 
+/// The default minimum time between two reloads of the certificate/key files.
+const DEFAULT_MIN_RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The default time the files must go unmodified before they are reloaded.
+const DEFAULT_DEBOUNCE_INTERVAL: Duration = Duration::from_millis(250);
+
 /// The `grpcio::ServerCredentialsFetcher` demands a root certificate for
 /// verifying client identities, even when we explicitly specify
 /// DontRequestClientCertificate. As such, we need to provide it with some
@@ -65,8 +75,19 @@ Ot9sOqRdWgMdWsiqJpUVKE2ziZ7+Y1KWgK+qCEv2GD3GL6uR+LNChGPzyp1NHZmo
 pub enum ServerCertReloaderError {
     /// IO: {0}
     IO(io::Error),
+
+    /// Invalid certificate PEM: {0}
+    InvalidCertificatePem(String),
+
+    /// Invalid private key PEM: {0}
+    InvalidPrivateKeyPem(String),
+
+    /// Invalid client CA bundle PEM: {0}
+    InvalidClientCaPem(String),
 }
 
+impl std::error::Error for ServerCertReloaderError {}
+
 impl From<io::Error> for ServerCertReloaderError {
     fn from(src: io::Error) -> Self {
         Self::IO(src)
@@ -74,7 +95,17 @@ impl From<io::Error> for ServerCertReloaderError {
 }
 
 /// A `grpcio::ServerCredentialsFetcher` implementation that reloads a GRPC's
-/// server TLS certificate/key when a SIGHUP is received.
+/// server TLS certificate/key when a SIGHUP is received. SIGHUP is only
+/// handled with the `signal` feature, which is enabled by default.
+///
+/// Some secret-management tools touch the files frequently without changing
+/// them, so reloads are skipped when the contents are identical to what is
+/// already loaded, and reloads requested within `min_reload_interval` of the
+/// previous one are coalesced into a single reload once it has elapsed.
+///
+/// Other tools write the files in several steps, so reloads wait until the
+/// files have not been modified for `debounce_interval`, and files which do
+/// not parse as complete PEM are not applied.
 pub struct ServerCertReloader {
     /// Certificate file to watch.
     cert_file: PathBuf,
@@ -82,13 +113,54 @@ pub struct ServerCertReloader {
     /// Private key file to watch.
     key_file: PathBuf,
 
+    /// CA bundle file to verify client certificates against, if clients must
+    /// present certificates.
+    client_ca_file: Option<PathBuf>,
+
     /// Signal that we need to re-load the certificate/key files.
     load_needed: Arc<AtomicBool>,
 
+    /// The minimum time between two reloads.
+    min_reload_interval: Duration,
+
+    /// The time the files must go unmodified before they are reloaded.
+    debounce_interval: Duration,
+
+    /// What was loaded last, and when.
+    state: Mutex<ReloadState>,
+
     /// Logger.
     logger: Logger,
 }
 
+/// Requests that a `ServerCertReloader` reload its files, like a SIGHUP does,
+/// but for just that reloader. It stays usable after the reloader was handed
+/// to a server.
+#[derive(Clone)]
+pub struct ReloadHandle {
+    /// The reloader's `load_needed` flag.
+    load_needed: Arc<AtomicBool>,
+}
+
+impl ReloadHandle {
+    /// Reload the files before the next TLS handshake, subject to the
+    /// reloader's minimum reload and debounce intervals.
+    pub fn request_reload(&self) {
+        self.load_needed.store(true, Ordering::SeqCst);
+    }
+}
+
+/// The state of the files loaded by a `ServerCertReloader`.
+#[derive(Default)]
+struct ReloadState {
+    /// When the files were last read.
+    last_load: Option<Instant>,
+
+    /// A hash of the contents of the certificate, key and CA bundle files
+    /// last loaded.
+    content_hash: Option<Vec<u8>>,
+}
+
 impl ServerCertReloader {
     /// Create a new ServerCertReloader that watches `cert_file`/`key_file`.
     pub fn new(
@@ -98,15 +170,170 @@ impl ServerCertReloader {
     ) -> Result<Self, ServerCertReloaderError> {
         let load_needed = Arc::new(AtomicBool::new(true));
 
+        #[cfg(feature = "signal")]
         flag::register(SIGHUP, load_needed.clone())?;
 
         Ok(Self {
             cert_file: cert_file.as_ref().to_path_buf(),
             key_file: key_file.as_ref().to_path_buf(),
+            client_ca_file: None,
             load_needed,
+            min_reload_interval: DEFAULT_MIN_RELOAD_INTERVAL,
+            debounce_interval: DEFAULT_DEBOUNCE_INTERVAL,
+            state: Mutex::new(ReloadState::default()),
             logger,
         })
     }
+
+    /// Set the minimum time between two reloads (one second by default).
+    /// Reloads requested sooner are deferred until this has elapsed since the
+    /// previous one.
+    pub fn with_min_reload_interval(mut self, min_reload_interval: Duration) -> Self {
+        self.min_reload_interval = min_reload_interval;
+        self
+    }
+
+    /// Set the time the files must go unmodified before they are reloaded
+    /// (250 milliseconds by default). Reloads requested while the files are
+    /// being written are deferred until writes have settled.
+    pub fn with_debounce_interval(mut self, debounce_interval: Duration) -> Self {
+        self.debounce_interval = debounce_interval;
+        self
+    }
+
+    /// Require clients to present a certificate signed by one of the CAs in
+    /// `client_ca_file`, a PEM bundle which is reloaded along with the
+    /// certificate and key.
+    ///
+    /// A bundle which does not parse, e.g. because it was only partially
+    /// written, is rejected as a whole, and the previously loaded credentials
+    /// stay in use.
+    pub fn with_client_ca_file(mut self, client_ca_file: &impl AsRef<Path>) -> Self {
+        self.client_ca_file = Some(client_ca_file.as_ref().to_path_buf());
+        self
+    }
+
+    /// The time since the most recent modification of any of the watched
+    /// files, if it is known.
+    fn time_since_modified(&self) -> Option<Duration> {
+        [
+            Some(&self.cert_file),
+            Some(&self.key_file),
+            self.client_ca_file.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .filter_map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+        // A modification time in the future is treated as settled, so that
+        // clock adjustments cannot hold up reloads indefinitely.
+        .map(|modified| {
+            SystemTime::now()
+                .duration_since(modified)
+                .unwrap_or(Duration::MAX)
+        })
+        .min()
+    }
+
+    /// A handle for requesting reloads, e.g. from an admin command.
+    pub fn reload_handle(&self) -> ReloadHandle {
+        ReloadHandle {
+            load_needed: self.load_needed.clone(),
+        }
+    }
+
+    /// The client certificate request type to bind the server with, given
+    /// whether a client CA bundle is configured.
+    pub fn certificate_request_type(&self) -> CertificateRequestType {
+        if self.client_ca_file.is_some() {
+            CertificateRequestType::RequestAndRequireClientCertificateAndVerify
+        } else {
+            CertificateRequestType::DontRequestClientCertificate
+        }
+    }
+}
+
+/// Hash the contents of the certificate, key and CA bundle files.
+fn content_hash(crt: &str, key: &str, client_ca: Option<&str>) -> Vec<u8> {
+    let mut hasher = Sha256::new()
+        .chain_update((crt.len() as u64).to_le_bytes())
+        .chain_update(crt)
+        .chain_update((key.len() as u64).to_le_bytes())
+        .chain_update(key);
+    if let Some(client_ca) = client_ca {
+        hasher = hasher
+            .chain_update((client_ca.len() as u64).to_le_bytes())
+            .chain_update(client_ca);
+    }
+    hasher.finalize().to_vec()
+}
+
+const PEM_CERTIFICATE_BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const PEM_CERTIFICATE_END: &str = "-----END CERTIFICATE-----";
+
+/// Decode the base64 body of a PEM block.
+fn decode_pem_body(body: &str) -> Result<Vec<u8>, String> {
+    let body = body
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace())
+        .collect::<String>();
+    match base64::decode(&body) {
+        Ok(der) if !der.is_empty() => Ok(der),
+        Ok(_) => Err("empty PEM block".to_owned()),
+        Err(_) => Err("invalid base64".to_owned()),
+    }
+}
+
+/// Check that `pem` is a complete bundle of one or more PEM certificates.
+fn validate_certificates_pem(pem: &str) -> Result<(), String> {
+    let mut num_certs = 0;
+    let mut rest = pem.trim();
+    while !rest.is_empty() {
+        let body = rest
+            .strip_prefix(PEM_CERTIFICATE_BEGIN)
+            .ok_or("expected BEGIN CERTIFICATE")?;
+        let (body, after) = body
+            .split_once(PEM_CERTIFICATE_END)
+            .ok_or("missing END CERTIFICATE")?;
+        decode_pem_body(body)?;
+        num_certs += 1;
+        rest = after.trim_start();
+    }
+
+    if num_certs == 0 {
+        return Err("no certificates".to_owned());
+    }
+    Ok(())
+}
+
+/// Check that `pem` is a complete PEM private key.
+fn validate_private_key_pem(pem: &str) -> Result<(), String> {
+    let pem = pem.trim();
+    let (label, rest) = pem
+        .strip_prefix("-----BEGIN ")
+        .and_then(|rest| rest.split_once("-----"))
+        .filter(|(label, _)| label.ends_with("PRIVATE KEY"))
+        .ok_or("expected BEGIN PRIVATE KEY")?;
+    let body = rest
+        .strip_suffix(&format!("-----END {}-----", label))
+        .ok_or_else(|| format!("missing END {}", label))?;
+    decode_pem_body(body)?;
+    Ok(())
+}
+
+/// Check that the certificate, key and CA bundle are complete PEM files, so
+/// that they can be applied.
+fn validate_pems(
+    crt: &str,
+    key: &str,
+    client_ca: Option<&str>,
+) -> Result<(), ServerCertReloaderError> {
+    validate_certificates_pem(crt).map_err(ServerCertReloaderError::InvalidCertificatePem)?;
+    validate_private_key_pem(key).map_err(ServerCertReloaderError::InvalidPrivateKeyPem)?;
+    if let Some(client_ca) = client_ca {
+        validate_certificates_pem(client_ca)
+            .map_err(ServerCertReloaderError::InvalidClientCaPem)?;
+    }
+    Ok(())
 }
 
 impl ServerCredentialsFetcher for ServerCertReloader {
@@ -115,28 +342,87 @@ impl ServerCredentialsFetcher for ServerCertReloader {
             return Ok(None);
         }
 
-        log::info!(self.logger, "Loading certificates");
+        let mut state = self.state.lock().expect("mutex poisoned");
+        if let Some(last_load) = state.last_load {
+            // Leave `load_needed` set, so that a burst of reload requests
+            // results in a single reload once the interval has elapsed.
+            if last_load.elapsed() < self.min_reload_interval {
+                return Ok(None);
+            }
+        }
+
+        // Leave `load_needed` set while the files are being written, so that
+        // they are reloaded once writes have settled. The initial load is not
+        // deferred, since there are no credentials to fall back to yet.
+        if state.content_hash.is_some() {
+            if let Some(since_modified) = self.time_since_modified() {
+                if since_modified < self.debounce_interval {
+                    log::debug!(
+                        self.logger,
+                        "Certificates recently modified, deferring reload"
+                    );
+                    return Ok(None);
+                }
+            }
+        }
 
         let crt = fs::read_to_string(&self.cert_file)?;
         let key = fs::read_to_string(&self.key_file)?;
+        let client_ca = self
+            .client_ca_file
+            .as_ref()
+            .map(fs::read_to_string)
+            .transpose()?;
+        state.last_load = Some(Instant::now());
+
+        let content_hash = content_hash(&crt, &key, client_ca.as_deref());
+        if state.content_hash.as_ref() == Some(&content_hash) {
+            log::debug!(self.logger, "Certificates unchanged, skipping reload");
+            self.load_needed.store(false, Ordering::SeqCst);
+            return Ok(None);
+        }
 
-        let new_cred = ServerCredentialsBuilder::new()
+        if let Err(err) = validate_pems(&crt, &key, client_ca.as_deref()) {
+            // Keep using the previous credentials until the next reload is
+            // requested.
+            log::warn!(self.logger, "Not reloading certificates: {}", err);
+            self.load_needed.store(false, Ordering::SeqCst);
+            return Err(Box::new(err));
+        }
+
+        let root_cert = match client_ca {
+            Some(client_ca) => {
+                log::info!(
+                    self.logger,
+                    "Loading client CA bundle (sha256: {})",
+                    hex::encode(Sha256::digest(&client_ca))
+                );
+                client_ca.into_bytes()
+            }
             // This sets the client root certificate to verify client's identity.
             // We are not using this feature, however grpcio still requires something to be set
             // there when using the ServerCredentialsFetcher mechanism. As a workaround we are
-            // using the server's certificate chain here.
-            .root_cert(
-                HARDCODED_CLIENT_ROOT_CERT.as_bytes().to_vec(),
-                CertificateRequestType::DontRequestClientCertificate,
-            )
+            // using a hardcoded certificate here.
+            None => HARDCODED_CLIENT_ROOT_CERT.as_bytes().to_vec(),
+        };
+
+        log::info!(
+            self.logger,
+            "Loading certificates (certificate sha256: {})",
+            hex::encode(Sha256::digest(&crt))
+        );
+
+        let new_cred = ServerCredentialsBuilder::new()
+            .root_cert(root_cert, self.certificate_request_type())
             .add_cert(crt.into(), key.into());
 
+        state.content_hash = Some(content_hash);
         self.load_needed.store(false, Ordering::SeqCst);
         Ok(Some(new_cred))
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "signal"))]
 mod tests {
     use super::*;
     use crate::{
@@ -430,4 +716,296 @@ mod tests {
         let reply = client1.ping(&req).expect("rpc");
         assert_eq!(reply.get_data(), vec![1, 2, 3]);
     }
+
+    #[test_with_logger]
+    fn test_unchanged_files_are_not_reloaded(logger: Logger) {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let cert_file = temp_dir.path().join("server.crt");
+        let key_file = temp_dir.path().join("server.key");
+
+        let (server1_cert, server1_key) = ok_self_signed_1();
+        fs::write(&cert_file, &server1_cert).unwrap();
+        fs::write(&key_file, &server1_key).unwrap();
+
+        let reloader = ServerCertReloader::new(&cert_file, &key_file, logger)
+            .unwrap()
+            .with_min_reload_interval(Duration::ZERO)
+            .with_debounce_interval(Duration::ZERO);
+        assert!(reloader.fetch().unwrap().is_some());
+
+        // Touch the files without changing their contents, and request a reload.
+        for _ in 0..3 {
+            fs::write(&cert_file, &server1_cert).unwrap();
+            fs::write(&key_file, &server1_key).unwrap();
+            reloader.load_needed.store(true, Ordering::SeqCst);
+            assert!(reloader.fetch().unwrap().is_none());
+        }
+
+        // Changing the contents does reload, also when requested through a
+        // handle.
+        let (server2_cert, server2_key) = ok_self_signed_2();
+        fs::write(&cert_file, &server2_cert).unwrap();
+        fs::write(&key_file, &server2_key).unwrap();
+        reloader.reload_handle().request_reload();
+        assert!(reloader.fetch().unwrap().is_some());
+    }
+
+    #[test_with_logger]
+    fn test_reloads_are_coalesced(logger: Logger) {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let cert_file = temp_dir.path().join("server.crt");
+        let key_file = temp_dir.path().join("server.key");
+
+        let (server1_cert, server1_key) = ok_self_signed_1();
+        fs::write(&cert_file, &server1_cert).unwrap();
+        fs::write(&key_file, &server1_key).unwrap();
+
+        let reloader = ServerCertReloader::new(&cert_file, &key_file, logger)
+            .unwrap()
+            .with_min_reload_interval(Duration::from_millis(500));
+
+        // The initial load is not delayed.
+        assert!(reloader.fetch().unwrap().is_some());
+
+        // A burst of changes right after the load is deferred...
+        let (server2_cert, server2_key) = ok_self_signed_2();
+        fs::write(&cert_file, &server2_cert).unwrap();
+        fs::write(&key_file, &server2_key).unwrap();
+        for _ in 0..3 {
+            reloader.load_needed.store(true, Ordering::SeqCst);
+            assert!(reloader.fetch().unwrap().is_none());
+        }
+        assert!(reloader.load_needed.load(Ordering::SeqCst));
+
+        // ...and results in a single reload once the interval has elapsed.
+        thread::sleep(Duration::from_millis(600));
+        assert!(reloader.fetch().unwrap().is_some());
+        assert!(reloader.fetch().unwrap().is_none());
+    }
+
+    fn create_test_mtls_client(
+        server_cert: &str,
+        client_cert: &str,
+        client_key: &str,
+        port: u16,
+    ) -> HealthClient {
+        let env = Arc::new(EnvBuilder::new().build());
+        let cred = ChannelCredentialsBuilder::new()
+            .root_cert(server_cert.into())
+            .cert(client_cert.into(), client_key.into())
+            .build();
+        let ch = ChannelBuilder::new(env)
+            .override_ssl_target("www.server1.com")
+            .secure_connect(&format!("localhost:{}", port), cred);
+        HealthClient::new(ch)
+    }
+
+    #[test_with_logger]
+    fn test_client_ca_reloading(logger: Logger) {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let cert_file = temp_dir.path().join("server.crt");
+        let key_file = temp_dir.path().join("server.key");
+        let client_ca_file = temp_dir.path().join("client-ca.crt");
+
+        // The test certificates are self-signed, so each acts as its own CA.
+        let (server1_cert, server1_key) = ok_self_signed_1();
+        let (server2_cert, server2_key) = ok_self_signed_2();
+        fs::write(&cert_file, &server1_cert).unwrap();
+        fs::write(&key_file, &server1_key).unwrap();
+        fs::write(&client_ca_file, &server2_cert).unwrap();
+
+        let reloader = ServerCertReloader::new(&cert_file, &key_file, logger.clone())
+            .unwrap()
+            .with_client_ca_file(&client_ca_file);
+        let request_type = reloader.certificate_request_type();
+        let env = Arc::new(EnvBuilder::new().build());
+        let mut server = ServerBuilder::new(env)
+            .register_service(HealthService::new(None, logger).into_service())
+            .bind_with_fetcher("localhost", 0, Box::new(reloader), request_type)
+            .build()
+            .unwrap();
+        server.start();
+        let port = server.bind_addrs().next().unwrap().1;
+
+        let req = PingRequest::default();
+
+        // Only clients with a certificate signed by the current CA are accepted.
+        let client = create_test_mtls_client(&server1_cert, &server2_cert, &server2_key, port);
+        client.ping(&req).expect("rpc");
+        let client = create_test_mtls_client(&server1_cert, &server1_cert, &server1_key, port);
+        assert!(client.ping(&req).is_err());
+
+        // Rotate the CA bundle, and trigger reloading.
+        fs::write(&client_ca_file, &server1_cert).unwrap();
+        unsafe {
+            libc::kill(libc::getpid(), libc::SIGHUP);
+        }
+        thread::sleep(Duration::from_secs(2));
+
+        // Clients signed by the retired CA are now rejected.
+        let client = create_test_mtls_client(&server1_cert, &server1_cert, &server1_key, port);
+        client.ping(&req).expect("rpc");
+        let client = create_test_mtls_client(&server1_cert, &server2_cert, &server2_key, port);
+        assert!(client.ping(&req).is_err());
+    }
+
+    #[test_with_logger]
+    fn test_run_tls_server_requires_client_certificate(logger: Logger) {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let cert_file = temp_dir.path().join("server.crt");
+        let key_file = temp_dir.path().join("server.key");
+        let client_ca_file = temp_dir.path().join("client-ca.crt");
+
+        let (server1_cert, server1_key) = ok_self_signed_1();
+        let (server2_cert, server2_key) = ok_self_signed_2();
+        fs::write(&cert_file, &server1_cert).unwrap();
+        fs::write(&key_file, &server1_key).unwrap();
+        fs::write(&client_ca_file, &server2_cert).unwrap();
+
+        let reloader = ServerCertReloader::new(&cert_file, &key_file, logger.clone())
+            .unwrap()
+            .with_client_ca_file(&client_ca_file);
+        let server = crate::run_tls_server(
+            Arc::new(EnvBuilder::new().build()),
+            vec![HealthService::new(None, logger.clone()).into_service()],
+            "127.0.0.1".parse().unwrap(),
+            0,
+            reloader,
+            &logger,
+        )
+        .unwrap();
+        let port = server.bind_addrs().next().unwrap().1;
+
+        let req = PingRequest::default();
+
+        // Clients without a certificate, or with one not signed by the CA,
+        // are rejected.
+        let client = create_test_client(&server1_cert, "www.server1.com", port);
+        assert!(client.ping(&req).is_err());
+        let client = create_test_mtls_client(&server1_cert, &server1_cert, &server1_key, port);
+        assert!(client.ping(&req).is_err());
+
+        let client = create_test_mtls_client(&server1_cert, &server2_cert, &server2_key, port);
+        client.ping(&req).expect("rpc");
+    }
+
+    #[test_with_logger]
+    fn test_partial_client_ca_is_not_applied(logger: Logger) {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let cert_file = temp_dir.path().join("server.crt");
+        let key_file = temp_dir.path().join("server.key");
+        let client_ca_file = temp_dir.path().join("client-ca.crt");
+
+        let (server1_cert, server1_key) = ok_self_signed_1();
+        let (server2_cert, _server2_key) = ok_self_signed_2();
+        let bundle = format!("{}\n{}", server1_cert, server2_cert);
+        assert!(validate_certificates_pem(&bundle).is_ok());
+
+        fs::write(&cert_file, &server1_cert).unwrap();
+        fs::write(&key_file, &server1_key).unwrap();
+        fs::write(&client_ca_file, &bundle).unwrap();
+        let reloader = ServerCertReloader::new(&cert_file, &key_file, logger)
+            .unwrap()
+            .with_client_ca_file(&client_ca_file)
+            .with_min_reload_interval(Duration::ZERO)
+            .with_debounce_interval(Duration::ZERO);
+        assert!(reloader.fetch().unwrap().is_some());
+
+        // A bundle cut off in the middle of its second certificate is rejected.
+        let partial = &bundle[..bundle.len() - 100];
+        fs::write(&client_ca_file, partial).unwrap();
+        reloader.load_needed.store(true, Ordering::SeqCst);
+        let err = reloader.fetch().unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ServerCertReloaderError>(),
+            Some(ServerCertReloaderError::InvalidClientCaPem(_))
+        ));
+
+        for invalid in ["", "junk", server1_key.as_str(), &server1_cert[..40]] {
+            assert!(validate_certificates_pem(invalid).is_err());
+        }
+
+        // Once the bundle is complete again, it is applied.
+        fs::write(&client_ca_file, &server2_cert).unwrap();
+        reloader.load_needed.store(true, Ordering::SeqCst);
+        assert!(reloader.fetch().unwrap().is_some());
+    }
+
+    #[test_with_logger]
+    fn test_rapid_writes_are_debounced(logger: Logger) {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let cert_file = temp_dir.path().join("server.crt");
+        let key_file = temp_dir.path().join("server.key");
+
+        let (server1_cert, server1_key) = ok_self_signed_1();
+        fs::write(&cert_file, &server1_cert).unwrap();
+        fs::write(&key_file, &server1_key).unwrap();
+
+        let reloader = ServerCertReloader::new(&cert_file, &key_file, logger)
+            .unwrap()
+            .with_min_reload_interval(Duration::ZERO)
+            .with_debounce_interval(Duration::from_millis(500));
+
+        // The initial load is not debounced.
+        assert!(reloader.fetch().unwrap().is_some());
+
+        // The certificate is written in two steps. Reloads requested in between
+        // are deferred, without surfacing an error for the partial file.
+        let (server2_cert, server2_key) = ok_self_signed_2();
+        let mut reloads = 0;
+        let (first_half, _) = server2_cert.split_at(server2_cert.len() / 2);
+        fs::write(&cert_file, first_half).unwrap();
+        reloader.load_needed.store(true, Ordering::SeqCst);
+        reloads += reloader.fetch().unwrap().iter().count();
+        fs::write(&cert_file, &server2_cert).unwrap();
+        fs::write(&key_file, &server2_key).unwrap();
+        reloader.load_needed.store(true, Ordering::SeqCst);
+        reloads += reloader.fetch().unwrap().iter().count();
+        assert_eq!(reloads, 0);
+
+        // Once writes have settled, the complete files are loaded, once.
+        thread::sleep(Duration::from_millis(600));
+        for _ in 0..3 {
+            reloads += reloader.fetch().unwrap().iter().count();
+        }
+        assert_eq!(reloads, 1);
+    }
+
+    #[test_with_logger]
+    fn test_incomplete_files_are_not_applied(logger: Logger) {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let cert_file = temp_dir.path().join("server.crt");
+        let key_file = temp_dir.path().join("server.key");
+
+        let (server1_cert, server1_key) = ok_self_signed_1();
+        assert!(validate_private_key_pem(&server1_key).is_ok());
+        fs::write(&cert_file, &server1_cert).unwrap();
+        fs::write(&key_file, &server1_key).unwrap();
+
+        let reloader = ServerCertReloader::new(&cert_file, &key_file, logger)
+            .unwrap()
+            .with_min_reload_interval(Duration::ZERO)
+            .with_debounce_interval(Duration::ZERO);
+        assert!(reloader.fetch().unwrap().is_some());
+
+        // A key cut off before its end is not applied.
+        fs::write(&key_file, &server1_key[..server1_key.len() - 40]).unwrap();
+        reloader.load_needed.store(true, Ordering::SeqCst);
+        let err = reloader.fetch().unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ServerCertReloaderError>(),
+            Some(ServerCertReloaderError::InvalidPrivateKeyPem(_))
+        ));
+        assert!(!reloader.load_needed.load(Ordering::SeqCst));
+
+        // Nor is a certificate cut off before its end.
+        fs::write(&key_file, &server1_key).unwrap();
+        fs::write(&cert_file, &server1_cert[..server1_cert.len() - 40]).unwrap();
+        reloader.load_needed.store(true, Ordering::SeqCst);
+        let err = reloader.fetch().unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ServerCertReloaderError>(),
+            Some(ServerCertReloaderError::InvalidCertificatePem(_))
+        ));
+    }
 }
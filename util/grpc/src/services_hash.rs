@@ -0,0 +1,93 @@
+// Copyright (c) 2018-2022 The MobileCoin Foundation
+
+//! A stable hash of the set of services a server exposes, which nodes can
+//! report so that a node running a different set of services stands out.
+
+use sha2::{Digest, Sha256};
+
+/// Domain separator for the services hash.
+const SERVICES_HASH_DOMAIN_TAG: &[u8] = b"mc-grpc-services-hash";
+
+/// Compute a stable, hex-encoded hash of a set of services.
+///
+/// Each service is identified by its fully-qualified proto service name (e.g.
+/// "admin.AdminAPI"), and, if available, a version. The hash does not depend
+/// on the order in which services are given, or on duplicates.
+pub fn services_hash<'a>(services: impl IntoIterator<Item = (&'a str, Option<&'a str>)>) -> String {
+    let mut services: Vec<_> = services.into_iter().collect();
+    services.sort_unstable();
+    services.dedup();
+
+    let mut hasher = Sha256::new();
+    hasher.update(SERVICES_HASH_DOMAIN_TAG);
+    hasher.update((services.len() as u64).to_le_bytes());
+    for (name, version) in services {
+        // Length-prefix each field so that distinct sets cannot collide by
+        // shifting bytes between names and versions.
+        hasher.update((name.len() as u64).to_le_bytes());
+        hasher.update(name);
+        match version {
+            Some(version) => {
+                hasher.update([1u8]);
+                hasher.update((version.len() as u64).to_le_bytes());
+                hasher.update(version);
+            }
+            None => hasher.update([0u8]),
+        }
+    }
+    hex::encode(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical_sets_hash_identically() {
+        let a = services_hash([
+            ("admin.AdminAPI", None),
+            ("grpc.health.v1.Health", None),
+            ("consensus_client.ConsensusClientAPI", Some("1.2")),
+        ]);
+        let b = services_hash([
+            ("consensus_client.ConsensusClientAPI", Some("1.2")),
+            ("grpc.health.v1.Health", None),
+            ("admin.AdminAPI", None),
+            ("admin.AdminAPI", None),
+        ]);
+        assert_eq!(a, b);
+        assert_eq!(a.len(), 64);
+    }
+
+    #[test]
+    fn different_sets_hash_differently() {
+        let base = services_hash([("admin.AdminAPI", None), ("grpc.health.v1.Health", None)]);
+
+        // A missing service.
+        assert_ne!(base, services_hash([("admin.AdminAPI", None)]));
+        // An extra service.
+        assert_ne!(
+            base,
+            services_hash([
+                ("admin.AdminAPI", None),
+                ("grpc.health.v1.Health", None),
+                ("build_info.BuildInfoAPI", None),
+            ])
+        );
+        // A versioned service.
+        assert_ne!(
+            base,
+            services_hash([
+                ("admin.AdminAPI", Some("1")),
+                ("grpc.health.v1.Health", None)
+            ])
+        );
+        // Bytes moved between a name and its version.
+        assert_ne!(
+            services_hash([("ab", Some("c"))]),
+            services_hash([("a", Some("bc"))])
+        );
+        // The empty set.
+        assert_ne!(base, services_hash([]));
+    }
+}
@@ -0,0 +1,217 @@
+// Copyright (c) 2018-2022 The MobileCoin Foundation
+
+//! Structured error details (google.rpc.ErrorInfo and google.rpc.RetryInfo)
+//! which can be attached to an `RpcStatus`, so that clients get a typed
+//! reason and retry hint in addition to the human-readable message.
+
+use crate::error_details::{ErrorInfo, RetryInfo, Status};
+use grpcio::{RpcStatus, RpcStatusCode};
+use protobuf::{
+    well_known_types::{Any, Duration as ProtobufDuration},
+    Message, ProtobufResult,
+};
+use std::{collections::HashMap, time::Duration};
+
+/// The domain of the `ErrorInfo` reasons produced by our services.
+pub const ERROR_INFO_DOMAIN: &str = "mobilecoin.com";
+
+/// The prefix of the type URL of messages packed into an `Any`.
+const TYPE_URL_PREFIX: &str = "type.googleapis.com/";
+
+/// Structured details of an error, carried by an `RpcStatus`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RpcStatusDetails {
+    /// The cause of the error.
+    pub error_info: Option<ErrorInfo>,
+
+    /// How long the client should wait before retrying.
+    pub retry_info: Option<RetryInfo>,
+}
+
+impl RpcStatusDetails {
+    /// Details with an `ErrorInfo` for the given reason, in our domain.
+    ///
+    /// Reasons should be constant UPPER_SNAKE_CASE strings.
+    pub fn with_reason(reason: &str) -> Self {
+        let mut error_info = ErrorInfo::new();
+        error_info.set_reason(reason.to_string());
+        error_info.set_domain(ERROR_INFO_DOMAIN.to_string());
+        Self {
+            error_info: Some(error_info),
+            retry_info: None,
+        }
+    }
+
+    /// Add metadata to the `ErrorInfo`, creating it if needed.
+    pub fn metadata(mut self, key: &str, value: &str) -> Self {
+        self.error_info
+            .get_or_insert_with(ErrorInfo::new)
+            .mut_metadata()
+            .insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Add a `RetryInfo` asking the client to wait at least `retry_delay`
+    /// before retrying.
+    pub fn retry_after(mut self, retry_delay: Duration) -> Self {
+        let mut delay = ProtobufDuration::new();
+        delay.set_seconds(retry_delay.as_secs() as i64);
+        delay.set_nanos(retry_delay.subsec_nanos() as i32);
+
+        let mut retry_info = RetryInfo::new();
+        retry_info.set_retry_delay(delay);
+        self.retry_info = Some(retry_info);
+        self
+    }
+
+    /// The retry delay, if any.
+    pub fn retry_delay(&self) -> Option<Duration> {
+        self.retry_info.as_ref().map(|retry_info| {
+            let delay = retry_info.get_retry_delay();
+            Duration::new(delay.seconds.max(0) as u64, delay.nanos.max(0) as u32)
+        })
+    }
+
+    /// The reason of the `ErrorInfo`, if any.
+    pub fn reason(&self) -> Option<&str> {
+        self.error_info.as_ref().map(ErrorInfo::get_reason)
+    }
+
+    /// The metadata of the `ErrorInfo`, if any.
+    pub fn get_metadata(&self) -> Option<&HashMap<String, String>> {
+        self.error_info.as_ref().map(ErrorInfo::get_metadata)
+    }
+
+    /// Build an `RpcStatus` with the given code and message, carrying these
+    /// details.
+    pub fn into_rpc_status(self, code: RpcStatusCode, message: String) -> RpcStatus {
+        let mut status = Status::new();
+        status.set_code(code.into());
+        status.set_message(message.clone());
+        if let Some(error_info) = self.error_info.as_ref() {
+            status.mut_details().push(pack(error_info));
+        }
+        if let Some(retry_info) = self.retry_info.as_ref() {
+            status.mut_details().push(pack(retry_info));
+        }
+
+        let details = status
+            .write_to_bytes()
+            .expect("Serializing status details failed");
+        RpcStatus::with_details(code, message, details)
+    }
+
+    /// Decode the details carried by an `RpcStatus`, e.g. one received by a
+    /// client in a `grpcio::Error::RpcFailure`.
+    ///
+    /// Details of types other than `ErrorInfo` and `RetryInfo` are ignored.
+    pub fn from_rpc_status(rpc_status: &RpcStatus) -> ProtobufResult<Self> {
+        let status = Status::parse_from_bytes(rpc_status.details())?;
+
+        let mut result = Self::default();
+        for any in status.get_details() {
+            let type_name = match any.get_type_url().strip_prefix(TYPE_URL_PREFIX) {
+                Some(type_name) => type_name,
+                None => continue,
+            };
+            if type_name == ErrorInfo::descriptor_static().full_name() {
+                result.error_info = Some(ErrorInfo::parse_from_bytes(any.get_value())?);
+            } else if type_name == RetryInfo::descriptor_static().full_name() {
+                result.retry_info = Some(RetryInfo::parse_from_bytes(any.get_value())?);
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// Pack a message into an `Any`.
+fn pack(message: &impl Message) -> Any {
+    let mut any = Any::new();
+    any.set_type_url(format!(
+        "{}{}",
+        TYPE_URL_PREFIX,
+        message.descriptor().full_name()
+    ));
+    any.set_value(
+        message
+            .write_to_bytes()
+            .expect("Serializing status detail failed"),
+    );
+    any
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_error_and_retry_info() {
+        let status = RpcStatusDetails::with_reason("RATE_LIMITED")
+            .metadata("limit", "100")
+            .retry_after(Duration::from_millis(1500))
+            .into_rpc_status(RpcStatusCode::RESOURCE_EXHAUSTED, "Slow down".to_string());
+        assert_eq!(status.code(), RpcStatusCode::RESOURCE_EXHAUSTED);
+        assert_eq!(status.message(), "Slow down");
+
+        // This is what a client sees.
+        let details = RpcStatusDetails::from_rpc_status(&status).unwrap();
+        assert_eq!(details.reason(), Some("RATE_LIMITED"));
+        assert_eq!(
+            details.error_info.as_ref().unwrap().get_domain(),
+            ERROR_INFO_DOMAIN
+        );
+        assert_eq!(
+            details
+                .get_metadata()
+                .unwrap()
+                .get("limit")
+                .map(String::as_str),
+            Some("100")
+        );
+        assert_eq!(details.retry_delay(), Some(Duration::from_millis(1500)));
+
+        // The google.rpc.Status carries the code and message too.
+        let decoded = Status::parse_from_bytes(status.details()).unwrap();
+        assert_eq!(
+            decoded.get_code(),
+            i32::from(RpcStatusCode::RESOURCE_EXHAUSTED)
+        );
+        assert_eq!(decoded.get_message(), "Slow down");
+        assert_eq!(decoded.get_details().len(), 2);
+    }
+
+    #[test]
+    fn status_without_details() {
+        let status = RpcStatus::with_message(RpcStatusCode::INTERNAL, "oops".to_string());
+        let details = RpcStatusDetails::from_rpc_status(&status).unwrap();
+        assert_eq!(details, RpcStatusDetails::default());
+        assert_eq!(details.reason(), None);
+        assert_eq!(details.retry_delay(), None);
+    }
+
+    #[test]
+    fn unknown_detail_types_are_ignored() {
+        let mut other = Any::new();
+        other.set_type_url("type.googleapis.com/google.rpc.DebugInfo".to_string());
+        other.set_value(vec![1, 2, 3]);
+
+        let mut status = Status::new();
+        status.set_code(RpcStatusCode::INTERNAL.into());
+        status.mut_details().push(other);
+        status.mut_details().push(pack(
+            RpcStatusDetails::with_reason("BROKEN")
+                .error_info
+                .as_ref()
+                .unwrap(),
+        ));
+        let rpc_status = RpcStatus::with_details(
+            RpcStatusCode::INTERNAL,
+            String::new(),
+            status.write_to_bytes().unwrap(),
+        );
+
+        let details = RpcStatusDetails::from_rpc_status(&rpc_status).unwrap();
+        assert_eq!(details.reason(), Some("BROKEN"));
+        assert!(details.retry_info.is_none());
+    }
+}
@@ -0,0 +1,178 @@
+// Copyright (c) 2018-2022 The MobileCoin Foundation
+
+//! A health service with pluggable handlers, shared by the unit tests of this
+//! crate.
+
+use crate::{
+    create_health, send_result, Health, HealthCheckRequest, HealthCheckResponse, HealthClient,
+    PingRequest, PingResponse,
+};
+use futures::executor::block_on;
+use grpcio::{
+    ChannelBuilder, EnvBuilder, RpcContext, RpcStatus, RpcStatusCode, Server, ServerBuilder,
+    ServerStreamingSink, UnarySink,
+};
+use mc_common::logger::Logger;
+use std::{sync::Arc, thread, time::Duration};
+
+/// Handles `Check` calls to a `TestHealth`.
+pub type CheckHandler =
+    Arc<dyn Fn(RpcContext, HealthCheckRequest, UnarySink<HealthCheckResponse>) + Send + Sync>;
+
+/// Handles `Ping` calls to a `TestHealth`.
+pub type PingHandler = Arc<dyn Fn(RpcContext, PingRequest, UnarySink<PingResponse>) + Send + Sync>;
+
+/// Handles `Watch` calls to a `TestHealth`.
+pub type WatchHandler = Arc<
+    dyn Fn(RpcContext, HealthCheckRequest, ServerStreamingSink<HealthCheckResponse>) + Send + Sync,
+>;
+
+/// A health service whose methods are handled by closures. Methods without a
+/// handler fail with `UNIMPLEMENTED`.
+#[derive(Clone)]
+pub struct TestHealth {
+    check: Option<CheckHandler>,
+    ping: Option<PingHandler>,
+    watch: Option<WatchHandler>,
+    logger: Logger,
+}
+
+impl TestHealth {
+    /// Create a service with no handlers.
+    pub fn new(logger: Logger) -> Self {
+        Self {
+            check: None,
+            ping: None,
+            watch: None,
+            logger,
+        }
+    }
+
+    /// Handle `Check` calls with `handler`.
+    pub fn with_check(
+        mut self,
+        handler: impl Fn(RpcContext, HealthCheckRequest, UnarySink<HealthCheckResponse>)
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.check = Some(Arc::new(handler));
+        self
+    }
+
+    /// Handle `Ping` calls with `handler`.
+    pub fn with_ping(
+        mut self,
+        handler: impl Fn(RpcContext, PingRequest, UnarySink<PingResponse>) + Send + Sync + 'static,
+    ) -> Self {
+        self.ping = Some(Arc::new(handler));
+        self
+    }
+
+    /// Handle `Watch` calls with `handler`.
+    pub fn with_watch(
+        mut self,
+        handler: impl Fn(RpcContext, HealthCheckRequest, ServerStreamingSink<HealthCheckResponse>)
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.watch = Some(Arc::new(handler));
+        self
+    }
+
+    /// Serve this service on localhost, and connect a client to it.
+    pub fn start(self) -> (Server, HealthClient) {
+        let env = Arc::new(EnvBuilder::new().build());
+        let mut server = ServerBuilder::new(env.clone())
+            .register_service(create_health(self))
+            .bind("127.0.0.1", 0)
+            .build()
+            .unwrap();
+        server.start();
+        let port = server.bind_addrs().next().unwrap().1;
+
+        let channel = ChannelBuilder::new(env).connect(&format!("127.0.0.1:{}", port));
+        (server, HealthClient::new(channel))
+    }
+}
+
+impl Health for TestHealth {
+    fn check(
+        &mut self,
+        ctx: RpcContext,
+        req: HealthCheckRequest,
+        sink: UnarySink<HealthCheckResponse>,
+    ) {
+        match &self.check {
+            Some(handler) => handler(ctx, req, sink),
+            None => send_result(ctx, sink, Err(unimplemented()), &self.logger),
+        }
+    }
+
+    fn ping(&mut self, ctx: RpcContext, req: PingRequest, sink: UnarySink<PingResponse>) {
+        match &self.ping {
+            Some(handler) => handler(ctx, req, sink),
+            None => send_result(ctx, sink, Err(unimplemented()), &self.logger),
+        }
+    }
+
+    fn watch(
+        &mut self,
+        ctx: RpcContext,
+        req: HealthCheckRequest,
+        sink: ServerStreamingSink<HealthCheckResponse>,
+    ) {
+        match &self.watch {
+            Some(handler) => handler(ctx, req, sink),
+            None => ctx.spawn(async move {
+                let _ = sink.fail(unimplemented()).await;
+            }),
+        }
+    }
+}
+
+fn unimplemented() -> RpcStatus {
+    RpcStatus::new(RpcStatusCode::UNIMPLEMENTED)
+}
+
+/// Echo the data of `req` after `delay`, from another thread, so that slow
+/// calls overlap. `guard`, e.g. a concurrency permit, is held until just before
+/// the response is sent.
+pub fn respond_after(
+    delay: Duration,
+    mut req: PingRequest,
+    sink: UnarySink<PingResponse>,
+    guard: impl Send + 'static,
+) {
+    thread::spawn(move || {
+        thread::sleep(delay);
+        drop(guard);
+        let mut resp = PingResponse::new();
+        resp.set_data(req.take_data());
+        let _ = block_on(sink.success(resp));
+    });
+}
+
+/// A health service whose calls must first be admitted by `admit`, e.g. a
+/// concurrency limiter's `acquire`. Admitted pings are echoed after `delay`,
+/// holding on to what `admit` returned until then.
+pub fn slow_admitting_health<P: Send + 'static>(
+    delay: Duration,
+    admit: impl Fn(&RpcContext) -> Result<P, RpcStatus> + Send + Sync + 'static,
+    logger: Logger,
+) -> TestHealth {
+    let admit = Arc::new(admit);
+    let check_admit = admit.clone();
+    let check_logger = logger.clone();
+    let ping_logger = logger.clone();
+    TestHealth::new(logger)
+        .with_check(move |ctx, _req, sink| {
+            let resp = check_admit(&ctx).map(|_permit| HealthCheckResponse::new());
+            send_result(ctx, sink, resp, &check_logger)
+        })
+        .with_ping(move |ctx, req, sink| match admit(&ctx) {
+            Ok(permit) => respond_after(delay, req, sink, permit),
+            Err(err) => send_result(ctx, sink, Err(err), &ping_logger),
+        })
+}
@@ -13,6 +13,7 @@ For each method, the counters that are captured are:
 - num_status_code: number of gRPC status codes (to establish statistics on
 gRPC status codes, similar to how HTTP 2XX/4XX/5XX codes are profiled)
 - duration: duration (in units determined by the exporter) the request took, bucketed
+- payload_size: serialized size of requests and responses, in bytes, bucketed
 
 Example use:
 call `req` when entering service method, and call `resp` on
@@ -63,6 +64,9 @@ pub struct ServiceMetrics {
 
     /// Histogram of message sizes for each gRPC message type tracked
     message_size: HistogramVec,
+
+    /// Histogram of request and response sizes for each gRPC method tracked
+    payload_size: HistogramVec,
 }
 
 impl ServiceMetrics {
@@ -70,6 +74,8 @@ impl ServiceMetrics {
     pub fn default() -> ServiceMetrics {
         let message_size_buckets = exponential_buckets(2.0, 2.0, 22)
             .expect("Could not create buckets for message-size histogram");
+        let payload_size_buckets = exponential_buckets(2.0, 2.0, 22)
+            .expect("Could not create buckets for payload-size histogram");
 
         ServiceMetrics {
             num_req: IntCounterVec::new(Opts::new("num_req", "Number of requests"), &["method"])
@@ -93,6 +99,15 @@ impl ServiceMetrics {
                 &["message"],
             )
             .unwrap(),
+            payload_size: HistogramVec::new(
+                HistogramOpts::new(
+                    "payload_size",
+                    "Serialized size of requests and responses, in bytes",
+                )
+                .buckets(payload_size_buckets),
+                &["method", "direction"],
+            )
+            .unwrap(),
         }
     }
 
@@ -154,6 +169,28 @@ impl ServiceMetrics {
             .observe(f64::from(computed_size));
     }
 
+    /// Takes the RpcContext used during a gRPC method call to get the method
+    /// name and records the serialized size of its request, in bytes
+    pub fn request_size(&self, ctx: &RpcContext, size: u32) {
+        if let Some(name) = path_from_ctx(ctx) {
+            self.observe_payload_size(&name, "request", size);
+        }
+    }
+
+    /// Takes the RpcContext used during a gRPC method call to get the method
+    /// name and records the serialized size of its response, in bytes
+    pub fn response_size(&self, ctx: &RpcContext, size: u32) {
+        if let Some(name) = path_from_ctx(ctx) {
+            self.observe_payload_size(&name, "response", size);
+        }
+    }
+
+    fn observe_payload_size(&self, method: &str, direction: &str, size: u32) {
+        self.payload_size
+            .with_label_values(&[method, direction])
+            .observe(f64::from(size));
+    }
+
     pub fn register_default(&self) -> Result<()> {
         prometheus::register(Box::new(self.clone()))
     }
@@ -169,6 +206,7 @@ impl Collector for ServiceMetrics {
             self.num_status_code.desc(),
             self.duration.desc(),
             self.message_size.desc(),
+            self.payload_size.desc(),
         ]
         .into_iter()
         .map(|m| m[0])
@@ -184,6 +222,7 @@ impl Collector for ServiceMetrics {
             self.num_status_code.collect(),
             self.duration.collect(),
             self.message_size.collect(),
+            self.payload_size.collect(),
         ];
 
         vs.into_iter().fold(vec![], |mut l, v| {
@@ -225,6 +264,7 @@ fn path_from_byte_slice(bytes: &[u8]) -> Option<String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use protobuf::well_known_types::StringValue;
 
     #[test]
     fn test_path_from_bytes() {
@@ -249,4 +289,33 @@ mod tests {
             Some(String::from("Ab.c"))
         );
     }
+
+    #[test]
+    fn test_payload_size() {
+        let metrics = ServiceMetrics::default();
+
+        // A string field with tag 1 takes two bytes plus the string itself.
+        let request = StringValue {
+            value: "hello".to_owned(),
+            ..Default::default()
+        };
+        let response = StringValue {
+            value: "hello, world".to_owned(),
+            ..Default::default()
+        };
+        assert_eq!(request.compute_size(), 7);
+        assert_eq!(response.compute_size(), 14);
+
+        metrics.observe_payload_size("A.b", "request", request.compute_size());
+        metrics.observe_payload_size("A.b", "response", response.compute_size());
+        metrics.observe_payload_size("A.b", "response", response.compute_size());
+
+        let request_sizes = metrics.payload_size.with_label_values(&["A.b", "request"]);
+        assert_eq!(request_sizes.get_sample_count(), 1);
+        assert_eq!(request_sizes.get_sample_sum(), 7.0);
+
+        let response_sizes = metrics.payload_size.with_label_values(&["A.b", "response"]);
+        assert_eq!(response_sizes.get_sample_count(), 2);
+        assert_eq!(response_sizes.get_sample_sum(), 28.0);
+    }
 }